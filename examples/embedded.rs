@@ -0,0 +1,55 @@
+//! Runs three nodes in one process, without the binaries: the first mines coins, the second pays
+//! some of them on and mines the payment, and all three must end up with the same chain.
+//!
+//! ```sh
+//! cargo run --example embedded
+//! ```
+
+use nb::{BlockId, Node, NodeConfig, NodeHandle};
+use std::env;
+use std::fs;
+use std::process;
+
+#[tokio::main]
+async fn main() -> nb::Result<()> {
+    let data_dir = env::temp_dir().join(format!("nb-embedded-{}", process::id()));
+    let mut nodes = Vec::new();
+    for port in 4700..4703 {
+        let addr = format!("127.0.0.1:{}", port);
+        let mut config = NodeConfig::new(&addr);
+        config.data_dir = data_dir.join(&addr);
+        config.difficulty = Some(4);
+        config.reward_address = Some(format!("miner-{}", port));
+        nodes.push(Node::spawn(config)?);
+    }
+    for node in &nodes[1..] {
+        node.add_peer(nodes[0].addr()).await?;
+    }
+
+    nodes[0].mine().await?;
+    sync(&nodes).await?;
+    let submission = nodes[1].new_transaction("miner-4700", "bob", 1, 0).await?;
+    println!("sent {}", submission);
+    let block = nodes[1].mine().await?;
+    println!("mined block {} ({})", block.index, block.hash);
+    sync(&nodes).await?;
+
+    for node in &nodes {
+        assert_eq!(node.balance("bob").await?, 1);
+        assert_eq!(node.block(BlockId::Index(2)).await?.get_hash(), block.hash);
+        println!("{}: {:?}", node.addr(), node.stats().await?);
+    }
+    for node in nodes {
+        node.shutdown().await?;
+    }
+    fs::remove_dir_all(data_dir)?;
+    Ok(())
+}
+
+/// Has every node take over the longest chain of its peers.
+async fn sync(nodes: &[NodeHandle]) -> nb::Result<()> {
+    for node in nodes {
+        node.resolve().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,305 @@
+//! Addresses encoding public keys
+//!
+//! An address of a key is its public key written in bech32, as BIP-173 does: a prefix naming
+//! the network, `1`, and the 32 bytes of the key in 5 bit groups followed by a checksum over
+//! all of it, e.g. `nb1...`. A mistyped address fails the checksum, and one of another network
+//! has another prefix, rather than either being paid.
+//!
+//! Chains whose genesis block sets a prefix, see `GenesisSpec::address_prefix`, only know such
+//! addresses, and new chains set one. Legacy chains, set up without, take free-form ones too,
+//! like `alice`, and public keys as hex.
+
+use crate::key::{from_hex, is_public_key, to_hex};
+use std::fmt;
+use std::str::FromStr;
+
+/// The 32 characters the 5 bit groups are written in.
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// How many characters the key and the checksum take: 52 for 32 bytes, and 6.
+const DATA_LEN: usize = 58;
+const CHECKSUM_LEN: usize = 6;
+/// The longest prefix, which keeps addresses within the 64 characters of `is_valid_address`.
+pub const MAX_PREFIX_LEN: usize = 5;
+
+/// A public key under the prefix of a network, both checked, see `Address::new`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    prefix: String,
+    public_key: String,
+}
+
+/// Why a string is not an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddressError {
+    /// no `1` ends a prefix of 1 to `MAX_PREFIX_LEN` lowercase letters
+    BadPrefix,
+    /// addresses are lowercase only, so that each has one spelling
+    Uppercase,
+    /// with the first character outside of the charset
+    BadCharacter(char),
+    /// the key is not 32 bytes
+    BadLength,
+    BadChecksum,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::BadPrefix => write!(
+                f,
+                "it does not start with a prefix of 1 to {} letters and 1",
+                MAX_PREFIX_LEN
+            ),
+            AddressError::Uppercase => write!(f, "it is not lowercase"),
+            AddressError::BadCharacter(c) => write!(f, "{:?} is no bech32 character", c),
+            AddressError::BadLength => write!(f, "it does not hold a public key"),
+            AddressError::BadChecksum => write!(f, "the checksum does not match, is it mistyped?"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl Address {
+    /// The address of `public_key`, in hex, under `prefix`. `None` if either is malformed.
+    pub fn new(prefix: &str, public_key: &str) -> Option<Self> {
+        if !is_address_prefix(prefix) || !is_public_key(public_key) {
+            return None;
+        }
+        Some(Address {
+            prefix: prefix.to_owned(),
+            public_key: public_key.to_owned(),
+        })
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The public key, in hex.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = regroup(
+            &from_hex(&self.public_key).expect("a public key is hex"),
+            8,
+            5,
+        );
+        data.extend(checksum(&self.prefix, &data));
+        let data: String = data.iter().map(|&d| CHARSET[d as usize] as char).collect();
+        write!(f, "{}1{}", self.prefix, data)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<Self, AddressError> {
+        if address.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(AddressError::Uppercase);
+        }
+        let (prefix, data) = address.rsplit_once('1').ok_or(AddressError::BadPrefix)?;
+        if !is_address_prefix(prefix) {
+            return Err(AddressError::BadPrefix);
+        }
+        let data = data
+            .chars()
+            .map(|c| {
+                CHARSET
+                    .iter()
+                    .position(|&d| d as char == c)
+                    .map(|d| d as u8)
+                    .ok_or(AddressError::BadCharacter(c))
+            })
+            .collect::<Result<Vec<u8>, AddressError>>()?;
+        if data.len() != DATA_LEN {
+            return Err(AddressError::BadLength);
+        }
+        if polymod(&expand(prefix), &data) != 1 {
+            return Err(AddressError::BadChecksum);
+        }
+        let key = regroup(&data[..DATA_LEN - CHECKSUM_LEN], 5, 8);
+        // 52 groups are 260 bits, the last 4 of which pad the key
+        if key.len() != 33 || key[32] != 0 {
+            return Err(AddressError::BadLength);
+        }
+        Ok(Address {
+            prefix: prefix.to_owned(),
+            public_key: to_hex(&key[..32]),
+        })
+    }
+}
+
+/// Whether `prefix` can name a network: 1 to `MAX_PREFIX_LEN` lowercase letters.
+pub fn is_address_prefix(prefix: &str) -> bool {
+    (1..=MAX_PREFIX_LEN).contains(&prefix.len()) && prefix.chars().all(|c| c.is_ascii_lowercase())
+}
+
+/// The public key holding the coins of `address`: the address itself if it is a public key, or
+/// the key it encodes. `None` for addresses without a key, such as `alice`.
+pub fn key_of(address: &str) -> Option<String> {
+    if is_public_key(address) {
+        return Some(address.to_owned());
+    }
+    address
+        .parse::<Address>()
+        .ok()
+        .map(|address| address.public_key)
+}
+
+/// Whether `address` is shaped like an encoded address, whatever the case, so that it must be one
+/// rather than pass as a free-form address with a bad checksum.
+pub(crate) fn looks_encoded(address: &str) -> bool {
+    match address.rsplit_once('1') {
+        Some((prefix, data)) => {
+            (1..=MAX_PREFIX_LEN).contains(&prefix.len())
+                && prefix.chars().all(|c| c.is_ascii_alphabetic())
+                && data.len() == DATA_LEN
+                && data
+                    .bytes()
+                    .all(|c| CHARSET.contains(&c.to_ascii_lowercase()))
+        }
+        None => false,
+    }
+}
+
+/// Tells what is wrong with `address`, which `is_valid_address` turns down, e.g. that the
+/// checksum of an encoded one does not match.
+pub(crate) fn invalid_address(address: &str) -> String {
+    match address.parse::<Address>() {
+        Err(e) if looks_encoded(address) => format!("{:?} is not a valid address, {}", address, e),
+        _ => format!("{:?} is not a valid address", address),
+    }
+}
+
+/// Splits the bits of `values`, `from` bits each, into groups of `to` bits, padding the last
+/// with zeros.
+fn regroup(values: &[u8], from: u32, to: u32) -> Vec<u8> {
+    let mut groups = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for &value in values {
+        acc = (acc << from) | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            groups.push((acc >> bits) as u8 & ((1 << to) - 1) as u8);
+        }
+    }
+    if bits > 0 {
+        groups.push((acc << (to - bits)) as u8 & ((1 << to) - 1) as u8);
+    }
+    groups
+}
+
+/// The prefix as the checksum covers it: the high bits of its characters, 0, and the low bits.
+fn expand(prefix: &str) -> Vec<u8> {
+    let bytes = prefix.bytes();
+    bytes
+        .clone()
+        .map(|c| c >> 5)
+        .chain(std::iter::once(0))
+        .chain(bytes.map(|c| c & 31))
+        .collect()
+}
+
+/// The BCH code of BIP-173 over `prefix` and `data`.
+fn polymod(prefix: &[u8], data: &[u8]) -> u32 {
+    const GENERATORS: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for &value in prefix.iter().chain(data) {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ value as u32;
+        for (i, generator) in GENERATORS.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// The 6 groups that make `polymod` of the prefix, the data and them 1.
+fn checksum(prefix: &str, data: &[u8]) -> Vec<u8> {
+    let padded: Vec<u8> = data.iter().copied().chain([0; CHECKSUM_LEN]).collect();
+    let checksum = polymod(&expand(prefix), &padded) ^ 1;
+    (0..CHECKSUM_LEN)
+        .map(|i| (checksum >> (5 * (5 - i))) as u8 & 31)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeKey;
+
+    #[test]
+    fn test_checksum() {
+        // valid strings of BIP-173, holding no key but checksums
+        for valid in ["a12uel5l", "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw"] {
+            let (prefix, data) = valid.rsplit_once('1').unwrap();
+            let data: Vec<u8> = data
+                .bytes()
+                .map(|c| CHARSET.iter().position(|&d| d == c).unwrap() as u8)
+                .collect();
+            assert_eq!(polymod(&expand(prefix), &data), 1, "{}", valid);
+            let (data, sum) = data.split_at(data.len() - CHECKSUM_LEN);
+            assert_eq!(checksum(prefix, data), sum);
+        }
+        assert_eq!(
+            regroup(&regroup(&[0xff, 0x01], 8, 5), 5, 8),
+            [0xff, 0x01, 0]
+        );
+    }
+
+    #[test]
+    fn test_address() {
+        let key = NodeKey::generate().public_key();
+        let address = Address::new("nb", &key).unwrap();
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("nb1"));
+        assert_eq!(encoded.len(), 61);
+        assert!(looks_encoded(&encoded));
+        assert_eq!(encoded.parse::<Address>(), Ok(address));
+        assert_eq!(key_of(&encoded), Some(key.clone()));
+        assert_eq!(key_of(&key), Some(key.clone()));
+        assert_eq!(key_of("alice"), None);
+        assert!(!looks_encoded("alice") && !looks_encoded("node1"));
+
+        // a prefix of another network makes another address
+        let other = Address::new("tnb", &key).unwrap().to_string();
+        assert_eq!(other.parse::<Address>().unwrap().prefix, "tnb");
+        assert_ne!(other[4..], encoded[3..]);
+
+        // any one mistyped character is caught
+        let mut typo = encoded.clone().into_bytes();
+        typo[10] = if typo[10] == b'q' { b'p' } else { b'q' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert_eq!(typo.parse::<Address>(), Err(AddressError::BadChecksum));
+        assert_eq!(
+            encoded.to_uppercase().parse::<Address>(),
+            Err(AddressError::Uppercase)
+        );
+        assert_eq!(
+            format!("{}b", &encoded[..60]).parse::<Address>(),
+            Err(AddressError::BadCharacter('b'))
+        );
+        assert_eq!(
+            encoded[..59].parse::<Address>(),
+            Err(AddressError::BadLength)
+        );
+        assert_eq!(
+            format!("n{}", &encoded[2..]).parse::<Address>(),
+            Err(AddressError::BadChecksum)
+        );
+        assert_eq!(
+            format!("nbnbnb{}", &encoded[2..]).parse::<Address>(),
+            Err(AddressError::BadPrefix)
+        );
+        assert_eq!(Address::new("NB", &key), None);
+        assert_eq!(Address::new("nb", "alice"), None);
+    }
+}
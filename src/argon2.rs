@@ -0,0 +1,330 @@
+//! Argon2id, see RFC 9106
+//!
+//! The passphrase hash of wallets, see `keystore`. Memory is filled in 1 KiB blocks, lane by
+//! lane within each of the four slices of a pass: the first half of the first pass picks the
+//! blocks it mixes in independently of the passphrase, as Argon2i does, and the rest from the
+//! blocks already filled, as Argon2d does.
+
+use crypto::blake2b::Blake2b;
+use crypto::digest::Digest;
+
+/// The version of Argon2 this is, 1.3.
+const VERSION: u32 = 0x13;
+
+/// The type of Argon2id, as hashed into the memory.
+const ARGON2ID: u32 = 2;
+
+/// The words of a block of memory.
+const BLOCK_WORDS: usize = 128;
+
+/// The slices of a lane, between which the lanes may only reference each other.
+const SYNC_POINTS: usize = 4;
+
+type Block = [u64; BLOCK_WORDS];
+
+/// The costs of a hash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    /// the memory, in KiB, at least 8 per lane
+    pub m_cost: u32,
+    /// the passes over the memory
+    pub t_cost: u32,
+    /// the lanes
+    pub p_cost: u32,
+}
+
+/// Hashes `password` with `salt` into `out`, of 4 bytes or more.
+pub fn argon2id(password: &[u8], salt: &[u8], params: Params, out: &mut [u8]) {
+    hash(password, salt, &[], &[], params, out)
+}
+
+fn hash(password: &[u8], salt: &[u8], secret: &[u8], data: &[u8], params: Params, out: &mut [u8]) {
+    let Params {
+        m_cost,
+        t_cost,
+        p_cost,
+    } = params;
+    assert!(out.len() >= 4 && t_cost >= 1 && p_cost >= 1);
+    let lanes = p_cost as usize;
+    let segment_length = (m_cost as usize).max(2 * SYNC_POINTS * lanes) / (lanes * SYNC_POINTS);
+    let lane_length = segment_length * SYNC_POINTS;
+
+    let mut h0 = Blake2b::new(64);
+    for word in [p_cost, out.len() as u32, m_cost, t_cost, VERSION, ARGON2ID] {
+        h0.input(&word.to_le_bytes());
+    }
+    for input in [password, salt, secret, data] {
+        h0.input(&(input.len() as u32).to_le_bytes());
+        h0.input(input);
+    }
+    let mut seed = [0; 72];
+    h0.result(&mut seed[..64]);
+
+    let mut memory = vec![[0; BLOCK_WORDS]; lane_length * lanes];
+    let mut bytes = [0; 1024];
+    for lane in 0..lanes {
+        for column in 0..2 {
+            seed[64..68].copy_from_slice(&(column as u32).to_le_bytes());
+            seed[68..].copy_from_slice(&(lane as u32).to_le_bytes());
+            variable_hash(&seed, &mut bytes);
+            memory[lane * lane_length + column] = block_of(&bytes);
+        }
+    }
+
+    let instance = Instance {
+        lanes,
+        lane_length,
+        segment_length,
+        passes: u64::from(t_cost),
+    };
+    for pass in 0..instance.passes {
+        for slice in 0..SYNC_POINTS {
+            for lane in 0..lanes {
+                instance.fill_segment(&mut memory, pass, slice, lane);
+            }
+        }
+    }
+
+    let mut last = memory[lane_length - 1];
+    for lane in 1..lanes {
+        xor(&mut last, &memory[lane * lane_length + lane_length - 1]);
+    }
+    for (chunk, word) in bytes.chunks_mut(8).zip(last.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    variable_hash(&bytes, out);
+}
+
+struct Instance {
+    lanes: usize,
+    lane_length: usize,
+    segment_length: usize,
+    passes: u64,
+}
+
+impl Instance {
+    fn fill_segment(&self, memory: &mut [Block], pass: u64, slice: usize, lane: usize) {
+        let independent = pass == 0 && slice < SYNC_POINTS / 2;
+        let zero = [0; BLOCK_WORDS];
+        let mut input = [0; BLOCK_WORDS];
+        let mut addresses = [0; BLOCK_WORDS];
+        input[..6].copy_from_slice(&[
+            pass,
+            lane as u64,
+            slice as u64,
+            memory.len() as u64,
+            self.passes,
+            u64::from(ARGON2ID),
+        ]);
+        let next_addresses = |input: &mut Block, addresses: &mut Block| {
+            input[6] += 1;
+            *addresses = compress(&zero, input);
+            *addresses = compress(&zero, addresses);
+        };
+
+        let first = if pass == 0 && slice == 0 {
+            if independent {
+                next_addresses(&mut input, &mut addresses);
+            }
+            2
+        } else {
+            0
+        };
+        for index in first..self.segment_length {
+            let offset = lane * self.lane_length + slice * self.segment_length + index;
+            let previous = if offset.is_multiple_of(self.lane_length) {
+                offset + self.lane_length - 1
+            } else {
+                offset - 1
+            };
+            let random = if independent {
+                if index % BLOCK_WORDS == 0 {
+                    next_addresses(&mut input, &mut addresses);
+                }
+                addresses[index % BLOCK_WORDS]
+            } else {
+                memory[previous][0]
+            };
+            let reference_lane = if pass == 0 && slice == 0 {
+                lane
+            } else {
+                (random >> 32) as usize % self.lanes
+            };
+            let reference_index =
+                self.reference_index(pass, slice, index, random as u32, reference_lane == lane);
+            let mixed = compress(
+                &memory[previous],
+                &memory[reference_lane * self.lane_length + reference_index],
+            );
+            if pass == 0 {
+                memory[offset] = mixed;
+            } else {
+                xor(&mut memory[offset], &mixed);
+            }
+        }
+    }
+
+    /// The block of the reference lane the block at `index` of the segment mixes in, picked
+    /// by `random` among those filled and not being filled by other lanes.
+    fn reference_index(
+        &self,
+        pass: u64,
+        slice: usize,
+        index: usize,
+        random: u32,
+        same_lane: bool,
+    ) -> usize {
+        let (filled, start) = if pass == 0 {
+            (slice * self.segment_length, 0)
+        } else {
+            (
+                self.lane_length - self.segment_length,
+                (slice + 1) % SYNC_POINTS * self.segment_length,
+            )
+        };
+        // the block before the current one is mixed in anyway, and the last one of the
+        // previous segment of another lane only while it is not being overwritten
+        let area = if same_lane {
+            filled + index - 1
+        } else if index == 0 {
+            filled - 1
+        } else {
+            filled
+        } as u64;
+        let random = u64::from(random);
+        let relative = area - 1 - ((area * ((random * random) >> 32)) >> 32);
+        (start + relative as usize) % self.lane_length
+    }
+}
+
+/// The compression function G.
+fn compress(x: &Block, y: &Block) -> Block {
+    let mut r = *x;
+    xor(&mut r, y);
+    let mut z = r;
+    for row in 0..8 {
+        let mut words: [usize; 16] = [0; 16];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = 16 * row + i;
+        }
+        permute(&mut z, words);
+    }
+    for column in 0..8 {
+        let mut words: [usize; 16] = [0; 16];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = 16 * (i / 2) + 2 * column + i % 2;
+        }
+        permute(&mut z, words);
+    }
+    xor(&mut z, &r);
+    z
+}
+
+/// The permutation P, of the `words` of `block`.
+fn permute(block: &mut Block, words: [usize; 16]) {
+    let mut v = [0; 16];
+    for (v, &word) in v.iter_mut().zip(words.iter()) {
+        *v = block[word];
+    }
+    for &(a, b, c, d) in &[
+        (0, 4, 8, 12),
+        (1, 5, 9, 13),
+        (2, 6, 10, 14),
+        (3, 7, 11, 15),
+        (0, 5, 10, 15),
+        (1, 6, 11, 12),
+        (2, 7, 8, 13),
+        (3, 4, 9, 14),
+    ] {
+        mix(&mut v, a, b, c, d);
+    }
+    for (v, &word) in v.iter().zip(words.iter()) {
+        block[word] = *v;
+    }
+}
+
+/// The function GB, which is BLAKE2b's G with multiplications added.
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize) {
+    let add = |x: u64, y: u64| {
+        x.wrapping_add(y).wrapping_add(
+            (x & 0xffff_ffff)
+                .wrapping_mul(y & 0xffff_ffff)
+                .wrapping_mul(2),
+        )
+    };
+    v[a] = add(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = add(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = add(v[a], v[b]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = add(v[c], v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// The variable-length hash H', of `input` into `out`.
+fn variable_hash(input: &[u8], out: &mut [u8]) {
+    let length = (out.len() as u32).to_le_bytes();
+    if out.len() <= 64 {
+        let mut hasher = Blake2b::new(out.len());
+        hasher.input(&length);
+        hasher.input(input);
+        hasher.result(out);
+        return;
+    }
+    let mut v = [0; 64];
+    let mut hasher = Blake2b::new(64);
+    hasher.input(&length);
+    hasher.input(input);
+    hasher.result(&mut v);
+    // the first halves of a chain of hashes, then the whole last one
+    let halves = out.len().div_ceil(32) - 2;
+    out[..32].copy_from_slice(&v[..32]);
+    for i in 1..halves {
+        let previous = v;
+        Blake2b::blake2b(&mut v, &previous, &[]);
+        out[32 * i..32 * (i + 1)].copy_from_slice(&v[..32]);
+    }
+    let rest = out.len() - 32 * halves;
+    let mut hasher = Blake2b::new(rest);
+    hasher.input(&v);
+    hasher.result(&mut out[32 * halves..]);
+}
+
+fn block_of(bytes: &[u8; 1024]) -> Block {
+    let mut block = [0; BLOCK_WORDS];
+    for (word, chunk) in block.iter_mut().zip(bytes.chunks(8)) {
+        let mut le = [0; 8];
+        le.copy_from_slice(chunk);
+        *word = u64::from_le_bytes(le);
+    }
+    block
+}
+
+fn xor(block: &mut Block, other: &Block) {
+    for (word, other) in block.iter_mut().zip(other.iter()) {
+        *word ^= other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::to_hex;
+
+    #[test]
+    fn test_argon2id() {
+        // the test vector of RFC 9106, section 5.3
+        let mut tag = [0; 32];
+        let params = Params {
+            m_cost: 32,
+            t_cost: 3,
+            p_cost: 4,
+        };
+        hash(&[1; 32], &[2; 16], &[3; 8], &[4; 12], params, &mut tag);
+        assert_eq!(
+            to_hex(&tag),
+            "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659"
+        );
+    }
+}
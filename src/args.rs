@@ -0,0 +1,523 @@
+//! The flags of `nb` and `nbd`, and the node config they make
+//!
+//! Both binaries take the flags of the chain, and `nbd` also those of the node, which `nb` passes
+//! on when it runs an `nbd` of its own.
+
+use crate::{
+    default_control_addr, ConsensusMode, GenesisSpec, NodeConfig, Result, RewardSchedule,
+    TransactionModel, INITIAL_DIFFICULTY, MAX_DIFFICULTY,
+};
+use clap::{Arg, ArgMatches};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// The flags of the chain, which pick its data directory and genesis block, `global` for the
+/// subcommands of `nb`.
+pub fn chain_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("addr")
+            .long("addr")
+            .takes_value(true)
+            .value_name("IP-PORT")
+            .default_value("127.0.0.1:4000")
+            .global(true)
+            .help("the node's address"),
+        Arg::with_name("data-dir")
+            .long("data-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .global(true)
+            .help("where to persist the chains and peers, one database per genesis block [default: nb_data/<IP-PORT>]"),
+        Arg::with_name("db")
+            .long("db")
+            .takes_value(true)
+            .possible_values(&["sled", "rocksdb"])
+            .default_value("sled")
+            .global(true)
+            .help("the database of the data directory (rocksdb needs the `rocksdb` feature)"),
+        Arg::with_name("difficulty")
+            .long("difficulty")
+            .takes_value(true)
+            .value_name("BITS")
+            .global(true)
+            .help("how many leading zero bits the block header hashes of the chain need, low values mine instantly [default: 16]"),
+        Arg::with_name("block-reward")
+            .long("block-reward")
+            .takes_value(true)
+            .value_name("COINS")
+            .global(true)
+            .help("how many coins mining a block of the chain mints at first [default: 1]"),
+        Arg::with_name("halving-interval")
+            .long("halving-interval")
+            .takes_value(true)
+            .value_name("BLOCKS")
+            .global(true)
+            .help("halves the block reward of the chain every this many blocks, never if 0 [default: 0]"),
+        Arg::with_name("max-supply")
+            .long("max-supply")
+            .takes_value(true)
+            .value_name("COINS")
+            .global(true)
+            .help("stops minting coins on the chain once this many were minted [default: no cap]"),
+        Arg::with_name("coinbase-maturity")
+            .long("coinbase-maturity")
+            .takes_value(true)
+            .value_name("BLOCKS")
+            .global(true)
+            .help("keeps the reward of a block from being spent until this many more blocks are mined, 0 to spend it right away [default: 0]"),
+        Arg::with_name("transactions")
+            .long("transactions")
+            .takes_value(true)
+            .possible_values(&["account", "utxo"])
+            .default_value("account")
+            .global(true)
+            .help("whether transactions of the chain move amounts between account balances, or spend unspent outputs of earlier ones"),
+        Arg::with_name("genesis")
+            .long("genesis")
+            .takes_value(true)
+            .value_name("FILE")
+            .global(true)
+            .help("sets up the genesis block of the chain by the JSON spec in FILE, with its name, timestamp and initial balances; the flags above fill in what it leaves out"),
+        Arg::with_name("consensus")
+            .long("consensus")
+            .takes_value(true)
+            .possible_values(&["pow", "poa", "pos"])
+            .default_value("pow")
+            .global(true)
+            .help("mines blocks with proof of work, has authorities sign them with proof of authority, or stakers with an experimental proof of stake"),
+        Arg::with_name("authority")
+            .long("authority")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PUBKEY")
+            .global(true)
+            .help("the public key of a node allowed to produce blocks with proof of authority, or of a node producing blocks until anyone holds stake with proof of stake, see `nb key` (repeatable)"),
+        Arg::with_name("assume-valid")
+            .long("assume-valid")
+            .takes_value(true)
+            .value_name("HASH")
+            .global(true)
+            .help("trusts the block with this hash and its ancestors, validating them without checking their proofs of work or signatures [default: checks all]"),
+        Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("INDEX=HASH")
+            .global(true)
+            .help("requires the block at INDEX to have HASH, refusing any chain that holds another block there (repeatable)"),
+    ]
+}
+
+/// The flags of the chain passed on to the nodes `nb` runs, as they were given.
+pub const CHAIN_FLAGS: &[&str] = &[
+    "db",
+    "difficulty",
+    "block-reward",
+    "halving-interval",
+    "max-supply",
+    "coinbase-maturity",
+    "transactions",
+    "genesis",
+    "consensus",
+    "authority",
+    "assume-valid",
+    "checkpoint",
+];
+
+/// The flags of the node passed on to the node `nb --standalone` runs, as they were given.
+pub const NODE_FLAGS: &[&str] = &[
+    "addr",
+    "data-dir",
+    "ephemeral",
+    "control-addr",
+    "listen-fd",
+    "control-fd",
+    "reward-address",
+    "telemetry",
+    "gossip",
+    "fanout",
+    "stats-window",
+    "stall-warning",
+    "min-peers-to-mine",
+    "skip-empty-blocks",
+    "mempool-capacity",
+    "max-connections",
+    "idle-timeout",
+    "key-grace-period",
+    "verify-pace",
+    "latency",
+    "diffusion-delay",
+    "signal",
+];
+
+pub fn control_addr_arg() -> Arg<'static, 'static> {
+    Arg::with_name("control-addr")
+        .long("control-addr")
+        .takes_value(true)
+        .value_name("IP-PORT")
+        .global(true)
+        .help("where the node takes commands from its consoles, a loopback address [default: 127.0.0.1 and the port of --addr plus 1000]")
+}
+
+/// The control address of the node the flags of `matches` run.
+pub fn control_addr(matches: &ArgMatches) -> String {
+    match matches.value_of("control-addr") {
+        Some(control_addr) => control_addr.to_owned(),
+        None => default_control_addr(matches.value_of("addr").unwrap()),
+    }
+}
+
+/// The control cookie of the node the flags of `matches` run.
+pub fn control_cookie(matches: &ArgMatches) -> PathBuf {
+    let mut config = NodeConfig::new(matches.value_of("addr").unwrap());
+    config.control_addr = control_addr(matches);
+    if let Some(dir) = matches.value_of("data-dir") {
+        config.data_dir = dir.into();
+    }
+    config.ephemeral = matches.is_present("ephemeral");
+    config.control_cookie()
+}
+
+/// Passes the `flags` given in `matches` on to `command`.
+pub fn forward(matches: &ArgMatches, flags: &[&str], command: &mut Command) {
+    for &flag in flags {
+        if matches.occurrences_of(flag) == 0 {
+            continue;
+        }
+        let values: Vec<&str> = matches.values_of(flag).into_iter().flatten().collect();
+        if values.is_empty() {
+            // a switch
+            command.arg(format!("--{}", flag));
+        }
+        for value in values {
+            command.arg(format!("--{}", flag)).arg(value);
+        }
+    }
+}
+
+/// The flags only running a node takes, `hidden` where they are also taken for `--standalone`.
+pub fn node_args(hidden: bool) -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("ephemeral")
+            .long("ephemeral")
+            .hidden(hidden)
+            .help("keeps the chain, the peers and the node key in memory only, writing nothing to disk, so that they are lost on exit"),
+        Arg::with_name("listen-fd")
+            .long("listen-fd")
+            .takes_value(true)
+            .value_name("FD")
+            .hidden(hidden)
+            .help("takes peers on the listening socket FD the parent process bound, instead of binding --addr, which is still advertised [default: the first socket of systemd socket activation, if any]"),
+        Arg::with_name("control-fd")
+            .long("control-fd")
+            .takes_value(true)
+            .value_name("FD")
+            .hidden(hidden)
+            .help("takes commands on the listening socket FD the parent process bound, a loopback one, instead of binding --control-addr [default: the second socket of systemd socket activation, or the one named control, if any]"),
+        Arg::with_name("reward-address")
+            .long("reward-address")
+            .takes_value(true)
+            .value_name("ADDRESS")
+            .hidden(hidden)
+            .help("where the rewards of mined blocks go, which the node key signs as their miner only if it is the address of the key [default: the address of the node's public key, which is also its id]"),
+        Arg::with_name("telemetry")
+            .long("telemetry")
+            .takes_value(true)
+            .value_name("COLLECTOR")
+            .hidden(hidden)
+            .help("reports block sightings to the collector node at IP-PORT for the propagation experiment (pass the node's own address to make it the collector)"),
+        Arg::with_name("gossip")
+            .long("gossip")
+            .takes_value(true)
+            .possible_values(&["flood", "random", "push-pull"])
+            .default_value("flood")
+            .hidden(hidden)
+            .help("how blocks and transactions are relayed to peers"),
+        Arg::with_name("fanout")
+            .long("fanout")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("3")
+            .hidden(hidden)
+            .help("how many random peers the random and push-pull strategies relay to"),
+        Arg::with_name("stats-window")
+            .long("stats-window")
+            .takes_value(true)
+            .value_name("SECS")
+            .default_value("600")
+            .hidden(hidden)
+            .help("the window the stats command measures throughput over"),
+        Arg::with_name("stall-warning")
+            .long("stall-warning")
+            .takes_value(true)
+            .value_name("SECS")
+            .default_value("600")
+            .hidden(hidden)
+            .help("warns when pending transactions wait this long without any getting confirmed"),
+        Arg::with_name("min-peers-to-mine")
+            .long("min-peers-to-mine")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .hidden(hidden)
+            .help("refuses to mine with fewer peers, 0 to mine solo"),
+        Arg::with_name("skip-empty-blocks")
+            .long("skip-empty-blocks")
+            .hidden(hidden)
+            .help("refuses to mine a block while no transaction is pending, so that the chain holds no blocks of nothing but their reward"),
+        Arg::with_name("mempool-capacity")
+            .long("mempool-capacity")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("10000")
+            .hidden(hidden)
+            .help("keeps at most this many pending transactions, evicting those paying the lowest fees first"),
+        Arg::with_name("max-connections")
+            .long("max-connections")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("64")
+            .hidden(hidden)
+            .help("refuses incoming connections while this many are open"),
+        Arg::with_name("idle-timeout")
+            .long("idle-timeout")
+            .takes_value(true)
+            .value_name("SECS")
+            .default_value("30")
+            .hidden(hidden)
+            .help("closes connections idle for this long, and gives up on silent peers"),
+        Arg::with_name("key-grace-period")
+            .long("key-grace-period")
+            .takes_value(true)
+            .value_name("SECS")
+            .default_value("86400")
+            .hidden(hidden)
+            .help("keeps accepting the old id of a peer for this long after it rotated its key"),
+        Arg::with_name("verify-pace")
+            .long("verify-pace")
+            .takes_value(true)
+            .value_name("MILLIS")
+            .hidden(hidden)
+            .help("re-verifies the stored chain in the background, one block every MILLIS, to catch disk corruption [default: off]"),
+        Arg::with_name("latency")
+            .long("latency")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("[IP-PORT=]MS[~JITTER]")
+            .hidden(hidden)
+            .help("delays outgoing messages to the peer at IP-PORT, or to all peers, to emulate a WAN on one machine (repeatable)"),
+        Arg::with_name("diffusion-delay")
+            .long("diffusion-delay")
+            .takes_value(true)
+            .value_name("MILLIS")
+            .hidden(hidden)
+            .help("relays the transactions the node creates to every peer after a random delay up to MILLIS, hiding which node made them [default: off]"),
+        Arg::with_name("signal")
+            .long("signal")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("BIT")
+            .hidden(hidden)
+            .help("signals readiness for the rule change of BIT, 0 to 31, in the blocks the node mines (repeatable)"),
+    ]
+}
+
+/// The config of the node the flags of `matches` run.
+pub fn node_config(matches: &ArgMatches) -> Result<NodeConfig> {
+    let mut config = NodeConfig::new(matches.value_of("addr").unwrap());
+    if let Some(control_addr) = matches.value_of("control-addr") {
+        config.control_addr = control_addr.to_owned();
+    }
+    if let Some(dir) = matches.value_of("data-dir") {
+        config.data_dir = dir.into();
+    }
+    if let Some(db) = matches.value_of("db") {
+        config.db = db.parse()?;
+    }
+    config.ephemeral = matches.is_present("ephemeral");
+    if matches.is_present("difficulty") {
+        config.difficulty = Some(difficulty(matches)?);
+    }
+    config.consensus = consensus(matches)?;
+    config.reward_schedule = reward_schedule(matches)?;
+    if matches.value_of("transactions") == Some("utxo") {
+        config.transaction_model = TransactionModel::Utxo;
+    }
+    if let Some(path) = matches.value_of("genesis") {
+        set_genesis_spec(&mut config, GenesisSpec::load(Path::new(path))?);
+    }
+    if matches.is_present("listen-fd") {
+        config.listen_fd = Some(descriptor(matches, "listen-fd")?);
+    }
+    if matches.is_present("control-fd") {
+        config.control_fd = Some(descriptor(matches, "control-fd")?);
+    }
+    config.reward_address = matches.value_of("reward-address").map(str::to_owned);
+    config.telemetry_collector = matches.value_of("telemetry").map(str::to_owned);
+    if let Some(gossip) = matches.value_of("gossip") {
+        config.gossip = gossip.parse()?;
+    }
+    if let Some(fanout) = matches.value_of("fanout") {
+        config.fanout = fanout
+            .parse()
+            .map_err(|_| failure::err_msg("--fanout must be a number"))?;
+    }
+    if matches.is_present("stats-window") {
+        config.stats_window = Duration::from_secs(number(matches, "stats-window")?);
+    }
+    if matches.is_present("stall-warning") {
+        config.stall_warning = Duration::from_secs(number(matches, "stall-warning")?);
+    }
+    if matches.is_present("min-peers-to-mine") {
+        config.min_peers_to_mine = number(matches, "min-peers-to-mine")? as usize;
+    }
+    config.skip_empty_blocks = matches.is_present("skip-empty-blocks");
+    if matches.is_present("mempool-capacity") {
+        config.mempool_capacity = number(matches, "mempool-capacity")? as usize;
+    }
+    if matches.is_present("max-connections") {
+        config.max_connections = number(matches, "max-connections")? as usize;
+    }
+    if matches.is_present("idle-timeout") {
+        config.idle_timeout = Duration::from_secs(number(matches, "idle-timeout")?);
+    }
+    if matches.is_present("key-grace-period") {
+        config.key_grace_period = Duration::from_secs(number(matches, "key-grace-period")?);
+    }
+    if matches.is_present("verify-pace") {
+        config.verify_pace = Some(Duration::from_millis(number(matches, "verify-pace")?));
+    }
+    if matches.is_present("diffusion-delay") {
+        config.diffusion = Some(Duration::from_millis(number(matches, "diffusion-delay")?));
+    }
+    config.assume_valid = matches.value_of("assume-valid").map(str::to_owned);
+    for checkpoint in matches.values_of("checkpoint").into_iter().flatten() {
+        let (index, hash) = parse_checkpoint(checkpoint)?;
+        config.checkpoints.insert(index, hash);
+    }
+    for latency in matches.values_of("latency").into_iter().flatten() {
+        config.latency.add(latency)?;
+    }
+    for bit in matches.values_of("signal").into_iter().flatten() {
+        config.signals.push(
+            bit.parse()
+                .map_err(|_| failure::err_msg("--signal must be a bit from 0 to 31"))?,
+        );
+    }
+    config.check()?;
+    Ok(config)
+}
+
+/// Runs the chain of `spec`, the flags filling in what it leaves out.
+pub fn set_genesis_spec(config: &mut NodeConfig, mut spec: GenesisSpec) {
+    spec.difficulty = spec.difficulty.or(config.difficulty);
+    spec.reward_schedule = spec.reward_schedule.or(config.reward_schedule);
+    config.transaction_model = *spec
+        .transaction_model
+        .get_or_insert(config.transaction_model);
+    config.difficulty = spec.difficulty;
+    config.reward_schedule = spec.reward_schedule;
+    config.genesis_spec = Some(spec);
+}
+
+fn descriptor(matches: &ArgMatches, name: &str) -> Result<i32> {
+    matches.value_of(name).unwrap().parse().map_err(|_| {
+        failure::err_msg(format!(
+            "--{} must be the number of a file descriptor",
+            name
+        ))
+    })
+}
+
+pub fn number(matches: &ArgMatches, name: &str) -> Result<u64> {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .map_err(|_| failure::err_msg(format!("--{} must be a number", name)))
+}
+
+fn parse_checkpoint(checkpoint: &str) -> Result<(u64, String)> {
+    let invalid = || {
+        failure::err_msg(format!(
+            "--checkpoint {} must be an index and a block hash, INDEX=HASH",
+            checkpoint
+        ))
+    };
+    let (index, hash) = checkpoint.split_once('=').ok_or_else(invalid)?;
+    let index = index.parse().map_err(|_| invalid())?;
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    Ok((index, hash.to_ascii_lowercase()))
+}
+
+fn reward_schedule(matches: &ArgMatches) -> Result<Option<RewardSchedule>> {
+    if ![
+        "block-reward",
+        "halving-interval",
+        "max-supply",
+        "coinbase-maturity",
+    ]
+    .iter()
+    .any(|name| matches.is_present(name))
+    {
+        return Ok(None);
+    }
+    let coins = |name| -> Result<i64> {
+        let coins = number(matches, name)?;
+        if coins > i64::MAX as u64 {
+            return Err(failure::err_msg(format!("--{} is too large", name)));
+        }
+        Ok(coins as i64)
+    };
+    let mut schedule = RewardSchedule::default();
+    if matches.is_present("block-reward") {
+        schedule.initial_reward = coins("block-reward")?;
+    }
+    if matches.is_present("halving-interval") {
+        schedule.halving_interval = number(matches, "halving-interval")?;
+    }
+    if matches.is_present("max-supply") {
+        schedule.max_supply = Some(coins("max-supply")?);
+    }
+    if matches.is_present("coinbase-maturity") {
+        schedule.coinbase_maturity = number(matches, "coinbase-maturity")?;
+    }
+    Ok(Some(schedule))
+}
+
+fn consensus(matches: &ArgMatches) -> Result<ConsensusMode> {
+    let authorities: Vec<String> = matches
+        .values_of("authority")
+        .into_iter()
+        .flatten()
+        .map(str::to_owned)
+        .collect();
+    match matches.value_of("consensus") {
+        Some("poa") => Ok(ConsensusMode::ProofOfAuthority(authorities)),
+        Some("pos") => Ok(ConsensusMode::ProofOfStake(authorities)),
+        _ => Ok(ConsensusMode::ProofOfWork),
+    }
+}
+
+pub fn difficulty(matches: &ArgMatches) -> Result<u32> {
+    match matches.value_of("difficulty") {
+        Some(difficulty) => difficulty
+            .parse()
+            .ok()
+            .filter(|difficulty| *difficulty <= MAX_DIFFICULTY)
+            .ok_or_else(|| {
+                failure::err_msg(format!(
+                    "--difficulty must be a number of bits up to {}",
+                    MAX_DIFFICULTY
+                ))
+            }),
+        None => Ok(INITIAL_DIFFICULTY),
+    }
+}
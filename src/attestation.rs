@@ -0,0 +1,117 @@
+//! Block attestations, for anchoring a chain in other systems
+//!
+//! A node attests that a block of its chain had a hash at a point in time, signed with its key.
+//! Published elsewhere, e.g. posted to another chain or a transparency log, the attestation
+//! shows that the block existed by then, and anyone can check it against their own copy of the
+//! chain.
+
+use crate::blockchain::Blockchain;
+use crate::key::{self, NodeKey};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+
+/// A node's statement that the block at `index` of the chain `chain_id` had `hash` at `at`, in
+/// milliseconds since the Unix epoch, signed by `key`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attestation {
+    pub chain_id: String,
+    pub index: u64,
+    pub hash: String,
+    pub at: u128,
+    pub key: String,
+    pub signature: String,
+}
+
+impl Attestation {
+    /// Attests the last block of `chain` with `key`.
+    pub fn new(key: &NodeKey, chain: &Blockchain, at: u128) -> Self {
+        let block = chain.last_block();
+        let (chain_id, index, hash) = (chain.chain_id(), block.get_index(), block.get_hash());
+        let message = Attestation::message(&chain_id, index, &hash, at);
+        Attestation {
+            chain_id,
+            index,
+            hash,
+            at,
+            key: key.public_key(),
+            signature: key.sign(&message),
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        let message = Attestation::message(&self.chain_id, self.index, &self.hash, self.at);
+        key::verify(&self.key, &message, &self.signature)
+    }
+
+    /// Checks the attestation against `chain`: it must be signed by its key, and the chain must
+    /// hold the attested block.
+    pub fn verify(&self, chain: &Blockchain) -> Result<()> {
+        if !self.is_signed() {
+            return Err(failure::err_msg(format!(
+                "The attestation is not signed by {}",
+                self.key
+            )));
+        }
+        if self.chain_id != chain.chain_id() {
+            return Err(failure::err_msg(format!(
+                "The attestation is about the chain {}, not ours {}",
+                self.chain_id,
+                chain.chain_id()
+            )));
+        }
+        match chain.get_block_by_index(self.index) {
+            Some(block) if block.get_hash() == self.hash => Ok(()),
+            Some(block) => Err(failure::err_msg(format!(
+                "The chain holds block {} at {}, not the attested {}",
+                block.get_hash(),
+                self.index,
+                self.hash
+            ))),
+            None => Err(failure::err_msg(format!(
+                "The chain holds no block {} yet",
+                self.index
+            ))),
+        }
+    }
+
+    fn message(chain_id: &str, index: u64, hash: &str, at: u128) -> Vec<u8> {
+        format!("nb attestation {} {} {} {}", chain_id, index, hash, at).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Transaction, REWARD_SENDER};
+
+    #[test]
+    fn test_attestation() {
+        let key = NodeKey::generate();
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        chain.mine_new_block();
+        let attestation = Attestation::new(&key, &chain, 42);
+        assert_eq!(attestation.index, 1);
+        assert_eq!(attestation.key, key.public_key());
+        assert!(attestation.verify(&chain).is_ok());
+
+        // a later chain still holds the block, another does not
+        let mut ours = Blockchain::from_blocks(chain.get_blocks());
+        ours.mine_new_block();
+        assert!(attestation.verify(&ours).is_ok());
+        let mut rival = Blockchain::from_blocks(chain.get_blocks()[..1].to_vec());
+        assert!(attestation.verify(&rival).is_err());
+        rival.mine_new_block();
+        assert!(attestation.verify(&rival).is_err());
+
+        let mut forged = attestation.clone();
+        forged.at += 1;
+        assert!(!forged.is_signed());
+        assert!(forged.verify(&chain).is_err());
+        let mut foreign = attestation;
+        foreign.chain_id = "other".to_owned();
+        assert!(foreign.verify(&chain).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! The node daemon: the network, consensus and storage of a node, without a console
+//!
+//! `nb` attaches a console to a running `nbd` over its control address.
+
+#[macro_use]
+extern crate log;
+
+use clap::App;
+use env_logger::Env;
+use nb::args;
+use nb::logging::{NET, REPL};
+use nb::Node;
+use tokio::runtime::Runtime;
+
+fn main() {
+    let matches = App::new("nbd")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("A simple blockchain node, headless: attach a console with nb")
+        .args(&args::chain_args())
+        .arg(args::control_addr_arg())
+        .args(&args::node_args(false))
+        .get_matches();
+
+    env_logger::from_env(Env::default().default_filter_or("debug,sled=info")).init();
+
+    let result = args::node_config(&matches).and_then(|config| {
+        info!(target: NET, "nbd {}", env!("CARGO_PKG_VERSION"));
+        info!(target: NET, "Listening on {}", config.addr);
+        info!(target: REPL, "Taking commands on {}", config.control_addr);
+        let rt = Runtime::new().expect("tokio runtime can be initialized");
+        rt.block_on(Node::handle_events(config))
+    });
+    if let Err(e) = result {
+        error!(target: REPL, "{}", e);
+        std::process::exit(1);
+    }
+}
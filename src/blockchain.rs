@@ -1,333 +1,5900 @@
 //! The blockchain data structure
 
+use crate::address::{self, Address};
+use crate::consensus::{self, Consensus, ProofOfWork};
+use crate::encoding::{Encoder, ENCODING_VERSION};
+use crate::genesis::GenesisSpec;
+use crate::key::{self, NodeKey};
+use crate::ledger::{Asset, BlockAccounting, Ledger};
+use crate::logging::{CONSENSUS, MEMPOOL, STORAGE};
+use crate::merkle::{self, MerkleProof};
+use crate::script::{self, Op};
+use crate::storage::Store;
+use crate::validator::TxValidator;
+use crate::Result;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::io::stdout;
 use std::mem;
+use std::sync::{Arc, OnceLock};
 use std::time::SystemTime;
 use uuid::Uuid;
 
-fn get_time() -> u128 {
+/// The sender of mining rewards, signifying that the coin is newly mined.
+pub const REWARD_SENDER: &str = "0";
+
+/// Whether `address` can hold coins: 1 to 64 letters, digits, `-` or `_`, like node ids and
+/// public keys, but not `REWARD_SENDER`. Those shaped like an `Address` must be one, with a
+/// matching checksum.
+pub fn is_valid_address(address: &str) -> bool {
+    (1..=64).contains(&address.len())
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && address != REWARD_SENDER
+        && (!address::looks_encoded(address) || address.parse::<Address>().is_ok())
+}
+
+/// How many characters a hash prefix needs at least to look up a block or a transaction by, see
+/// `Blockchain::resolve_block`.
+pub const MIN_PREFIX_LEN: usize = 4;
+
+/// How many of the hashes an ambiguous prefix matches are shown.
+const MAX_CANDIDATES: usize = 10;
+
+/// The largest amount, and fee, a transaction may carry, which any JSON parser reads exactly.
+pub const MAX_AMOUNT: i64 = 1 << 53;
+
+/// The default difficulty of the genesis block, in leading zero bits of the header hash.
+pub const INITIAL_DIFFICULTY: u32 = 16;
+pub const MAX_DIFFICULTY: u32 = 32;
+/// Retargeting never lowers the difficulty below this, though a genesis block may start lower.
+pub const MIN_DIFFICULTY: u32 = 1;
+/// The difficulty is retargeted every this many blocks.
+pub const RETARGET_INTERVAL: u64 = 10;
+/// The block interval in milliseconds the difficulty aims for.
+pub const TARGET_BLOCK_TIME: u128 = 10_000;
+/// A block may not be made before the median timestamp of this many blocks before it.
+pub const MEDIAN_TIME_SPAN: u64 = 11;
+/// How many milliseconds ahead of our clock the timestamp of a block may be.
+pub const MAX_FUTURE_DRIFT: u128 = 2 * 60 * 60 * 1000;
+/// How many bytes the JSON of a block may take.
+pub const MAX_BLOCK_SIZE: usize = 1 << 20;
+/// How many bytes of `MAX_BLOCK_SIZE` blocks made here leave for their header, seal and miner
+/// signature.
+const BLOCK_HEADER_ROOM: usize = 4 << 10;
+/// How many transactions may be pending by default, see `Blockchain::set_mempool_capacity`.
+pub const MEMPOOL_CAPACITY: usize = 10_000;
+/// How many blocks of branches competing with the chain are held, see `Blockchain::add_side_block`.
+pub const SIDE_BLOCK_CAPACITY: usize = 256;
+/// The version of the rules new blocks are made under until miner signatures lock in, which
+/// older nodes validate too. Blocks of versions outside `MIN_BLOCK_VERSION..=SIGNED_BLOCK_VERSION`
+/// are rejected, those of older versions name none.
+pub const BLOCK_VERSION: u32 = 1;
+pub const MIN_BLOCK_VERSION: u32 = 0;
+/// Blocks of this version are signed by their miner, see `Block::check_miner`. Chains move to it
+/// once `MINER_SIGNATURE_BIT` locks in, and stay in it, see `Blockchain::requires_miner_signatures`.
+pub const SIGNED_BLOCK_VERSION: u32 = 2;
+/// The bit nodes that can sign the blocks they mine signal readiness for miner signatures with.
+pub const MINER_SIGNATURE_BIT: u8 = 0;
+/// How many of the last blocks the readiness for a rule change is counted over, see
+/// `Blockchain::signalling`.
+pub const SIGNAL_WINDOW: u64 = 100;
+/// How many blocks of the window must signal readiness for a rule change to lock it in.
+pub const SIGNAL_THRESHOLD: u64 = 95;
+
+pub(crate) fn get_time() -> u128 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_millis()
 }
 
+/// A header and the transactions it commits to, its body.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Block {
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
+    // only set by engines that sign blocks instead of mining them, left out of the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seal: Option<AuthoritySeal>,
+}
+
+/// The signature of the authority that produced a block, over the block hash.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuthoritySeal {
+    pub authority: String,
+    pub signature: String,
+}
+
+/// What identifies a Block and what its proof of work is done on. Peers compare their chains by
+/// the headers alone before shipping the transactions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockHeader {
     index: u64,
     timestamp: u128,
-    proof: u64,
-    transactions: Vec<Transaction>,
     previous_hash: String,
+    // commits to the transactions, so that single ones can be proven to be in the block
+    merkle_root: String,
+    // the target the header hash must meet, as a number of leading zero bits
+    difficulty_target: u32,
+    // varied by miners until the header hash meets the target
+    nonce: u64,
+    // only set by genesis blocks, which fall back to the default schedule without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reward_schedule: Option<RewardSchedule>,
+    // only set by genesis blocks of chains moving coins other than by `TransactionModel::Account`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transaction_model: Option<TransactionModel>,
+    // only set by genesis blocks of named chains, see `GenesisSpec`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    // only set by genesis blocks of chains whose addresses must encode public keys under it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address_prefix: Option<String>,
+    // the chain the block was made for, only set by the other blocks of named chains, which
+    // older versions never ran, see `Blockchain::chain_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain_id: Option<String>,
+    // the version of the encoding the header is hashed in, see `crate::encoding`, left out for
+    // the JSON that genesis blocks and the blocks of older versions are hashed in
+    #[serde(default, skip_serializing_if = "is_zero")]
+    encoding: u32,
+    // the rules the block was made under, see `BLOCK_VERSION`, left out by older versions
+    #[serde(default, skip_serializing_if = "is_zero")]
+    version: u32,
+    // the rule changes the miner is ready for, one bit each, see `Blockchain::signalling`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    signals: u32,
+    // the address of the node that mined the block, whose rewards it claims, and its signature,
+    // see `Block::sign_miner`; only left out by blocks before `SIGNED_BLOCK_VERSION`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    miner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    miner_signature: Option<String>,
+    // the hash, once taken, so that validating and comparing chains hashes every header once;
+    // fields are only changed through `Block::header_mut`, which resets it
+    #[serde(skip)]
+    cached_hash: OnceLock<[u8; 32]>,
+}
+
+/// How the transactions of a chain move coins, set by its genesis block.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransactionModel {
+    /// transactions move their amount from the balance of the sender to the recipient
+    #[default]
+    Account,
+    /// transactions spend unspent outputs of earlier transactions, and create new outputs
+    Utxo,
+}
+
+/// How many of the last blocks signal readiness for a rule change, see `Blockchain::signalling`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Readiness {
+    pub bit: u8,
+    pub blocks: u64,
+    /// how many blocks were counted, `SIGNAL_WINDOW` unless the chain is shorter
+    pub window: u64,
+}
+
+impl Readiness {
+    /// Whether enough blocks signal for the rule change to be locked in.
+    pub fn is_locked_in(&self) -> bool {
+        self.blocks >= SIGNAL_THRESHOLD
+    }
+}
+
+/// How many coins mining a block mints, set by the genesis block of a chain.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewardSchedule {
+    /// the reward of the first blocks
+    pub initial_reward: i64,
+    /// the reward halves every this many blocks, never if 0
+    pub halving_interval: u64,
+    /// no more than this many coins are ever minted, if set
+    pub max_supply: Option<i64>,
+    /// how many blocks after its own the reward of a block may be spent from, right away if 0
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub coinbase_maturity: u64,
+}
+
+/// The flat reward of 1 that chains without a schedule mint forever.
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        RewardSchedule {
+            initial_reward: 1,
+            halving_interval: 0,
+            max_supply: None,
+            coinbase_maturity: 0,
+        }
+    }
+}
+
+impl RewardSchedule {
+    /// The reward of the block at `index`, given that `minted` coins were minted before it.
+    pub fn reward(&self, index: u64, minted: i64) -> i64 {
+        if index == 0 {
+            return 0;
+        }
+        let halvings = (index - 1).checked_div(self.halving_interval).unwrap_or(0);
+        let reward = self
+            .initial_reward
+            .checked_shr(halvings as u32)
+            .unwrap_or(0);
+        match self.max_supply {
+            Some(max_supply) => reward.min((max_supply - minted).max(0)),
+            None => reward,
+        }
+    }
+
+    fn is_sane(&self) -> bool {
+        self.initial_reward >= 0 && self.max_supply.is_none_or(|max_supply| max_supply >= 0)
+    }
 }
 
 impl Block {
-    pub fn get_genesis() -> Self {
+    /// Returns the genesis block of a chain whose first blocks are mined with `difficulty`.
+    pub fn get_genesis(difficulty: u32) -> Self {
+        Block::get_genesis_with(difficulty, None, TransactionModel::Account)
+    }
+
+    /// Like `get_genesis`, but minting coins by `reward_schedule` instead of the default one,
+    /// and moving them by `transaction_model`.
+    pub fn get_genesis_with(
+        difficulty: u32,
+        reward_schedule: Option<RewardSchedule>,
+        transaction_model: TransactionModel,
+    ) -> Self {
         Block {
-            index: 0,
-            timestamp: 0,
-            proof: 100,
+            header: BlockHeader {
+                index: 0,
+                timestamp: 0,
+                previous_hash: String::from("1"),
+                merkle_root: merkle::EMPTY_ROOT.to_owned(),
+                difficulty_target: difficulty,
+                nonce: 100,
+                reward_schedule,
+                // account chains keep the genesis block they always had
+                transaction_model: Some(transaction_model)
+                    .filter(|model| *model != TransactionModel::Account),
+                name: None,
+                address_prefix: None,
+                chain_id: None,
+                encoding: 0,
+                version: 0,
+                signals: 0,
+                miner: None,
+                miner_signature: None,
+                cached_hash: OnceLock::new(),
+            },
             transactions: Vec::new(),
-            previous_hash: String::from("1"),
+            seal: None,
         }
     }
 
+    /// The genesis block `spec` sets up. Its allocations are minted by transactions numbered by
+    /// their nonces, so that the same spec always makes the same block.
+    pub fn get_genesis_from(spec: &GenesisSpec) -> Self {
+        let model = spec.transaction_model.unwrap_or_default();
+        let mut genesis = Block::get_genesis_with(
+            spec.difficulty.unwrap_or(INITIAL_DIFFICULTY),
+            spec.reward_schedule,
+            model,
+        );
+        genesis.transactions = spec
+            .allocations
+            .iter()
+            .enumerate()
+            .map(|(nonce, allocation)| {
+                let minted = match model {
+                    TransactionModel::Account => {
+                        Transaction::new(REWARD_SENDER, &allocation.address, allocation.amount)
+                    }
+                    TransactionModel::Utxo => Transaction::spend(
+                        REWARD_SENDER,
+                        Vec::new(),
+                        vec![Output {
+                            recipient: allocation.address.clone(),
+                            amount: allocation.amount,
+                        }],
+                    ),
+                };
+                minted.with_nonce(nonce as u64)
+            })
+            .collect();
+        genesis.header_mut().merkle_root =
+            merkle::merkle_root(&Block::transaction_hashes(&genesis.transactions));
+        genesis.header_mut().timestamp = spec.timestamp;
+        genesis.header_mut().name = spec.name.clone();
+        genesis.header_mut().address_prefix = spec.address_prefix.clone();
+        genesis
+    }
+
     /// Returns the index of the Block in the chain.
     pub fn get_index(&self) -> u64 {
-        self.index
+        self.header.index
     }
 
-    /// Hashes a Block.
-    pub fn get_hash(&self) -> String {
-        let block_string = serde_json::to_string(self).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.input_str(&block_string);
-        hasher.result_str()
+    /// The hash of the block this one links to.
+    pub fn get_previous_hash(&self) -> &str {
+        &self.header.previous_hash
     }
-}
 
-pub struct Blockchain {
-    current_transactions: Vec<Transaction>,
-    // blocks is non-empty
-    blocks: Vec<Block>,
-}
+    /// Returns the id of the chain the block was made for, if it tells.
+    pub fn get_chain_id(&self) -> Option<&str> {
+        self.header.chain_id.as_deref()
+    }
 
-impl Default for Blockchain {
-    fn default() -> Self {
-        Blockchain::new()
+    /// Returns when the Block was mined, in milliseconds since the Unix epoch.
+    pub fn get_timestamp(&self) -> u128 {
+        self.header.timestamp
     }
-}
 
-impl Blockchain {
-    /// Creates a new Blockchain with only the genesis block.
-    pub fn new() -> Self {
-        Blockchain {
-            current_transactions: vec![],
-            blocks: vec![Block::get_genesis()],
-        }
+    /// Returns the transactions included in the Block.
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
     }
 
-    /// Creates a blockchain from given blocks.
-    pub fn from_blocks(blocks: Vec<Block>) -> Self {
-        Blockchain {
-            current_transactions: vec![],
-            blocks,
-        }
+    pub fn get_difficulty(&self) -> u32 {
+        self.header.difficulty_target
     }
 
-    /// Returns a copy of the blocks the chain owns.
-    pub fn get_blocks(&self) -> Vec<Block> {
-        self.blocks.clone()
+    /// How many header hashes mining the Block takes on average, see `BlockHeader::work`.
+    pub fn work(&self) -> u128 {
+        self.header.work()
     }
 
-    /// Returns the number of blocks in the blockchain, also referred to as its 'length'.
-    pub fn len(&self) -> usize {
-        self.blocks.len()
+    pub fn get_merkle_root(&self) -> &str {
+        &self.header.merkle_root
+    }
+
+    /// How many bytes the JSON of the Block takes.
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self).map_or(usize::MAX, |json| json.len())
+    }
+
+    /// How many of the leading `transactions` fit in a block, see `MAX_BLOCK_SIZE`. A leading
+    /// reward, the coinbase, takes the room left for the header, so that what fits does not
+    /// depend on whether the reward is among `transactions` yet.
+    fn fitting(transactions: &[Transaction]) -> usize {
+        let coinbase = transactions
+            .first()
+            .is_some_and(|t| t.get_sender() == REWARD_SENDER);
+        let mut room = MAX_BLOCK_SIZE - BLOCK_HEADER_ROOM;
+        let fitting = transactions[coinbase as usize..]
+            .iter()
+            .take_while(|t| {
+                // and the comma separating it
+                room = room.saturating_sub(t.size().saturating_add(1));
+                room > 0
+            })
+            .count();
+        fitting + coinbase as usize
+    }
+
+    /// How many bytes of `MAX_BLOCK_SIZE` the Block left unused, reckoned as in `fitting`: a
+    /// transaction of `size` bytes would have fit if `size + 1 < room`.
+    pub(crate) fn room(&self) -> usize {
+        let coinbase = self
+            .transactions
+            .first()
+            .is_some_and(|t| t.get_sender() == REWARD_SENDER);
+        self.transactions[coinbase as usize..]
+            .iter()
+            .fold(MAX_BLOCK_SIZE - BLOCK_HEADER_ROOM, |room, t| {
+                room.saturating_sub(t.size().saturating_add(1))
+            })
+    }
+
+    fn transaction_hashes(transactions: &[Transaction]) -> Vec<String> {
+        transactions.iter().map(Transaction::merkle_leaf).collect()
+    }
+
+    /// Whether the Block holds transactions that expired before it.
+    pub fn has_expired_transactions(&self) -> bool {
+        self.transactions
+            .iter()
+            .any(|t| t.is_expired_at(self.header.index, self.header.timestamp))
+    }
+
+    /// Checks that the transactions match their ids, or carry the random ids of older versions.
+    pub fn has_valid_ids(&self) -> bool {
+        self.transactions
+            .iter()
+            .all(|t| t.has_valid_id() || t.has_legacy_id())
     }
 
-    /// Adds a new transaction to the list of transactions.
-    pub fn add_new_transaction(&mut self, transaction: &Transaction) -> bool {
-        // check whether it already exists in current transactions
-        for t in &self.current_transactions {
-            if t.get_id() == transaction.get_id() {
-                return false;
+    /// The first transaction that is not valid by itself, see `Transaction::validate`, or that
+    /// the Block holds twice, with why.
+    pub fn invalid_transaction(&self) -> Option<(&str, Rejection)> {
+        let mut ids = HashSet::new();
+        self.transactions.iter().find_map(|t| {
+            if !ids.insert(t.get_id()) {
+                return Some((t.get_id(), Rejection::Duplicate));
             }
+            t.validate_in_block()
+                .err()
+                .map(|rejection| (t.get_id(), rejection))
+        })
+    }
+
+    /// Checks that the scripts of the transactions hold.
+    pub fn has_valid_scripts(&self) -> bool {
+        self.transactions.iter().all(|t| t.check_script().is_ok())
+    }
+
+    /// Checks that the Block is of a version this build validates, see `BLOCK_VERSION`. Headers
+    /// hashed in encodings before version 2 do not commit to a version or signals, and must not
+    /// claim any.
+    pub fn has_supported_version(&self) -> bool {
+        let header = &self.header;
+        (MIN_BLOCK_VERSION..=SIGNED_BLOCK_VERSION).contains(&header.version)
+            && (header.encoding >= 2 || header.version == 0 && header.signals == 0)
+    }
+
+    /// Checks that the Merkle root matches the transactions, which must be listed once: the same
+    /// root covers the transactions with the last ones repeated, see `merkle`.
+    pub fn has_valid_merkle_root(&self) -> bool {
+        self.duplicate_transaction().is_none()
+            && self.header.merkle_root
+                == merkle::merkle_root(&Block::transaction_hashes(&self.transactions))
+    }
+
+    /// The first transaction the Block holds twice.
+    fn duplicate_transaction(&self) -> Option<&str> {
+        let mut ids = HashSet::new();
+        self.transactions
+            .iter()
+            .map(Transaction::get_id)
+            .find(|id| !ids.insert(*id))
+    }
+
+    /// Returns who mined the Block: the miner that signed it, see `sign_miner`, else the
+    /// recipient of its mining reward. The genesis block is not mined, what it mints are the
+    /// allocations of its spec.
+    pub fn get_miner(&self) -> Option<&str> {
+        if self.header.index == 0 {
+            return None;
         }
-        // check whether it has already been added to the blockchain
-        for b in &self.blocks {
-            for t in &b.transactions {
-                if t.get_id() == transaction.get_id() {
-                    return false;
-                }
-            }
+        if let Some(miner) = &self.header.miner {
+            return Some(miner);
         }
-        self.current_transactions.push(transaction.clone());
-        debug!("New transaction {:?} added", transaction.id);
-        true
+        self.transactions
+            .iter()
+            .find(|t| t.sender == REWARD_SENDER)
+            .map(|t| t.get_recipient())
     }
 
-    /// Creates a new Block containing current transactions and adds it to the chain.
-    pub fn create_new_block(&mut self, proof: u64, previous_hash: String) -> &Block {
-        let transactions = mem::replace(&mut self.current_transactions, Vec::new());
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
 
-        let block = Block {
-            index: self.blocks.len() as u64,
-            timestamp: get_time(),
-            proof,
-            transactions,
-            previous_hash,
-        };
+    /// The header to change, whose hash has to be taken anew then.
+    fn header_mut(&mut self) -> &mut BlockHeader {
+        self.header.cached_hash = OnceLock::new();
+        &mut self.header
+    }
 
-        self.blocks.push(block);
-        self.last_block()
+    pub fn get_nonce(&self) -> u64 {
+        self.header.nonce
     }
 
-    /// Adds a given block to the chain. Returns `false` if the new block is invalid.
-    pub fn add_new_block(&mut self, block: &Block) -> bool {
-        let (block_idx, current_len) = (block.get_index(), self.blocks.len() as u64);
-        match block_idx.cmp(&current_len) {
-            Ordering::Less => {
-                debug!("The incoming block is too old, so it is dropped");
-                false
-            }
-            Ordering::Equal => {
-                let last_block = self.last_block();
-                if last_block.get_hash() != block.previous_hash
-                    || !Blockchain::valid_proof(last_block.proof, block.proof)
-                {
-                    debug!("The incoming block is not valid");
-                    false
-                } else {
-                    // okay, now this block looks good to us
-                    // but we should check whether the block contains duplicate transactions with us
-                    for t in &block.transactions {
-                        let mut i = 0;
-                        while i < self.current_transactions.len() {
-                            if t.get_id() == self.current_transactions[i].get_id() {
-                                self.current_transactions.remove(i);
-                            } else {
-                                i += 1;
-                            }
-                        }
-                    }
-                    debug!("The incoming block is accepted :)");
-                    self.blocks.push(block.clone());
-                    true
-                }
+    pub(crate) fn set_nonce(&mut self, nonce: u64) {
+        self.header_mut().nonce = nonce;
+    }
+
+    /// The reward schedule of the chain, if this is its genesis block.
+    pub(crate) fn reward_schedule(&self) -> RewardSchedule {
+        self.header.reward_schedule.unwrap_or_default()
+    }
+
+    /// The transaction model of the chain, if this is its genesis block.
+    pub(crate) fn transaction_model(&self) -> TransactionModel {
+        self.header.transaction_model.unwrap_or_default()
+    }
+
+    /// The prefix the addresses of the chain are encoded under, if this is its genesis block.
+    pub(crate) fn address_prefix(&self) -> Option<&str> {
+        self.header.address_prefix.as_deref()
+    }
+
+    /// Checks that the transactions only touch addresses under `prefix`, if the chain has one,
+    /// see `Transaction::check_prefix`.
+    pub(crate) fn check_addresses(
+        &self,
+        prefix: Option<&str>,
+    ) -> std::result::Result<(), BlockRejection> {
+        for t in &self.transactions {
+            t.check_prefix(prefix)
+                .map_err(|rejection| BlockRejection::InvalidTransaction {
+                    id: t.id.clone(),
+                    reason: rejection.to_string(),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Has the node of `key` sign the Block as its miner, under its `address`, before the proof
+    /// of work. Its rewards must pay that address, see `check_miner`.
+    pub fn sign_miner(&mut self, key: &NodeKey, address: &str) {
+        self.header_mut().miner = Some(address.to_owned());
+        let signature = key.sign(&self.header.miner_message());
+        self.header_mut().miner_signature = Some(signature);
+    }
+
+    /// Whether the miner of the Block signed it, which `check_miner` verifies.
+    pub fn is_signed_by_miner(&self) -> bool {
+        self.header.miner.is_some()
+    }
+
+    /// Checks that a miner, if any, signed the Block with the key of its address, in an encoding
+    /// that hashes both, and that the rewards pay it. Blocks of `SIGNED_BLOCK_VERSION` on must
+    /// name their miner.
+    pub fn check_miner(&self) -> std::result::Result<(), BlockRejection> {
+        let miner = match (&self.header.miner, &self.header.miner_signature) {
+            (None, None) if self.header.version >= SIGNED_BLOCK_VERSION => {
+                return Err(BlockRejection::UnsignedBlock)
             }
-            Ordering::Greater => {
-                debug!("The incoming block is too new for us, we need to resolve conflicts");
-                false
+            (None, None) => return Ok(()),
+            (Some(miner), Some(signature))
+                if self.header.encoding >= 3
+                    && address::key_of(miner).is_some_and(|key| {
+                        key::verify(&key, &self.header.miner_message(), signature)
+                    }) =>
+            {
+                miner
             }
+            _ => return Err(BlockRejection::BadMinerSignature),
+        };
+        match self.transactions.iter().find(|t| {
+            t.get_sender() == REWARD_SENDER && t.addresses().iter().any(|address| address != miner)
+        }) {
+            Some(t) => Err(BlockRejection::RewardNotToMiner { id: t.id.clone() }),
+            None => Ok(()),
         }
     }
 
-    /// Returns a copy of current transactions.
-    pub fn get_current_transactions(&self) -> Vec<Transaction> {
-        self.current_transactions.clone()
+    /// Checks what the Block shows by itself: its version, its Merkle root, its miner and its
+    /// transactions, which must be valid, listed once and unexpired.
+    pub fn check(&self) -> std::result::Result<(), BlockRejection> {
+        if !self.has_supported_version() {
+            return Err(BlockRejection::UnsupportedVersion);
+        }
+        if self.size() > MAX_BLOCK_SIZE {
+            return Err(BlockRejection::OversizedBlock);
+        }
+        if let Some(id) = self.duplicate_transaction() {
+            return Err(BlockRejection::DuplicateTransaction { id: id.to_owned() });
+        }
+        if !self.has_valid_merkle_root() {
+            return Err(BlockRejection::BadMerkleRoot);
+        }
+        self.check_miner()?;
+        if let Some((id, rejection)) = self.invalid_transaction() {
+            let id = id.to_owned();
+            return Err(match rejection {
+                Rejection::Duplicate => BlockRejection::DuplicateTransaction { id },
+                Rejection::Unsigned | Rejection::BadSignature => {
+                    BlockRejection::InvalidTxSignature { id }
+                }
+                rejection => BlockRejection::InvalidTransaction {
+                    id,
+                    reason: rejection.to_string(),
+                },
+            });
+        }
+        if let Some(t) = self
+            .transactions
+            .iter()
+            .find(|t| t.is_expired_at(self.header.index, self.header.timestamp))
+        {
+            return Err(BlockRejection::ExpiredTransaction { id: t.id.clone() });
+        }
+        Ok(())
     }
 
-    /// Returns the last Block in the chain.
-    pub fn last_block(&self) -> &Block {
-        &self.blocks.last().unwrap()
+    /// Checks that the Block, following the balances of `ledger`, spends only coins that exist,
+    /// once and in an order they can be spent in, and that its coinbase claims exactly `reward`
+    /// and the fees of its transactions, if it claims anything.
+    pub(crate) fn check_spends(
+        &self,
+        ledger: &Ledger,
+        reward: i64,
+    ) -> std::result::Result<(), BlockRejection> {
+        if let Some((id, first)) = ledger.double_spend(&self.transactions) {
+            return Err(BlockRejection::DoubleSpend { id, first });
+        }
+        if let Some((id, later)) = ledger.misordered(self) {
+            return Err(BlockRejection::MisorderedTransaction { id, later });
+        }
+        if !ledger.affords_block(self) {
+            return Err(BlockRejection::Overspend);
+        }
+        if !ledger.admits(self, reward) {
+            return Err(BlockRejection::InvalidCoinbase);
+        }
+        Ok(())
     }
 
-    /// Proof of Work algorithm.
-    pub fn proof_of_work(last_proof: u64) -> u64 {
-        let mut proof = 0;
-        while !Blockchain::valid_proof(last_proof, proof) {
-            proof += 1;
+    /// Re-checks what the Block shows by itself, following `previous` and moving the balances of
+    /// `ledger`: the link, the Merkle root, the proof of work or the signature of its seal, and
+    /// that it mints exactly `reward`, if anything, and spends only coins that exist. Which engine's
+    /// rules the seal follows, and the difficulty, take the whole chain and are left out.
+    pub(crate) fn verify_after(
+        &self,
+        previous: &Block,
+        ledger: &Ledger,
+        reward: i64,
+    ) -> std::result::Result<(), BlockRejection> {
+        if self.header.previous_hash != previous.get_hash() {
+            return Err(BlockRejection::BadParent);
         }
-        proof
+        if self.header.index != previous.header.index + 1 {
+            return Err(BlockRejection::BadIndex {
+                expected: previous.header.index + 1,
+            });
+        }
+        self.check()?;
+        match self.seal {
+            Some(_) if !consensus::signed(self) => return Err(BlockRejection::BadSeal),
+            None if !self.has_valid_proof() => return Err(BlockRejection::BadProof),
+            _ => {}
+        }
+        self.check_spends(ledger, reward)
+    }
+
+    pub fn get_seal(&self) -> Option<&AuthoritySeal> {
+        self.seal.as_ref()
+    }
+
+    pub(crate) fn set_seal(&mut self, seal: AuthoritySeal) {
+        self.seal = Some(seal);
+    }
+
+    /// Validates the Proof. Does the header hash meet the difficulty target?
+    pub fn has_valid_proof(&self) -> bool {
+        self.header.has_valid_proof()
+    }
+
+    /// Hashes a Block, i.e. its header.
+    pub fn get_hash(&self) -> String {
+        self.header.get_hash()
+    }
+}
+
+impl BlockHeader {
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn get_previous_hash(&self) -> &str {
+        &self.previous_hash
+    }
+
+    pub fn get_difficulty(&self) -> u32 {
+        self.difficulty_target
     }
 
-    /// Run PoW in the chain.
-    pub fn run_pow(&self) -> u64 {
-        Blockchain::proof_of_work(self.last_block().proof)
+    /// The rules the block was made under, see `BLOCK_VERSION`.
+    pub fn get_version(&self) -> u32 {
+        self.version
     }
 
-    /// Validates the Proof. Does hash(last_proof, proof) contain 4 leading zeroes?
-    fn valid_proof(last_proof: u64, proof: u64) -> bool {
+    /// Whether the miner of the block signals readiness for the rule change of `bit`.
+    pub fn signals(&self, bit: u8) -> bool {
+        bit < 32 && self.signals & 1 << bit != 0
+    }
+
+    /// How many hashes mining the block takes on average, 2 to the power of its difficulty.
+    pub fn work(&self) -> u128 {
+        1 << self.difficulty_target.min(127)
+    }
+
+    /// Hashes the header, which commits to the transactions via the Merkle root, in the encoding
+    /// it names.
+    fn hash(&self) -> [u8; 32] {
+        *self.cached_hash.get_or_init(|| self.compute_hash())
+    }
+
+    fn compute_hash(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
-        hasher.input_str(&format!("{}{}", last_proof, proof));
-        &hasher.result_str()[0..4] == "0000"
+        hasher.input(&self.preimage());
+        let mut hash = [0; 32];
+        hasher.result(&mut hash);
+        hash
     }
 
-    /// Displays the full blockchain.
-    pub fn display(&self) {
-        serde_json::to_writer_pretty(stdout(), &self.blocks).expect("fail to display blockchain");
+    /// The bytes the hash is taken over: the encoding the header names, its JSON for version 0.
+    pub fn preimage(&self) -> Vec<u8> {
+        match self.encoding {
+            0 => serde_json::to_vec(self).unwrap(),
+            version => self.encode(version),
+        }
     }
 
-    /// Validates a given blockchain.
-    pub fn valid_chain(chain: &Self) -> bool {
-        let mut prev_block = &chain.blocks[0];
-        let mut block;
+    /// The canonical encoding of the header in `version`, see `crate::encoding`, of which there
+    /// are versions 1 to 3 so far.
+    fn encode(&self, version: u32) -> Vec<u8> {
+        let mut encoder = Encoder::new(version);
+        encoder.u64(self.index);
+        encoder.u128(self.timestamp);
+        encoder.str(&self.previous_hash);
+        encoder.str(&self.merkle_root);
+        encoder.u32(self.difficulty_target);
+        encoder.u64(self.nonce);
+        encoder.option(self.reward_schedule, |encoder, schedule| {
+            encoder.i64(schedule.initial_reward);
+            encoder.u64(schedule.halving_interval);
+            encoder.option(schedule.max_supply, Encoder::i64);
+            // only if set, keeping the encoding of the schedules before it
+            if schedule.coinbase_maturity > 0 {
+                encoder.u64(schedule.coinbase_maturity);
+            }
+        });
+        encoder.option(self.transaction_model, |encoder, model| {
+            encoder.u8(match model {
+                TransactionModel::Account => 0,
+                TransactionModel::Utxo => 1,
+            })
+        });
+        encoder.option(self.name.as_deref(), Encoder::str);
+        encoder.option(self.chain_id.as_deref(), Encoder::str);
+        if version >= 2 {
+            encoder.u32(self.version);
+            encoder.u32(self.signals);
+        }
+        if version >= 3 {
+            encoder.option(self.miner.as_deref(), Encoder::str);
+            encoder.option(self.miner_signature.as_deref(), Encoder::str);
+        }
+        // only if set, keeping the encoding of the headers before it
+        if let Some(prefix) = &self.address_prefix {
+            encoder.str(prefix);
+        }
+        encoder.finish()
+    }
 
-        // check the genesis block
-        if prev_block.proof != 100
-            || !prev_block.transactions.is_empty()
-            || prev_block.previous_hash != "1"
-        {
+    /// What the miner signs: the encoding of the header without the nonce, which the proof of
+    /// work goes on to vary, and without the signature.
+    fn miner_message(&self) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        unsigned.nonce = 0;
+        unsigned.miner_signature = None;
+        unsigned.encode(self.encoding)
+    }
+
+    /// Does the header hash meet the difficulty target?
+    pub fn has_valid_proof(&self) -> bool {
+        // a later encoding cannot be told apart from a forged one
+        if self.encoding > ENCODING_VERSION {
             return false;
         }
-
-        for i in 1..chain.blocks.len() {
-            block = &chain.blocks[i];
-            trace!("validating chain ...");
-            trace!(
-                "prev_block: {}",
-                serde_json::to_string(&prev_block).unwrap()
-            );
-            trace!("block: {}", serde_json::to_string(&block).unwrap());
-            trace!("");
-            if prev_block.get_hash() != block.previous_hash {
-                return false;
+        let mut zeros = 0;
+        for byte in self.hash().iter() {
+            zeros += byte.leading_zeros();
+            if *byte != 0 {
+                break;
             }
-            if !Blockchain::valid_proof(prev_block.proof, block.proof) {
-                return false;
+        }
+        zeros >= self.difficulty_target
+    }
+
+    /// The hash of the block the header is of.
+    pub fn get_hash(&self) -> String {
+        self.hash()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The work done on the chain of `headers`, which must follow each other from the genesis
+    /// block on. `None` if they do not link up. Only the links are checked: a chain that looks
+    /// better by its headers still has to be downloaded and validated.
+    pub fn chain_work(headers: &[BlockHeader]) -> Option<u128> {
+        let mut work = 0u128;
+        for (index, header) in headers.iter().enumerate() {
+            if header.index != index as u64
+                || index > 0 && headers[index - 1].get_hash() != header.previous_hash
+            {
+                return None;
             }
-            prev_block = block;
+            work = work.saturating_add(header.work());
         }
-        true
+        Some(work)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Transaction {
-    id: String,
-    // unique identifier for one transaction
-    sender: String,
-    recipient: String,
-    amount: i64,
+/// How many of its most recent blocks a persisted chain keeps in memory.
+const RESIDENT_BLOCKS: usize = 128;
+
+/// The blocks of a chain, written through to a store. Only the most recent blocks are kept in
+/// memory, older ones are loaded from the store when asked for.
+struct Blocks {
+    store: Store,
+    len: u64,
+    // the last blocks of the chain, never empty
+    resident: VecDeque<Block>,
+    capacity: usize,
+    // the hash of the last block, which the next one must link to
+    tip: String,
 }
 
-impl Transaction {
-    pub fn new(sender: &str, recipient: &str, amount: i64) -> Self {
-        Transaction {
-            id: Uuid::new_v4().to_string(),
-            sender: sender.to_owned(),
-            recipient: recipient.to_owned(),
-            amount,
+impl Blocks {
+    /// Keeps all `blocks` in memory.
+    fn in_memory(blocks: Vec<Block>) -> Self {
+        let store = Store::memory();
+        store
+            .replace_blocks(&blocks)
+            .expect("memory storage does not fail");
+        let tip = blocks.last().map(Block::get_hash).unwrap_or_default();
+        Blocks {
+            store,
+            len: blocks.len() as u64,
+            resident: blocks.into(),
+            capacity: usize::MAX,
+            tip,
         }
     }
 
-    pub fn get_id(&self) -> &str {
-        self.id.as_str()
+    /// Loads the most recent `capacity` blocks stored in `store`, which must not be empty.
+    fn open(store: Store, capacity: usize) -> Result<Self> {
+        let len = store.block_count()?;
+        let resident = (len.saturating_sub(capacity as u64)..len)
+            .map(|index| {
+                store.get_block(index)?.ok_or_else(|| {
+                    failure::err_msg(format!("Block {} is missing from the store", index))
+                })
+            })
+            .collect::<Result<VecDeque<_>>>()?;
+        let tip = resident.back().map(Block::get_hash).unwrap_or_default();
+        Ok(Blocks {
+            store,
+            len,
+            resident,
+            capacity,
+            tip,
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    //    use env_logger::Env;
+    fn len(&self) -> u64 {
+        self.len
+    }
 
-    #[test]
-    fn test_pow() {
-        assert!(Blockchain::valid_proof(100, 35293));
-        assert!(Blockchain::valid_proof(35293, 35089));
+    fn first_resident(&self) -> u64 {
+        self.len - self.resident.len() as u64
+    }
 
-        assert_eq!(Blockchain::proof_of_work(100), 35293);
-        assert_eq!(Blockchain::proof_of_work(35293), 35089);
+    /// Returns the block at `index`, loading it from the store if it is not resident.
+    fn get(&self, index: u64) -> Option<Cow<'_, Block>> {
+        if index >= self.len {
+            return None;
+        }
+        let first = self.first_resident();
+        if index >= first {
+            return self
+                .resident
+                .get((index - first) as usize)
+                .map(Cow::Borrowed);
+        }
+        match self.store.get_block(index) {
+            Ok(Some(block)) => Some(Cow::Owned(block)),
+            Ok(None) => {
+                error!(target: STORAGE, "Block {} is missing from the store", index);
+                None
+            }
+            Err(e) => {
+                error!(target: STORAGE, "Failed to load block {}: {}", index, e);
+                None
+            }
+        }
     }
 
-    #[test]
-    fn test_valid_chain() {
-        //        env_logger::from_env(Env::default().default_filter_or("debug")).init();
+    fn last(&self) -> &Block {
+        self.resident.back().unwrap()
+    }
 
-        let mut chain = Blockchain::new();
-        assert!(Blockchain::valid_chain(&chain));
+    fn tip(&self) -> &str {
+        &self.tip
+    }
 
-        // play with the genesis block
-        chain.blocks[0]
-            .transactions
-            .push(Transaction::new("good", "evil", 100));
-        assert!(!Blockchain::valid_chain(&chain));
-        chain.blocks[0].transactions.pop();
-        assert!(Blockchain::valid_chain(&chain));
-        chain.blocks[0].proof = 101;
-        assert!(!Blockchain::valid_chain(&chain));
-        chain.blocks[0].proof = 100;
-        assert!(Blockchain::valid_chain(&chain));
-        chain.blocks[0].previous_hash = "2".to_owned();
-        assert!(!Blockchain::valid_chain(&chain));
-        chain.blocks[0].previous_hash = "1".to_owned();
-        assert!(Blockchain::valid_chain(&chain));
+    fn push(&mut self, block: Block, accounting: &BlockAccounting) {
+        let index = block.header.index;
+        if let Err(e) = self
+            .store
+            .put_block(&block)
+            .and_then(|_| self.store.put_accounting(index, accounting))
+        {
+            error!(target: STORAGE, "Failed to persist block {}: {}", index, e);
+        }
+        self.tip = block.get_hash();
+        self.resident.push_back(block);
+        self.len += 1;
+        if self.resident.len() > self.capacity {
+            self.resident.pop_front();
+        }
+    }
 
-        // perform some normal operations
-        chain.add_new_transaction(&Transaction::new("0", "1", 1));
-        chain.add_new_transaction(&Transaction::new("1", "2", 2));
-        chain.add_new_transaction(&Transaction::new("2", "3", 3));
-        chain.create_new_block(chain.run_pow(), chain.last_block().get_hash());
-        assert!(Blockchain::valid_chain(&chain));
-        chain.create_new_block(chain.run_pow(), chain.last_block().get_hash());
-        assert!(Blockchain::valid_chain(&chain));
+    /// Removes the last block, the genesis block always stays.
+    fn pop(&mut self) -> Option<Block> {
+        if self.len <= 1 {
+            return None;
+        }
+        let block = self.resident.pop_back()?;
+        self.len -= 1;
+        if let Err(e) = self.store.remove_block(self.len) {
+            error!(target: STORAGE, "Failed to remove block {}: {}", self.len, e);
+        }
+        if self.resident.is_empty() {
+            let last = self.get(self.len - 1)?.into_owned();
+            self.resident.push_back(last);
+        }
+        self.tip = self.last().get_hash();
+        Some(block)
+    }
+}
 
-        // tamper an intermediate block
-        chain.blocks[1]
-            .transactions
-            .push(Transaction::new("good", "evil", 100));
-        assert!(!Blockchain::valid_chain(&chain));
+/// The blocks of a chain as far as it reached when the view was taken, read from the store the
+/// chain shares rather than copied, e.g. for another thread to go through. Blocks the chain
+/// rolls back meanwhile are read as the ones replacing them, or not at all.
+#[derive(Clone)]
+pub struct BlocksView {
+    store: Store,
+    len: u64,
+}
+
+impl BlocksView {
+    // a view always holds at least the genesis block
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Loads the block at `index`, `None` past the end of the view or if it cannot be read.
+    pub fn get(&self, index: u64) -> Option<Block> {
+        if index >= self.len {
+            return None;
+        }
+        match self.store.get_block(index) {
+            Ok(block) => block,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to load block {}: {}", index, e);
+                None
+            }
+        }
+    }
+
+    /// Loads the blocks one at a time, stopping at the first that cannot be read.
+    pub fn iter(&self) -> impl Iterator<Item = Block> + '_ {
+        (0..self.len).map_while(move |index| self.get(index))
+    }
+}
+
+impl Serialize for BlocksView {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl fmt::Debug for BlocksView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlocksView")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// A transaction found by its id, and how far it made it, see `Blockchain::find_transaction`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionInclusion {
+    pub transaction: Transaction,
+    /// the height of the block confirming it, `None` while it is pending
+    pub height: Option<u64>,
+    /// how many blocks confirm it, the one holding it included
+    pub confirmations: u64,
+}
+
+impl fmt::Display for TransactionInclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.height {
+            Some(height) => write!(
+                f,
+                "confirmed in block {}, {} confirmations",
+                height, self.confirmations
+            ),
+            None => write!(f, "pending"),
+        }
+    }
+}
+
+/// A block of a chain, by its height or its hash.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BlockId {
+    Index(u64),
+    Hash(String),
+}
+
+impl std::str::FromStr for BlockId {
+    type Err = std::convert::Infallible;
+
+    /// Reads a number as a height, anything else as a hash.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => BlockId::Index(index),
+            Err(_) => BlockId::Hash(s.to_owned()),
+        })
+    }
+}
+
+/// Why a hash prefix does not name a single block or transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixError {
+    /// shorter than `MIN_PREFIX_LEN`
+    TooShort,
+    NotFound,
+    /// with the hashes starting with it, sorted
+    Ambiguous(Vec<String>),
+}
+
+/// Reads after the prefix, e.g. `ab12 is ambiguous, ...`.
+impl fmt::Display for PrefixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefixError::TooShort => write!(
+                f,
+                "is shorter than the {} characters a hash prefix needs",
+                MIN_PREFIX_LEN
+            ),
+            PrefixError::NotFound => write!(f, "matches nothing"),
+            PrefixError::Ambiguous(candidates) => {
+                write!(f, "is ambiguous, candidates:")?;
+                for candidate in candidates.iter().take(MAX_CANDIDATES) {
+                    write!(f, " {}", candidate)?;
+                }
+                if candidates.len() > MAX_CANDIDATES {
+                    write!(f, " and {} more", candidates.len() - MAX_CANDIDATES)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The one of `candidates`, unless there are none or several.
+fn single(mut candidates: Vec<String>) -> std::result::Result<String, PrefixError> {
+    candidates.sort_unstable();
+    candidates.dedup();
+    match candidates.len() {
+        0 => Err(PrefixError::NotFound),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(PrefixError::Ambiguous(candidates)),
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockId::Index(index) => write!(f, "{}", index),
+            BlockId::Hash(hash) => write!(f, "{}", hash),
+        }
+    }
+}
+
+// tests tamper with blocks in place, which only works for resident ones
+#[cfg(test)]
+impl std::ops::Index<usize> for Blocks {
+    type Output = Block;
+
+    fn index(&self, index: usize) -> &Block {
+        &self.resident[index - self.first_resident() as usize]
+    }
+}
+
+#[cfg(test)]
+impl std::ops::IndexMut<usize> for Blocks {
+    fn index_mut(&mut self, index: usize) -> &mut Block {
+        let first = self.first_resident() as usize;
+        &mut self.resident[index - first]
+    }
+}
+
+pub struct Blockchain {
+    current_transactions: Vec<Transaction>,
+    // the ids of `current_transactions`, which only change through `push_pending` and the like
+    pending_ids: HashSet<String>,
+    // blocks is non-empty
+    blocks: Blocks,
+    consensus: Box<dyn Consensus>,
+    // the balances left by all blocks so far
+    ledger: Ledger,
+    // how many transactions other than rewards may be pending
+    mempool_capacity: usize,
+    // the rule changes the blocks mined on the chain signal readiness for, see `set_signals`
+    signals: u32,
+    // the rules of this node for the transactions it admits, see `add_validator`
+    validators: Vec<Arc<dyn TxValidator>>,
+    // the hash of a block known to be good, whose seal and those of its ancestors are not
+    // checked when validating a chain
+    assume_valid: Option<String>,
+    // the hashes the blocks at these indices must have, in any chain
+    checkpoints: BTreeMap<u64, String>,
+    // the blocks of branches forking off the chain, by hash, which may overtake it
+    side_blocks: HashMap<String, Block>,
+    // the transactions that left the mempool other than into a block, until taken
+    evictions: VecDeque<(Transaction, Eviction)>,
+    // signs the blocks mined on the chain, see `set_miner_key`
+    miner_key: Option<NodeKey>,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Blockchain::new()
+    }
+}
+
+impl Blockchain {
+    /// Creates a new Blockchain with only the genesis block, kept in memory.
+    pub fn new() -> Self {
+        Blockchain::with_difficulty(INITIAL_DIFFICULTY)
+    }
+
+    /// Like `new`, but starting at `difficulty` instead of `INITIAL_DIFFICULTY`.
+    pub fn with_difficulty(difficulty: u32) -> Self {
+        Blockchain::from_blocks(vec![Block::get_genesis(difficulty)])
+    }
+
+    /// Like `with_difficulty`, but minting coins by `reward_schedule`.
+    pub fn with_reward_schedule(difficulty: u32, reward_schedule: RewardSchedule) -> Self {
+        Blockchain::from_blocks(vec![Block::get_genesis_with(
+            difficulty,
+            Some(reward_schedule),
+            TransactionModel::Account,
+        )])
+    }
+
+    /// Like `with_difficulty`, but moving coins by `transaction_model`.
+    pub fn with_transaction_model(difficulty: u32, transaction_model: TransactionModel) -> Self {
+        Blockchain::from_blocks(vec![Block::get_genesis_with(
+            difficulty,
+            None,
+            transaction_model,
+        )])
+    }
+
+    /// Creates a blockchain from given blocks, kept in memory.
+    pub fn from_blocks(blocks: Vec<Block>) -> Self {
+        let model = blocks
+            .first()
+            .map(Block::transaction_model)
+            .unwrap_or_default();
+        let mut ledger = Ledger::new(model);
+        let blocks = Blocks::in_memory(blocks);
+        if let Err(e) = Blockchain::account(&mut ledger, &blocks) {
+            error!(target: CONSENSUS, "Failed to account for the blocks: {}", e);
+        }
+        Blockchain {
+            current_transactions: vec![],
+            pending_ids: HashSet::new(),
+            blocks,
+            consensus: Box::new(ProofOfWork),
+            ledger,
+            mempool_capacity: MEMPOOL_CAPACITY,
+            signals: 0,
+            validators: Vec::new(),
+            assume_valid: None,
+            checkpoints: BTreeMap::new(),
+            side_blocks: HashMap::new(),
+            evictions: VecDeque::new(),
+            miner_key: None,
+        }
+    }
+
+    /// Loads the blockchain persisted in `store`. An empty store gets a fresh chain, starting at
+    /// `difficulty` if given. A stored chain must have started at `difficulty`, if given.
+    ///
+    /// Only the most recent blocks are kept in memory, older ones are read from `store`.
+    pub fn open(store: Store, difficulty: Option<u32>) -> Result<Self> {
+        Blockchain::open_with(store, difficulty, None, None, Box::new(ProofOfWork))
+    }
+
+    /// Like `open`, but validating and extending the chain with `consensus`. A fresh chain mints
+    /// coins by `reward_schedule` and moves them by `transaction_model` if given, which a stored
+    /// chain must have been started with.
+    pub fn open_with(
+        store: Store,
+        difficulty: Option<u32>,
+        reward_schedule: Option<RewardSchedule>,
+        transaction_model: Option<TransactionModel>,
+        consensus: Box<dyn Consensus>,
+    ) -> Result<Self> {
+        Blockchain::open_trusting(
+            store,
+            difficulty,
+            reward_schedule,
+            transaction_model,
+            consensus,
+            None,
+        )
+    }
+
+    /// Like `open_with`, but assuming the block with hash `assume_valid` and its ancestors to be
+    /// valid, if given, see `set_assume_valid`. This skips checking their seals, the costly part
+    /// of loading a long chain.
+    pub fn open_trusting(
+        store: Store,
+        difficulty: Option<u32>,
+        reward_schedule: Option<RewardSchedule>,
+        transaction_model: Option<TransactionModel>,
+        consensus: Box<dyn Consensus>,
+        assume_valid: Option<String>,
+    ) -> Result<Self> {
+        Blockchain::open_with_capacity(
+            store,
+            difficulty,
+            reward_schedule,
+            transaction_model,
+            consensus,
+            RESIDENT_BLOCKS,
+            assume_valid,
+        )
+    }
+
+    /// Like `open_trusting`, but starting an empty store with `genesis`, e.g. one set up by a
+    /// `GenesisSpec`. A stored chain must have started with it.
+    pub fn open_genesis(
+        store: Store,
+        genesis: &Block,
+        consensus: Box<dyn Consensus>,
+        assume_valid: Option<String>,
+    ) -> Result<Self> {
+        if store.block_count()? == 0 {
+            store.put_block(genesis)?;
+            let accounting = Ledger::new(genesis.transaction_model()).record(genesis);
+            store.put_accounting(0, &accounting)?;
+        }
+        let chain = Blockchain::open_trusting(store, None, None, None, consensus, assume_valid)?;
+        let stored = chain.get_block_by_index(0).map(|block| block.get_hash());
+        if stored.as_deref() != Some(genesis.get_hash().as_str()) {
+            return Err(failure::err_msg(format!(
+                "The stored blockchain started with genesis block {}, not {}",
+                stored.unwrap_or_default(),
+                genesis.get_hash()
+            )));
+        }
+        Ok(chain)
+    }
+
+    fn open_with_capacity(
+        store: Store,
+        difficulty: Option<u32>,
+        reward_schedule: Option<RewardSchedule>,
+        transaction_model: Option<TransactionModel>,
+        consensus: Box<dyn Consensus>,
+        capacity: usize,
+        assume_valid: Option<String>,
+    ) -> Result<Self> {
+        if store.block_count()? == 0 {
+            let genesis = Block::get_genesis_with(
+                difficulty.unwrap_or(INITIAL_DIFFICULTY),
+                reward_schedule,
+                transaction_model.unwrap_or_default(),
+            );
+            store.put_block(&genesis)?;
+            store.put_accounting(0, &BlockAccounting::default())?;
+        }
+        let mut chain = Blockchain {
+            current_transactions: vec![],
+            pending_ids: HashSet::new(),
+            blocks: Blocks::open(store, capacity)?,
+            consensus,
+            ledger: Ledger::default(),
+            mempool_capacity: MEMPOOL_CAPACITY,
+            signals: 0,
+            validators: Vec::new(),
+            assume_valid,
+            checkpoints: BTreeMap::new(),
+            side_blocks: HashMap::new(),
+            evictions: VecDeque::new(),
+            miner_key: None,
+        };
+        // the stored blocks were timed right when they came in, and the clock has moved on since
+        let stored = chain.blocks.len();
+        chain.ledger = Blockchain::replay(&*chain.consensus, chain.assume_valid(), &chain, stored)
+            .ok_or_else(|| failure::err_msg("The stored blockchain is not valid"))?;
+        let initial = chain.initial_difficulty();
+        match (difficulty, reward_schedule) {
+            (Some(difficulty), _) if difficulty != initial => Err(failure::err_msg(format!(
+                "The stored blockchain started at difficulty {}, not {}",
+                initial, difficulty
+            ))),
+            (_, Some(schedule)) if schedule != chain.reward_schedule() => {
+                Err(failure::err_msg(format!(
+                    "The stored blockchain mints coins by {:?}, not {:?}",
+                    chain.reward_schedule(),
+                    schedule
+                )))
+            }
+            _ => match transaction_model {
+                Some(model) if model != chain.transaction_model() => {
+                    Err(failure::err_msg(format!(
+                        "The stored blockchain uses the {:?} transaction model, not {:?}",
+                        chain.transaction_model(),
+                        model
+                    )))
+                }
+                _ => Ok(chain),
+            },
+        }
+    }
+
+    /// Replaces the consensus engine, `ProofOfWork` by default, which the chain is extended and
+    /// validated by from now on.
+    pub fn set_consensus(&mut self, consensus: Box<dyn Consensus>) {
+        self.consensus = consensus;
+    }
+
+    /// Whether the consensus engine lets this node seal the next block.
+    pub fn can_seal(&self) -> bool {
+        self.consensus.can_seal(self)
+    }
+
+    /// Moves the chain onto `store`, replacing the blocks stored there.
+    pub fn attach_store(&mut self, store: Store) -> Result<()> {
+        store.replace_blocks(&self.get_blocks())?;
+        self.blocks = Blocks::open(store, RESIDENT_BLOCKS)?;
+        Blockchain::account(&mut Ledger::new(self.transaction_model()), &self.blocks)
+    }
+
+    /// Records every block of `blocks` in `ledger`, storing what they minted, collected and moved.
+    fn account(ledger: &mut Ledger, blocks: &Blocks) -> Result<()> {
+        for index in 0..blocks.len() {
+            if let Some(block) = blocks.get(index) {
+                blocks.store.put_accounting(index, &ledger.record(&block))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// What the block at `index` minted, collected and moved, `None` if the chain is not that
+    /// long or it cannot be read.
+    pub fn get_block_accounting(&self, index: u64) -> Option<BlockAccounting> {
+        if index >= self.blocks.len() {
+            return None;
+        }
+        match self.blocks.store.get_accounting(index) {
+            Ok(accounting) => accounting,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to load the accounting of block {}: {}", index, e);
+                None
+            }
+        }
+    }
+
+    /// Returns a copy of the blocks the chain owns. This loads the whole chain into memory, see
+    /// `iter_blocks` and `view` to go through them without.
+    pub fn get_blocks(&self) -> Vec<Block> {
+        self.iter_blocks().map(Cow::into_owned).collect()
+    }
+
+    /// Goes through the blocks from the genesis block on, borrowing the resident ones and
+    /// loading older ones one at a time.
+    pub fn iter_blocks(&self) -> impl Iterator<Item = Cow<'_, Block>> {
+        (0..self.blocks.len()).filter_map(move |index| self.blocks.get(index))
+    }
+
+    /// A view of the blocks so far that other threads can go through, sharing the store of the
+    /// chain.
+    pub fn view(&self) -> BlocksView {
+        BlocksView {
+            store: self.blocks.store.clone(),
+            len: self.blocks.len(),
+        }
+    }
+
+    /// Returns the headers of up to `limit` blocks from index `start` on.
+    pub fn get_headers(&self, start: u64, limit: usize) -> Vec<BlockHeader> {
+        (start..self.blocks.len().min(start.saturating_add(limit as u64)))
+            .filter_map(|index| self.blocks.get(index).map(|block| block.header.clone()))
+            .collect()
+    }
+
+    /// Returns the number of blocks in the blockchain, also referred to as its 'length'.
+    // a blockchain always holds at least the genesis block
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.blocks.len() as usize
+    }
+
+    /// Returns the block at `index`, if the chain is long enough.
+    pub fn get_block_by_index(&self, index: u64) -> Option<Cow<'_, Block>> {
+        self.blocks.get(index)
+    }
+
+    /// Returns the block of the chain with `hash`, looking it up in the index of the store.
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<Cow<'_, Block>> {
+        match self.blocks.store.find_block(hash) {
+            Ok(index) => index
+                .and_then(|index| self.get_block_by_index(index))
+                .filter(|block| block.get_hash() == hash),
+            Err(e) => {
+                error!(target: STORAGE, "Failed to look up block {}: {}", hash, e);
+                None
+            }
+        }
+    }
+
+    /// Resolves `prefix` to the hash of the one block of the chain starting with it, like the
+    /// short object ids of git, going by the block index.
+    pub fn resolve_block(&self, prefix: &str) -> std::result::Result<String, PrefixError> {
+        if prefix.len() < MIN_PREFIX_LEN {
+            return Err(PrefixError::TooShort);
+        }
+        let found = self
+            .blocks
+            .store
+            .find_blocks_by_prefix(prefix)
+            .unwrap_or_else(|e| {
+                error!(target: STORAGE, "Failed to look up blocks {}: {}", prefix, e);
+                Vec::new()
+            });
+        // the index may still name blocks of a branch given up
+        single(
+            found
+                .into_iter()
+                .filter(|hash| self.get_block_by_hash(hash).is_some())
+                .collect(),
+        )
+    }
+
+    pub fn get_block(&self, id: &BlockId) -> Option<Cow<'_, Block>> {
+        match id {
+            BlockId::Index(index) => self.get_block_by_index(*index),
+            BlockId::Hash(hash) => self.get_block_by_hash(hash),
+        }
+    }
+
+    /// Returns the balance of `address` as of the block at `height`, replaying the chain up to it.
+    /// `None` if the chain is not that long yet.
+    pub fn get_balance_at(&self, address: &str, height: u64) -> Option<i64> {
+        Some(self.ledger_at(height)?.balance(address))
+    }
+
+    /// The balances left by the blocks up to `height`, `None` if the chain is not that long.
+    fn ledger_at(&self, height: u64) -> Option<Ledger> {
+        if height >= self.blocks.len() {
+            return None;
+        }
+        let mut ledger = Ledger::new(self.transaction_model());
+        for index in 0..=height {
+            ledger.record(&*self.blocks.get(index)?);
+        }
+        Some(ledger)
+    }
+
+    /// Returns whether the block with `hash` is at `index` of the chain.
+    pub fn contains_block(&self, index: u64, hash: &str) -> bool {
+        self.get_block_by_index(index)
+            .is_some_and(|block| block.get_hash() == hash)
+    }
+
+    /// The indices of the stored blocks found corrupt, which have to be downloaded again.
+    pub fn corrupt_blocks(&self) -> Vec<u64> {
+        self.blocks.store.corrupt_blocks().unwrap_or_else(|e| {
+            error!(target: STORAGE, "Failed to list the corrupt blocks: {}", e);
+            vec![]
+        })
+    }
+
+    /// The hash the block at `index` has, as the block following it links to it. `None` if that
+    /// block cannot be read either, or the chain is not that long.
+    pub fn expected_hash(&self, index: u64) -> Option<String> {
+        if index + 1 == self.blocks.len() {
+            return Some(self.tip_hash().to_owned());
+        }
+        self.blocks
+            .get(index + 1)
+            .map(|next| next.header.previous_hash.clone())
+    }
+
+    /// Stores `block`, downloaded from `source`, in place of the corrupt block at its index, if it
+    /// is the block the chain holds there. Returns whether it repaired the chain.
+    pub fn repair_block(&mut self, block: &Block, source: &str) -> bool {
+        let index = block.get_index();
+        if !self.corrupt_blocks().contains(&index)
+            || self.expected_hash(index).as_deref() != Some(block.get_hash().as_str())
+            || !block.has_valid_merkle_root()
+            || !block.has_valid_ids()
+        {
+            return false;
+        }
+        match self.blocks.store.repair_block(block, source) {
+            Ok(()) => {
+                info!(target: STORAGE, "Repaired the corrupt block {} from {}", index, source);
+                true
+            }
+            Err(e) => {
+                error!(target: STORAGE, "Failed to repair block {}: {}", index, e);
+                false
+            }
+        }
+    }
+
+    /// The hash of the last block, which identifies the tip of the chain.
+    pub fn tip_hash(&self) -> &str {
+        self.blocks.tip()
+    }
+
+    /// The id of the chain, derived from its genesis block and so from everything the genesis
+    /// block sets up. Nodes tell it when greeting each other, and the blocks of named chains
+    /// carry it.
+    pub fn chain_id(&self) -> String {
+        self.blocks
+            .get(0)
+            .map(|genesis| crate::storage::chain_id(&genesis))
+            .unwrap_or_default()
+    }
+
+    /// The chain id the blocks after the genesis block must carry, if any.
+    fn block_chain_id(&self) -> Option<String> {
+        self.blocks
+            .get(0)
+            .filter(|genesis| genesis.header.name.is_some())
+            .map(|genesis| crate::storage::chain_id(&genesis))
+    }
+
+    /// Whether `block` was made for another chain, so that it can be dropped right away instead
+    /// of being held as an orphan or a side block.
+    pub fn is_foreign(&self, block: &Block) -> bool {
+        block.get_index() > 0 && block.header.chain_id != self.block_chain_id()
+    }
+
+    /// Whether `block` links to a block this chain does not hold, so that it misses the blocks
+    /// in between or follows another branch. The index of `block` only tells where to look for
+    /// its parent, which must have the hash it links to.
+    pub fn is_orphan(&self, block: &Block) -> bool {
+        block.header.previous_hash != self.tip_hash()
+            && !self.side_blocks.contains_key(&block.header.previous_hash)
+            && block
+                .get_index()
+                .checked_sub(1)
+                .is_some_and(|parent| !self.contains_block(parent, &block.header.previous_hash))
+    }
+
+    /// Returns the pending transaction with `id`.
+    pub fn get_pending_transaction(&self, id: &str) -> Option<&Transaction> {
+        if !self.pending_ids.contains(id) {
+            return None;
+        }
+        self.current_transactions.iter().find(|t| t.get_id() == id)
+    }
+
+    /// Resolves `prefix` to the id of the one transaction, pending or on the chain, starting with
+    /// it, see `resolve_block`.
+    pub fn resolve_transaction(&self, prefix: &str) -> std::result::Result<String, PrefixError> {
+        if prefix.len() < MIN_PREFIX_LEN {
+            return Err(PrefixError::TooShort);
+        }
+        let mut found = self
+            .blocks
+            .store
+            .find_transactions_by_prefix(prefix)
+            .unwrap_or_else(|e| {
+                error!(target: STORAGE, "Failed to look up transactions {}: {}", prefix, e);
+                Vec::new()
+            });
+        found.extend(
+            self.pending_ids
+                .iter()
+                .filter(|id| id.starts_with(prefix))
+                .cloned(),
+        );
+        single(found)
+    }
+
+    /// Returns the transaction with `id`, pending or on the chain, with the block confirming it.
+    pub fn find_transaction(&self, id: &str) -> Option<TransactionInclusion> {
+        if let Some(transaction) = self.get_pending_transaction(id) {
+            return Some(TransactionInclusion {
+                transaction: transaction.clone(),
+                height: None,
+                confirmations: 0,
+            });
+        }
+        let index = match self.blocks.store.find_transaction(id) {
+            Ok(index) => index?,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to look up transaction {}: {}", id, e);
+                return None;
+            }
+        };
+        let block = self.get_block_by_index(index)?;
+        let transaction = block.transactions.iter().find(|t| t.get_id() == id)?;
+        Some(TransactionInclusion {
+            transaction: transaction.clone(),
+            height: Some(index),
+            confirmations: self.blocks.len() - index,
+        })
+    }
+
+    /// Returns the transactions touching `address`, the confirmed ones oldest first, then the
+    /// pending ones. The confirmed ones are looked up in the address index of the store.
+    pub fn get_history(&self, address: &str) -> Vec<TransactionInclusion> {
+        let confirmed = match self.blocks.store.find_history(address) {
+            Ok(confirmed) => confirmed,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to look up the history of {}: {}", address, e);
+                Vec::new()
+            }
+        };
+        let mut history = Vec::new();
+        // the transactions of a block come one after the other
+        let mut block: Option<Cow<'_, Block>> = None;
+        for (index, id) in confirmed {
+            if block.as_ref().map(|block| block.get_index()) != Some(index) {
+                block = self.get_block_by_index(index);
+            }
+            let transactions = block
+                .as_ref()
+                .map_or(&[][..], |block| block.get_transactions());
+            if let Some(transaction) = transactions.iter().find(|t| t.get_id() == id) {
+                history.push(TransactionInclusion {
+                    transaction: transaction.clone(),
+                    height: Some(index),
+                    confirmations: self.blocks.len() - index,
+                });
+            }
+        }
+        history.extend(
+            self.current_transactions
+                .iter()
+                .filter(|t| t.addresses().contains(&address))
+                .map(|t| TransactionInclusion {
+                    transaction: t.clone(),
+                    height: None,
+                    confirmations: 0,
+                }),
+        );
+        history
+    }
+
+    /// Returns whether a transaction with `id` is pending or already on the chain, looking it up
+    /// in the index of either.
+    pub fn contains_transaction(&self, id: &str) -> bool {
+        self.pending_ids.contains(id) || self.is_confirmed(id)
+    }
+
+    /// The first transaction of `block` that a block of the chain holds already.
+    fn confirmed_transaction<'a>(&self, block: &'a Block) -> Option<&'a str> {
+        block
+            .transactions
+            .iter()
+            .map(|t| t.get_id())
+            .find(|id| self.is_confirmed(id))
+    }
+
+    fn is_confirmed(&self, id: &str) -> bool {
+        match self.blocks.store.find_transaction(id) {
+            Ok(found) => found.is_some(),
+            Err(e) => {
+                error!(target: STORAGE, "Failed to look up transaction {}: {}", id, e);
+                false
+            }
+        }
+    }
+
+    /// Returns how many transactions the chain holds, mining rewards included.
+    pub fn transaction_count(&self) -> u64 {
+        match self.blocks.store.transaction_count() {
+            Ok(count) => count,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to count transactions: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Returns the balance of `address` as of the last block.
+    pub fn get_balance(&self, address: &str) -> i64 {
+        self.ledger.balance(address)
+    }
+
+    /// Returns the tokens of the asset `asset` that `address` holds as of the last block.
+    pub fn get_asset_balance(&self, asset: &str, address: &str) -> i64 {
+        self.ledger.holding(asset, address)
+    }
+
+    /// Returns the asset `asset` as of the last block, `None` if it is not issued yet.
+    pub fn get_asset(&self, asset: &str) -> Option<Asset> {
+        self.ledger.asset(asset).cloned()
+    }
+
+    /// Returns the balance of `address` once the pending transactions are confirmed.
+    pub fn get_pending_balance(&self, address: &str) -> i64 {
+        self.ledger
+            .balance_after(&self.current_transactions, address)
+    }
+
+    /// Makes the transaction sending `amount` from `sender` to `recipient` for `fee` in the
+    /// transaction model of the chain. On UTXO chains it spends unspent outputs of `sender` that
+    /// no pending transaction spends yet, sending the change back, and is `None` if there are not
+    /// enough. Rewards, sent by `REWARD_SENDER`, spend nothing and pay no fee.
+    pub fn new_transaction(
+        &self,
+        sender: &str,
+        recipient: &str,
+        amount: i64,
+        fee: i64,
+    ) -> Option<Transaction> {
+        if self.transaction_model() == TransactionModel::Account {
+            return Some(Transaction::new(sender, recipient, amount).with_fee(fee));
+        }
+        let payment = Output {
+            recipient: recipient.to_owned(),
+            amount,
+        };
+        if sender == REWARD_SENDER {
+            return Some(Transaction::spend(sender, Vec::new(), vec![payment]));
+        }
+        self.spend(&self.current_transactions, sender, payment, fee)
+    }
+
+    /// Makes the transaction replacing the pending transaction `id` by the same payment for a
+    /// higher `fee`, see `add_new_transaction`. On UTXO chains it may spend the coins of the
+    /// transaction it replaces. `None` if `id` is not pending, or if its sender lacks the coins
+    /// for the fee there.
+    pub fn new_replacement(&self, id: &str, fee: i64) -> Option<Transaction> {
+        let original = self.get_pending_transaction(id)?;
+        let (sender, recipient, amount) = (
+            original.get_sender(),
+            original.get_recipient(),
+            original.get_amount(),
+        );
+        let mut replacement = if original.get_utxo().is_none() {
+            Transaction {
+                asset_id: original.asset_id.clone(),
+                issuance: original.issuance,
+                ..Transaction::new(sender, recipient, amount).with_fee(fee)
+            }
+            .with_content_id()
+        } else {
+            let others: Vec<Transaction> = self
+                .current_transactions
+                .iter()
+                .filter(|t| t.get_id() != id)
+                .cloned()
+                .collect();
+            let payment = Output {
+                recipient: recipient.to_owned(),
+                amount,
+            };
+            self.spend(&others, sender, payment, fee)?
+        };
+        if let Some(expiry) = original.get_expiry() {
+            replacement = replacement.with_expiry(expiry);
+        }
+        Some(replacement.replacing(id))
+    }
+
+    /// Makes a UTXO transaction from `sender` making `payment` for `fee`, spending coins none of
+    /// the `pending` transactions spends and sending the change back.
+    fn spend(
+        &self,
+        pending: &[Transaction],
+        sender: &str,
+        payment: Output,
+        fee: i64,
+    ) -> Option<Transaction> {
+        let amount = payment.amount;
+        let cost = amount.checked_add(fee)?;
+        let (inputs, total) = self.ledger.select(pending, sender, cost)?;
+        let mut outputs = vec![payment];
+        if total > cost {
+            outputs.push(Output {
+                recipient: sender.to_owned(),
+                amount: total - cost,
+            });
+        }
+        Some(Transaction::spend(sender, inputs, outputs).with_fee(fee))
+    }
+
+    /// Whether the sender of `transaction` can afford it after the pending transactions.
+    pub fn is_affordable(&self, transaction: &Transaction) -> bool {
+        self.ledger.affords(&self.current_transactions, transaction)
+    }
+
+    /// Trusts the block with `hash` and its ancestors when validating a chain, ours or one it
+    /// may be replaced by: their links, ids, Merkle roots and transactions are still checked,
+    /// but not their proofs of work or signatures. A chain not holding the block is checked in
+    /// full. `None` checks every chain in full, which is the default.
+    pub fn set_assume_valid(&mut self, hash: Option<String>) {
+        self.assume_valid = hash;
+    }
+
+    pub fn assume_valid(&self) -> Option<&str> {
+        self.assume_valid.as_deref()
+    }
+
+    /// Signals readiness for the rule changes of `bits`, one bit each, in the blocks mined on the
+    /// chain, so that a change can be locked in once most miners are ready, see `signalling`.
+    pub fn set_signals(&mut self, bits: &[u8]) -> Result<()> {
+        if let Some(bit) = bits.iter().find(|&&bit| bit >= 32) {
+            return Err(failure::err_msg(format!(
+                "Signal bit {} is out of range, bits go from 0 to 31",
+                bit
+            )));
+        }
+        self.signals = bits.iter().fold(0, |signals, bit| signals | 1 << bit);
+        Ok(())
+    }
+
+    /// Runs `validator` on every transaction but rewards that enters the mempool from now on, in
+    /// addition to the consensus rules, see `TxValidator`.
+    pub fn add_validator(&mut self, validator: Arc<dyn TxValidator>) {
+        self.validators.push(validator);
+    }
+
+    pub fn validators(&self) -> &[Arc<dyn TxValidator>] {
+        &self.validators
+    }
+
+    /// The bits the blocks mined on the chain signal readiness for.
+    pub fn signals(&self) -> Vec<u8> {
+        (0..32).filter(|bit| self.signals & 1 << bit != 0).collect()
+    }
+
+    /// Has `key` sign the blocks mined on the chain whose rewards go to its address, naming it
+    /// as their miner, see `Block::sign_miner`, once the chain requires miner signatures. Until
+    /// then, the blocks signal readiness for them with `MINER_SIGNATURE_BIT`. Blocks paying others
+    /// are left unsigned, and are only valid until the chain requires signatures.
+    pub fn set_miner_key(&mut self, key: Option<NodeKey>) {
+        self.miner_key = key;
+    }
+
+    pub fn miner_key(&self) -> Option<&NodeKey> {
+        self.miner_key.as_ref()
+    }
+
+    /// Whether the next block must be signed by its miner, see `signatures_required_after`.
+    pub fn requires_miner_signatures(&self) -> bool {
+        self.signatures_required_after(self.last_block())
+    }
+
+    /// Whether the block following `parent` must be signed by its miner, in
+    /// `SIGNED_BLOCK_VERSION`: once `parent` is, or if `MINER_SIGNATURE_BIT` is locked in over
+    /// the `SIGNAL_WINDOW` blocks up to it. Readiness is only counted at the end of each window,
+    /// so that every node moves at the same block.
+    fn signatures_required_after(&self, parent: &Block) -> bool {
+        if parent.header.version >= SIGNED_BLOCK_VERSION {
+            return true;
+        }
+        let next = parent.header.index + 1;
+        if !next.is_multiple_of(SIGNAL_WINDOW) {
+            return false;
+        }
+        let blocks = (next - SIGNAL_WINDOW..next)
+            .filter(|&index| {
+                self.blocks
+                    .get(index)
+                    .is_some_and(|block| block.header.signals(MINER_SIGNATURE_BIT))
+            })
+            .count() as u64;
+        Readiness {
+            bit: MINER_SIGNATURE_BIT,
+            blocks,
+            window: SIGNAL_WINDOW,
+        }
+        .is_locked_in()
+    }
+
+    /// Checks that `block`, following `parent`, is of `SIGNED_BLOCK_VERSION` exactly when miner
+    /// signatures are required, so that unsigned blocks cannot follow signed ones by claiming an
+    /// older version, nor signed ones come before nodes are ready for them.
+    fn check_version_after(
+        &self,
+        block: &Block,
+        parent: &Block,
+    ) -> std::result::Result<(), BlockRejection> {
+        let signed = block.header.version >= SIGNED_BLOCK_VERSION;
+        match (self.signatures_required_after(parent), signed) {
+            (true, false) => Err(BlockRejection::UnsignedBlock),
+            (false, true) => Err(BlockRejection::UnsupportedVersion),
+            _ => Ok(()),
+        }
+    }
+
+    // makes `block` in `SIGNED_BLOCK_VERSION` once the chain requires miner signatures, signed
+    // with the miner key if its rewards pay the address of the key; until then, has it signal
+    // readiness for signatures if there is a key to sign with
+    fn sign_as_miner(&self, block: &mut Block) {
+        if !self.requires_miner_signatures() {
+            if self.miner_key.is_some() {
+                block.header_mut().signals |= 1 << MINER_SIGNATURE_BIT;
+            }
+            return;
+        }
+        block.header_mut().version = SIGNED_BLOCK_VERSION;
+        if let Some(key) = &self.miner_key {
+            let address = self.address_of(&key.public_key());
+            if block
+                .transactions
+                .iter()
+                .filter(|t| t.sender == REWARD_SENDER)
+                .all(|t| t.addresses().iter().all(|recipient| *recipient == address))
+            {
+                block.sign_miner(key, &address);
+            }
+        }
+    }
+
+    /// How many of the last `SIGNAL_WINDOW` blocks signal readiness for each rule change that
+    /// any of them, or we, signal, by bit.
+    pub fn signalling(&self) -> Vec<Readiness> {
+        let len = self.blocks.len();
+        let first = len.saturating_sub(SIGNAL_WINDOW).max(1);
+        let mut counts = [0; 32];
+        for index in first..len {
+            if let Some(block) = self.blocks.get(index) {
+                for (bit, count) in counts.iter_mut().enumerate() {
+                    if block.header.signals(bit as u8) {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        (0..32)
+            .filter(|&bit| counts[bit as usize] > 0 || self.signals & 1 << bit != 0)
+            .map(|bit| Readiness {
+                bit,
+                blocks: counts[bit as usize],
+                window: len - first,
+            })
+            .collect()
+    }
+
+    /// Requires the block at each index of `checkpoints` to have the hash it maps to, in our
+    /// chain and in any chain it may be replaced by, so that history before the last checkpoint
+    /// cannot be rewritten however much work a rival chain has. Checkpoints beyond the tip are
+    /// checked once the chain gets there. Fails, keeping the checkpoints as they were, if our
+    /// chain disagrees with one of them.
+    pub fn set_checkpoints(&mut self, checkpoints: BTreeMap<u64, String>) -> Result<()> {
+        if let Some((index, hash)) = Blockchain::violated_checkpoint(&checkpoints, self) {
+            return Err(failure::err_msg(format!(
+                "The blockchain disagrees with checkpoint {}, {}",
+                index, hash
+            )));
+        }
+        self.checkpoints = checkpoints;
+        Ok(())
+    }
+
+    pub fn checkpoints(&self) -> &BTreeMap<u64, String> {
+        &self.checkpoints
+    }
+
+    /// The first checkpoint `chain` holds another block at, as its index and the hash required.
+    fn violated_checkpoint<'a>(
+        checkpoints: &'a BTreeMap<u64, String>,
+        chain: &Self,
+    ) -> Option<(u64, &'a str)> {
+        checkpoints
+            .range(..chain.blocks.len())
+            .find(|(&index, hash)| !chain.contains_block(index, hash))
+            .map(|(&index, hash)| (index, hash.as_str()))
+    }
+
+    /// Limits the pending transactions to `capacity`, not counting rewards, so that a flood of
+    /// transactions cannot exhaust memory. Once it is reached, every new transaction evicts the
+    /// one paying the lowest fee, the oldest of those paying the same, unless that is the new
+    /// one.
+    pub fn set_mempool_capacity(&mut self, capacity: usize) {
+        self.mempool_capacity = capacity;
+        while self.pending_count() > capacity {
+            let evicted = self.evict();
+            debug!(target: MEMPOOL, "The mempool shrank, evicting transaction {:?}", evicted);
+        }
+    }
+
+    /// Takes the transactions that left the mempool since last asked, other than into a block,
+    /// and why, e.g. for a wallet to send or sign them again. Only the last `MEMPOOL_CAPACITY`
+    /// are kept for a caller that never asks.
+    pub fn take_evictions(&mut self) -> Vec<(Transaction, Eviction)> {
+        self.evictions.drain(..).collect()
+    }
+
+    fn note_eviction(&mut self, transaction: Transaction, eviction: Eviction) {
+        self.evictions.push_back((transaction, eviction));
+        while self.evictions.len() > MEMPOOL_CAPACITY {
+            self.evictions.pop_front();
+        }
+    }
+
+    /// Returns how many transactions are pending, mining rewards left out.
+    pub fn pending_count(&self) -> usize {
+        self.current_transactions
+            .iter()
+            .filter(|t| t.get_sender() != REWARD_SENDER)
+            .count()
+    }
+
+    /// The position of the pending transaction to evict first, if there is any but rewards.
+    fn eviction_candidate(&self) -> Option<usize> {
+        // `min_by_key` picks the first of equal fees, the oldest
+        self.current_transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.get_sender() != REWARD_SENDER)
+            .min_by_key(|(_, t)| t.get_fee())
+            .map(|(i, _)| i)
+    }
+
+    fn evict(&mut self) -> Option<String> {
+        let i = self.eviction_candidate()?;
+        let evicted = self.remove_pending(i);
+        let id = evicted.id.clone();
+        self.note_eviction(evicted, Eviction::Evicted);
+        Some(id)
+    }
+
+    /// Adds a new transaction to the list of transactions, unless it is malformed, see
+    /// `Transaction::validate`, its sender cannot afford it or the mempool is full of
+    /// transactions paying higher fees. Tells why it was rejected otherwise.
+    ///
+    /// A transaction declaring to replace a pending one, see `Transaction::replacing`, takes its
+    /// place if it has the same sender and pays a higher fee, and is dropped otherwise.
+    pub fn add_new_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> std::result::Result<(), Rejection> {
+        // check whether it already exists in current transactions or on the blockchain
+        if self.contains_transaction(transaction.get_id()) {
+            return Err(Rejection::Duplicate);
+        }
+        transaction.validate()?;
+        transaction.check_prefix(self.address_prefix().as_deref())?;
+        if transaction.is_expired_at(self.blocks.len(), get_time()) {
+            return Err(Rejection::Expired);
+        }
+        let replaced = match transaction.get_replaced() {
+            Some(id) => match self
+                .current_transactions
+                .iter()
+                .position(|t| t.get_id() == id)
+            {
+                Some(i)
+                    if self.current_transactions[i].get_sender() == transaction.get_sender()
+                        && self.current_transactions[i].get_fee() < transaction.get_fee() =>
+                {
+                    Some(i)
+                }
+                _ => return Err(Rejection::NothingToReplace(id.to_owned())),
+            },
+            None => None,
+        };
+        // the replacement may spend what the replaced transaction spends
+        let affordable = match replaced {
+            Some(i) => {
+                let mut pending = self.current_transactions.clone();
+                pending.remove(i);
+                self.ledger.affords(&pending, transaction)
+            }
+            None => self.is_affordable(transaction),
+        };
+        if !affordable {
+            let mut attempt = self.current_transactions.clone();
+            if let Some(i) = replaced {
+                attempt.remove(i);
+            }
+            let issued = match (transaction.get_asset_id(), transaction.get_issuance()) {
+                (Some(asset), Some(issuance)) => {
+                    let state = self.ledger.asset_after(&attempt, asset);
+                    let issued = state.as_ref().map_or(0, |state| state.issued);
+                    if state.is_some_and(|state| {
+                        state.issuer != transaction.get_sender()
+                            || state.max_supply != issuance.max_supply
+                    }) {
+                        Some(Rejection::NotIssuer(asset.to_owned()))
+                    } else if transaction.get_amount() > issuance.max_supply - issued {
+                        Some(Rejection::OverIssuance(asset.to_owned()))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            let immature = self.ledger.affords_once_mature(&attempt, transaction);
+            attempt.push(transaction.clone());
+            return Err(match self.ledger.double_spend(&attempt) {
+                Some((id, first)) if id == transaction.id => Rejection::DoubleSpend(first),
+                _ if immature => Rejection::ImmatureReward,
+                _ => issued.unwrap_or(Rejection::Overspend),
+            });
+        }
+        if transaction.get_sender() != REWARD_SENDER {
+            for validator in &self.validators {
+                if let Err(reason) = validator.validate(transaction, self) {
+                    debug!(
+                        target: MEMPOOL,
+                        "Validator {} turns down transaction {:?}: {}",
+                        validator.name(),
+                        transaction.id,
+                        reason
+                    );
+                    return Err(Rejection::Policy(reason));
+                }
+            }
+        }
+        if let Some(i) = replaced {
+            let original = self.remove_pending(i);
+            debug!(
+                target: MEMPOOL,
+                "Transaction {:?} replaces transaction {:?}",
+                transaction.id, original.id
+            );
+            self.note_eviction(original, Eviction::Replaced(transaction.id.clone()));
+        } else if transaction.get_sender() != REWARD_SENDER
+            && self.pending_count() >= self.mempool_capacity
+        {
+            let lowest = self
+                .eviction_candidate()
+                .map(|i| self.current_transactions[i].get_fee());
+            if lowest.is_none_or(|fee| fee > transaction.get_fee()) {
+                return Err(Rejection::MempoolFull);
+            }
+            let evicted = self.evict();
+            debug!(target: MEMPOOL, "The mempool is full, evicting transaction {:?}", evicted);
+        }
+        self.push_pending(transaction.clone());
+        debug!(target: MEMPOOL, "New transaction {:?} added", transaction.id);
+        Ok(())
+    }
+
+    /// Adds `transaction` to the pending ones, unchecked, see `add_new_transaction`.
+    pub(crate) fn push_pending(&mut self, transaction: Transaction) {
+        self.pending_ids.insert(transaction.id.clone());
+        self.current_transactions.push(transaction);
+    }
+
+    fn remove_pending(&mut self, i: usize) -> Transaction {
+        let transaction = self.current_transactions.remove(i);
+        self.pending_ids.remove(&transaction.id);
+        transaction
+    }
+
+    /// Takes out the pending transactions `is_dropped` holds for, keeping the order of the others.
+    fn drop_pending(&mut self, is_dropped: impl Fn(&Transaction) -> bool) -> Vec<Transaction> {
+        let (dropped, kept): (Vec<_>, Vec<_>) = mem::take(&mut self.current_transactions)
+            .into_iter()
+            .partition(|t| is_dropped(t));
+        self.current_transactions = kept;
+        for t in &dropped {
+            self.pending_ids.remove(&t.id);
+        }
+        dropped
+    }
+
+    fn take_pending(&mut self) -> Vec<Transaction> {
+        self.pending_ids.clear();
+        mem::take(&mut self.current_transactions)
+    }
+
+    /// Creates a new Block containing current transactions and adds it to the chain.
+    ///
+    /// The block is only valid if `nonce` meets its difficulty target and the transactions are
+    /// still affordable, so this is mostly for tests that skip the work, see `mine_new_block` to
+    /// do it.
+    pub fn create_new_block(&mut self, nonce: u64, previous_hash: String) -> &Block {
+        self.create_new_block_at(nonce, previous_hash, get_time())
+    }
+
+    /// Like `create_new_block`, but with the given `timestamp`.
+    pub fn create_new_block_at(
+        &mut self,
+        nonce: u64,
+        previous_hash: String,
+        timestamp: u128,
+    ) -> &Block {
+        let mut block = self.assemble_block(previous_hash, timestamp);
+        self.sign_as_miner(&mut block);
+        block.header_mut().nonce = nonce;
+        self.push(block);
+        self.last_block()
+    }
+
+    /// Creates a new Block containing current transactions on top of the last block, has the
+    /// consensus engine seal it, e.g. by running the proof of work, and adds it to the chain.
+    /// The transactions paying the highest fees go first, and those that expired or became
+    /// unaffordable since they were added, e.g. because a block spent the same coins, are left
+    /// out.
+    pub fn mine_new_block(&mut self) -> &Block {
+        self.mine_new_block_at(get_time())
+    }
+
+    /// Like `mine_new_block`, but with the given `timestamp`, e.g. for simulations.
+    pub fn mine_new_block_at(&mut self, timestamp: u128) -> &Block {
+        self.drop_expired(timestamp);
+        self.current_transactions = self
+            .ledger
+            .prioritized(mem::take(&mut self.current_transactions));
+        let mut block = self.assemble_block(self.last_block().get_hash(), timestamp);
+        self.sign_as_miner(&mut block);
+        self.consensus.seal(self, &mut block);
+        self.push(block);
+        self.last_block()
+    }
+
+    fn push(&mut self, block: Block) {
+        let accounting = self.ledger.record(&block);
+        let timestamp = block.header.timestamp;
+        self.blocks.push(block, &accounting);
+        // the next block comes no earlier
+        self.drop_expired(timestamp);
+        self.drop_double_spends();
+    }
+
+    /// Drops the pending transactions spending coins that a block spent since they were added,
+    /// keeping the order of the others.
+    fn drop_double_spends(&mut self) {
+        if self.current_transactions.is_empty() {
+            return;
+        }
+        let kept: HashSet<String> = self
+            .ledger
+            .prioritized(self.current_transactions.clone())
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        for t in self.drop_pending(|t| !kept.contains(&t.id)) {
+            debug!(
+                target: MEMPOOL,
+                "Transaction {:?} spends coins spent meanwhile, dropping it",
+                t.id
+            );
+            self.note_eviction(t, Eviction::Conflicted);
+        }
+    }
+
+    /// Drops the pending transactions that expire before a next block made at `timestamp`.
+    pub fn drop_expired(&mut self, timestamp: u128) {
+        let index = self.blocks.len();
+        for t in self.drop_pending(|t| t.is_expired_at(index, timestamp)) {
+            debug!(target: MEMPOOL, "Transaction {:?} expired, dropping it", t.id);
+            self.note_eviction(t, Eviction::Expired);
+        }
+    }
+
+    /// The reward schedule set by the genesis block.
+    pub fn reward_schedule(&self) -> RewardSchedule {
+        self.blocks
+            .get(0)
+            .map(|genesis| genesis.reward_schedule())
+            .unwrap_or_default()
+    }
+
+    /// The transaction model set by the genesis block.
+    pub fn transaction_model(&self) -> TransactionModel {
+        self.blocks
+            .get(0)
+            .map(|genesis| genesis.transaction_model())
+            .unwrap_or_default()
+    }
+
+    /// The prefix set by the genesis block, under which every address of the chain must encode a
+    /// public key, see `Address`. Any valid address goes on chains without one.
+    pub fn address_prefix(&self) -> Option<String> {
+        self.blocks
+            .get(0)
+            .and_then(|genesis| genesis.address_prefix().map(str::to_owned))
+    }
+
+    /// The address of the holder of `public_key`: the key under the prefix of the chain, or the
+    /// key itself on chains without one.
+    pub fn address_of(&self, public_key: &str) -> String {
+        self.address_prefix()
+            .and_then(|prefix| Address::new(&prefix, public_key))
+            .map_or_else(|| public_key.to_owned(), |address| address.to_string())
+    }
+
+    /// Whether `address` is valid and, on chains with a prefix, encodes a public key under it.
+    pub fn is_address(&self, address: &str) -> bool {
+        is_valid_address(address)
+            && Transaction::new(REWARD_SENDER, address, 1)
+                .check_prefix(self.address_prefix().as_deref())
+                .is_ok()
+    }
+
+    /// How many coins the next block may mint.
+    pub fn next_reward(&self) -> i64 {
+        self.reward_schedule()
+            .reward(self.blocks.len(), self.ledger.minted())
+    }
+
+    /// The fees the pending transactions that make it into the next block pay, which its miner
+    /// may claim on top of `next_reward`.
+    pub fn pending_fees(&self) -> i64 {
+        self.ledger
+            .prioritized(self.current_transactions.clone())
+            .iter()
+            .filter(|t| t.get_sender() != REWARD_SENDER)
+            .fold(0i64, |fees, t| fees.saturating_add(t.get_fee()))
+    }
+
+    /// What the miner of a next block made at `timestamp` must claim: `next_reward` and the fees
+    /// of the pending transactions that make it into the block.
+    pub fn claimable_reward(&self, timestamp: u128) -> i64 {
+        let mut transactions = self.ledger.prioritized(self.unexpired_pending(timestamp));
+        transactions.truncate(Block::fitting(&transactions));
+        let fees = transactions
+            .iter()
+            .filter(|t| t.get_sender() != REWARD_SENDER)
+            .fold(0i64, |fees, t| fees.saturating_add(t.get_fee()));
+        self.next_reward().saturating_add(fees)
+    }
+
+    fn unexpired_pending(&self, timestamp: u128) -> Vec<Transaction> {
+        let index = self.blocks.len();
+        self.current_transactions
+            .iter()
+            .filter(|t| !t.is_expired_at(index, timestamp))
+            .cloned()
+            .collect()
+    }
+
+    /// The block `mine_new_block_at(timestamp)` would seal with the pending transactions and
+    /// `reward`, if given, without changing the chain or doing any work.
+    pub fn candidate_block(&self, reward: Option<Transaction>, timestamp: u128) -> Block {
+        let mut pending = self.unexpired_pending(timestamp);
+        pending.truncate(Block::fitting(&pending));
+        pending.extend(reward);
+        let transactions = self.ledger.prioritized(pending);
+        let mut block = self.block_of(transactions, self.last_block().get_hash(), timestamp);
+        self.sign_as_miner(&mut block);
+        block
+    }
+
+    /// What `block` would mint, collect and move on top of the chain. This copies the balances.
+    pub fn candidate_accounting(&self, block: &Block) -> BlockAccounting {
+        self.ledger.clone().record(block)
+    }
+
+    /// How many coins were minted so far.
+    pub fn get_supply(&self) -> i64 {
+        self.ledger.minted()
+    }
+
+    /// The work done on the chain so far, the sum of the `Block::work` of all blocks.
+    pub fn total_work(&self) -> u128 {
+        self.ledger.work()
+    }
+
+    /// The block holding as many of the pending transactions as fit, which stop being pending.
+    fn assemble_block(&mut self, previous_hash: String, timestamp: u128) -> Block {
+        let mut transactions = self.take_pending();
+        for t in transactions.split_off(Block::fitting(&transactions)) {
+            self.push_pending(t);
+        }
+        self.block_of(transactions, previous_hash, timestamp)
+    }
+
+    /// The unsealed block holding `transactions` on top of the last block.
+    fn block_of(
+        &self,
+        transactions: Vec<Transaction>,
+        previous_hash: String,
+        timestamp: u128,
+    ) -> Block {
+        // a clock running behind the chain would make the block invalid
+        let timestamp = timestamp.max(self.median_time_past(self.blocks.len()));
+        Block {
+            header: BlockHeader {
+                index: self.blocks.len(),
+                timestamp,
+                previous_hash,
+                merkle_root: merkle::merkle_root(&Block::transaction_hashes(&transactions)),
+                difficulty_target: self.next_difficulty(),
+                nonce: 0,
+                reward_schedule: None,
+                transaction_model: None,
+                name: None,
+                address_prefix: None,
+                chain_id: self.block_chain_id(),
+                encoding: ENCODING_VERSION,
+                version: BLOCK_VERSION,
+                signals: self.signals,
+                miner: None,
+                miner_signature: None,
+                cached_hash: OnceLock::new(),
+            },
+            transactions,
+            seal: None,
+        }
+    }
+
+    /// Adds a given block to the chain. Returns `false` if the new block is invalid or does not
+    /// extend the tip, see `try_add_block`.
+    pub fn add_new_block(&mut self, block: &Block) -> bool {
+        self.try_add_block(block).is_ok()
+    }
+
+    /// Adds a given block to the chain, or tells why it does not, see `validate_block`.
+    pub fn try_add_block(&mut self, block: &Block) -> std::result::Result<(), BlockRejection> {
+        if let Err(rejection) = self.validate_block(block) {
+            debug!(
+                target: CONSENSUS,
+                "The incoming block {} is dropped, {}",
+                block.get_hash(),
+                rejection
+            );
+            return Err(rejection);
+        }
+        // okay, now this block looks good to us
+        // but we should check whether the block contains duplicate transactions with us
+        let ids: HashSet<&str> = block.transactions.iter().map(|t| t.get_id()).collect();
+        self.drop_pending(|t| ids.contains(t.get_id()));
+        debug!(target: CONSENSUS, "The incoming block is accepted :)");
+        self.push(block.clone());
+        Ok(())
+    }
+
+    /// Checks that `block` extends the tip, with why it does not.
+    ///
+    /// A block extends the tip if it links to the hash of the last block, whatever index it
+    /// claims. The index is derived from that link and only has to be consistent with it.
+    pub fn validate_block(&self, block: &Block) -> std::result::Result<(), BlockRejection> {
+        if block.header.previous_hash != self.tip_hash() {
+            return Err(BlockRejection::BadParent);
+        }
+        if block.get_index() != self.blocks.len() {
+            return Err(BlockRejection::BadIndex {
+                expected: self.blocks.len(),
+            });
+        }
+        if self.is_foreign(block) {
+            return Err(BlockRejection::ForeignChain);
+        }
+        if let Some(hash) = self.checkpoints.get(&block.get_index()) {
+            if hash != &block.get_hash() {
+                return Err(BlockRejection::CheckpointMismatch { hash: hash.clone() });
+            }
+        }
+        self.check_timestamp(block, get_time())?;
+        self.check_version_after(block, self.last_block())?;
+        self.consensus.check_seal(self, block)?;
+        block.check()?;
+        block.check_addresses(self.address_prefix().as_deref())?;
+        if let Some(id) = self.confirmed_transaction(block) {
+            return Err(BlockRejection::ConfirmedTransaction { id: id.to_owned() });
+        }
+        block.check_spends(&self.ledger, self.next_reward())
+    }
+
+    /// Holds `block` as part of a branch competing with the chain, and reorganizes onto that
+    /// branch once it wins the fork choice of our consensus engine. Returns how many blocks were
+    /// rolled back, `None` if the chain stays as it is.
+    ///
+    /// The branch must fork off a block we hold. Its blocks depend on the balances at the fork,
+    /// so they are only validated as a whole, when the branch could overtake the chain.
+    pub fn add_side_block(&mut self, block: &Block) -> Option<u64> {
+        let hash = block.get_hash();
+        if block.header.previous_hash == self.tip_hash()
+            || self.is_foreign(block)
+            || self.is_orphan(block)
+            || self.side_blocks.contains_key(&hash)
+            || self.contains_block(block.get_index(), &hash)
+        {
+            return None;
+        }
+        if let Err(rejection) = block.check() {
+            debug!(target: CONSENSUS, "The incoming side block is not valid, {}", rejection);
+            return None;
+        }
+        self.hold_side_block(block.clone());
+        let (fork, branch) = self.branch_of(&hash)?;
+        let candidate = Blockchain::from_blocks(
+            (0..=fork)
+                .filter_map(|index| self.blocks.get(index).map(Cow::into_owned))
+                .chain(branch.iter().cloned())
+                .collect(),
+        );
+        if !self.prefers(&candidate) {
+            debug!(
+                target: CONSENSUS,
+                "Holding side block {} of a branch forking at block {}",
+                block.get_index(),
+                fork
+            );
+            return None;
+        }
+        Some(self.reorganize(fork, branch))
+    }
+
+    /// Whether the block with `hash` is held as part of a branch competing with the chain.
+    pub fn contains_side_block(&self, hash: &str) -> bool {
+        self.side_blocks.contains_key(hash)
+    }
+
+    fn hold_side_block(&mut self, block: Block) {
+        self.side_blocks.insert(block.get_hash(), block);
+        if self.side_blocks.len() > SIDE_BLOCK_CAPACITY {
+            // the branch forking deepest is the least likely to overtake the chain
+            if let Some(lowest) = self
+                .side_blocks
+                .values()
+                .min_by_key(|block| block.get_index())
+                .map(Block::get_hash)
+            {
+                self.side_blocks.remove(&lowest);
+            }
+        }
+    }
+
+    /// The index of the block of the chain the branch ending in the side block with `hash` forks
+    /// off at, and the blocks of that branch, in order.
+    fn branch_of(&self, hash: &str) -> Option<(u64, Vec<Block>)> {
+        let mut branch = vec![];
+        let mut hash = hash;
+        while let Some(block) = self.side_blocks.get(hash) {
+            hash = &block.header.previous_hash;
+            branch.push(block.clone());
+        }
+        branch.reverse();
+        let fork = branch.first()?.get_index().checked_sub(1)?;
+        if !self.contains_block(fork, hash) {
+            return None;
+        }
+        Some((fork, branch))
+    }
+
+    /// Rolls the chain back to the block at `fork` and applies `branch` on top of it. The rolled
+    /// back blocks are held as a side branch in turn, and their transactions that are not on the
+    /// new branch go back to the pending ones, as far as they are still valid.
+    fn reorganize(&mut self, fork: u64, branch: Vec<Block>) -> u64 {
+        let mut rolled_back = vec![];
+        while self.blocks.len() > fork + 1 {
+            match self.blocks.pop() {
+                Some(block) => rolled_back.push(block),
+                None => break,
+            }
+        }
+        let depth = rolled_back.len() as u64;
+        self.ledger = self
+            .ledger_at(fork)
+            .unwrap_or_else(|| Ledger::new(self.transaction_model()));
+        for block in branch {
+            self.side_blocks.remove(&block.get_hash());
+            let ids: HashSet<&str> = block.transactions.iter().map(|t| t.get_id()).collect();
+            self.drop_pending(|t| ids.contains(t.get_id()));
+            self.push(block);
+        }
+        for block in rolled_back.into_iter().rev() {
+            for t in &block.transactions {
+                if t.get_sender() == REWARD_SENDER || self.contains_transaction(t.get_id()) {
+                    continue;
+                }
+                if let Err(rejection) = self.add_new_transaction(t) {
+                    debug!(
+                        target: MEMPOOL,
+                        "Dropping rolled back transaction {}: {}",
+                        t.get_id(),
+                        rejection
+                    );
+                    self.note_eviction(t.clone(), Eviction::RolledBack(rejection));
+                }
+            }
+            self.hold_side_block(block);
+        }
+        info!(
+            target: CONSENSUS,
+            "Reorganized the chain onto a branch forking at block {}, {} blocks deep",
+            fork, depth
+        );
+        depth
+    }
+
+    /// Returns a copy of current transactions.
+    pub fn get_current_transactions(&self) -> Vec<Transaction> {
+        self.current_transactions.clone()
+    }
+
+    /// Returns up to `limit` of the current transactions but the rewards, in the order they
+    /// would fill the next block, see `mine_new_block`.
+    pub fn get_prioritized_transactions(&self, limit: usize) -> Vec<Transaction> {
+        self.ledger
+            .prioritized(self.current_transactions.clone())
+            .into_iter()
+            .filter(|t| t.get_sender() != REWARD_SENDER)
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns the last Block in the chain.
+    pub fn last_block(&self) -> &Block {
+        self.blocks.last()
+    }
+
+    /// Proof of Work algorithm: finds the nonce making the header hash of `block` meet its
+    /// difficulty target.
+    pub fn proof_of_work(block: &Block) -> u64 {
+        let mut block = block.clone();
+        block.header_mut().nonce = 0;
+        while !block.has_valid_proof() {
+            block.header_mut().nonce += 1;
+        }
+        block.header.nonce
+    }
+
+    /// The difficulty the chain started at, set by its genesis block.
+    pub fn initial_difficulty(&self) -> u32 {
+        self.blocks.get(0).map_or(INITIAL_DIFFICULTY, |genesis| {
+            genesis.header.difficulty_target
+        })
+    }
+
+    /// The median timestamp of the up to `MEDIAN_TIME_SPAN` blocks before the one at `index`,
+    /// which may be the next one.
+    pub fn median_time_past(&self, index: u64) -> u128 {
+        let mut timestamps: Vec<u128> = (index.saturating_sub(MEDIAN_TIME_SPAN)..index)
+            .filter_map(|i| self.blocks.get(i).map(|block| block.header.timestamp))
+            .collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Checks that `block` is made no earlier than `median_time_past` and no later than
+    /// `MAX_FUTURE_DRIFT` past `now`, as the difficulty is retargeted from these timestamps.
+    fn check_timestamp(&self, block: &Block, now: u128) -> std::result::Result<(), BlockRejection> {
+        let timestamp = block.header.timestamp;
+        if timestamp < self.median_time_past(block.get_index())
+            || timestamp > now.saturating_add(MAX_FUTURE_DRIFT)
+        {
+            return Err(BlockRejection::BadTimestamp);
+        }
+        Ok(())
+    }
+
+    /// The lowest difficulty the blocks of the chain may be mined with.
+    fn min_difficulty(&self) -> u32 {
+        MIN_DIFFICULTY.min(self.initial_difficulty())
+    }
+
+    /// The difficulty the next block must be mined with.
+    pub fn next_difficulty(&self) -> u32 {
+        self.expected_difficulty(self.blocks.len())
+            .unwrap_or_else(|| self.initial_difficulty())
+    }
+
+    /// Returns the difficulty required of the block at `index`, which may be the next one.
+    ///
+    /// Every `RETARGET_INTERVAL` blocks, the difficulty goes up a bit if the previous interval
+    /// was mined in less than half the targeted time, and down a bit if it took more than twice
+    /// the targeted time. The genesis block is left out, as its timestamp is fixed. The result
+    /// stays within `min_difficulty()..=MAX_DIFFICULTY` whatever the previous block claims.
+    pub(crate) fn expected_difficulty(&self, index: u64) -> Option<u32> {
+        if index == 0 {
+            return Some(self.initial_difficulty());
+        }
+        let previous = self.blocks.get(index - 1)?.header.difficulty_target;
+        let difficulty =
+            if !index.is_multiple_of(RETARGET_INTERVAL) || index < 2 * RETARGET_INTERVAL {
+                previous
+            } else {
+                let first = self.blocks.get(index - RETARGET_INTERVAL)?.header.timestamp;
+                let last = self.blocks.get(index - 1)?.header.timestamp;
+                retarget(previous, last.saturating_sub(first))
+            };
+        Some(difficulty.clamp(self.min_difficulty(), MAX_DIFFICULTY))
+    }
+
+    /// Displays the full blockchain.
+    pub fn display(&self) {
+        serde_json::to_writer_pretty(stdout(), &self.view()).expect("fail to display blockchain");
+    }
+
+    /// Proves that the transaction with `id` is on the chain, `None` if it is not.
+    pub fn merkle_proof(&self, id: &str) -> Option<MerkleProof> {
+        let index = match self.blocks.store.find_transaction(id) {
+            Ok(index) => index?,
+            Err(e) => {
+                error!(target: STORAGE, "Failed to look up transaction {}: {}", id, e);
+                return None;
+            }
+        };
+        let block = self.get_block_by_index(index)?;
+        let position = block.transactions.iter().position(|t| t.get_id() == id)?;
+        let path = merkle::merkle_path(&Block::transaction_hashes(&block.transactions), position)?;
+        Some(MerkleProof {
+            block_index: index,
+            path,
+        })
+    }
+
+    /// Checks that `proof` shows `transaction` to be in the block whose Merkle root is `merkle_root`.
+    pub fn verify_merkle_proof(
+        transaction: &Transaction,
+        proof: &MerkleProof,
+        merkle_root: &str,
+    ) -> bool {
+        merkle::verify_path(&transaction.merkle_leaf(), &proof.path, merkle_root)
+    }
+
+    /// Whether `candidate` should replace this chain, i.e. it shares our genesis block, is valid
+    /// and wins the fork choice of our consensus engine.
+    pub fn prefers(&self, candidate: &Self) -> bool {
+        let same_genesis = match (self.blocks.get(0), candidate.blocks.get(0)) {
+            (Some(ours), Some(theirs)) => ours.get_hash() == theirs.get_hash(),
+            _ => false,
+        };
+        same_genesis
+            && self.consensus.prefers(self, candidate)
+            && Blockchain::valid_under(
+                &*self.consensus,
+                self.assume_valid(),
+                &self.checkpoints,
+                candidate,
+                self.fork_index(candidate),
+            )
+    }
+
+    /// The index of the first block of `other` that this chain does not hold.
+    fn fork_index(&self, other: &Self) -> u64 {
+        (0..other.blocks.len())
+            .find(|&i| {
+                let ours = self.blocks.get(i).map(|block| block.get_hash());
+                ours.is_none() || ours != other.blocks.get(i).map(|block| block.get_hash())
+            })
+            .unwrap_or_else(|| other.blocks.len())
+    }
+
+    /// Validates a given blockchain under its own consensus engine.
+    pub fn valid_chain(chain: &Self) -> bool {
+        Blockchain::valid_under(
+            &*chain.consensus,
+            chain.assume_valid(),
+            &chain.checkpoints,
+            chain,
+            0,
+        )
+    }
+
+    fn valid_under(
+        consensus: &dyn Consensus,
+        assume_valid: Option<&str>,
+        checkpoints: &BTreeMap<u64, String>,
+        chain: &Self,
+        timed_from: u64,
+    ) -> bool {
+        if let Some((index, hash)) = Blockchain::violated_checkpoint(checkpoints, chain) {
+            debug!(target: CONSENSUS, "The chain disagrees with checkpoint {}, {}", index, hash);
+            return false;
+        }
+        Blockchain::replay(consensus, assume_valid, chain, timed_from).is_some()
+    }
+
+    /// The index of the block with `hash` in `chain`, going by the links of the blocks. Replaying
+    /// the chain checks the links, and so that the block is there.
+    fn index_of(chain: &Self, hash: &str) -> Option<u64> {
+        if chain.tip_hash() == hash {
+            return Some(chain.blocks.len() - 1);
+        }
+        (1..chain.blocks.len())
+            .find(|&i| {
+                chain
+                    .blocks
+                    .get(i)
+                    .is_some_and(|block| block.header.previous_hash == hash)
+            })
+            .map(|i| i - 1)
+    }
+
+    /// Validates `chain` under `consensus`, returning the balances it leaves if it is valid. The
+    /// seals of the block with hash `assume_valid` and its ancestors are not checked, nor are the
+    /// timestamps of the blocks before `timed_from`, which were checked when they were new.
+    fn replay(
+        consensus: &dyn Consensus,
+        assume_valid: Option<&str>,
+        chain: &Self,
+        timed_from: u64,
+    ) -> Option<Ledger> {
+        let mut prev_block = chain.blocks.get(0)?;
+        let assumed = assume_valid.and_then(|hash| Blockchain::index_of(chain, hash));
+        if let Some(assumed) = assumed {
+            debug!(target: CONSENSUS, "Assuming the seals of the blocks up to {} valid", assumed);
+        }
+
+        // check the genesis block
+        // it only holds the allocations of its spec, if any
+        if prev_block.header.nonce != 100
+            || !prev_block.transactions.iter().all(|t| {
+                t.get_sender() == REWARD_SENDER
+                    && t.get_amount() > 0
+                    && is_valid_address(t.get_recipient())
+            })
+            || !prev_block.has_valid_ids()
+            || !prev_block.has_supported_version()
+            || prev_block.header.difficulty_target > MAX_DIFFICULTY
+            || !prev_block.has_valid_merkle_root()
+            || prev_block.header.previous_hash != "1"
+            || prev_block.header.chain_id.is_some()
+            || !prev_block
+                .header
+                .reward_schedule
+                .is_none_or(|schedule| schedule.is_sane())
+            || !prev_block
+                .address_prefix()
+                .is_none_or(address::is_address_prefix)
+            || prev_block
+                .check_addresses(prev_block.address_prefix())
+                .is_err()
+        {
+            return None;
+        }
+        let schedule = chain.reward_schedule();
+        let chain_id = chain.block_chain_id();
+        let prefix = prev_block.address_prefix().map(str::to_owned);
+        let now = get_time();
+        let mut ledger = Ledger::new(chain.transaction_model());
+        ledger.record(&prev_block);
+        // the ids of the transactions of the blocks so far, which no later block may hold again
+        let mut confirmed: HashSet<String> = prev_block
+            .transactions
+            .iter()
+            .map(|t| t.id.clone())
+            .collect();
+
+        for i in 1..chain.blocks.len() {
+            let block = chain.blocks.get(i)?;
+            trace!(target: CONSENSUS, "validating chain ...");
+            trace!(
+                target: CONSENSUS,
+                "prev_block: {}",
+                serde_json::to_string(&prev_block).unwrap()
+            );
+            trace!(target: CONSENSUS, "block: {}", serde_json::to_string(&block).unwrap());
+            trace!(target: CONSENSUS, "");
+            let rejection = if prev_block.get_hash() != block.header.previous_hash {
+                Some(BlockRejection::BadParent)
+            } else if block.header.index != i {
+                Some(BlockRejection::BadIndex { expected: i })
+            } else if block.header.chain_id != chain_id {
+                Some(BlockRejection::ForeignChain)
+            } else if let Some(rejection) = chain
+                .check_timestamp(&block, now)
+                .err()
+                .filter(|_| i >= timed_from)
+            {
+                Some(rejection)
+            } else if let Err(rejection) = chain.check_version_after(&block, &prev_block) {
+                Some(rejection)
+            } else if assumed.is_none_or(|assumed| i > assumed) {
+                consensus.check_seal(chain, &block).err()
+            } else {
+                None
+            };
+            let rejection = rejection
+                .or_else(|| block.check().err())
+                .or_else(|| block.check_addresses(prefix.as_deref()).err());
+            let rejection = rejection.or_else(|| {
+                block
+                    .transactions
+                    .iter()
+                    .find(|t| !confirmed.insert(t.id.clone()))
+                    .map(|t| BlockRejection::ConfirmedTransaction { id: t.id.clone() })
+            });
+            let rejection = rejection.or_else(|| {
+                block
+                    .check_spends(&ledger, schedule.reward(i, ledger.minted()))
+                    .err()
+            });
+            if let Some(rejection) = rejection {
+                debug!(target: CONSENSUS, "Block {} is not valid, {}", i, rejection);
+                return None;
+            }
+            ledger.record(&block);
+            prev_block = block;
+        }
+        Some(ledger)
+    }
+}
+
+/// Adjusts `difficulty` given how many milliseconds the last retarget interval took. It is never
+/// lowered below `MIN_DIFFICULTY`.
+pub fn retarget(difficulty: u32, timespan: u128) -> u32 {
+    let target = TARGET_BLOCK_TIME * (RETARGET_INTERVAL - 1) as u128;
+    if timespan < target / 2 {
+        (difficulty + 1).min(MAX_DIFFICULTY)
+    } else if timespan > target * 2 && difficulty > MIN_DIFFICULTY {
+        difficulty - 1
+    } else {
+        difficulty
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Transaction {
+    // the hash of everything else, see `Transaction::content_hash`
+    id: String,
+    sender: String,
+    recipient: String,
+    amount: i64,
+    // paid by the sender on top of the amount, to whoever mines the transaction
+    #[serde(default, skip_serializing_if = "is_zero")]
+    fee: i64,
+    // random, so that paying the same twice makes two transactions
+    #[serde(default, skip_serializing_if = "is_zero")]
+    nonce: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiry: Option<Expiry>,
+    // the id of the pending transaction this one replaces, paying a higher fee
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replaces: Option<String>,
+    // only set on chains using `TransactionModel::Utxo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    utxo: Option<Utxo>,
+    // the spending condition, see `script`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    script: Option<Vec<Op>>,
+    // the token the amount is of, the coin of the chain if none, see `Transaction::issue`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asset_id: Option<String>,
+    // only set on transactions minting tokens of the asset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issuance: Option<Issuance>,
+    // the key of the sender and its signature of the `signature_hash`, see `Transaction::sign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// What the id of a transaction is the hash of, all of it but the id, in this order.
+#[derive(Serialize)]
+struct Content<'a> {
+    sender: &'a str,
+    recipient: &'a str,
+    amount: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    fee: i64,
+    #[serde(skip_serializing_if = "is_zero")]
+    nonce: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiry: Option<Expiry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replaces: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utxo: Option<&'a Utxo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script: Option<&'a [Op]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issuance: Option<Issuance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_key: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<&'a str>,
+}
+
+/// What a transaction minting tokens of its asset commits to. The first issuance of an asset
+/// makes its sender the issuer, and only the issuer may issue more, with the same `max_supply`,
+/// until the tokens issued reach it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Issuance {
+    /// how many tokens of the asset may ever be issued, between 1 and `MAX_AMOUNT`
+    pub max_supply: i64,
+}
+
+/// The last block a transaction may be mined in. After it the transaction expires, miners and
+/// mempools drop it and blocks holding it are rejected.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expiry {
+    /// the block at this index
+    Height(u64),
+    /// the last block whose timestamp is at most this, in milliseconds since the Unix epoch
+    Time(u128),
+}
+
+impl Expiry {
+    /// Whether a block at `index` made at `timestamp` comes too late.
+    pub fn has_passed(&self, index: u64, timestamp: u128) -> bool {
+        match *self {
+            Expiry::Height(height) => index > height,
+            Expiry::Time(time) => timestamp > time,
+        }
+    }
+}
+
+/// Why `Blockchain::add_new_transaction` turned a transaction down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// it is pending or on the chain already
+    Duplicate,
+    /// its id is not the hash of its content
+    InvalidId,
+    InvalidSender(String),
+    InvalidRecipient(String),
+    /// not between 1 and `MAX_AMOUNT`
+    InvalidAmount(i64),
+    /// negative, beyond `MAX_AMOUNT`, or paid by a reward
+    InvalidFee(i64),
+    Expired,
+    /// with the id of the transaction it claims to replace
+    NothingToReplace(String),
+    /// with the id of the transaction spending the coins first
+    DoubleSpend(String),
+    Overspend,
+    MempoolFull,
+    /// with why it does not hold
+    ScriptFailed(String),
+    /// with the asset, not a well-formed id, or moved by a reward or a UTXO transaction
+    InvalidAsset(String),
+    /// with the asset, whose issuer or max supply differ
+    NotIssuer(String),
+    /// with the asset, whose max supply the issuance would exceed
+    OverIssuance(String),
+    /// the sender can only afford it with mining rewards that are not spendable yet
+    ImmatureReward,
+    /// with why a rule of the node turns it down, see `Blockchain::add_validator`
+    Policy(String),
+    /// the sender is a public key, whose signature it lacks, see `Transaction::verify`
+    Unsigned,
+    /// the signature is not of the sender, or not of the transaction
+    BadSignature,
+    /// with the address, which is not a public key under the prefix of the chain
+    ForeignAddress(String),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Duplicate => write!(f, "the transaction already exists"),
+            Rejection::InvalidId => write!(f, "the id does not match the transaction"),
+            Rejection::InvalidSender(sender) => {
+                write!(f, "the sender {:?} is not a valid address", sender)
+            }
+            Rejection::InvalidRecipient(recipient) => {
+                write!(f, "the recipient {:?} is not a valid address", recipient)
+            }
+            Rejection::InvalidAmount(amount) => write!(
+                f,
+                "the amount {} is not between 1 and {}",
+                amount, MAX_AMOUNT
+            ),
+            Rejection::InvalidFee(fee) => write!(f, "the fee {} is not allowed", fee),
+            Rejection::Expired => write!(f, "the transaction has expired"),
+            Rejection::NothingToReplace(id) => write!(
+                f,
+                "no pending transaction {} of the sender pays a lower fee",
+                id
+            ),
+            Rejection::DoubleSpend(first) => {
+                write!(f, "the coins are spent by transaction {} already", first)
+            }
+            Rejection::Overspend => write!(f, "the sender cannot afford the transaction"),
+            Rejection::MempoolFull => {
+                write!(f, "the mempool is full of transactions paying higher fees")
+            }
+            Rejection::ScriptFailed(reason) => write!(f, "the script fails: {}", reason),
+            Rejection::InvalidAsset(asset) => {
+                write!(
+                    f,
+                    "the asset {:?} cannot be moved by the transaction",
+                    asset
+                )
+            }
+            Rejection::NotIssuer(asset) => write!(
+                f,
+                "the asset {} was issued by another sender or with another max supply",
+                asset
+            ),
+            Rejection::ImmatureReward => write!(
+                f,
+                "the sender can only afford the transaction with rewards not spendable yet"
+            ),
+            Rejection::Policy(reason) => write!(f, "the node does not admit it: {}", reason),
+            Rejection::Unsigned => write!(f, "the key of the sender must sign it"),
+            Rejection::BadSignature => {
+                write!(
+                    f,
+                    "the signature does not match the sender and the transaction"
+                )
+            }
+            Rejection::OverIssuance(asset) => {
+                write!(
+                    f,
+                    "the issuance exceeds the max supply of the asset {}",
+                    asset
+                )
+            }
+            Rejection::ForeignAddress(address) => write!(
+                f,
+                "{:?} is no address of this chain, which encodes public keys under its prefix",
+                address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Why a block is turned down, see `Blockchain::validate_block`. Clients submitting blocks get
+/// it back, see `Request::SubmitBlock`, as the name of its variant, which is its code and stays
+/// the same across versions, with the details the variant carries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BlockRejection {
+    /// it does not link to the tip of the chain, or to the block before it
+    BadParent,
+    /// with the index following its parent
+    BadIndex {
+        expected: u64,
+    },
+    /// it was made for another chain
+    ForeignChain,
+    /// with the hash the checkpoint at its index requires
+    CheckpointMismatch {
+        hash: String,
+    },
+    UnsupportedVersion,
+    /// it is made before the median time of the blocks before it, or too far in the future
+    BadTimestamp,
+    /// its JSON takes more than `MAX_BLOCK_SIZE` bytes
+    OversizedBlock,
+    /// with the difficulty the retargeting asks for at its index
+    BadDifficulty {
+        expected: u32,
+    },
+    /// its header hash does not meet its difficulty
+    BadProof,
+    /// its seal is missing, or not signed by the authority whose block it is
+    BadSeal,
+    BadMerkleRoot,
+    /// with the id of the transaction it holds twice
+    DuplicateTransaction {
+        id: String,
+    },
+    /// with the id of the transaction that lacks the signature of its sender or carries a bad one
+    InvalidTxSignature {
+        id: String,
+    },
+    /// with the id of the transaction that is not valid by itself, and why, see `Rejection`
+    InvalidTransaction {
+        id: String,
+        reason: String,
+    },
+    /// with the id of the transaction an earlier block confirmed already
+    ConfirmedTransaction {
+        id: String,
+    },
+    /// with the id of the transaction that expired before the block
+    ExpiredTransaction {
+        id: String,
+    },
+    /// with the id of the transaction spending the coins that `first` spent
+    DoubleSpend {
+        id: String,
+        first: String,
+    },
+    /// with the id of the transaction listed before the `later` one it depends on
+    MisorderedTransaction {
+        id: String,
+        later: String,
+    },
+    /// it does not mint exactly the reward and the fees of its transactions
+    InvalidCoinbase,
+    /// it spends coins that do not exist
+    Overspend,
+    /// it names a miner whose key did not sign it, see `Block::sign_miner`
+    BadMinerSignature,
+    /// with the id of the reward paying another address than the miner
+    RewardNotToMiner {
+        id: String,
+    },
+    /// it does not name its miner, see `SIGNED_BLOCK_VERSION`
+    UnsignedBlock,
+}
+
+impl BlockRejection {
+    /// The name of the variant, see the type docs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BlockRejection::BadParent => "BadParent",
+            BlockRejection::BadIndex { .. } => "BadIndex",
+            BlockRejection::ForeignChain => "ForeignChain",
+            BlockRejection::CheckpointMismatch { .. } => "CheckpointMismatch",
+            BlockRejection::UnsupportedVersion => "UnsupportedVersion",
+            BlockRejection::BadTimestamp => "BadTimestamp",
+            BlockRejection::OversizedBlock => "OversizedBlock",
+            BlockRejection::BadDifficulty { .. } => "BadDifficulty",
+            BlockRejection::BadProof => "BadProof",
+            BlockRejection::BadSeal => "BadSeal",
+            BlockRejection::BadMerkleRoot => "BadMerkleRoot",
+            BlockRejection::DuplicateTransaction { .. } => "DuplicateTransaction",
+            BlockRejection::InvalidTxSignature { .. } => "InvalidTxSignature",
+            BlockRejection::InvalidTransaction { .. } => "InvalidTransaction",
+            BlockRejection::ConfirmedTransaction { .. } => "ConfirmedTransaction",
+            BlockRejection::ExpiredTransaction { .. } => "ExpiredTransaction",
+            BlockRejection::DoubleSpend { .. } => "DoubleSpend",
+            BlockRejection::MisorderedTransaction { .. } => "MisorderedTransaction",
+            BlockRejection::InvalidCoinbase => "InvalidCoinbase",
+            BlockRejection::Overspend => "Overspend",
+            BlockRejection::BadMinerSignature => "BadMinerSignature",
+            BlockRejection::RewardNotToMiner { .. } => "RewardNotToMiner",
+            BlockRejection::UnsignedBlock => "UnsignedBlock",
+        }
+    }
+}
+
+impl fmt::Display for BlockRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match self {
+            BlockRejection::BadParent => write!(f, "it does not link to the previous block"),
+            BlockRejection::BadIndex { expected } => {
+                write!(
+                    f,
+                    "its index is not {}, the one following its parent",
+                    expected
+                )
+            }
+            BlockRejection::ForeignChain => write!(f, "it was made for another chain"),
+            BlockRejection::CheckpointMismatch { hash } => {
+                write!(f, "the checkpoint at its index is {}", hash)
+            }
+            BlockRejection::UnsupportedVersion => {
+                write!(f, "it is of a version that is not supported")
+            }
+            BlockRejection::BadTimestamp => {
+                write!(
+                    f,
+                    "it is made before the blocks before it or too far ahead of our time"
+                )
+            }
+            BlockRejection::OversizedBlock => {
+                write!(f, "it takes more than {} bytes", MAX_BLOCK_SIZE)
+            }
+            BlockRejection::BadDifficulty { expected } => {
+                write!(f, "its difficulty is not {}", expected)
+            }
+            BlockRejection::BadProof => write!(f, "its proof of work is not valid"),
+            BlockRejection::BadSeal => {
+                write!(
+                    f,
+                    "its seal is not signed by the authority whose block it is"
+                )
+            }
+            BlockRejection::BadMerkleRoot => {
+                write!(f, "its Merkle root does not match its transactions")
+            }
+            BlockRejection::DuplicateTransaction { id } => {
+                write!(f, "it holds transaction {} twice", id)
+            }
+            BlockRejection::InvalidTxSignature { id } => {
+                write!(f, "transaction {} is not signed by its sender", id)
+            }
+            BlockRejection::InvalidTransaction { id, reason } => {
+                write!(f, "transaction {} is not valid, {}", id, reason)
+            }
+            BlockRejection::ConfirmedTransaction { id } => {
+                write!(f, "transaction {} is on the chain already", id)
+            }
+            BlockRejection::ExpiredTransaction { id } => {
+                write!(f, "transaction {} expired before the block", id)
+            }
+            BlockRejection::DoubleSpend { id, first } => write!(
+                f,
+                "transaction {} spends the coins of transaction {} again",
+                id, first
+            ),
+            BlockRejection::MisorderedTransaction { id, later } => write!(
+                f,
+                "transaction {} depends on the later transaction {}",
+                id, later
+            ),
+            BlockRejection::InvalidCoinbase => {
+                write!(
+                    f,
+                    "it does not mint exactly its reward and the fees it collects"
+                )
+            }
+            BlockRejection::Overspend => write!(f, "it spends coins that do not exist"),
+            BlockRejection::BadMinerSignature => {
+                write!(f, "it is not signed by the miner it names")
+            }
+            BlockRejection::RewardNotToMiner { id } => {
+                write!(f, "reward {} does not pay the miner of the block", id)
+            }
+            BlockRejection::UnsignedBlock => write!(
+                f,
+                "it is not signed by its miner, as blocks of version {} are once miner signatures lock in",
+                SIGNED_BLOCK_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BlockRejection {}
+
+/// Why a transaction left the mempool other than into a block, see
+/// `Blockchain::take_evictions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Eviction {
+    /// the mempool filled up with transactions paying higher fees
+    Evicted,
+    Expired,
+    /// with the id of the transaction paying a higher fee in its place
+    Replaced(String),
+    /// a block spent its coins first
+    Conflicted,
+    /// its block was rolled back, and the transaction did not fit the new branch
+    RolledBack(Rejection),
+}
+
+impl fmt::Display for Eviction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Eviction::Evicted => write!(f, "evicted from the full mempool"),
+            Eviction::Expired => write!(f, "expired"),
+            Eviction::Replaced(id) => write!(f, "replaced by transaction {}", id),
+            Eviction::Conflicted => write!(f, "its coins were spent by a block"),
+            Eviction::RolledBack(rejection) => {
+                write!(f, "rolled back with its block, {}", rejection)
+            }
+        }
+    }
+}
+
+/// The outputs a transaction spends and those it creates. Every input must be an unspent output
+/// of the sender, and the outputs and the fee must add up to the inputs, except for rewards,
+/// which have no inputs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Utxo {
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<Output>,
+}
+
+/// Refers to the output at `index` of the transaction with id `transaction`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutPoint {
+    pub transaction: String,
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Output {
+    pub recipient: String,
+    pub amount: i64,
+}
+
+impl Transaction {
+    pub fn new(sender: &str, recipient: &str, amount: i64) -> Self {
+        Transaction {
+            id: String::new(),
+            sender: sender.to_owned(),
+            recipient: recipient.to_owned(),
+            amount,
+            fee: 0,
+            nonce: rand::random(),
+            expiry: None,
+            replaces: None,
+            utxo: None,
+            script: None,
+            asset_id: None,
+            issuance: None,
+            public_key: None,
+            signature: None,
+        }
+        .with_content_id()
+    }
+
+    /// The transaction paying `fee` to the miner, which miners fill blocks by, highest first.
+    pub fn with_fee(mut self, fee: i64) -> Self {
+        self.fee = fee;
+        self.with_content_id()
+    }
+
+    /// The transaction expiring after `expiry`.
+    pub fn with_expiry(mut self, expiry: Expiry) -> Self {
+        self.expiry = Some(expiry);
+        self.with_content_id()
+    }
+
+    /// The transaction replacing the pending transaction `id`, see
+    /// `Blockchain::add_new_transaction`.
+    pub fn replacing(mut self, id: &str) -> Self {
+        self.replaces = Some(id.to_owned());
+        self.with_content_id()
+    }
+
+    /// The transaction valid only if `script` holds, see `script`. Signatures in the script are
+    /// taken over the `signature_hash`, which stays the same.
+    pub fn with_script(mut self, script: Vec<Op>) -> Self {
+        self.script = Some(script);
+        self.with_content_id()
+    }
+
+    /// The transaction moving `amount` tokens of the asset `asset_id` instead of coins, see
+    /// `Transaction::issue`. The fee is still paid in coins.
+    pub fn with_asset(mut self, asset_id: &str) -> Self {
+        self.asset_id = Some(asset_id.to_owned());
+        self.with_content_id()
+    }
+
+    /// Creates the transaction by `issuer` minting `amount` new tokens of the asset `asset_id`
+    /// for `recipient`, of which at most `max_supply` may ever be issued, see `Issuance`.
+    pub fn issue(
+        issuer: &str,
+        recipient: &str,
+        asset_id: &str,
+        amount: i64,
+        max_supply: i64,
+    ) -> Self {
+        let mut issue = Transaction::new(issuer, recipient, amount);
+        issue.issuance = Some(Issuance { max_supply });
+        issue.with_asset(asset_id)
+    }
+
+    /// The transaction signed by `key`, the key of its sender, see `Transaction::verify`. Signs
+    /// the `signature_hash`, so it comes last, after the other fields are set.
+    pub fn sign(mut self, key: &NodeKey) -> Self {
+        self.public_key = Some(key.public_key());
+        self.signature = Some(key.sign(self.signature_hash().as_bytes()));
+        self.with_content_id()
+    }
+
+    /// The transaction with `nonce` instead of a random one, e.g. to make the same one again.
+    pub(crate) fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self.with_content_id()
+    }
+
+    fn with_content_id(mut self) -> Self {
+        self.id = self.content_hash();
+        self
+    }
+
+    /// Creates a UTXO transaction by `sender` spending `inputs`. Its recipient and amount are the
+    /// ones of the first of `outputs`, the payment, which the others, e.g. the change, follow.
+    pub fn spend(sender: &str, inputs: Vec<OutPoint>, outputs: Vec<Output>) -> Self {
+        let (recipient, amount) = outputs.first().map_or((String::new(), 0), |payment| {
+            (payment.recipient.clone(), payment.amount)
+        });
+        Transaction {
+            id: String::new(),
+            sender: sender.to_owned(),
+            recipient,
+            amount,
+            fee: 0,
+            nonce: rand::random(),
+            expiry: None,
+            replaces: None,
+            utxo: Some(Utxo { inputs, outputs }),
+            script: None,
+            asset_id: None,
+            issuance: None,
+            public_key: None,
+            signature: None,
+        }
+        .with_content_id()
+    }
+
+    pub fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    pub fn get_sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn get_recipient(&self) -> &str {
+        &self.recipient
+    }
+
+    pub fn get_amount(&self) -> i64 {
+        self.amount
+    }
+
+    pub fn get_fee(&self) -> i64 {
+        self.fee
+    }
+
+    /// How many bytes the JSON of the Transaction takes.
+    pub fn size(&self) -> usize {
+        serde_json::to_vec(self).map_or(usize::MAX, |json| json.len())
+    }
+
+    pub fn get_expiry(&self) -> Option<Expiry> {
+        self.expiry
+    }
+
+    pub fn get_replaced(&self) -> Option<&str> {
+        self.replaces.as_deref()
+    }
+
+    /// Whether the transaction may no longer be mined in a block at `index` made at `timestamp`.
+    pub fn is_expired_at(&self, index: u64, timestamp: u128) -> bool {
+        self.expiry
+            .is_some_and(|expiry| expiry.has_passed(index, timestamp))
+    }
+
+    pub fn get_utxo(&self) -> Option<&Utxo> {
+        self.utxo.as_ref()
+    }
+
+    pub fn get_script(&self) -> Option<&[Op]> {
+        self.script.as_deref()
+    }
+
+    /// The asset the amount is of, `None` for coins.
+    pub fn get_asset_id(&self) -> Option<&str> {
+        self.asset_id.as_deref()
+    }
+
+    pub fn get_issuance(&self) -> Option<Issuance> {
+        self.issuance
+    }
+
+    pub fn get_public_key(&self) -> Option<&str> {
+        self.public_key.as_deref()
+    }
+
+    pub fn get_signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    /// Checks the signature: senders that are public keys, or `Address`es of them, must sign
+    /// with their key, see `Transaction::sign`, and a signature must be of the sender. Other
+    /// senders, such as `alice`, hold no key and spend unsigned.
+    pub fn verify(&self) -> std::result::Result<(), Rejection> {
+        let sender_key = address::key_of(&self.sender);
+        match (&self.public_key, &self.signature) {
+            (None, None) if sender_key.is_some() => Err(Rejection::Unsigned),
+            (None, None) => Ok(()),
+            (Some(public_key), Some(signature))
+                if sender_key.as_ref() == Some(public_key)
+                    && key::verify(public_key, self.signature_hash().as_bytes(), signature) =>
+            {
+                Ok(())
+            }
+            _ => Err(Rejection::BadSignature),
+        }
+    }
+
+    /// The amount followed by the asset, if any, e.g. `5 gold`.
+    pub fn amount_label(&self) -> String {
+        match &self.asset_id {
+            Some(asset) => format!("{} {}", self.amount, asset),
+            None => self.amount.to_string(),
+        }
+    }
+
+    /// Runs the script, if any, failing with why it does not hold.
+    pub fn check_script(&self) -> std::result::Result<(), String> {
+        match &self.script {
+            Some(script) => script::run(script, self.signature_hash().as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// The addresses the transaction touches: its sender, unless it is a reward, its recipient
+    /// and, on UTXO chains, those of its outputs, each once.
+    pub(crate) fn addresses(&self) -> Vec<&str> {
+        let mut addresses = vec![self.get_sender(), self.get_recipient()];
+        if let Some(utxo) = &self.utxo {
+            addresses.extend(utxo.outputs.iter().map(|output| output.recipient.as_str()));
+        }
+        addresses.retain(|address| *address != REWARD_SENDER);
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+    }
+
+    /// Checks that the addresses the transaction touches, see `addresses`, encode public keys
+    /// under `prefix`, if the chain has one, see `Blockchain::address_prefix`.
+    pub fn check_prefix(&self, prefix: Option<&str>) -> std::result::Result<(), Rejection> {
+        let prefix = match prefix {
+            Some(prefix) => prefix,
+            None => return Ok(()),
+        };
+        match self.addresses().into_iter().find(|address| {
+            address
+                .parse::<Address>()
+                .map_or(true, |address| address.prefix() != prefix)
+        }) {
+            Some(address) => Err(Rejection::ForeignAddress(address.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// Hashes a Transaction.
+    pub fn get_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(&serde_json::to_string(self).unwrap());
+        hasher.result_str()
+    }
+
+    /// Hashes everything but the id, which is what the id must be, so that the same transaction
+    /// cannot be passed around under several ids.
+    pub fn content_hash(&self) -> String {
+        self.hash_content(true)
+    }
+
+    /// Hashes everything but the id, the script and the signature, which is what signatures,
+    /// those in the script included, sign.
+    pub fn signature_hash(&self) -> String {
+        self.hash_content(false)
+    }
+
+    // with the script and the signature if `whole`
+    fn hash_content(&self, whole: bool) -> String {
+        let content = Content {
+            sender: &self.sender,
+            recipient: &self.recipient,
+            amount: self.amount,
+            fee: self.fee,
+            nonce: self.nonce,
+            expiry: self.expiry,
+            replaces: self.replaces.as_deref(),
+            utxo: self.utxo.as_ref(),
+            script: self.script.as_deref().filter(|_| whole),
+            asset_id: self.asset_id.as_deref(),
+            issuance: self.issuance,
+            public_key: self.public_key.as_deref().filter(|_| whole),
+            signature: self.signature.as_deref().filter(|_| whole),
+        };
+        let mut hasher = Sha256::new();
+        hasher.input_str(&serde_json::to_string(&content).unwrap());
+        hasher.result_str()
+    }
+
+    /// Checks what can be told from the transaction alone: its id matches its content, the sender
+    /// and the recipient are well-formed addresses, the amount and the fee are in range, it is
+    /// signed as `verify` requires, and its script holds. Rewards come from `REWARD_SENDER` and pay no fee. Only account transactions
+    /// other than rewards move assets, whose ids are well-formed like addresses, and issuances
+    /// name an asset and a max supply in range.
+    pub fn validate(&self) -> std::result::Result<(), Rejection> {
+        if !self.has_valid_id() {
+            return Err(Rejection::InvalidId);
+        }
+        self.validate_fields()
+    }
+
+    /// Like `validate`, but letting the random ids of older versions pass, as blocks may hold
+    /// them.
+    fn validate_in_block(&self) -> std::result::Result<(), Rejection> {
+        if !self.has_valid_id() && !self.has_legacy_id() {
+            return Err(Rejection::InvalidId);
+        }
+        self.validate_fields()
+    }
+
+    fn validate_fields(&self) -> std::result::Result<(), Rejection> {
+        if self.sender != REWARD_SENDER && !is_valid_address(&self.sender) {
+            return Err(Rejection::InvalidSender(self.sender.clone()));
+        }
+        if !is_valid_address(&self.recipient) {
+            return Err(Rejection::InvalidRecipient(self.recipient.clone()));
+        }
+        if !(1..=MAX_AMOUNT).contains(&self.amount) {
+            return Err(Rejection::InvalidAmount(self.amount));
+        }
+        let max_fee = if self.sender == REWARD_SENDER {
+            0
+        } else {
+            MAX_AMOUNT
+        };
+        if !(0..=max_fee).contains(&self.fee) {
+            return Err(Rejection::InvalidFee(self.fee));
+        }
+        if self.asset_id.is_some() || self.issuance.is_some() {
+            let asset = self.asset_id.clone().unwrap_or_default();
+            let max_supply = self.issuance.map_or(1, |issuance| issuance.max_supply);
+            if !is_valid_address(&asset)
+                || self.sender == REWARD_SENDER
+                || self.utxo.is_some()
+                || !(1..=MAX_AMOUNT).contains(&max_supply)
+            {
+                return Err(Rejection::InvalidAsset(asset));
+            }
+        }
+        self.verify()?;
+        self.check_script().map_err(Rejection::ScriptFailed)
+    }
+
+    /// Whether the id is the hash of the content, see `content_hash`.
+    pub fn has_valid_id(&self) -> bool {
+        self.id == self.content_hash()
+    }
+
+    /// Whether the id is a random one, as older versions made, which blocks may still hold.
+    fn has_legacy_id(&self) -> bool {
+        Uuid::parse_str(&self.id).is_ok()
+    }
+
+    /// The leaf of the transaction in the Merkle tree of its block: its id, or the hash of the
+    /// whole transaction if the id is not a content hash, as with older versions.
+    fn merkle_leaf(&self) -> String {
+        if self.has_valid_id() {
+            self.id.clone()
+        } else {
+            self.get_hash()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    //    use env_logger::Env;
+
+    #[test]
+    fn test_pow() {
+        let mut chain = Blockchain::new();
+        chain
+            .add_new_transaction(&Transaction::new("0", "1", 1))
+            .unwrap();
+        let mut block = chain.mine_new_block().clone();
+        assert!(block.has_valid_proof());
+        assert_eq!(Blockchain::proof_of_work(&block), block.header.nonce);
+
+        // the work is bound to the contents of the block
+        block.transactions[0].amount = 100;
+        block.header_mut().merkle_root =
+            merkle::merkle_root(&Block::transaction_hashes(&block.transactions));
+        assert!(!block.has_valid_proof());
+        block.header_mut().nonce = Blockchain::proof_of_work(&block);
+        assert!(block.has_valid_proof());
+        block.header_mut().timestamp += 1;
+        assert!(!block.has_valid_proof());
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        //        env_logger::from_env(Env::default().default_filter_or("debug")).init();
+
+        let mut chain = Blockchain::new();
+        assert!(Blockchain::valid_chain(&chain));
+
+        // play with the genesis block
+        chain.blocks[0]
+            .transactions
+            .push(Transaction::new("good", "evil", 100));
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.blocks[0].transactions.pop();
+        assert!(Blockchain::valid_chain(&chain));
+        chain.blocks[0].header_mut().nonce = 101;
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.blocks[0].header_mut().nonce = 100;
+        assert!(Blockchain::valid_chain(&chain));
+        chain.blocks[0].header_mut().previous_hash = "2".to_owned();
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.blocks[0].header_mut().previous_hash = "1".to_owned();
+        assert!(Blockchain::valid_chain(&chain));
+
+        // perform some normal operations
+        chain
+            .add_new_transaction(&Transaction::new("0", "1", 1))
+            .unwrap();
+        chain
+            .add_new_transaction(&Transaction::new("1", "2", 1))
+            .unwrap();
+        chain
+            .add_new_transaction(&Transaction::new("2", "3", 1))
+            .unwrap();
+        chain.mine_new_block();
+        assert!(Blockchain::valid_chain(&chain));
+        chain.mine_new_block();
+        assert!(Blockchain::valid_chain(&chain));
+
+        // tamper an intermediate block
+        chain.blocks[1]
+            .transactions
+            .push(Transaction::new("good", "evil", 100));
+        assert!(!Blockchain::valid_chain(&chain));
         chain.blocks[1].transactions.pop();
         assert!(Blockchain::valid_chain(&chain));
-        let true_proof = mem::replace(&mut chain.blocks[1].proof, 123);
+        let true_proof = mem::replace(&mut chain.blocks[1].header_mut().nonce, 123);
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.blocks[1].header_mut().nonce = true_proof;
+        assert!(Blockchain::valid_chain(&chain));
+
+        // add a block without running pow
+        chain.create_new_block(456, chain.last_block().get_hash());
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.blocks.pop();
+        assert!(Blockchain::valid_chain(&chain));
+
+        // play with the genesis block again
+        chain.blocks[0]
+            .transactions
+            .push(Transaction::new("good", "evil", 100));
+        assert!(!Blockchain::valid_chain(&chain));
+    }
+
+    #[test]
+    fn test_append() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let block = other.mine_new_block().clone();
+        let next = other.mine_new_block().clone();
+        assert_eq!(other.tip_hash(), next.get_hash());
+
+        // the index must agree with the link to the tip
+        let mut misplaced = block.clone();
+        misplaced.header_mut().index = 5;
+        assert!(!chain.add_new_block(&misplaced));
+        assert!(!chain.is_orphan(&misplaced));
+        // and the link orders blocks, whatever the index claims
+        assert!(!chain.add_new_block(&next));
+        assert!(chain.is_orphan(&next));
+        assert!(chain.add_new_block(&block));
+        assert_eq!(chain.tip_hash(), block.get_hash());
+        assert!(!chain.add_new_block(&block));
+        assert!(!chain.is_orphan(&block));
+
+        // a sibling of the tip forks off no further than the chain is
+        let mut fork = Blockchain::from_blocks(vec![chain.get_blocks()[0].clone()]);
+        fork.add_new_transaction(&Transaction::new(REWARD_SENDER, "mallory", 1))
+            .unwrap();
+        let sibling = fork.mine_new_block().clone();
+        assert!(!chain.add_new_block(&sibling));
+        assert!(!chain.is_orphan(&sibling));
+        assert!(chain.add_new_block(&next));
+        assert_eq!(chain.tip_hash(), other.tip_hash());
+    }
+
+    #[test]
+    fn test_block_rejections() {
+        let mut chain = Blockchain::with_difficulty(4);
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        other.add_new_transaction(&reward).unwrap();
+        let block = other.mine_new_block().clone();
+        assert_eq!(chain.validate_block(&block), Ok(()));
+
+        let mut misplaced = block.clone();
+        misplaced.header_mut().index = 5;
+        assert_eq!(
+            chain.validate_block(&misplaced),
+            Err(BlockRejection::BadIndex { expected: 1 })
+        );
+        let mut unlinked = block.clone();
+        unlinked.header_mut().previous_hash = "1".to_owned();
+        assert_eq!(
+            chain.validate_block(&unlinked),
+            Err(BlockRejection::BadParent)
+        );
+        let mut easier = block.clone();
+        easier.header_mut().difficulty_target = 1;
+        assert_eq!(
+            chain.validate_block(&easier),
+            Err(BlockRejection::BadDifficulty { expected: 4 })
+        );
+        let mut unproven = block.clone();
+        while unproven.has_valid_proof() {
+            unproven.set_nonce(unproven.get_nonce() + 1);
+        }
+        assert_eq!(
+            chain.validate_block(&unproven),
+            Err(BlockRejection::BadProof)
+        );
+        // the header, and so the proof, stays the same
+        let mut padded = block.clone();
+        padded
+            .transactions
+            .push(Transaction::new(REWARD_SENDER, "mallory", 1));
+        assert_eq!(
+            chain.validate_block(&padded),
+            Err(BlockRejection::BadMerkleRoot)
+        );
+
+        // minting more than the reward of 1
+        let mut greedy = Blockchain::from_blocks(chain.get_blocks());
+        greedy.push_pending(Transaction::new(REWARD_SENDER, "mallory", 2));
+        let tip = greedy.tip_hash().to_owned();
+        let mut minting = greedy.create_new_block(0, tip).clone();
+        minting.set_nonce(Blockchain::proof_of_work(&minting));
+        assert_eq!(
+            chain.validate_block(&minting),
+            Err(BlockRejection::InvalidCoinbase)
+        );
+
+        // confirming a transaction again
+        assert_eq!(chain.try_add_block(&block), Ok(()));
+        assert_eq!(chain.try_add_block(&block), Err(BlockRejection::BadParent));
+        other.push_pending(reward.clone());
+        let tip = other.tip_hash().to_owned();
+        let mut again = other.create_new_block(0, tip).clone();
+        again.set_nonce(Blockchain::proof_of_work(&again));
+        assert_eq!(
+            chain.validate_block(&again),
+            Err(BlockRejection::ConfirmedTransaction {
+                id: reward.get_id().to_owned()
+            })
+        );
+
+        // the variant names the failure on the wire
+        let rejection = BlockRejection::BadIndex { expected: 1 };
+        assert_eq!(
+            serde_json::to_string(&rejection).unwrap(),
+            r#"{"BadIndex":{"expected":1}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&BlockRejection::BadProof).unwrap(),
+            r#""BadProof""#
+        );
+        assert_eq!(rejection.code(), "BadIndex");
+    }
+
+    #[test]
+    fn test_block_size() {
+        let chain = Blockchain::with_difficulty(0);
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let count = MAX_BLOCK_SIZE / 100;
+        for i in 0..count {
+            other.push_pending(Transaction::new(REWARD_SENDER, "alice", i as i64 + 1));
+        }
+        let all = other.candidate_block(None, get_time());
+        let mined = other.mine_new_block().clone();
+        assert!(mined.size() <= MAX_BLOCK_SIZE);
+        assert_eq!(all.get_transactions().len(), mined.get_transactions().len());
+        let left = other.get_current_transactions().len();
+        assert!(left > 0);
+        assert_eq!(mined.get_transactions().len() + left, count);
+
+        let mut oversized = mined.clone();
+        oversized
+            .transactions
+            .extend(other.get_current_transactions());
+        assert!(oversized.size() > MAX_BLOCK_SIZE);
+        assert_eq!(
+            chain.validate_block(&oversized),
+            Err(BlockRejection::OversizedBlock)
+        );
+        assert_eq!(
+            chain.validate_block(&mined).err(),
+            Some(BlockRejection::InvalidCoinbase)
+        );
+    }
+
+    #[test]
+    fn test_block_timestamps() {
+        let mut chain = Blockchain::with_difficulty(0);
+        for i in 1..=MEDIAN_TIME_SPAN {
+            chain.mine_new_block_at(i as u128 * TARGET_BLOCK_TIME);
+        }
+        let median = chain.median_time_past(chain.len() as u64);
+        assert_eq!(median, 6 * TARGET_BLOCK_TIME);
+
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let block = other.mine_new_block_at(median).clone();
+        assert_eq!(chain.validate_block(&block), Ok(()));
+        let mut early = block.clone();
+        early.header_mut().timestamp = median - 1;
+        assert_eq!(
+            chain.validate_block(&early),
+            Err(BlockRejection::BadTimestamp)
+        );
+        let mut late = block.clone();
+        late.header_mut().timestamp = get_time() + MAX_FUTURE_DRIFT + TARGET_BLOCK_TIME;
+        assert_eq!(
+            chain.validate_block(&late),
+            Err(BlockRejection::BadTimestamp)
+        );
+        let mut blocks = chain.get_blocks();
+        blocks.push(early);
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(blocks)));
+
+        // a clock running behind makes blocks at the median time
+        let mut behind = Blockchain::from_blocks(chain.get_blocks());
+        assert_eq!(behind.mine_new_block_at(0).get_timestamp(), median);
+        assert!(Blockchain::valid_chain(&behind));
+
+        // blocks past where a chain forks from ours are timed, stored ones are not again
+        let late = get_time() + MAX_FUTURE_DRIFT + TARGET_BLOCK_TIME;
+        let mut ahead = Blockchain::from_blocks(chain.get_blocks());
+        ahead.mine_new_block_at(late);
+        ahead.mine_new_block_at(late);
+        assert!(!chain.prefers(&ahead));
+        assert!(chain.prefers(&behind));
+        let store = Store::memory();
+        let mut stored = Blockchain::open(store.clone(), Some(0)).unwrap();
+        stored.mine_new_block_at(late);
+        assert!(Blockchain::open(store, Some(0)).is_ok());
+    }
+
+    #[test]
+    fn test_find_transaction() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        chain.add_new_transaction(&reward).unwrap();
+        let found = chain.find_transaction(reward.get_id()).unwrap();
+        assert_eq!(found.transaction.get_id(), reward.get_id());
+        assert_eq!((found.height, found.confirmations), (None, 0));
+        assert_eq!(found.to_string(), "pending");
+
+        chain.mine_new_block();
+        chain.mine_new_block();
+        let found = chain.find_transaction(reward.get_id()).unwrap();
+        assert_eq!((found.height, found.confirmations), (Some(1), 2));
+        assert!(chain.find_transaction("unknown").is_none());
+    }
+
+    #[test]
+    fn test_get_history() {
+        let mut chain = Blockchain::with_transaction_model(0, TransactionModel::Utxo);
+        let reward = chain.new_transaction(REWARD_SENDER, "alice", 1, 0).unwrap();
+        chain.add_new_transaction(&reward).unwrap();
+        chain.mine_new_block();
+        let mut rival = Blockchain::from_blocks(chain.get_blocks());
+        let payment = chain.new_transaction("alice", "bob", 1, 0).unwrap();
+        chain.add_new_transaction(&payment).unwrap();
+        chain.mine_new_block();
+        let pending = chain.new_transaction("bob", "carol", 1, 0).unwrap();
+        chain.add_new_transaction(&pending).unwrap();
+
+        let history = |chain: &Blockchain, address| -> Vec<(String, Option<u64>)> {
+            chain
+                .get_history(address)
+                .into_iter()
+                .map(|found| (found.transaction.get_id().to_owned(), found.height))
+                .collect()
+        };
+        let id = |t: &Transaction| t.get_id().to_owned();
+        assert_eq!(
+            history(&chain, "alice"),
+            [(id(&reward), Some(1)), (id(&payment), Some(2))]
+        );
+        assert_eq!(
+            history(&chain, "bob"),
+            [(id(&payment), Some(2)), (id(&pending), None)]
+        );
+        assert_eq!(history(&chain, "carol"), [(id(&pending), None)]);
+        assert!(history(&chain, "dave").is_empty());
+
+        // the blocks rolled back leave the history, their transactions pending again
+        for _ in 0..2 {
+            rival.mine_new_block();
+        }
+        let blocks = rival.get_blocks();
+        assert_eq!(chain.add_side_block(&blocks[2]), None);
+        assert!(chain.contains_side_block(&blocks[2].get_hash()));
+        assert_eq!(chain.add_side_block(&blocks[3]), Some(1));
+        assert_eq!(
+            history(&chain, "alice"),
+            [(id(&reward), Some(1)), (id(&payment), None)]
+        );
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let mut chain = Blockchain::new();
+        let transactions: Vec<_> = (1..6).map(|i| Transaction::new("0", "1", i)).collect();
+        for t in &transactions {
+            chain.add_new_transaction(t).unwrap();
+        }
+        chain.mine_new_block();
+
+        let root = chain.last_block().get_merkle_root().to_owned();
+        for t in &transactions {
+            let proof = chain.merkle_proof(t.get_id()).unwrap();
+            assert_eq!(proof.block_index, 1);
+            assert!(Blockchain::verify_merkle_proof(t, &proof, &root));
+        }
+        let proof = chain.merkle_proof(transactions[0].get_id()).unwrap();
+        assert!(!Blockchain::verify_merkle_proof(
+            &transactions[1],
+            &proof,
+            &root
+        ));
+        let pending = Transaction::new("0", "1", 1);
+        chain.add_new_transaction(&pending).unwrap();
+        assert!(chain.merkle_proof(pending.get_id()).is_none());
+
+        // repeating the last of an odd number of transactions keeps the root, and the hash
+        let mut mutated = chain.last_block().clone();
+        assert_eq!(mutated.transactions.len() % 2, 1);
+        let last = mutated.transactions.last().unwrap().clone();
+        mutated.transactions.push(last.clone());
+        assert_eq!(mutated.get_hash(), chain.tip_hash());
+        assert!(!mutated.has_valid_merkle_root());
+        assert_eq!(
+            mutated.check(),
+            Err(BlockRejection::DuplicateTransaction {
+                id: last.get_id().to_owned()
+            })
+        );
+        assert!(chain.last_block().has_valid_merkle_root());
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let mut chain = Blockchain::new();
+        // proofs are not checked here, so skip the work
+        for _ in 1..2 * RETARGET_INTERVAL {
+            assert_eq!(chain.next_difficulty(), INITIAL_DIFFICULTY);
+            chain.create_new_block(0, chain.last_block().get_hash());
+        }
+        let first = RETARGET_INTERVAL as usize;
+        let last = 2 * RETARGET_INTERVAL as usize - 1;
+        chain.blocks[last].header_mut().timestamp =
+            chain.blocks[first].header.timestamp + TARGET_BLOCK_TIME;
+        assert_eq!(chain.next_difficulty(), INITIAL_DIFFICULTY + 1);
+        chain.blocks[last].header_mut().timestamp =
+            chain.blocks[first].header.timestamp + TARGET_BLOCK_TIME * 100;
+        assert_eq!(chain.next_difficulty(), INITIAL_DIFFICULTY - 1);
+        chain.blocks[last].header_mut().timestamp =
+            chain.blocks[first].header.timestamp + TARGET_BLOCK_TIME * 9;
+        assert_eq!(chain.next_difficulty(), INITIAL_DIFFICULTY);
+
+        // a slow interval does not lower the difficulty past the floor
+        for i in 0..=last {
+            chain.blocks[i].header_mut().difficulty_target = MIN_DIFFICULTY;
+        }
+        chain.blocks[last].header_mut().timestamp =
+            chain.blocks[first].header.timestamp + TARGET_BLOCK_TIME * 100;
+        assert_eq!(chain.next_difficulty(), MIN_DIFFICULTY);
+        // nor does a block claiming less
+        chain.blocks[last].header_mut().difficulty_target = 0;
+        assert_eq!(
+            chain.expected_difficulty(last as u64 + 1),
+            Some(MIN_DIFFICULTY)
+        );
+
+        assert_eq!(retarget(MAX_DIFFICULTY, 0), MAX_DIFFICULTY);
+        assert_eq!(retarget(MIN_DIFFICULTY, u128::MAX), MIN_DIFFICULTY);
+        assert_eq!(retarget(MIN_DIFFICULTY + 1, u128::MAX), MIN_DIFFICULTY);
+        assert_eq!(retarget(0, u128::MAX), 0);
+    }
+
+    #[test]
+    fn test_balance_at() {
+        let mut chain = Blockchain::new();
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 10))
+            .unwrap();
+        chain.create_new_block(0, chain.last_block().get_hash());
+        chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 3))
+            .unwrap();
+        chain.create_new_block(0, chain.last_block().get_hash());
+
+        assert_eq!(chain.get_balance_at("alice", 0), Some(0));
+        assert_eq!(chain.get_balance_at("alice", 1), Some(10));
+        assert_eq!(chain.get_balance_at("alice", 2), Some(7));
+        assert_eq!(chain.get_balance_at("bob", 2), Some(3));
+        assert_eq!(chain.get_balance_at("bob", 3), None);
+    }
+
+    #[test]
+    fn test_overspend() {
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance("alice"), 1);
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 2))
+            .is_err());
+        assert!(chain
+            .add_new_transaction(&Transaction::new("bob", "alice", 1))
+            .is_err());
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 1))
+            .is_ok());
+        assert_eq!(chain.get_balance("bob"), 0);
+        assert_eq!(chain.get_pending_balance("bob"), 1);
+        assert_eq!(chain.get_pending_balance("alice"), 0);
+        chain.take_pending();
+
+        // the same coin spent by a block of another chain first
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let spent = Transaction::new("alice", "carol", 1);
+        assert!(other.add_new_transaction(&spent).is_ok());
+        let block = other.mine_new_block().clone();
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 1))
+            .is_ok());
+        assert!(chain.add_new_block(&block));
+        assert_eq!(chain.mine_new_block().get_transactions().len(), 0);
+        assert_eq!(chain.get_balance("carol"), 1);
+
+        // blocks and chains spending coins their senders do not hold are rejected
+        let mut forged = Blockchain::from_blocks(chain.get_blocks());
+        forged.push_pending(Transaction::new("alice", "mallory", 1));
+        let block = forged
+            .create_new_block(0, chain.last_block().get_hash())
+            .clone();
+        assert!(!Blockchain::valid_chain(&forged));
+        assert!(!chain.add_new_block(&block));
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let mut chain = Blockchain::new();
+        let mut add = |t: Transaction| chain.add_new_transaction(&t);
+        assert_eq!(
+            add(Transaction::new("", "bob", 1)),
+            Err(Rejection::InvalidSender(String::new()))
+        );
+        assert_eq!(
+            add(Transaction::new("alice", "bob smith", 1)),
+            Err(Rejection::InvalidRecipient("bob smith".to_owned()))
+        );
+        assert_eq!(
+            add(Transaction::new("alice", REWARD_SENDER, 1)),
+            Err(Rejection::InvalidRecipient(REWARD_SENDER.to_owned()))
+        );
+        assert_eq!(
+            add(Transaction::new("alice", "bob", 0)),
+            Err(Rejection::InvalidAmount(0))
+        );
+        assert_eq!(
+            add(Transaction::new("alice", "bob", MAX_AMOUNT + 1)),
+            Err(Rejection::InvalidAmount(MAX_AMOUNT + 1))
+        );
+        assert_eq!(
+            add(Transaction::new(REWARD_SENDER, "alice", 1).with_fee(1)),
+            Err(Rejection::InvalidFee(1))
+        );
+        assert_eq!(
+            add(Transaction::new("alice", "bob", 1)),
+            Err(Rejection::Overspend)
+        );
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        assert_eq!(add(reward.clone()), Ok(()));
+        assert_eq!(add(reward), Err(Rejection::Duplicate));
+    }
+
+    #[test]
+    fn test_transaction_id() {
+        let t = Transaction::new("alice", "bob", 1);
+        assert!(t.has_valid_id());
+        assert_eq!(t.get_id(), t.content_hash());
+        // paying the same again is another transaction
+        assert_ne!(t.get_id(), Transaction::new("alice", "bob", 1).get_id());
+        assert!(t.clone().with_fee(1).has_valid_id());
+        assert_ne!(t.clone().with_fee(1).get_id(), t.get_id());
+
+        // the same payload under another id is turned away
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        let mut renamed = t.clone();
+        renamed.id = Uuid::new_v4().to_string();
+        assert!(chain.add_new_transaction(&renamed).is_err());
+        let mut tampered = t.clone();
+        tampered.amount = 2;
+        assert!(chain.add_new_transaction(&tampered).is_err());
+        assert!(chain.add_new_transaction(&t).is_ok());
+        assert!(chain.add_new_transaction(&t).is_err());
+        let block = chain.mine_new_block().clone();
+        assert!(block.has_valid_ids());
+        assert_eq!(block.get_transactions()[1].merkle_leaf(), t.get_id());
+
+        // blocks may still hold the random ids of older versions, but nothing else
+        let mut reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        let mut legacy = Blockchain::with_difficulty(0);
+        reward.id = Uuid::new_v4().to_string();
+        legacy.push_pending(reward.clone());
+        legacy.mine_new_block();
+        assert!(Blockchain::valid_chain(&legacy));
+        let mut forged = Blockchain::with_difficulty(0);
+        reward.id = "f".repeat(64);
+        forged.push_pending(reward);
+        let block = forged.mine_new_block().clone();
+        assert!(!block.has_valid_ids());
+        assert!(!Blockchain::valid_chain(&forged));
+        assert!(!Blockchain::with_difficulty(0).add_new_block(&block));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut chain = Blockchain::with_difficulty(0);
+        for _ in 0..2 {
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+                .unwrap();
+            chain.mine_new_block();
+        }
+        let pay = |expiry| Transaction::new("alice", "bob", 1).with_expiry(expiry);
+        // the next block is at index 3
+        assert!(chain.add_new_transaction(&pay(Expiry::Height(2))).is_err());
+        assert!(chain.add_new_transaction(&pay(Expiry::Time(1))).is_err());
+        let by_height = pay(Expiry::Height(3));
+        let later = get_time() + 60_000;
+        let by_time = pay(Expiry::Time(later));
+        assert!(chain.add_new_transaction(&by_height).is_ok());
+        assert!(chain.add_new_transaction(&by_time).is_ok());
+
+        // the mempool drops transactions once blocks pass them
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let empty = other.mine_new_block().clone();
+        assert!(chain.add_new_block(&empty));
+        assert!(!chain.contains_transaction(by_height.get_id()));
+        assert!(chain.contains_transaction(by_time.get_id()));
+        assert_eq!(
+            chain.mine_new_block_at(later + 1).get_transactions().len(),
+            0
+        );
+
+        // blocks holding expired transactions are rejected
+        let mut forged = Blockchain::from_blocks(other.get_blocks());
+        forged.push_pending(by_height);
+        let block = forged
+            .create_new_block(0, forged.last_block().get_hash())
+            .clone();
+        assert!(block.has_expired_transactions());
+        assert!(!Blockchain::valid_chain(&forged));
+        assert!(!other.add_new_block(&block));
+        other.push_pending(by_time);
+        assert!(!other.mine_new_block().has_expired_transactions());
+        assert!(Blockchain::valid_chain(&other));
+    }
+
+    #[test]
+    fn test_validators() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let is_prime = |n: i64| n > 1 && (2..n).take_while(|d| d * d <= n).all(|d| n % d != 0);
+        chain.add_validator(Arc::new(move |t: &Transaction, _: &Blockchain| {
+            if is_prime(t.get_amount()) {
+                Ok(())
+            } else {
+                Err(format!("{} is not prime", t.get_amount()))
+            }
+        }));
+        // rewards are left alone
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 20))
+            .unwrap();
+        chain.mine_new_block();
+        assert_eq!(
+            chain.add_new_transaction(&Transaction::new("alice", "bob", 4)),
+            Err(Rejection::Policy("4 is not prime".to_owned()))
+        );
+        // on top of the consensus rules
+        assert_eq!(
+            chain.add_new_transaction(&Transaction::new("alice", "bob", 23)),
+            Err(Rejection::Overspend)
+        );
+        chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 5))
+            .unwrap();
+        assert_eq!(chain.get_current_transactions().len(), 1);
+
+        // blocks of other miners are still valid with whatever amounts
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        other
+            .add_new_transaction(&Transaction::new("alice", "bob", 4))
+            .unwrap();
+        assert!(chain.add_new_block(other.mine_new_block()));
+        assert_eq!(chain.get_balance("bob"), 4);
+    }
+
+    #[test]
+    fn test_mempool_capacity() {
+        let schedule = RewardSchedule {
+            initial_reward: 10,
+            ..RewardSchedule::default()
+        };
+        let genesis = Block::get_genesis_with(0, Some(schedule), TransactionModel::Account);
+        let mut chain = Blockchain::from_blocks(vec![genesis]);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 10))
+            .unwrap();
+        chain.mine_new_block();
+        chain.set_mempool_capacity(2);
+
+        let pay = |fee| Transaction::new("alice", "bob", 1).with_fee(fee);
+        let (old, dear, new) = (pay(0), pay(2), pay(0));
+        assert!(chain.add_new_transaction(&old).is_ok());
+        assert!(chain.add_new_transaction(&dear).is_ok());
+        // the oldest of the lowest fees goes first
+        assert!(chain.add_new_transaction(&new).is_ok());
+        assert!(!chain.contains_transaction(old.get_id()));
+        let dearer = pay(1);
+        assert!(chain.add_new_transaction(&dearer).is_ok());
+        // a transaction paying less than all pending ones is dropped instead
+        assert!(chain.add_new_transaction(&pay(0)).is_err());
+        let pending: Vec<String> = chain
+            .get_current_transactions()
+            .iter()
+            .map(|t| t.get_id().to_owned())
+            .collect();
+        assert_eq!(pending, [dear.get_id(), dearer.get_id()]);
+        // rewards are not limited
+        assert!(chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "miner", 1))
+            .is_ok());
+        assert_eq!(chain.get_current_transactions().len(), 3);
+
+        chain.set_mempool_capacity(1);
+        assert!(!chain.contains_transaction(dearer.get_id()));
+        assert!(chain.contains_transaction(dear.get_id()));
+        chain.set_mempool_capacity(0);
+        assert!(chain.add_new_transaction(&pay(5)).is_err());
+        assert_eq!(chain.get_current_transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_fees() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        let genesis = Block::get_genesis_with(0, Some(schedule), TransactionModel::Account);
+        let mut chain = Blockchain::from_blocks(vec![genesis]);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 5))
+            .unwrap();
+        chain.mine_new_block();
+
+        // the sender pays the fee on top of the amount
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 5).with_fee(1))
+            .is_err());
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 1).with_fee(-1))
+            .is_err());
+        let cheap = Transaction::new("alice", "bob", 1);
+        let dear = Transaction::new("alice", "carol", 1).with_fee(2);
+        assert!(chain.add_new_transaction(&cheap).is_ok());
+        assert!(chain.add_new_transaction(&dear).is_ok());
+        assert_eq!(chain.pending_fees(), 2);
+        let prioritized = chain.get_prioritized_transactions(1);
+        assert_eq!(prioritized[0].get_id(), dear.get_id());
+        assert_eq!(prioritized.len(), 1);
+
+        // the miner claims the fees on top of the reward, and the dearer transaction goes first
+        let reward = chain.next_reward() + chain.pending_fees();
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "miner", reward))
+            .unwrap();
+        let block = chain.mine_new_block().clone();
+        let ids: Vec<&str> = block
+            .get_transactions()
+            .iter()
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(ids[1..], [dear.get_id(), cheap.get_id()]);
+        assert_eq!(chain.get_balance("alice"), 1);
+        assert_eq!(chain.get_balance("miner"), 7);
+        // the fees were paid, not minted
+        assert_eq!(chain.get_supply(), 10);
+        let accounting = BlockAccounting {
+            subsidy: 5,
+            fees: 2,
+            miner: Some("miner".to_owned()),
+            transferred: 2,
+        };
+        assert_eq!(chain.get_block_accounting(2), Some(accounting.clone()));
+        assert_eq!(
+            chain.get_block_accounting(0),
+            Some(BlockAccounting::default())
+        );
+        assert_eq!(chain.get_block_accounting(3), None);
+        let store = Store::memory();
+        chain.attach_store(store.clone()).unwrap();
+        assert_eq!(store.get_accounting(2).unwrap(), Some(accounting));
+        assert!(Blockchain::valid_chain(&chain));
+
+        // without fees to collect, a miner claiming more than the reward is rejected
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "miner", 6))
+            .unwrap();
+        chain.mine_new_block();
         assert!(!Blockchain::valid_chain(&chain));
-        chain.blocks[1].proof = true_proof;
+    }
+
+    #[test]
+    fn test_candidate_block() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        let genesis = Block::get_genesis_with(0, Some(schedule), TransactionModel::Account);
+        let mut chain = Blockchain::from_blocks(vec![genesis]);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 5))
+            .unwrap();
+        chain.mine_new_block();
+        let cheap = Transaction::new("alice", "bob", 1);
+        let dear = Transaction::new("alice", "carol", 1).with_fee(1);
+        let soon = get_time() + 60_000;
+        let expired = Transaction::new("alice", "dave", 1)
+            .with_fee(1)
+            .with_expiry(Expiry::Time(soon));
+        chain.add_new_transaction(&cheap).unwrap();
+        chain.add_new_transaction(&dear).unwrap();
+        chain.add_new_transaction(&expired).unwrap();
+
+        // a block made later leaves out the expired transaction, and its fee
+        let now = soon + 1;
+        assert_eq!(chain.pending_fees(), 2);
+        assert_eq!(chain.claimable_reward(now), 5 + 1);
+        let reward = Transaction::new(REWARD_SENDER, "miner", chain.claimable_reward(now));
+        let candidate = chain.candidate_block(Some(reward.clone()), now);
+        let ids: Vec<&str> = candidate
+            .get_transactions()
+            .iter()
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(ids, [reward.get_id(), dear.get_id(), cheap.get_id()]);
+        assert_eq!(candidate.get_index(), 2);
+        assert_eq!(
+            chain.candidate_accounting(&candidate),
+            BlockAccounting {
+                subsidy: 5,
+                fees: 1,
+                miner: Some("miner".to_owned()),
+                transferred: 2,
+            }
+        );
+        // nothing changed, and mining makes the same block
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain.get_current_transactions().len(), 3);
+        chain.add_new_transaction(&reward).unwrap();
+        let mined = chain.mine_new_block_at(now).clone();
+        assert_eq!(mined.header.merkle_root, candidate.header.merkle_root);
+        assert_eq!(mined.get_difficulty(), candidate.get_difficulty());
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        for &model in &[TransactionModel::Account, TransactionModel::Utxo] {
+            let genesis = Block::get_genesis_with(0, Some(schedule), model);
+            let mut chain = Blockchain::from_blocks(vec![genesis]);
+            let reward = chain.new_transaction(REWARD_SENDER, "alice", 5, 0).unwrap();
+            chain.add_new_transaction(&reward).unwrap();
+            chain.mine_new_block();
+
+            let original = chain.new_transaction("alice", "bob", 4, 0).unwrap();
+            assert!(chain.add_new_transaction(&original).is_ok());
+            // the replacement must pay more, and its sender must afford it
+            let same = chain.new_replacement(original.get_id(), 0).unwrap();
+            assert!(chain.add_new_transaction(&same).is_err());
+            let dear = chain.new_replacement(original.get_id(), 2);
+            assert!(dear.is_none_or(|dear| chain.add_new_transaction(&dear).is_err()));
+            assert!(chain.new_replacement("unknown", 1).is_none());
+            let replacement = chain.new_replacement(original.get_id(), 1).unwrap();
+            assert_eq!(replacement.get_replaced(), Some(original.get_id()));
+            assert!(chain.add_new_transaction(&replacement).is_ok());
+            assert!(!chain.contains_transaction(original.get_id()));
+            assert_eq!(chain.pending_fees(), 1);
+            // the original cannot come back, nor be replaced once gone
+            assert!(chain.add_new_transaction(&original).is_err());
+            assert!(chain
+                .add_new_transaction(&same.clone().with_fee(2))
+                .is_err());
+
+            let block = chain.mine_new_block().clone();
+            assert_eq!(block.get_transactions()[0].get_id(), replacement.get_id());
+            assert_eq!(chain.get_balance("bob"), 4);
+            assert!(Blockchain::valid_chain(&chain));
+        }
+
+        // nobody but the sender replaces a transaction
+        let mut chain = Blockchain::with_difficulty(0);
+        for address in &["alice", "mallory"] {
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, address, 1))
+                .unwrap();
+            chain.mine_new_block();
+        }
+        let original = Transaction::new("alice", "bob", 1);
+        assert!(chain.add_new_transaction(&original).is_ok());
+        let hijack = Transaction::new("mallory", "carol", 1)
+            .with_fee(0)
+            .replacing(original.get_id());
+        assert!(chain.add_new_transaction(&hijack).is_err());
+        assert!(chain.contains_transaction(original.get_id()));
+    }
+
+    #[test]
+    fn test_pending_ids() {
+        let pending_ids = |chain: &Blockchain| {
+            let ids: HashSet<String> = chain
+                .get_current_transactions()
+                .iter()
+                .map(|t| t.get_id().to_owned())
+                .collect();
+            assert_eq!(ids, chain.pending_ids);
+            ids.len()
+        };
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 9))
+            .unwrap();
+        assert_eq!(pending_ids(&chain), 1);
+        chain.mine_new_block();
+        assert_eq!(pending_ids(&chain), 0);
+
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let spent = Transaction::new("alice", "bob", 1);
+        other.add_new_transaction(&spent).unwrap();
+        let original = Transaction::new("alice", "carol", 1);
+        chain.add_new_transaction(&spent).unwrap();
+        chain.add_new_transaction(&original).unwrap();
+        let replacement = chain.new_replacement(original.get_id(), 1).unwrap();
+        chain.add_new_transaction(&replacement).unwrap();
+        assert!(!chain.contains_transaction(original.get_id()));
+        assert_eq!(pending_ids(&chain), 2);
+        // a block taking one of them leaves the other
+        assert!(chain.add_new_block(other.mine_new_block()));
+        assert!(chain.contains_transaction(spent.get_id()));
+        assert!(chain.get_pending_transaction(spent.get_id()).is_none());
+        assert_eq!(pending_ids(&chain), 1);
+        chain.set_mempool_capacity(0);
+        assert_eq!(pending_ids(&chain), 0);
+    }
+
+    #[test]
+    fn test_evictions() {
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 9))
+            .unwrap();
+        chain.mine_new_block();
+        chain.set_mempool_capacity(2);
+        let cheap = Transaction::new("alice", "bob", 1);
+        let original = Transaction::new("alice", "carol", 1).with_fee(1);
+        let soon = Transaction::new("alice", "dave", 1)
+            .with_fee(1)
+            .with_expiry(Expiry::Height(2));
+        chain.add_new_transaction(&cheap).unwrap();
+        chain.add_new_transaction(&original).unwrap();
+        chain.add_new_transaction(&soon).unwrap();
+        let replacement = chain.new_replacement(original.get_id(), 2).unwrap();
+        chain.add_new_transaction(&replacement).unwrap();
+        // nothing else is pending, so this block leaves `soon` behind
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        assert!(chain.add_new_block(other.mine_new_block()));
+
+        let evictions: Vec<(String, Eviction)> = chain
+            .take_evictions()
+            .into_iter()
+            .map(|(t, eviction)| (t.id, eviction))
+            .collect();
+        assert_eq!(
+            evictions,
+            [
+                (cheap.id, Eviction::Evicted),
+                (original.id, Eviction::Replaced(replacement.id)),
+                (soon.id, Eviction::Expired),
+            ]
+        );
+        assert!(chain.take_evictions().is_empty());
+    }
+
+    #[test]
+    fn test_reorganize() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let mut rival = Blockchain::from_blocks(chain.get_blocks());
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        chain.mine_new_block();
+        let payment = Transaction::new("alice", "bob", 1);
+        chain.add_new_transaction(&payment).unwrap();
+        chain.mine_new_block();
+        let mut ours = Blockchain::from_blocks(chain.get_blocks());
+        rival
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        for _ in 0..3 {
+            rival.mine_new_block();
+        }
+        let theirs = rival.get_blocks();
+
+        // a branch no longer than ours is only held
+        assert_eq!(chain.add_side_block(&theirs[1]), None);
+        assert_eq!(chain.add_side_block(&theirs[2]), None);
+        assert!(chain.contains_side_block(&theirs[2].get_hash()));
+        assert!(!chain.is_orphan(&theirs[3]));
+        assert_eq!(chain.tip_hash(), ours.tip_hash());
+
+        // a longer one takes over, and the payment goes back to the pending transactions
+        assert_eq!(chain.add_side_block(&theirs[3]), Some(2));
+        assert_eq!(chain.tip_hash(), rival.tip_hash());
+        let found = chain.get_block_by_hash(rival.tip_hash()).unwrap();
+        assert_eq!(found.get_index(), 3);
+        assert_eq!(
+            chain.get_block(&BlockId::Index(3)).unwrap().get_hash(),
+            rival.tip_hash()
+        );
+        // the blocks rolled back are not found by their hashes anymore
+        assert!(chain.get_block_by_hash(ours.tip_hash()).is_none());
+        assert!(chain.get_block(&"nope".parse().unwrap()).is_none());
         assert!(Blockchain::valid_chain(&chain));
+        assert_eq!(chain.ledger.balance("bob"), 0);
+        let pending = chain.get_current_transactions();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].get_id(), payment.get_id());
 
-        // add a block without running pow
-        chain.create_new_block(456, chain.last_block().get_hash());
+        // until our old branch overtakes it again
+        for _ in 0..2 {
+            ours.mine_new_block();
+        }
+        let blocks = ours.get_blocks();
+        assert_eq!(chain.add_side_block(&blocks[3]), None);
+        assert_eq!(chain.add_side_block(&blocks[4]), Some(3));
+        assert_eq!(chain.tip_hash(), ours.tip_hash());
+        assert_eq!(chain.ledger.balance("bob"), 1);
+        assert!(chain.get_current_transactions().is_empty());
+        assert!(chain.contains_transaction(payment.get_id()));
+    }
+
+    #[test]
+    fn test_double_spend() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        for &model in &[TransactionModel::Account, TransactionModel::Utxo] {
+            let genesis = Block::get_genesis_with(0, Some(schedule), model);
+            let mut chain = Blockchain::from_blocks(vec![genesis]);
+            let reward = chain.new_transaction(REWARD_SENDER, "alice", 5, 0).unwrap();
+            chain.add_new_transaction(&reward).unwrap();
+            chain.mine_new_block();
+
+            let first = chain.new_transaction("alice", "bob", 4, 0).unwrap();
+            let again = match first.get_utxo() {
+                Some(utxo) => Transaction::spend(
+                    "alice",
+                    utxo.inputs.clone(),
+                    vec![Output {
+                        recipient: "carol".to_owned(),
+                        amount: 5,
+                    }],
+                ),
+                None => Transaction::new("alice", "carol", 4),
+            };
+            let mut other = Blockchain::from_blocks(chain.get_blocks());
+            assert!(chain.add_new_transaction(&first).is_ok());
+            assert!(chain.add_new_transaction(&again).is_err());
+            assert_eq!(
+                chain.ledger.double_spend(&[first.clone(), again.clone()]),
+                Some((again.get_id().to_owned(), first.get_id().to_owned()))
+            );
+            assert_eq!(
+                chain.ledger.double_spend(std::slice::from_ref(&first)),
+                None
+            );
+
+            // a block spending the same coins drops the pending double spend
+            assert!(other.add_new_transaction(&again).is_ok());
+            let block = chain.mine_new_block().clone();
+            assert!(other.add_new_block(&block));
+            assert!(other.get_current_transactions().is_empty());
+
+            // and a block spending them again is rejected
+            let mut forged = Blockchain::from_blocks(chain.get_blocks());
+            forged.push_pending(again.clone());
+            let tip = forged.last_block().get_hash();
+            let block = forged.create_new_block(0, tip).clone();
+            let rejection = BlockRejection::DoubleSpend {
+                id: again.get_id().to_owned(),
+                first: first.get_id().to_owned(),
+            };
+            assert_eq!(
+                block.verify_after(chain.last_block(), &chain.ledger, chain.next_reward()),
+                Err(rejection.clone())
+            );
+            assert_eq!(chain.try_add_block(&block), Err(rejection));
+            assert!(!Blockchain::valid_chain(&forged));
+        }
+    }
+
+    #[test]
+    fn test_ordering() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        for &model in &[TransactionModel::Account, TransactionModel::Utxo] {
+            let genesis = Block::get_genesis_with(0, Some(schedule), model);
+            let mut chain = Blockchain::from_blocks(vec![genesis]);
+            let reward = chain.new_transaction(REWARD_SENDER, "alice", 5, 0).unwrap();
+            chain.add_new_transaction(&reward).unwrap();
+            chain.mine_new_block();
+
+            // bob pays carol out of what alice pays him, for a higher fee
+            let paid = chain.new_transaction("alice", "bob", 4, 0).unwrap();
+            chain.add_new_transaction(&paid).unwrap();
+            let passed = chain.new_transaction("bob", "carol", 3, 1).unwrap();
+            chain.add_new_transaction(&passed).unwrap();
+            let mut forged = Blockchain::from_blocks(chain.get_blocks());
+            let mut other = Blockchain::from_blocks(chain.get_blocks());
+
+            // listed the other way around, bob would spend coins he does not hold yet
+            forged.push_pending(passed.clone());
+            forged.push_pending(paid.clone());
+            let tip = forged.last_block().get_hash();
+            let block = forged.create_new_block(0, tip).clone();
+            assert_eq!(
+                chain.ledger.misordered(&block),
+                Some((passed.get_id().to_owned(), paid.get_id().to_owned()))
+            );
+            assert_eq!(
+                block.verify_after(chain.last_block(), &chain.ledger, chain.next_reward()),
+                Err(BlockRejection::MisorderedTransaction {
+                    id: passed.get_id().to_owned(),
+                    later: paid.get_id().to_owned(),
+                })
+            );
+            assert!(!other.add_new_block(&block));
+
+            let block = chain.mine_new_block().clone();
+            let ids: Vec<&str> = block
+                .get_transactions()
+                .iter()
+                .map(|t| t.get_id())
+                .collect();
+            assert_eq!(ids, [paid.get_id(), passed.get_id()]);
+            assert_eq!(other.ledger.misordered(&block), None);
+            assert!(other.add_new_block(&block));
+            assert_eq!(other.ledger.balance("carol"), 3);
+        }
+    }
+
+    #[test]
+    fn test_signatures() {
+        let key = NodeKey::generate();
+        let owner = key.public_key();
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, &owner, 2))
+            .unwrap();
+        chain.mine_new_block();
+
+        let payment = Transaction::new(&owner, "bob", 1);
+        assert_eq!(payment.verify(), Err(Rejection::Unsigned));
+        assert_eq!(
+            chain.add_new_transaction(&payment),
+            Err(Rejection::Unsigned)
+        );
+        let thief = NodeKey::generate();
+        for forged in [
+            payment.clone().sign(&thief),
+            // changed once signed
+            payment.clone().sign(&key).with_fee(1),
+            // senders without a key have nothing to sign with
+            Transaction::new("alice", "bob", 1).sign(&key),
+        ] {
+            assert_eq!(forged.verify(), Err(Rejection::BadSignature));
+        }
+
+        let signed = payment.sign(&key);
+        assert_eq!(signed.get_public_key(), Some(owner.as_str()));
+        assert!(signed.has_valid_id() && signed.verify().is_ok());
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        chain.add_new_transaction(&signed).unwrap();
+        chain.mine_new_block();
+        assert!(other.add_new_block(chain.last_block()));
+        assert_eq!(other.get_balance("bob"), 1);
+
+        // nor do blocks carry an unsigned spend, which the owner could afford
+        let mut block = other.mine_new_block().clone();
+        block.transactions.push(Transaction::new(&owner, "bob", 1));
+        block.header_mut().merkle_root =
+            merkle::merkle_root(&Block::transaction_hashes(&block.transactions));
+        assert!(!chain.add_new_block(&block));
+    }
+
+    #[test]
+    fn test_script() {
+        let key = crate::NodeKey::generate();
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 5))
+            .unwrap();
+        chain.mine_new_block();
+
+        // signed by a key, the signature going into the script it signs
+        let payment = Transaction::new("alice", "bob", 1);
+        let signature = key.sign(payment.signature_hash().as_bytes());
+        let script = |public_key: String| {
+            vec![
+                Op::Push(signature.clone()),
+                Op::Push(public_key),
+                Op::CheckSig,
+            ]
+        };
+        let signed = payment.clone().with_script(script(key.public_key()));
+        assert_eq!(signed.signature_hash(), payment.signature_hash());
+        assert_ne!(signed.get_id(), payment.get_id());
+        assert!(signed.has_valid_id());
+        let forged = payment.with_script(script(crate::NodeKey::generate().public_key()));
+        assert!(matches!(
+            chain.add_new_transaction(&forged),
+            Err(Rejection::ScriptFailed(_))
+        ));
+
+        // blocks holding a failing script are rejected
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let mut sneaky = Blockchain::from_blocks(chain.get_blocks());
+        sneaky.push_pending(forged);
+        let tip = sneaky.last_block().get_hash();
+        let block = sneaky.create_new_block(0, tip).clone();
+        assert!(!block.has_valid_scripts());
+        assert!(matches!(
+            block.verify_after(chain.last_block(), &chain.ledger, chain.next_reward()),
+            Err(BlockRejection::InvalidTransaction { .. })
+        ));
+        assert!(!other.add_new_block(&block));
+
+        chain.add_new_transaction(&signed).unwrap();
+        let block = chain.mine_new_block().clone();
+        assert!(other.add_new_block(&block));
+        assert_eq!(other.get_balance("bob"), 1);
+    }
+
+    #[test]
+    fn test_utxo() {
+        let schedule = RewardSchedule {
+            initial_reward: 5,
+            ..RewardSchedule::default()
+        };
+        let genesis = Block::get_genesis_with(0, Some(schedule), TransactionModel::Utxo);
+        let mut chain = Blockchain::from_blocks(vec![genesis]);
+        assert_ne!(
+            chain.last_block().get_hash(),
+            Block::get_genesis_with(0, Some(schedule), TransactionModel::Account).get_hash()
+        );
+        let reward = chain.new_transaction(REWARD_SENDER, "alice", 5, 0).unwrap();
+        assert!(chain.add_new_transaction(&reward).is_ok());
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance("alice"), 5);
+        // bare transactions have no place on a UTXO chain
+        assert!(chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 1))
+            .is_err());
+
+        // the reward is spent as a whole, sending the change back
+        let pay = chain.new_transaction("alice", "bob", 3, 0).unwrap();
+        let utxo = pay.get_utxo().unwrap().clone();
+        assert_eq!(utxo.inputs.len(), 1);
+        assert_eq!(utxo.outputs[1].amount, 2);
+        assert!(chain.add_new_transaction(&pay).is_ok());
+        assert!(chain.new_transaction("alice", "carol", 3, 0).is_none());
+        let change = chain.new_transaction("alice", "carol", 2, 0).unwrap();
+        assert!(chain.add_new_transaction(&change).is_ok());
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance("alice"), 0);
+        assert_eq!(chain.get_balance_at("bob", 2), Some(3));
+        assert_eq!(chain.get_balance_at("carol", 1), Some(0));
+        assert!(Blockchain::valid_chain(&chain));
+
+        let paid = OutPoint {
+            transaction: pay.get_id().to_owned(),
+            index: 0,
+        };
+        let to = |recipient: &str, amount| Output {
+            recipient: recipient.to_owned(),
+            amount,
+        };
+        let rejected = [
+            // spent already
+            Transaction::spend("alice", utxo.inputs.clone(), vec![to("dave", 5)]),
+            // twice at once
+            Transaction::spend("bob", vec![paid.clone(), paid.clone()], vec![to("dave", 6)]),
+            // not adding up
+            Transaction::spend("bob", vec![paid.clone()], vec![to("dave", 4)]),
+            // someone else's
+            Transaction::spend("carol", vec![paid.clone()], vec![to("carol", 3)]),
+            // a reward spending coins
+            Transaction::spend(REWARD_SENDER, vec![paid.clone()], vec![to("dave", 3)]),
+        ];
+        for t in &rejected {
+            assert!(chain.add_new_transaction(t).is_err(), "{:?}", t);
+        }
+        assert!(chain
+            .add_new_transaction(&Transaction::spend(
+                "bob",
+                vec![paid],
+                vec![to("dave", 1), to("erin", 2)]
+            ))
+            .is_ok());
+
+        // a stored chain keeps its model
+        let store = Store::memory();
+        Blockchain::open(store.clone(), Some(0)).unwrap();
+        let error = Blockchain::open_with(
+            store,
+            None,
+            None,
+            Some(TransactionModel::Utxo),
+            Box::new(ProofOfWork),
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("Account transaction model"));
+    }
+
+    #[test]
+    fn test_valid_address() {
+        assert!(is_valid_address("9f3c5e1a-59a4-4bb4-b6c1-2a1b1c0e7d3f"));
+        assert!(is_valid_address(&"ab".repeat(32)));
+        assert!(is_valid_address("alice"));
+        assert!(!is_valid_address(""));
+        assert!(!is_valid_address(REWARD_SENDER));
+        assert!(!is_valid_address("alice bob"));
+        assert!(!is_valid_address(&"a".repeat(65)));
+        // encoded addresses must carry their checksum
+        let address = Address::new("nb", &NodeKey::generate().public_key())
+            .unwrap()
+            .to_string();
+        assert!(is_valid_address(&address));
+        let typo = format!(
+            "{}{}",
+            &address[..60],
+            if address.ends_with('q') { 'p' } else { 'q' }
+        );
+        assert!(!is_valid_address(&typo));
+    }
+
+    #[test]
+    fn test_reward_schedule() {
+        let schedule = RewardSchedule {
+            initial_reward: 4,
+            halving_interval: 2,
+            max_supply: Some(11),
+            coinbase_maturity: 0,
+        };
+        let mut minted = 0;
+        let rewards: Vec<i64> = (0..8)
+            .map(|index| {
+                let reward = schedule.reward(index, minted);
+                minted += reward;
+                reward
+            })
+            .collect();
+        assert_eq!(rewards, vec![0, 4, 4, 2, 1, 0, 0, 0]);
+        assert_eq!(RewardSchedule::default().reward(1_000_000, 1_000_000), 1);
+
+        let mut chain = Blockchain::with_reward_schedule(0, schedule);
+        assert_eq!(chain.next_reward(), 4);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "miner", 4))
+            .unwrap();
+        chain.mine_new_block();
+        assert_eq!(chain.get_supply(), 4);
+        assert!(Blockchain::valid_chain(&chain));
+
+        // blocks minting more than the schedule allows are rejected
+        let mut greedy = Blockchain::from_blocks(chain.get_blocks());
+        greedy
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "miner", 5))
+            .unwrap();
+        let block = greedy.mine_new_block().clone();
+        assert!(!Blockchain::valid_chain(&greedy));
+        assert!(!chain.add_new_block(&block));
+        greedy.blocks.pop();
+        greedy.push_pending(Transaction::new(REWARD_SENDER, "miner", -1));
+        let block = greedy
+            .create_new_block(0, chain.last_block().get_hash())
+            .clone();
+        assert!(!chain.add_new_block(&block));
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let schedule = RewardSchedule {
+            initial_reward: 10,
+            ..RewardSchedule::default()
+        };
+        let mut chain = Blockchain::with_reward_schedule(0, schedule);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 10))
+            .unwrap();
+        chain.mine_new_block();
+        let payment = chain.new_transaction("alice", "bob", 1, 2).unwrap();
+        chain.add_new_transaction(&payment).unwrap();
+        assert_eq!(chain.claimable_reward(get_time()), 12);
+
+        let mined_with = |reward: Option<i64>| {
+            let mut other = Blockchain::from_blocks(chain.get_blocks());
+            if let Some(reward) = reward {
+                other.push_pending(Transaction::new(REWARD_SENDER, "miner", reward));
+            }
+            other.push_pending(payment.clone());
+            other.mine_new_block().clone()
+        };
+        // the subsidy and the fees, or nothing
+        assert_eq!(chain.validate_block(&mined_with(Some(12))), Ok(()));
+        assert_eq!(chain.validate_block(&mined_with(None)), Ok(()));
+        // leaving out the fees, or claiming more
+        for reward in [10, 13] {
+            assert_eq!(
+                chain.validate_block(&mined_with(Some(reward))),
+                Err(BlockRejection::InvalidCoinbase)
+            );
+        }
+        let mut blocks = chain.get_blocks();
+        blocks.push(mined_with(Some(10)));
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(blocks)));
+    }
+
+    #[test]
+    fn test_initial_difficulty() {
+        let store = Store::memory();
+        let mut chain = Blockchain::open(store.clone(), Some(4)).unwrap();
+        assert_eq!(chain.next_difficulty(), 4);
+        chain.mine_new_block();
+        assert!(Blockchain::valid_chain(&chain));
+        assert!(Blockchain::open(store.clone(), Some(8)).is_err());
+        assert_eq!(Blockchain::open(store, None).unwrap().len(), 2);
+
+        // chains with another genesis block never win
+        assert!(!Blockchain::new().prefers(&chain));
+        assert!(Blockchain::with_difficulty(4).prefers(&chain));
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let mut rival = Blockchain::from_blocks(chain.get_blocks());
+        chain.mine_new_block();
+        let checkpoint = chain.tip_hash().to_owned();
+        let next = chain.mine_new_block().clone();
+        rival
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        for _ in 0..3 {
+            rival.mine_new_block();
+        }
+
+        let mut ours = Blockchain::from_blocks(vec![chain.get_blocks()[0].clone()]);
+        let checkpoints: BTreeMap<u64, String> = vec![(1, checkpoint)].into_iter().collect();
+        assert!(rival.set_checkpoints(checkpoints.clone()).is_err());
+        assert!(rival.checkpoints().is_empty());
+        ours.set_checkpoints(checkpoints).unwrap();
+        // the longer rival rewrote the checkpointed block
+        assert!(!ours.prefers(&rival));
+        assert!(ours.prefers(&chain));
+        assert!(!Blockchain::valid_chain(&{
+            let mut rival = Blockchain::from_blocks(rival.get_blocks());
+            rival.checkpoints = ours.checkpoints().clone();
+            rival
+        }));
+        // and blocks at the checkpoint must be the one
+        let mut ours = Blockchain::from_blocks(vec![chain.get_blocks()[0].clone()]);
+        ours.set_checkpoints(vec![(1, next.get_hash())].into_iter().collect())
+            .unwrap();
+        assert!(!ours.add_new_block(&chain.get_blocks()[1]));
+        assert!(!ours.add_new_block(&rival.get_blocks()[1]));
+    }
+
+    #[test]
+    fn test_cached_hash() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let mut block = chain.mine_new_block().clone();
+        let hash = block.get_hash();
+        assert_eq!(
+            block.header.cached_hash.get(),
+            Some(&block.header.compute_hash())
+        );
+        // changing the header takes the hash anew, and so does sending the block
+        block.header_mut().nonce += 1;
+        assert_ne!(block.get_hash(), hash);
+        block.set_nonce(block.get_nonce() - 1);
+        assert_eq!(block.get_hash(), hash);
+        let sent: Block = serde_json::from_str(&serde_json::to_string(&block).unwrap()).unwrap();
+        assert!(sent.header.cached_hash.get().is_none());
+        assert_eq!(sent.get_hash(), hash);
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let json_hash = |header: &BlockHeader| {
+            let mut hasher = Sha256::new();
+            hasher.input_str(&serde_json::to_string(header).unwrap());
+            hasher.result_str()
+        };
+        let mut chain = Blockchain::with_difficulty(4);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        let block = chain.mine_new_block().clone();
+        assert_eq!(block.header.encoding, ENCODING_VERSION);
+        assert_ne!(block.get_hash(), json_hash(&block.header));
+        assert!(Blockchain::valid_chain(&chain));
+
+        // genesis blocks keep their hashes, and so chains their ids
+        let genesis = chain.get_block_by_index(0).unwrap();
+        assert_eq!(genesis.get_hash(), json_hash(&genesis.header));
+
+        // no nonce makes up for an encoding this build does not know
+        let mut future = block;
+        future.header_mut().encoding += 1;
+        future.header_mut().difficulty_target = 0;
+        assert!(!future.has_valid_proof());
+    }
+
+    #[test]
+    fn test_block_versions() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let block = chain.mine_new_block().clone();
+        // until miner signatures lock in, see `test_miner_signature`
+        assert_eq!(block.get_header().get_version(), BLOCK_VERSION);
+        assert!(block.has_supported_version());
+
+        // blocks of rules this build does not know are rejected, however they link
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        let mut future = other.mine_new_block().clone();
+        future.header_mut().version = SIGNED_BLOCK_VERSION + 1;
+        assert!(!chain.add_new_block(&future));
+        let mut forged = Blockchain::from_blocks(chain.get_blocks());
+        forged.push(future);
+        assert!(!Blockchain::valid_chain(&forged));
+        // nor may a header claim what its encoding does not commit to
+        let mut unhashed = block.clone();
+        unhashed.header_mut().encoding = 1;
+        unhashed.header_mut().signals = 1;
+        assert!(!unhashed.has_supported_version());
+        unhashed.header_mut().version = 0;
+        unhashed.header_mut().signals = 0;
+        assert!(unhashed.has_supported_version());
+
+        // readiness is counted over the last blocks
+        assert!(chain.set_signals(&[32]).is_err());
+        chain.set_signals(&[3, 5]).unwrap();
+        assert_eq!(chain.signals(), [3, 5]);
+        chain.mine_new_block();
+        chain.set_signals(&[5]).unwrap();
+        chain.mine_new_block();
+        assert!(chain.last_block().get_header().signals(5));
+        assert!(!chain.last_block().get_header().signals(3));
+        assert!(Blockchain::valid_chain(&chain));
+        let readiness = |bit, blocks, window| Readiness {
+            bit,
+            blocks,
+            window,
+        };
+        assert_eq!(chain.signalling(), [readiness(3, 1, 3), readiness(5, 2, 3)]);
+        // until the blocks signalling it leave the window
+        for _ in 0..SIGNAL_WINDOW {
+            chain.mine_new_block();
+        }
+        let signalling = chain.signalling();
+        assert_eq!(signalling, [readiness(5, SIGNAL_WINDOW, SIGNAL_WINDOW)]);
+        assert!(signalling[0].is_locked_in());
+        assert!(!readiness(5, SIGNAL_THRESHOLD - 1, SIGNAL_WINDOW).is_locked_in());
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut chain = Blockchain::with_difficulty(0);
+        chain.mine_new_block();
+        chain.mine_new_block();
+        let headers = chain.get_headers(0, 10);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[2].get_hash(), chain.tip_hash());
+        assert_eq!(BlockHeader::chain_work(&headers), Some(chain.total_work()));
+        assert_eq!(chain.get_headers(1, 1)[0].get_index(), 1);
+        assert!(chain.get_headers(3, 10).is_empty());
+        // the headers must follow each other from the genesis block on
+        assert_eq!(BlockHeader::chain_work(&headers[1..]), None);
+        let mut reordered = headers.clone();
+        reordered.swap(1, 2);
+        assert_eq!(BlockHeader::chain_work(&reordered), None);
+    }
+
+    #[test]
+    fn test_chain_id() {
+        let named = |name: &str| {
+            Blockchain::from_blocks(vec![GenesisSpec {
+                name: Some(name.to_owned()),
+                difficulty: Some(0),
+                ..GenesisSpec::default()
+            }
+            .block()])
+        };
+        let mut testnet = named("testnet");
+        let mut mainnet = named("mainnet");
+        let mut unnamed = Blockchain::with_difficulty(0);
+        assert_ne!(testnet.chain_id(), mainnet.chain_id());
+        let block = testnet.mine_new_block().clone();
+        assert_eq!(block.get_chain_id(), Some(testnet.chain_id().as_str()));
+        assert!(Blockchain::valid_chain(&testnet));
+        // blocks of another chain are foreign, even where they would link
+        assert!(mainnet.is_foreign(&block));
+        assert!(!mainnet.add_new_block(&block));
+        let plain = unnamed.mine_new_block().clone();
+        assert_eq!(plain.get_chain_id(), None);
+        assert!(!unnamed.is_foreign(&plain));
+        assert!(testnet.is_foreign(&plain));
+
+        let mut blocks = testnet.get_blocks();
+        blocks[1].header_mut().chain_id = None;
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(blocks)));
+    }
+
+    #[test]
+    fn test_address_prefix() {
+        let key = NodeKey::generate();
+        let address = Address::new("nb", &key.public_key()).unwrap().to_string();
+        let other = Address::new("nb", &NodeKey::generate().public_key())
+            .unwrap()
+            .to_string();
+        let mut chain = Blockchain::from_blocks(vec![GenesisSpec {
+            difficulty: Some(0),
+            address_prefix: Some("nb".to_owned()),
+            allocations: vec![crate::Allocation {
+                address: address.clone(),
+                amount: 10,
+            }],
+            ..GenesisSpec::default()
+        }
+        .block()]);
+        assert!(Blockchain::valid_chain(&chain));
+        assert_eq!(chain.address_prefix().as_deref(), Some("nb"));
+        assert_eq!(chain.address_of(&key.public_key()), address);
+        assert!(chain.is_address(&other) && !chain.is_address("alice"));
+        assert_eq!(chain.get_balance(&address), 10);
+
+        // the key of an address signs for it
+        let unsigned = Transaction::new(&address, &other, 3);
+        assert_eq!(
+            chain.add_new_transaction(&unsigned),
+            Err(Rejection::Unsigned)
+        );
+        chain
+            .add_new_transaction(&Transaction::new(&address, &other, 3).sign(&key))
+            .unwrap();
+        // only addresses under the prefix of the chain take coins
+        let foreign = Address::new("tnb", &key.public_key()).unwrap().to_string();
+        for recipient in ["alice", &key.public_key(), &foreign] {
+            let payment = Transaction::new(&address, recipient, 1).sign(&key);
+            assert_eq!(
+                chain.add_new_transaction(&payment),
+                Err(Rejection::ForeignAddress(recipient.to_owned()))
+            );
+        }
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance(&other), 3);
+        assert!(Blockchain::valid_chain(&chain));
+
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        let block = chain.candidate_block(Some(reward), get_time());
+        assert!(matches!(
+            chain.validate_block(&block),
+            Err(BlockRejection::InvalidTransaction { reason, .. }) if reason.contains("alice")
+        ));
+        let mut blocks = chain.get_blocks();
+        blocks.push(block);
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(blocks)));
+    }
+
+    #[test]
+    fn test_miner_signature() {
+        let key = NodeKey::generate();
+        // blocks signal readiness for signatures until the end of a window where enough did
+        let mut chain = Blockchain::with_difficulty(0);
+        chain.mine_new_block();
+        chain.set_miner_key(Some(key.clone()));
+        let block = chain.mine_new_block().clone();
+        assert!(!block.is_signed_by_miner());
+        assert!(block.get_header().signals(MINER_SIGNATURE_BIT));
+        assert_eq!(block.get_header().get_version(), BLOCK_VERSION);
+        let mut early = chain.candidate_block(None, get_time());
+        early.header_mut().version = SIGNED_BLOCK_VERSION;
+        assert_eq!(
+            chain.validate_block(&early),
+            Err(BlockRejection::UnsupportedVersion)
+        );
+        while chain.len() < SIGNAL_WINDOW as usize {
+            assert!(!chain.requires_miner_signatures());
+            chain.mine_new_block();
+        }
+        assert!(chain.requires_miner_signatures());
+        // one block short of the threshold
+        let mut short = Blockchain::with_difficulty(0);
+        while short.len() <= (SIGNAL_WINDOW - SIGNAL_THRESHOLD) as usize {
+            short.mine_new_block();
+        }
+        short.set_miner_key(Some(key.clone()));
+        while short.len() < SIGNAL_WINDOW as usize {
+            short.mine_new_block();
+        }
+        assert!(!short.requires_miner_signatures());
+
+        let reward = Transaction::new(REWARD_SENDER, &key.public_key(), 1);
+        chain.add_new_transaction(&reward).unwrap();
+        let block = chain.mine_new_block().clone();
+        assert!(block.is_signed_by_miner());
+        assert_eq!(block.get_header().get_version(), SIGNED_BLOCK_VERSION);
+        assert_eq!(block.get_miner(), Some(key.public_key().as_str()));
+        assert_eq!(block.check(), Ok(()));
+        assert!(Blockchain::valid_chain(&chain));
+
+        // the proof of work varies the nonce, which the signature leaves out
+        let mut reworked = block.clone();
+        reworked.set_nonce(42);
+        assert_eq!(reworked.check_miner(), Ok(()));
+        let mut claimed = block.clone();
+        claimed.header_mut().miner = Some(NodeKey::generate().public_key());
+        assert_eq!(claimed.check(), Err(BlockRejection::BadMinerSignature));
+        let mut unhashed = block.clone();
+        unhashed.header_mut().encoding = 2;
+        assert_eq!(unhashed.check(), Err(BlockRejection::BadMinerSignature));
+
+        // unsigned blocks cannot follow signed ones by claiming an older version
+        let mut legacy = chain.candidate_block(None, get_time());
+        legacy.header_mut().version = BLOCK_VERSION;
+        legacy.header_mut().miner = None;
+        legacy.header_mut().miner_signature = None;
+        assert_eq!(legacy.check(), Ok(()));
+        let mut blocks = chain.get_blocks();
+        blocks.push(legacy.clone());
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(blocks)));
+        assert_eq!(
+            chain.validate_block(&legacy),
+            Err(BlockRejection::UnsignedBlock)
+        );
+
+        // blocks paying another address are left unsigned, and cannot be signed
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1);
+        chain.add_new_transaction(&reward).unwrap();
+        let mut block = chain.mine_new_block().clone();
+        assert!(!block.is_signed_by_miner());
+        assert_eq!(block.get_miner(), Some("alice"));
+        assert_eq!(block.check(), Err(BlockRejection::UnsignedBlock));
+        block.sign_miner(&key, &key.public_key());
+        assert_eq!(
+            block.check(),
+            Err(BlockRejection::RewardNotToMiner {
+                id: reward.get_id().to_owned()
+            })
+        );
+        assert!(!Blockchain::valid_chain(&chain));
+    }
+
+    #[test]
+    fn test_assume_valid() {
+        let store = Store::memory();
+        let mut chain = Blockchain::open(store.clone(), Some(16)).unwrap();
+        let tip = chain.tip_hash().to_owned();
+        let unproven = chain.create_new_block(0, tip).clone();
+        assert!(!unproven.has_valid_proof());
+        chain.mine_new_block();
+        assert!(!Blockchain::valid_chain(&chain));
+        assert!(Blockchain::open(store.clone(), None).is_err());
+
+        // the block and its ancestors are trusted, but not what follows
+        chain.set_assume_valid(Some(unproven.get_hash()));
+        assert!(Blockchain::valid_chain(&chain));
+        let genesis = chain.get_block_by_index(0).unwrap().get_hash();
+        chain.set_assume_valid(Some(genesis));
         assert!(!Blockchain::valid_chain(&chain));
+        chain.set_assume_valid(Some("f".repeat(64)));
+        assert!(!Blockchain::valid_chain(&chain));
+        chain.set_assume_valid(Some(chain.tip_hash().to_owned()));
+        assert!(Blockchain::valid_chain(&chain));
+
+        let trusting = |hash: &str| {
+            let consensus = Box::new(ProofOfWork);
+            Blockchain::open_trusting(
+                store.clone(),
+                None,
+                None,
+                None,
+                consensus,
+                Some(hash.to_owned()),
+            )
+        };
+        let reopened = trusting(&unproven.get_hash()).unwrap();
+        assert_eq!(reopened.len(), 3);
+        // the structure is still checked
+        let mut blocks = chain.get_blocks();
+        blocks[1].header_mut().merkle_root = "1".repeat(64);
+        blocks[2].header_mut().previous_hash = blocks[1].get_hash();
+        let mut forged = Blockchain::from_blocks(blocks);
+        forged.set_assume_valid(Some(forged.tip_hash().to_owned()));
+        assert!(!Blockchain::valid_chain(&forged));
+
+        // candidates are validated with our trust
+        let mut ours = Blockchain::with_difficulty(16);
+        assert!(!ours.prefers(&Blockchain::from_blocks(chain.get_blocks())));
+        ours.set_assume_valid(Some(unproven.get_hash()));
+        assert!(ours.prefers(&Blockchain::from_blocks(chain.get_blocks())));
+    }
+
+    #[test]
+    fn test_lazy_blocks() {
+        let store = Store::memory();
+        let mut chain = Blockchain::open_with_capacity(
+            store.clone(),
+            None,
+            None,
+            None,
+            Box::new(ProofOfWork),
+            2,
+            None,
+        )
+        .unwrap();
+        let t = Transaction::new("0", "1", 1);
+        chain.add_new_transaction(&t).unwrap();
+        for _ in 0..4 {
+            chain.mine_new_block();
+        }
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain.blocks.resident.len(), 2);
+
+        // older blocks come from the store
+        let block = chain.get_block_by_index(1).unwrap();
+        assert!(matches!(block, Cow::Owned(_)));
+        assert_eq!(block.get_transactions()[0].get_id(), t.get_id());
+        assert!(chain.contains_transaction(t.get_id()));
+        assert_eq!(chain.get_blocks().len(), 5);
+        assert!(Blockchain::valid_chain(&chain));
+        // only the resident ones are borrowed
+        let borrowed: Vec<bool> = chain
+            .iter_blocks()
+            .map(|block| matches!(block, Cow::Borrowed(_)))
+            .collect();
+        assert_eq!(borrowed, [false, false, false, true, true]);
+        // a view goes through the blocks so far on another thread
+        let view = chain.view();
+        chain.mine_new_block();
+        let hashes = std::thread::spawn(move || {
+            view.iter()
+                .map(|block| block.get_hash())
+                .collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(hashes.len(), 5);
+        assert_eq!(hashes[4], chain.get_block_by_index(4).unwrap().get_hash());
+        chain.blocks.pop();
+
+        // popping below the resident window reloads from the store
+        chain.blocks.pop();
         chain.blocks.pop();
+        chain.blocks.pop();
+        assert_eq!(chain.last_block().get_index(), 1);
         assert!(Blockchain::valid_chain(&chain));
 
-        // play with the genesis block again
-        chain.blocks[0]
-            .transactions
-            .push(Transaction::new("good", "evil", 100));
-        assert!(!Blockchain::valid_chain(&chain));
+        let reopened =
+            Blockchain::open_with_capacity(store, None, None, None, Box::new(ProofOfWork), 2, None)
+                .unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert!(reopened.contains_transaction(t.get_id()));
+    }
+
+    #[test]
+    fn test_repair_block() {
+        use crate::storage::BLOCKS_TREE;
+
+        let store = Store::memory();
+        let mut chain = Blockchain::open_with_capacity(
+            store.clone(),
+            None,
+            None,
+            None,
+            Box::new(ProofOfWork),
+            2,
+            None,
+        )
+        .unwrap();
+        chain
+            .add_new_transaction(&Transaction::new("0", "alice", 1))
+            .unwrap();
+        for _ in 0..4 {
+            chain.mine_new_block();
+        }
+        let good = chain.get_block_by_index(1).unwrap().into_owned();
+
+        // rot a bit of a block that is only read from the store
+        let key = 1u64.to_be_bytes();
+        let record = store.backend().get(BLOCKS_TREE, &key).unwrap().unwrap();
+        let rotten = String::from_utf8(record).unwrap().replace("alice", "alicf");
+        store
+            .backend()
+            .insert(BLOCKS_TREE, &key, rotten.as_bytes())
+            .unwrap();
+        assert!(chain.get_block_by_index(1).is_none());
+        assert_eq!(chain.corrupt_blocks(), vec![1]);
+        assert_eq!(chain.expected_hash(1), Some(good.get_hash()));
+
+        // only the block the chain links to repairs it
+        let mut forged = good.clone();
+        forged.header_mut().timestamp += 1;
+        assert!(!chain.repair_block(&forged, "mallory"));
+        assert!(chain.repair_block(&good, "bob"));
+        assert!(chain.corrupt_blocks().is_empty());
+        assert!(Blockchain::valid_chain(&chain));
+        let log = store.load_audit_log().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[1].event, "block 1 repaired from bob");
+    }
+
+    #[test]
+    fn test_assets() {
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 10))
+            .unwrap();
+        chain.mine_new_block();
+        let gold = || "gold".to_owned();
+        let transfer = |sender, recipient, amount| {
+            Transaction::new(sender, recipient, amount).with_asset("gold")
+        };
+
+        // alice issues 60 of at most 100 tokens to bob
+        chain
+            .add_new_transaction(&Transaction::issue("alice", "bob", "gold", 60, 100).with_fee(1))
+            .unwrap();
+        let issue = |issuer, amount, max_supply| {
+            Transaction::issue(issuer, issuer, "gold", amount, max_supply)
+        };
+        assert_eq!(
+            chain.add_new_transaction(&issue("alice", 41, 100)),
+            Err(Rejection::OverIssuance(gold()))
+        );
+        assert_eq!(
+            chain.add_new_transaction(&issue("bob", 1, 100)),
+            Err(Rejection::NotIssuer(gold()))
+        );
+        assert_eq!(
+            chain.add_new_transaction(&issue("alice", 1, 200)),
+            Err(Rejection::NotIssuer(gold()))
+        );
+        assert_eq!(
+            chain.add_new_transaction(&issue("alice", 1, MAX_AMOUNT + 1)),
+            Err(Rejection::InvalidAsset(gold()))
+        );
+        assert_eq!(
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "bob", 1).with_asset("gold")),
+            Err(Rejection::InvalidAsset(gold()))
+        );
+        // bob holds tokens, but no coins for a fee
+        assert_eq!(
+            chain.add_new_transaction(&transfer("bob", "carol", 50).with_fee(1)),
+            Err(Rejection::Overspend)
+        );
+        chain
+            .add_new_transaction(&transfer("bob", "carol", 50))
+            .unwrap();
+        assert_eq!(
+            chain.add_new_transaction(&transfer("bob", "carol", 11)),
+            Err(Rejection::Overspend)
+        );
+        chain.mine_new_block();
+        assert_eq!(chain.get_asset_balance("gold", "bob"), 10);
+        assert_eq!(chain.get_asset_balance("gold", "carol"), 50);
+        assert_eq!(chain.get_asset_balance("silver", "carol"), 0);
+        assert_eq!(
+            chain.get_asset("gold"),
+            Some(Asset {
+                issuer: "alice".to_owned(),
+                max_supply: 100,
+                issued: 60,
+            })
+        );
+        assert_eq!(chain.get_asset("silver"), None);
+        // the fee is paid in coins, and tokens are no coins
+        assert_eq!(chain.get_balance("alice"), 9);
+        assert_eq!(
+            chain.add_new_transaction(&Transaction::new("carol", "dave", 1)),
+            Err(Rejection::Overspend)
+        );
+        // the rest of the supply may still be issued
+        chain.add_new_transaction(&issue("alice", 40, 100)).unwrap();
+        assert_eq!(
+            chain.add_new_transaction(&issue("alice", 1, 100)),
+            Err(Rejection::OverIssuance(gold()))
+        );
+
+        // a block issuing beyond the max supply is rejected
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        other.mine_new_block();
+        let issuing = |amount| {
+            let mut block = other.last_block().clone();
+            block.transactions.push(issue("alice", amount, 100));
+            block.header_mut().merkle_root =
+                merkle::merkle_root(&Block::transaction_hashes(&block.transactions));
+            block
+        };
+        assert!(!chain.add_new_block(&issuing(41)));
+        assert!(chain.add_new_block(&issuing(40)));
+        assert_eq!(chain.get_asset("gold").unwrap().issued, 100);
+    }
+
+    #[test]
+    fn test_prefix_lookup() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let hash = chain.mine_new_block().get_hash();
+        assert_eq!(
+            chain.resolve_block(&hash[..MIN_PREFIX_LEN]),
+            Ok(hash.clone())
+        );
+        assert_eq!(chain.resolve_block(&hash), Ok(hash.clone()));
+        assert_eq!(
+            chain.resolve_block(&hash[..MIN_PREFIX_LEN - 1]),
+            Err(PrefixError::TooShort)
+        );
+        assert_eq!(chain.resolve_block("zzzz"), Err(PrefixError::NotFound));
+
+        // two transactions sharing a prefix, both pending, then both mined
+        let mut seen: HashMap<String, Transaction> = HashMap::new();
+        let (first, second) = loop {
+            let t = Transaction::new(REWARD_SENDER, "alice", 1);
+            let prefix = t.get_id()[..MIN_PREFIX_LEN].to_owned();
+            if let Some(first) = seen.insert(prefix, t.clone()) {
+                break (first, t);
+            }
+        };
+        chain.add_new_transaction(&first).unwrap();
+        chain.add_new_transaction(&second).unwrap();
+        let prefix = &first.get_id()[..MIN_PREFIX_LEN];
+        let mut ids = vec![first.get_id().to_owned(), second.get_id().to_owned()];
+        ids.sort();
+        assert_eq!(
+            chain.resolve_transaction(prefix),
+            Err(PrefixError::Ambiguous(ids.clone()))
+        );
+        let longer = &first.get_id()[..MIN_PREFIX_LEN + 8];
+        assert_eq!(
+            chain.resolve_transaction(longer),
+            Ok(first.get_id().to_owned())
+        );
+        chain.mine_new_block();
+        assert_eq!(
+            chain.resolve_transaction(prefix),
+            Err(PrefixError::Ambiguous(ids))
+        );
+        assert!(PrefixError::Ambiguous(vec!["ab".to_owned(); 12])
+            .to_string()
+            .ends_with("ab ab and 2 more"));
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let schedule = RewardSchedule {
+            initial_reward: 10,
+            coinbase_maturity: 2,
+            ..RewardSchedule::default()
+        };
+        let mut chain = Blockchain::with_reward_schedule(0, schedule);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 10))
+            .unwrap();
+        chain.mine_new_block();
+        let pay = |sender, recipient| Transaction::new(sender, recipient, 1);
+        assert_eq!(
+            chain.add_new_transaction(&pay("alice", "bob")),
+            Err(Rejection::ImmatureReward)
+        );
+        // nor is a reward spendable in its own block
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "bob", 10))
+            .unwrap();
+        assert_eq!(
+            chain.add_new_transaction(&pay("bob", "carol")),
+            Err(Rejection::ImmatureReward)
+        );
+        assert_eq!(
+            chain.add_new_transaction(&pay("carol", "bob")),
+            Err(Rejection::Overspend)
+        );
+
+        // a block spending it early is rejected
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        other.mine_new_block();
+        let mut early = other.last_block().clone();
+        early.transactions.push(pay("alice", "bob"));
+        early.header_mut().merkle_root =
+            merkle::merkle_root(&Block::transaction_hashes(&early.transactions));
+        assert!(!chain.add_new_block(&early));
+
+        // two blocks after its own, it is spendable
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance("alice"), 10);
+        chain.add_new_transaction(&pay("alice", "bob")).unwrap();
+        chain.mine_new_block();
+        assert_eq!(chain.get_balance("bob"), 11);
+        assert!(Blockchain::valid_chain(&chain));
+
+        // outputs of rewards are not picked before they mature
+        let mut chain = Blockchain::from_blocks(vec![Block::get_genesis_with(
+            0,
+            Some(schedule),
+            TransactionModel::Utxo,
+        )]);
+        let reward = chain
+            .new_transaction(REWARD_SENDER, "alice", 10, 0)
+            .unwrap();
+        chain.add_new_transaction(&reward).unwrap();
+        chain.mine_new_block();
+        assert!(chain.new_transaction("alice", "bob", 1, 0).is_none());
+        let early = Transaction::spend(
+            "alice",
+            vec![OutPoint {
+                transaction: reward.get_id().to_owned(),
+                index: 0,
+            }],
+            vec![Output {
+                recipient: "bob".to_owned(),
+                amount: 10,
+            }],
+        );
+        assert_eq!(
+            chain.add_new_transaction(&early),
+            Err(Rejection::ImmatureReward)
+        );
+        chain.mine_new_block();
+        let payment = chain.new_transaction("alice", "bob", 1, 0).unwrap();
+        chain.add_new_transaction(&payment).unwrap();
+    }
+
+    #[test]
+    fn test_incoming_transactions() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let payment = Transaction::new("alice", "bob", 1);
+        for pending in [vec![], vec![&payment]] {
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+                .unwrap();
+            for t in pending {
+                chain.add_new_transaction(t).unwrap();
+            }
+            chain.mine_new_block();
+        }
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        other.mine_new_block();
+        let holding = |transactions: Vec<Transaction>| {
+            let mut block = other.last_block().clone();
+            block.transactions.extend(transactions);
+            block.header_mut().merkle_root =
+                merkle::merkle_root(&Block::transaction_hashes(&block.transactions));
+            block
+        };
+
+        // garbage, however well the block is made
+        let mut forged = Transaction::new("alice", "bob", 1);
+        forged.amount = 2;
+        for garbage in [
+            Transaction::new("alice", "bob", 0),
+            Transaction::new("alice", "not an address", 1),
+            Transaction::new(REWARD_SENDER, "alice", 1).with_fee(1),
+            forged,
+        ] {
+            let block = holding(vec![garbage]);
+            assert!(block.invalid_transaction().is_some());
+            assert!(!chain.add_new_block(&block));
+        }
+        // the same transaction twice
+        let twice = Transaction::new("alice", "bob", 1);
+        let block = holding(vec![twice.clone(), twice]);
+        assert_eq!(
+            block.invalid_transaction().map(|(_, rejection)| rejection),
+            Some(Rejection::Duplicate)
+        );
+        assert!(!chain.add_new_block(&block));
+        // a transaction the chain confirmed already, replayed
+        let replayed = holding(vec![payment]);
+        assert!(replayed.invalid_transaction().is_none());
+        assert!(!chain.add_new_block(&replayed));
+        let mut replaying = Blockchain::from_blocks(chain.get_blocks());
+        replaying.push(replayed);
+        assert!(!Blockchain::valid_chain(&replaying));
+
+        assert!(chain.add_new_block(&holding(vec![Transaction::new("alice", "bob", 1)])));
+        assert!(Blockchain::valid_chain(&chain));
     }
 }
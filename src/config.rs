@@ -0,0 +1,655 @@
+//! Node configuration
+
+use crate::storage::{Backend, CONTROL_COOKIE_FILE};
+use crate::{
+    is_public_key, is_valid_address, Block, Blockchain, GenesisSpec, NodeKey, RewardSchedule,
+    Store, TransactionModel, TxValidator, INITIAL_DIFFICULTY, MAX_DIFFICULTY, MEMPOOL_CAPACITY,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Everything needed to start a node.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// the address the node listens on and advertises to peers
+    pub addr: String,
+    /// the loopback address the node takes commands on from its consoles, see `control`
+    pub control_addr: String,
+    /// a listening socket the parent process passed to take peers on instead of binding `addr`,
+    /// which the node still advertises, see `activation`
+    pub listen_fd: Option<i32>,
+    /// a listening socket the parent process passed to take commands on instead of binding
+    /// `control_addr`
+    pub control_fd: Option<i32>,
+    /// where the chain and the peers are persisted
+    pub data_dir: PathBuf,
+    /// the database the data directory is kept in
+    pub db: Backend,
+    /// keeps the chain, the peers and the node key in memory only, leaving the data directory
+    /// alone, so that everything is lost on exit
+    pub ephemeral: bool,
+    /// reports block sightings to this node for the propagation experiment, see `telemetry`
+    pub telemetry_collector: Option<String>,
+    /// how blocks and transactions are relayed to peers
+    pub gossip: GossipStrategy,
+    /// how many peers a non-flooding strategy relays to
+    pub fanout: usize,
+    /// the difficulty the chain starts at, in leading zero bits of the header hash
+    pub difficulty: Option<u32>,
+    /// how many coins mining a block mints, the default schedule if not set
+    pub reward_schedule: Option<RewardSchedule>,
+    /// how the transactions of the chain move coins
+    pub transaction_model: TransactionModel,
+    /// the window the `stats` command measures the throughput over
+    pub stats_window: Duration,
+    /// warns when pending transactions wait this long without any getting confirmed
+    pub stall_warning: Duration,
+    /// artificial delays of outgoing messages, to emulate a WAN with nodes on one machine
+    pub latency: LinkLatencies,
+    /// delays the first relay of the transactions the node creates to every peer by a random
+    /// time up to this, so that the peer hearing of one first cannot tell the node made it
+    pub diffusion: Option<Duration>,
+    /// the rules blocks are produced and validated by
+    pub consensus: ConsensusMode,
+    /// where mining rewards go, the node's key if not set
+    pub reward_address: Option<String>,
+    /// how many peers the node needs before it mines, as a block mined alone forks off the
+    /// chain of the network once the node connects
+    pub min_peers_to_mine: usize,
+    /// refuses to mine a block while no transaction but its reward is pending
+    pub skip_empty_blocks: bool,
+    /// how many transactions may be pending, see `Blockchain::set_mempool_capacity`
+    pub mempool_capacity: usize,
+    /// how many incoming connections may be open at once
+    pub max_connections: usize,
+    /// how long a connection may stay idle, or silent when outgoing
+    pub idle_timeout: Duration,
+    /// how long peers still accept the id of the node after it rotated its key
+    pub key_grace_period: Duration,
+    /// re-verifies the stored chain in the background, waiting this long before every block
+    pub verify_pace: Option<Duration>,
+    /// the hash of a block known to be good, whose ancestors are validated without checking
+    /// their seals, see `Blockchain::set_assume_valid`
+    pub assume_valid: Option<String>,
+    /// the hashes the blocks at these indices must have, see `Blockchain::set_checkpoints`
+    pub checkpoints: BTreeMap<u64, String>,
+    /// the rule changes the blocks the node mines signal readiness for, see
+    /// `Blockchain::set_signals`
+    pub signals: Vec<u8>,
+    /// rules of the node for the transactions it admits to its mempool, on top of the
+    /// consensus rules, see `TxValidator`
+    pub validators: Vec<Arc<dyn TxValidator>>,
+    /// sets up the genesis block instead of the difficulty, reward schedule and transaction
+    /// model above, which should match it
+    pub genesis_spec: Option<GenesisSpec>,
+}
+
+impl NodeConfig {
+    /// Creates a config for a node at `addr`, keeping its data in a directory named after it.
+    pub fn new(addr: &str) -> Self {
+        NodeConfig {
+            addr: addr.to_owned(),
+            control_addr: default_control_addr(addr),
+            listen_fd: None,
+            control_fd: None,
+            data_dir: PathBuf::from("nb_data").join(addr.replace(':', "_")),
+            db: Backend::Sled,
+            ephemeral: false,
+            telemetry_collector: None,
+            gossip: GossipStrategy::Flood,
+            fanout: 3,
+            difficulty: None,
+            reward_schedule: None,
+            transaction_model: TransactionModel::Account,
+            stats_window: Duration::from_secs(600),
+            stall_warning: Duration::from_secs(600),
+            latency: LinkLatencies::default(),
+            diffusion: None,
+            consensus: ConsensusMode::ProofOfWork,
+            reward_address: None,
+            min_peers_to_mine: 1,
+            skip_empty_blocks: false,
+            mempool_capacity: MEMPOOL_CAPACITY,
+            max_connections: 64,
+            idle_timeout: Duration::from_secs(30),
+            key_grace_period: Duration::from_secs(86400),
+            verify_pace: None,
+            assume_valid: None,
+            checkpoints: BTreeMap::new(),
+            signals: Vec::new(),
+            validators: Vec::new(),
+            genesis_spec: None,
+        }
+    }
+
+    /// Where the node writes the secret its consoles authenticate with, see
+    /// `control::ControlCookie`. Ephemeral nodes leave the data directory alone, and write it to
+    /// the temporary directory under their control address instead.
+    pub fn control_cookie(&self) -> PathBuf {
+        if self.ephemeral {
+            let name = format!("nb-{}.cookie", self.control_addr.replace(':', "-"));
+            return std::env::temp_dir().join(name);
+        }
+        self.data_dir.join(CONTROL_COOKIE_FILE)
+    }
+
+    /// The genesis block of the chain this config runs, which picks its database in the data
+    /// directory.
+    pub fn genesis(&self) -> Block {
+        if let Some(spec) = &self.genesis_spec {
+            return spec.block();
+        }
+        Block::get_genesis_with(
+            self.difficulty.unwrap_or(INITIAL_DIFFICULTY),
+            self.reward_schedule,
+            self.transaction_model,
+        )
+    }
+}
+
+/// A value of a config the node cannot run with, and how to fix it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// the path of the value, e.g. `consensus.authorities[1]`
+    pub field: String,
+    pub problem: String,
+    pub fix: String,
+}
+
+impl ConfigProblem {
+    pub fn new(
+        field: impl Into<String>,
+        problem: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        ConfigProblem {
+            field: field.into(),
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+
+    /// The same problem in the config at `path` of a larger one.
+    pub fn within(self, path: &str) -> Self {
+        ConfigProblem {
+            field: format!("{}.{}", path, self.field),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} (fix: {})", self.field, self.problem, self.fix)
+    }
+}
+
+/// Fails with all of `problems` of the config named `what`, if there are any.
+pub fn check_problems(what: &str, problems: Vec<ConfigProblem>) -> crate::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let problems: Vec<String> = problems.iter().map(|p| format!("  {}", p)).collect();
+    Err(failure::err_msg(format!(
+        "Invalid {}:\n{}",
+        what,
+        problems.join("\n")
+    )))
+}
+
+impl NodeConfig {
+    /// Fails, naming every value the node cannot run with, before anything is started.
+    pub fn check(&self) -> crate::Result<()> {
+        check_problems("node config", self.problems())
+    }
+
+    /// The values of the config the node cannot run with.
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        let mut problem = |field: &str, problem: String, fix: String| {
+            problems.push(ConfigProblem::new(field, problem, fix))
+        };
+        let port = host_port(&self.addr);
+        if port.is_none() {
+            problem(
+                "addr",
+                format!("{:?} is not a HOST:PORT address", self.addr),
+                "give the interface and port to listen on, e.g. 127.0.0.1:4000".to_owned(),
+            );
+        }
+        let default_control = default_control_addr(&self.addr);
+        match self.control_addr.parse::<SocketAddr>() {
+            Err(_) => problem(
+                "control_addr",
+                format!("{:?} is not an IP:PORT address", self.control_addr),
+                format!("use a loopback address, e.g. {}", default_control),
+            ),
+            Ok(control) if !control.ip().is_loopback() => problem(
+                "control_addr",
+                format!(
+                    "{} is not a loopback address, anyone reaching it controls the node",
+                    control
+                ),
+                if port == Some(control.port()) {
+                    format!("use {}", default_control)
+                } else {
+                    format!("use 127.0.0.1:{}", control.port())
+                },
+            ),
+            Ok(control) if port == Some(control.port()) && is_local(&self.addr) => problem(
+                "control_addr",
+                format!("{} takes the port of addr {}", control, self.addr),
+                format!("use another port, e.g. {}", default_control),
+            ),
+            Ok(_) => {}
+        }
+        if let Some(fd) = self.listen_fd.filter(|fd| self.control_fd == Some(*fd)) {
+            problem(
+                "control_fd",
+                format!("{} is the listen_fd already", fd),
+                "pass the node a socket for consoles of its own".to_owned(),
+            );
+        }
+        if let Some(collector) = &self.telemetry_collector {
+            if host_port(collector).is_none() {
+                problem(
+                    "telemetry_collector",
+                    format!("{:?} is not a HOST:PORT address", collector),
+                    "give the address of the collecting node, e.g. 127.0.0.1:4000".to_owned(),
+                );
+            }
+        }
+        if self.gossip != GossipStrategy::Flood && self.fanout == 0 {
+            problem(
+                "fanout",
+                format!("the {} gossip relays to 0 peers", self.gossip),
+                "relay to 1 peer or more, e.g. 3".to_owned(),
+            );
+        }
+        match self.difficulty {
+            Some(difficulty) if difficulty > MAX_DIFFICULTY => problem(
+                "difficulty",
+                format!(
+                    "{} is more than the {} bits allowed",
+                    difficulty, MAX_DIFFICULTY
+                ),
+                format!("use at most {}", MAX_DIFFICULTY),
+            ),
+            Some(0) if self.consensus == ConsensusMode::ProofOfWork => problem(
+                "difficulty",
+                "0 lets every block through without any work".to_owned(),
+                format!("use 1 or more, e.g. {}", INITIAL_DIFFICULTY),
+            ),
+            _ => {}
+        }
+        if let Some(schedule) = &self.reward_schedule {
+            if schedule.initial_reward < 0 {
+                problem(
+                    "reward_schedule.initial_reward",
+                    format!("{} coins is a negative reward", schedule.initial_reward),
+                    "use 0 or more".to_owned(),
+                );
+            }
+            if schedule.max_supply.is_some_and(|supply| supply < 0) {
+                problem(
+                    "reward_schedule.max_supply",
+                    "a negative supply cannot be minted".to_owned(),
+                    "use 0 or more, or leave it out for no cap".to_owned(),
+                );
+            }
+        }
+        match &self.consensus {
+            ConsensusMode::ProofOfWork => {}
+            ConsensusMode::ProofOfAuthority(keys) | ConsensusMode::ProofOfStake(keys) => {
+                if keys.is_empty() {
+                    problem(
+                        "consensus.authorities",
+                        "nobody can seal the first block without an authority".to_owned(),
+                        "give at least one public key with --authority".to_owned(),
+                    );
+                }
+                for (i, key) in keys.iter().enumerate() {
+                    if !is_public_key(key) {
+                        problem(
+                            &format!("consensus.authorities[{}]", i),
+                            format!("{:?} is not a public key", key),
+                            "use the key a node logs as its node key at startup".to_owned(),
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(address) = &self.reward_address {
+            if !is_valid_address(address) {
+                problem(
+                    "reward_address",
+                    crate::address::invalid_address(address),
+                    "use 1 to 64 letters, digits, - or _".to_owned(),
+                );
+            }
+        }
+        for (field, value, example) in [
+            ("mempool_capacity", self.mempool_capacity, MEMPOOL_CAPACITY),
+            ("max_connections", self.max_connections, 64),
+        ] {
+            if value == 0 {
+                problem(
+                    field,
+                    "0 leaves no room for anything".to_owned(),
+                    format!("use 1 or more, e.g. {}", example),
+                );
+            }
+        }
+        for (field, duration) in [
+            ("stats_window", self.stats_window),
+            ("idle_timeout", self.idle_timeout),
+        ] {
+            if duration == Duration::default() {
+                problem(
+                    field,
+                    "it cannot be 0 seconds".to_owned(),
+                    "use 1 second or more".to_owned(),
+                );
+            }
+        }
+        if let Some(hash) = &self.assume_valid {
+            if !is_block_hash(hash) {
+                problem(
+                    "assume_valid",
+                    format!("{:?} is not a block hash", hash),
+                    "use the 64 hex digits of a block hash".to_owned(),
+                );
+            }
+        }
+        for (index, hash) in &self.checkpoints {
+            if !is_block_hash(hash) {
+                problem(
+                    &format!("checkpoints[{}]", index),
+                    format!("{:?} is not a block hash", hash),
+                    "use the 64 hex digits of a block hash".to_owned(),
+                );
+            }
+        }
+        for bit in &self.signals {
+            if *bit >= 32 {
+                problem(
+                    "signals",
+                    format!("bit {} is out of range", bit),
+                    "use bits from 0 to 31".to_owned(),
+                );
+            }
+        }
+        if let Some(spec) = &self.genesis_spec {
+            problems.extend(
+                spec.problems()
+                    .into_iter()
+                    .map(|problem| problem.within("genesis_spec")),
+            );
+        }
+        problems
+    }
+}
+
+/// The port of a `HOST:PORT` address, without resolving the host.
+fn host_port(addr: &str) -> Option<u16> {
+    let (host, port) = addr.rsplit_once(':')?;
+    if host.is_empty() {
+        return None;
+    }
+    port.parse().ok()
+}
+
+/// Whether the host of `addr` is this machine whatever interface the control address is on.
+fn is_local(addr: &str) -> bool {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().is_loopback() || addr.ip().is_unspecified(),
+        Err(_) => addr.starts_with("localhost:"),
+    }
+}
+
+pub(crate) fn is_block_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Where the node at `addr` takes commands unless told otherwise: on the loopback interface,
+/// 1000 ports above the one it listens on, e.g. 127.0.0.1:5000 for a node at 127.0.0.1:4000.
+pub fn default_control_addr(addr: &str) -> String {
+    let port = addr
+        .parse::<SocketAddr>()
+        .map_or(4000, |addr| addr.port())
+        .saturating_add(1000);
+    format!("127.0.0.1:{}", port)
+}
+
+impl NodeConfig {
+    /// Opens the chain this config runs in `store`, which gets its genesis block if it is empty,
+    /// and validates it. `key` seals the blocks of the node, if the consensus engine lets it,
+    /// and signs those it mines.
+    pub fn open_chain(&self, store: Store, key: Option<NodeKey>) -> crate::Result<Blockchain> {
+        let engine = self.consensus.engine(key.clone());
+        let mut chain = match &self.genesis_spec {
+            Some(_) => {
+                Blockchain::open_genesis(store, &self.genesis(), engine, self.assume_valid.clone())?
+            }
+            None => Blockchain::open_trusting(
+                store,
+                self.difficulty,
+                self.reward_schedule,
+                Some(self.transaction_model),
+                engine,
+                self.assume_valid.clone(),
+            )?,
+        };
+        chain.set_mempool_capacity(self.mempool_capacity);
+        chain.set_checkpoints(self.checkpoints.clone())?;
+        chain.set_signals(&self.signals)?;
+        chain.set_miner_key(key);
+        for validator in &self.validators {
+            chain.add_validator(validator.clone());
+        }
+        Ok(chain)
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig::new("127.0.0.1:4000")
+    }
+}
+
+/// How blocks and transactions are relayed to peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GossipStrategy {
+    /// send everything to every peer
+    Flood,
+    /// send everything to `fanout` random peers
+    RandomSubset,
+    /// announce ids to `fanout` random peers, which fetch what they don't have yet
+    PushPull,
+}
+
+impl FromStr for GossipStrategy {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flood" => Ok(GossipStrategy::Flood),
+            "random" => Ok(GossipStrategy::RandomSubset),
+            "push-pull" => Ok(GossipStrategy::PushPull),
+            _ => Err(failure::err_msg(format!(
+                "unknown gossip strategy {}, expected flood, random or push-pull",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for GossipStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GossipStrategy::Flood => "flood",
+            GossipStrategy::RandomSubset => "random",
+            GossipStrategy::PushPull => "push-pull",
+        })
+    }
+}
+
+/// The consensus engine of a node, see `Consensus`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsensusMode {
+    /// blocks are mined
+    ProofOfWork,
+    /// blocks are signed by one of the authorities with these public keys
+    ProofOfAuthority(Vec<String>),
+    /// blocks are signed by stakers picked by their balance, starting with these public keys
+    ProofOfStake(Vec<String>),
+}
+
+/// An artificial delay of `delay` give or take up to `jitter`, drawn for every message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Latency {
+    pub delay: Duration,
+    pub jitter: Duration,
+}
+
+impl FromStr for Latency {
+    type Err = failure::Error;
+
+    /// Parses `DELAY[~JITTER]` in milliseconds, e.g. `80~20`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let millis = |s: &str| {
+            s.parse().map(Duration::from_millis).map_err(|_| {
+                failure::err_msg(format!(
+                    "invalid latency {}, expected milliseconds like 80 or 80~20",
+                    s
+                ))
+            })
+        };
+        let (delay, jitter) = match s.find('~') {
+            Some(i) => (millis(&s[..i])?, millis(&s[i + 1..])?),
+            None => (millis(s)?, Duration::default()),
+        };
+        Ok(Latency { delay, jitter })
+    }
+}
+
+/// The latency of the links to peers, by peer address.
+#[derive(Clone, Debug, Default)]
+pub struct LinkLatencies {
+    /// the latency of the links without one of their own
+    pub default: Option<Latency>,
+    pub links: HashMap<SocketAddr, Latency>,
+}
+
+impl LinkLatencies {
+    /// Adds the latency given by `[IP:PORT=]DELAY[~JITTER]`, to all links if no peer is given.
+    pub fn add(&mut self, spec: &str) -> Result<(), failure::Error> {
+        match spec.find('=') {
+            Some(i) => {
+                let addr = spec[..i].parse().map_err(|_| {
+                    failure::err_msg(format!("invalid peer address {}", &spec[..i]))
+                })?;
+                self.links.insert(addr, spec[i + 1..].parse()?);
+            }
+            None => self.default = Some(spec.parse()?),
+        }
+        Ok(())
+    }
+
+    /// Returns the latency of the link to `addr`, if any.
+    pub fn get(&self, addr: &SocketAddr) -> Option<Latency> {
+        self.links.get(addr).copied().or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_latencies() {
+        let mut latencies = LinkLatencies::default();
+        latencies.add("127.0.0.1:4001=80~20").unwrap();
+        let peer = "127.0.0.1:4001".parse().unwrap();
+        let other = "127.0.0.1:4002".parse().unwrap();
+        assert_eq!(latencies.get(&other), None);
+        latencies.add("30").unwrap();
+        assert_eq!(
+            latencies.get(&peer),
+            Some(Latency {
+                delay: Duration::from_millis(80),
+                jitter: Duration::from_millis(20),
+            })
+        );
+        assert_eq!(
+            latencies.get(&other).unwrap().delay,
+            Duration::from_millis(30)
+        );
+        assert!(latencies.add("localhost=30").is_err());
+        assert!(latencies.add("30~").is_err());
+    }
+
+    #[test]
+    fn test_control_cookie() {
+        let mut config = NodeConfig::new("127.0.0.1:4000");
+        assert!(config.control_cookie().starts_with(&config.data_dir));
+        // ephemeral nodes sharing a data directory have a cookie each, outside it
+        config.ephemeral = true;
+        let cookie = config.control_cookie();
+        assert!(!cookie.starts_with(&config.data_dir));
+        config.control_addr = "127.0.0.1:5001".to_owned();
+        assert_ne!(config.control_cookie(), cookie);
+    }
+
+    #[test]
+    fn test_problems() {
+        let config = NodeConfig::default();
+        assert_eq!(config.problems(), []);
+        config.check().unwrap();
+
+        let fields = |config: &NodeConfig| -> Vec<String> {
+            config.problems().into_iter().map(|p| p.field).collect()
+        };
+        let mut config = NodeConfig::new("localhost:4000");
+        assert_eq!(fields(&config), Vec::<String>::new());
+        config.control_addr = "0.0.0.0:5000".to_owned();
+        assert_eq!(fields(&config), ["control_addr"]);
+        config.control_addr = "127.0.0.1:4000".to_owned();
+        assert_eq!(fields(&config), ["control_addr"]);
+        config.control_addr = default_control_addr(&config.addr);
+        config.difficulty = Some(0);
+        config.gossip = GossipStrategy::PushPull;
+        config.fanout = 0;
+        config.consensus = ConsensusMode::ProofOfAuthority(vec!["alice".to_owned()]);
+        config.checkpoints.insert(7, "abc".to_owned());
+        config.genesis_spec = Some(GenesisSpec {
+            allocations: vec![crate::Allocation {
+                address: "bob".to_owned(),
+                amount: -1,
+            }],
+            ..GenesisSpec::default()
+        });
+        // difficulty 0 is fine unless blocks are mined
+        assert_eq!(
+            fields(&config),
+            [
+                "fanout",
+                "consensus.authorities[0]",
+                "checkpoints[7]",
+                "genesis_spec.allocations[0].address",
+                "genesis_spec.allocations[0].amount",
+            ]
+        );
+        config.consensus = ConsensusMode::ProofOfWork;
+        assert_eq!(fields(&config)[1], "difficulty");
+        let error = config.check().unwrap_err().to_string();
+        assert!(error.contains("difficulty: 0 lets every block through without any work"));
+        assert!(error.contains("(fix: use 1 or more, e.g. 16)"));
+    }
+}
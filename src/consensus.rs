@@ -0,0 +1,392 @@
+//! Consensus engines
+//!
+//! A `Blockchain` checks what every engine agrees on, i.e. that blocks link to their
+//! predecessor and commit to their transactions. Everything else is up to its `Consensus`:
+//! how a block is sealed before it is added, when a seal is valid, and which of two chains
+//! with the same genesis block wins.
+
+use crate::address::key_of;
+use crate::blockchain::{AuthoritySeal, Block, BlockRejection, Blockchain};
+use crate::config::ConsensusMode;
+use crate::key::{self, NodeKey};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+/// The rules a chain is built and validated by.
+pub trait Consensus: Send {
+    /// Whether this node may seal the next block of `chain`.
+    fn can_seal(&self, _chain: &Blockchain) -> bool {
+        true
+    }
+
+    /// Seals `block`, which is to become the next block of `chain`.
+    fn seal(&self, chain: &Blockchain, block: &mut Block);
+
+    /// Whether the seal of `block` is valid, given the blocks of `chain` before it.
+    fn valid_seal(&self, chain: &Blockchain, block: &Block) -> bool;
+
+    /// Like `valid_seal`, with why the seal is not valid.
+    fn check_seal(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockRejection> {
+        if self.valid_seal(chain, block) {
+            Ok(())
+        } else {
+            Err(BlockRejection::BadSeal)
+        }
+    }
+
+    /// Fork choice: whether the valid `candidate` should replace `ours`. By default the longer
+    /// chain wins.
+    fn prefers(&self, ours: &Blockchain, candidate: &Blockchain) -> bool {
+        candidate.len() > ours.len()
+    }
+}
+
+/// Proof of work: the header hash of every block needs as many leading zero bits as the
+/// difficulty retargeting asks for at its index.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProofOfWork;
+
+impl Consensus for ProofOfWork {
+    fn seal(&self, _chain: &Blockchain, block: &mut Block) {
+        block.set_nonce(Blockchain::proof_of_work(block));
+    }
+
+    fn valid_seal(&self, chain: &Blockchain, block: &Block) -> bool {
+        self.check_seal(chain, block).is_ok()
+    }
+
+    fn check_seal(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockRejection> {
+        match chain.expected_difficulty(block.get_index()) {
+            Some(expected) if expected == block.get_difficulty() => {}
+            // past the tip, where nothing is expected yet
+            None => return Err(BlockRejection::BadParent),
+            Some(expected) => return Err(BlockRejection::BadDifficulty { expected }),
+        }
+        if !block.has_valid_proof() {
+            return Err(BlockRejection::BadProof);
+        }
+        Ok(())
+    }
+
+    /// The chain with the most work wins, which is not always the longer one once the
+    /// difficulty varies.
+    fn prefers(&self, ours: &Blockchain, candidate: &Blockchain) -> bool {
+        candidate.total_work() > ours.total_work()
+    }
+}
+
+/// Proof of authority: blocks are not mined but signed, by one of a fixed set of authorities.
+pub struct ProofOfAuthority {
+    // public keys
+    authorities: HashSet<String>,
+    // signs the blocks of this node, if it is an authority
+    key: Option<NodeKey>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(authorities: impl IntoIterator<Item = String>, key: Option<NodeKey>) -> Self {
+        ProofOfAuthority {
+            authorities: authorities.into_iter().collect(),
+            key,
+        }
+    }
+
+    fn is_authority(&self, public_key: &str) -> bool {
+        self.authorities.contains(public_key)
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn can_seal(&self, _chain: &Blockchain) -> bool {
+        self.key
+            .as_ref()
+            .is_some_and(|key| self.is_authority(&key.public_key()))
+    }
+
+    fn seal(&self, _chain: &Blockchain, block: &mut Block) {
+        if let Some(key) = &self.key {
+            sign(key, block);
+        }
+    }
+
+    fn valid_seal(&self, _chain: &Blockchain, block: &Block) -> bool {
+        block
+            .get_seal()
+            .is_some_and(|seal| self.is_authority(&seal.authority) && signed(block))
+    }
+}
+
+fn sign(key: &NodeKey, block: &mut Block) {
+    block.set_seal(AuthoritySeal {
+        authority: key.public_key(),
+        signature: key.sign(block.get_hash().as_bytes()),
+    });
+}
+
+/// Whether the seal of `block` was made by its authority.
+pub(crate) fn signed(block: &Block) -> bool {
+    block.get_seal().is_some_and(|seal| {
+        key::verify(
+            &seal.authority,
+            block.get_hash().as_bytes(),
+            &seal.signature,
+        )
+    })
+}
+
+/// A toy proof of stake: the producer of every block is drawn from the public keys holding coins,
+/// as themselves or as `Address`es, weighted by their balance before the block, and signs it. The draw is seeded by the hash of
+/// the previous block, so every node agrees on it. Until anyone holds coins, the producer is
+/// drawn from the bootstrap validators instead.
+///
+/// There are no slots or timeouts, so the chain stalls while the drawn producer is offline.
+pub struct ProofOfStake {
+    bootstrap: Vec<String>,
+    key: Option<NodeKey>,
+    // balances as of the first `height` blocks of the chain last looked at, ending with `tip`
+    balances: Mutex<Balances>,
+}
+
+#[derive(Default)]
+struct Balances {
+    height: u64,
+    tip: String,
+    balances: HashMap<String, i64>,
+}
+
+impl ProofOfStake {
+    pub fn new(bootstrap: impl IntoIterator<Item = String>, key: Option<NodeKey>) -> Self {
+        ProofOfStake {
+            bootstrap: bootstrap.into_iter().collect(),
+            key,
+            balances: Mutex::new(Balances::default()),
+        }
+    }
+
+    /// The public key allowed to produce the block at `index` of `chain`, which may be the next.
+    pub fn producer(&self, chain: &Blockchain, index: u64) -> Option<String> {
+        let previous = chain.get_block_by_index(index.checked_sub(1)?)?;
+        let seed = u64::from_str_radix(&previous.get_hash()[..16], 16).ok()?;
+        let stakes = self.stakes(chain, index)?;
+        if stakes.is_empty() {
+            let count = self.bootstrap.len() as u64;
+            return self.bootstrap.get((seed % count.max(1)) as usize).cloned();
+        }
+        key_of(draw(&stakes, seed))
+    }
+
+    /// The positive balances of the addresses of public keys before the block at `index`.
+    fn stakes(&self, chain: &Blockchain, index: u64) -> Option<BTreeMap<String, i64>> {
+        let mut cache = self.balances.lock().unwrap();
+        let on_chain = cache.height > 0
+            && cache.height <= index
+            && chain.contains_block(cache.height - 1, &cache.tip);
+        if !on_chain {
+            *cache = Balances::default();
+        }
+        for i in cache.height..index {
+            let block = chain.get_block_by_index(i)?;
+            // tokens are no stake
+            for t in block
+                .get_transactions()
+                .iter()
+                .filter(|t| t.get_asset_id().is_none())
+            {
+                *cache.balances.entry(t.get_sender().to_owned()).or_default() -= t.get_amount();
+                *cache
+                    .balances
+                    .entry(t.get_recipient().to_owned())
+                    .or_default() += t.get_amount();
+            }
+            cache.height = i + 1;
+            cache.tip = block.get_hash();
+        }
+        Some(
+            cache
+                .balances
+                .iter()
+                .filter(|(address, balance)| **balance > 0 && key_of(address).is_some())
+                .map(|(address, balance)| (address.clone(), *balance))
+                .collect(),
+        )
+    }
+}
+
+/// Draws an address from the non-empty `stakes`, weighted by their stake.
+fn draw(stakes: &BTreeMap<String, i64>, seed: u64) -> &str {
+    let total: i64 = stakes.values().sum();
+    let mut ticket = (seed % total as u64) as i64;
+    for (address, stake) in stakes {
+        if ticket < *stake {
+            return address;
+        }
+        ticket -= stake;
+    }
+    unreachable!("the ticket is less than the total stake")
+}
+
+impl Consensus for ProofOfStake {
+    fn can_seal(&self, chain: &Blockchain) -> bool {
+        match (&self.key, self.producer(chain, chain.len() as u64)) {
+            (Some(key), Some(producer)) => key.public_key() == producer,
+            _ => false,
+        }
+    }
+
+    fn seal(&self, _chain: &Blockchain, block: &mut Block) {
+        if let Some(key) = &self.key {
+            sign(key, block);
+        }
+    }
+
+    fn valid_seal(&self, chain: &Blockchain, block: &Block) -> bool {
+        match (block.get_seal(), self.producer(chain, block.get_index())) {
+            (Some(seal), Some(producer)) => seal.authority == producer && signed(block),
+            _ => false,
+        }
+    }
+}
+
+impl ConsensusMode {
+    /// Creates the engine of the mode, sealing blocks with `key` if it needs to.
+    pub fn engine(&self, key: Option<NodeKey>) -> Box<dyn Consensus> {
+        match self {
+            ConsensusMode::ProofOfWork => Box::new(ProofOfWork),
+            ConsensusMode::ProofOfAuthority(authorities) => {
+                Box::new(ProofOfAuthority::new(authorities.clone(), key))
+            }
+            ConsensusMode::ProofOfStake(bootstrap) => {
+                Box::new(ProofOfStake::new(bootstrap.clone(), key))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, MAX_DIFFICULTY, REWARD_SENDER};
+
+    // accepts any block, so a chain can grow without doing any work
+    struct Unsealed;
+
+    impl Consensus for Unsealed {
+        fn seal(&self, _chain: &Blockchain, _block: &mut Block) {}
+
+        fn valid_seal(&self, _chain: &Blockchain, _block: &Block) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_consensus() {
+        let mut chain = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        chain.set_consensus(Box::new(Unsealed));
+        let block = chain.mine_new_block().clone();
+        chain.mine_new_block();
+        assert!(Blockchain::valid_chain(&chain));
+
+        // the blocks were not mined, so proof of work rejects them
+        let mut other = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        assert!(!other.prefers(&chain));
+        assert!(!Blockchain::valid_chain(&Blockchain::from_blocks(
+            chain.get_blocks()
+        )));
+        assert!(!other.add_new_block(&block));
+        other.set_consensus(Box::new(Unsealed));
+        assert!(other.prefers(&chain));
+    }
+
+    #[test]
+    fn test_most_work() {
+        let mut long = Blockchain::with_difficulty(0);
+        for _ in 0..3 {
+            long.mine_new_block();
+        }
+        let mut hard = Blockchain::with_difficulty(4);
+        hard.mine_new_block();
+        assert_eq!((long.total_work(), hard.total_work()), (4, 32));
+        assert!(ProofOfWork.prefers(&long, &hard));
+        assert!(!ProofOfWork.prefers(&hard, &long));
+        assert!(!ProofOfWork.prefers(&hard, &hard));
+        // other engines go by length
+        assert!(Unsealed.prefers(&hard, &long));
+    }
+
+    #[test]
+    fn test_proof_of_authority() {
+        let (authority, outsider) = (NodeKey::generate(), NodeKey::generate());
+        let mode = ConsensusMode::ProofOfAuthority(vec![authority.public_key()]);
+        let mut chain = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        chain.set_consensus(mode.engine(Some(authority)));
+        assert!(chain.can_seal());
+        let block = chain.mine_new_block().clone();
+        assert!(block.get_seal().is_some());
+
+        // an authority's block is accepted without any work done
+        let mut follower = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        follower.set_consensus(mode.engine(None));
+        assert!(!follower.can_seal());
+        assert!(follower.add_new_block(&block));
+
+        // but not if it is signed by an outsider
+        let mut rogue = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        rogue.set_consensus(mode.engine(Some(outsider)));
+        rogue
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "rogue", 1))
+            .unwrap();
+        let mut forged = rogue.mine_new_block().clone();
+        let mut other = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        other.set_consensus(mode.engine(None));
+        assert!(!other.add_new_block(&forged));
+        // nor if the signature is moved to another block
+        forged.set_seal(block.get_seal().unwrap().clone());
+        assert!(!other.add_new_block(&forged));
+
+        // proof of work chains do not accept signed blocks
+        let mut mined = Blockchain::with_difficulty(MAX_DIFFICULTY);
+        assert!(!mined.add_new_block(&block));
+    }
+
+    #[test]
+    fn test_draw() {
+        let stakes: BTreeMap<String, i64> = vec![("a".to_owned(), 1), ("b".to_owned(), 3)]
+            .into_iter()
+            .collect();
+        let drawn: Vec<&str> = (0..8).map(|seed| draw(&stakes, seed)).collect();
+        assert_eq!(drawn, vec!["a", "b", "b", "b", "a", "b", "b", "b"]);
+    }
+
+    #[test]
+    fn test_proof_of_stake() {
+        let (first, second) = (NodeKey::generate(), NodeKey::generate());
+        let mode = ConsensusMode::ProofOfStake(vec![first.public_key()]);
+        let mut chains: Vec<Blockchain> = vec![first.clone(), second.clone()]
+            .into_iter()
+            .map(|key| {
+                let mut chain = Blockchain::with_difficulty(MAX_DIFFICULTY);
+                chain.set_consensus(mode.engine(Some(key)));
+                chain
+            })
+            .collect();
+
+        // nobody holds coins yet, so the bootstrap validator produces the first block
+        assert!(chains[0].can_seal());
+        assert!(!chains[1].can_seal());
+        let t = Transaction::new(REWARD_SENDER, &second.public_key(), 1);
+        chains[0].add_new_transaction(&t).unwrap();
+        let block = chains[0].mine_new_block().clone();
+        assert!(chains[1].add_new_block(&block));
+
+        // from now on the second key holds all the stake
+        assert!(!chains[0].can_seal());
+        assert!(chains[1].can_seal());
+        let forged = chains[0].mine_new_block().clone();
+        let block = chains[1].mine_new_block().clone();
+        let mut follower = Blockchain::from_blocks(chains[1].get_blocks()[..2].to_vec());
+        follower.set_consensus(mode.engine(None));
+        assert!(!follower.add_new_block(&forged));
+        assert!(follower.add_new_block(&block));
+        assert!(Blockchain::valid_chain(&follower));
+    }
+}
@@ -0,0 +1,95 @@
+//! The canonical encoding of block headers, which their hashes and proofs of work are taken over
+//!
+//! Unlike the JSON headers are stored and sent as, the encoding does not depend on how a serde
+//! version orders or formats fields: every field is written in a fixed order, integers as
+//! big-endian bytes, strings after their length, and optional fields after a byte telling
+//! whether they are set. It starts with its version, which a header names, so that the layout
+//! can change without changing the hashes of the blocks made before.
+//!
+//! Version 0 is no encoding of its own but the compact JSON of the header, which the blocks of
+//! older versions were hashed over, and genesis blocks still are so that chains keep their ids.
+//! Version 2 appends the block version and the signals of the miner to version 1.
+//! Version 3 appends the miner of the block and its signature, each optional, to version 2.
+
+/// The version of the encoding new blocks are hashed in.
+pub const ENCODING_VERSION: u32 = 3;
+
+/// Writes the fields of a header one after the other.
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    /// Starts an encoding of `version`.
+    pub fn new(version: u32) -> Self {
+        let mut encoder = Encoder { bytes: Vec::new() };
+        encoder.u32(version);
+        encoder
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Writes the UTF-8 bytes of `value` after their length.
+    pub fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Writes 0 if `value` is not set, 1 and then the value by `write` if it is.
+    pub fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                write(self, value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoder() {
+        let mut encoder = Encoder::new(1);
+        encoder.u64(2);
+        encoder.str("ab");
+        encoder.option(None::<u8>, Encoder::u8);
+        encoder.option(Some(-1), Encoder::i64);
+        assert_eq!(
+            encoder.finish(),
+            [
+                vec![0, 0, 0, 1],
+                vec![0, 0, 0, 0, 0, 0, 0, 2],
+                vec![0, 0, 0, 2, b'a', b'b'],
+                vec![0],
+                vec![1, 255, 255, 255, 255, 255, 255, 255, 255],
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Genesis specs, which set up the genesis block of a network of its own
+//!
+//! A spec is a JSON file like
+//!
+//! ```json
+//! {
+//!     "name": "testnet",
+//!     "timestamp": 1600000000000,
+//!     "difficulty": 8,
+//!     "address_prefix": "nb",
+//!     "allocations": [{"address": "nb1...", "amount": 100}]
+//! }
+//! ```
+//!
+//! Every field but the `address_prefix` may be left out. The allocations are minted by the
+//! genesis block and count towards the supply of the chain. Every address of the chain, those of
+//! the allocations included, must be a public key encoded under the prefix, see `Address`.
+//!
+//! Chains set up before specs needed a prefix keep their free-form addresses, like `alice`: their
+//! specs name the genesis block they make as `legacy_genesis` instead.
+
+use crate::address::invalid_address;
+use crate::config::{check_problems, ConfigProblem};
+use crate::{
+    is_address_prefix, is_valid_address, Address, Block, Result, RewardSchedule, TransactionModel,
+    MAX_DIFFICULTY, MAX_PREFIX_LEN,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The prefix of the addresses of new chains, unless their specs name another.
+pub const DEFAULT_ADDRESS_PREFIX: &str = "nb";
+
+/// What the genesis block of a chain holds, see the module docs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisSpec {
+    /// tells networks with the same parameters apart
+    #[serde(default)]
+    pub name: Option<String>,
+    /// in milliseconds since the Unix epoch
+    #[serde(default)]
+    pub timestamp: u128,
+    /// the difficulty the first blocks are mined with, `INITIAL_DIFFICULTY` if not set
+    #[serde(default)]
+    pub difficulty: Option<u32>,
+    #[serde(default)]
+    pub reward_schedule: Option<RewardSchedule>,
+    #[serde(default)]
+    pub transaction_model: Option<TransactionModel>,
+    /// the prefix addresses encode public keys under, only left out by legacy chains
+    #[serde(default)]
+    pub address_prefix: Option<String>,
+    /// the hash of the genesis block of a chain set up without a prefix, whose addresses stay
+    /// free-form, see the module docs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy_genesis: Option<String>,
+    /// the coins addresses hold from the start
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,
+}
+
+impl Default for GenesisSpec {
+    fn default() -> Self {
+        GenesisSpec {
+            name: None,
+            timestamp: 0,
+            difficulty: None,
+            reward_schedule: None,
+            transaction_model: None,
+            address_prefix: Some(DEFAULT_ADDRESS_PREFIX.to_owned()),
+            legacy_genesis: None,
+            allocations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Allocation {
+    pub address: String,
+    pub amount: i64,
+}
+
+impl GenesisSpec {
+    /// Reads the spec in the JSON file at `path`, checking that it makes a valid genesis block.
+    pub fn load(path: &Path) -> Result<Self> {
+        let spec: GenesisSpec = serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+            failure::err_msg(format!("{} is not a genesis spec: {}", path.display(), e))
+        })?;
+        spec.check()?;
+        Ok(spec)
+    }
+
+    /// Fails if the spec does not make a valid genesis block.
+    pub fn check(&self) -> Result<()> {
+        check_problems("genesis spec", self.problems())
+    }
+
+    /// What keeps the spec from making a valid genesis block.
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        if let Some(difficulty) = self.difficulty.filter(|&d| d > MAX_DIFFICULTY) {
+            problems.push(ConfigProblem::new(
+                "difficulty",
+                format!(
+                    "{} is more than the {} bits allowed",
+                    difficulty, MAX_DIFFICULTY
+                ),
+                format!("use at most {}", MAX_DIFFICULTY),
+            ));
+        }
+        if let Some(prefix) = self
+            .address_prefix
+            .as_ref()
+            .filter(|prefix| !is_address_prefix(prefix))
+        {
+            problems.push(ConfigProblem::new(
+                "address_prefix",
+                format!("{:?} is not a prefix of addresses", prefix),
+                format!("use 1 to {} lowercase letters", MAX_PREFIX_LEN),
+            ));
+        }
+        match (&self.address_prefix, &self.legacy_genesis) {
+            (None, None) => problems.push(ConfigProblem::new(
+                "address_prefix",
+                "new chains need a prefix of their addresses",
+                format!(
+                    "use {:?}, or set legacy_genesis to {} if the chain was set up without one",
+                    DEFAULT_ADDRESS_PREFIX,
+                    self.block().get_hash()
+                ),
+            )),
+            (None, Some(hash)) if *hash != self.block().get_hash() => {
+                problems.push(ConfigProblem::new(
+                    "legacy_genesis",
+                    format!(
+                        "the spec makes the genesis block {}, not {}",
+                        self.block().get_hash(),
+                        hash
+                    ),
+                    "name the genesis block of the chain set up without a prefix",
+                ))
+            }
+            (Some(_), Some(_)) => problems.push(ConfigProblem::new(
+                "legacy_genesis",
+                "chains with a prefix are no legacy chains",
+                "drop legacy_genesis",
+            )),
+            _ => {}
+        }
+        for (i, allocation) in self.allocations.iter().enumerate() {
+            if !is_valid_address(&allocation.address) {
+                problems.push(ConfigProblem::new(
+                    format!("allocations[{}].address", i),
+                    invalid_address(&allocation.address),
+                    "use 1 to 64 letters, digits, - or _",
+                ));
+            } else if let Some(prefix) = &self.address_prefix {
+                match allocation.address.parse::<Address>() {
+                    Ok(address) if address.prefix() == prefix => {}
+                    _ => problems.push(ConfigProblem::new(
+                        format!("allocations[{}].address", i),
+                        format!("{:?} is not an address of the chain", allocation.address),
+                        format!("use a public key encoded under {:?}", prefix),
+                    )),
+                }
+            }
+            if allocation.amount <= 0 {
+                problems.push(ConfigProblem::new(
+                    format!("allocations[{}].amount", i),
+                    format!("{} coins is not a positive amount", allocation.amount),
+                    "allocate 1 coin or more, or drop the allocation",
+                ));
+            }
+        }
+        let total = self.allocations.iter().try_fold(0i64, |total, allocation| {
+            total.checked_add(allocation.amount)
+        });
+        if total.is_none() {
+            problems.push(ConfigProblem::new(
+                "allocations",
+                "they add up to more coins than can be counted",
+                "allocate fewer coins",
+            ));
+        }
+        problems
+    }
+
+    /// The genesis block the spec sets up.
+    pub fn block(&self) -> Block {
+        Block::get_genesis_from(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Blockchain;
+
+    #[test]
+    fn test_genesis_spec() {
+        let key = crate::NodeKey::generate().public_key();
+        let address = Address::new("nb", &key).unwrap().to_string();
+        let spec: GenesisSpec = serde_json::from_str(&format!(
+            r#"{{"name": "testnet", "difficulty": 0, "address_prefix": "nb", "allocations": [
+                {{"address": "{0}", "amount": 100}}, {{"address": "{0}", "amount": 5}}
+            ]}}"#,
+            address
+        ))
+        .unwrap();
+        spec.check().unwrap();
+        let chain = Blockchain::from_blocks(vec![spec.block()]);
+        assert!(Blockchain::valid_chain(&chain));
+        assert_eq!(chain.get_supply(), 105);
+        assert_eq!(chain.get_balance(&address), 105);
+        // the same spec makes the same block, another name another one
+        assert_eq!(spec.block().get_hash(), chain.tip_hash());
+        let other = GenesisSpec {
+            name: Some("mainnet".to_owned()),
+            ..spec.clone()
+        };
+        assert_ne!(other.block().get_hash(), chain.tip_hash());
+
+        let invalid = GenesisSpec {
+            allocations: vec![Allocation {
+                address: address.clone(),
+                amount: 0,
+            }],
+            ..spec
+        };
+        assert_eq!(
+            invalid.problems(),
+            [ConfigProblem::new(
+                "allocations[0].amount",
+                "0 coins is not a positive amount",
+                "allocate 1 coin or more, or drop the allocation"
+            )]
+        );
+        assert!(invalid.check().is_err());
+        assert!(serde_json::from_str::<GenesisSpec>(r#"{"names": "typo"}"#).is_err());
+
+        // with a prefix, allocations go to public keys encoded under it only
+        let prefixed = GenesisSpec {
+            allocations: vec![
+                Allocation {
+                    address: address.clone(),
+                    amount: 1,
+                },
+                Allocation {
+                    address: "alice".to_owned(),
+                    amount: 1,
+                },
+            ],
+            ..GenesisSpec::default()
+        };
+        let problems = prefixed.problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].field, "allocations[1].address");
+        let problems = GenesisSpec {
+            address_prefix: Some("NB".to_owned()),
+            ..GenesisSpec::default()
+        }
+        .problems();
+        assert_eq!(problems[0].field, "address_prefix");
+
+        // chains without a prefix are legacy ones only, like the usual one
+        let usual = Block::get_genesis(crate::INITIAL_DIFFICULTY).get_hash();
+        assert_ne!(GenesisSpec::default().block().get_hash(), usual);
+        let unprefixed: GenesisSpec = serde_json::from_str(r#"{"allocations": []}"#).unwrap();
+        let problems = unprefixed.problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].field, "address_prefix");
+        assert!(problems[0].fix.contains(&usual));
+        let legacy = GenesisSpec {
+            legacy_genesis: Some(usual.clone()),
+            ..unprefixed.clone()
+        };
+        legacy.check().unwrap();
+        assert_eq!(legacy.block().get_hash(), usual);
+        let allocated = GenesisSpec {
+            allocations: vec![Allocation {
+                address: "alice".to_owned(),
+                amount: 1,
+            }],
+            ..legacy.clone()
+        };
+        assert_eq!(allocated.problems()[0].field, "legacy_genesis");
+        let problems = GenesisSpec {
+            legacy_genesis: Some(usual),
+            ..GenesisSpec::default()
+        }
+        .problems();
+        assert_eq!(problems[0].field, "legacy_genesis");
+    }
+}
@@ -0,0 +1,278 @@
+//! Node keys
+//!
+//! Every node has an ed25519 keypair, kept in its data directory, whose public key is its id.
+//! Public keys and signatures travel as lowercase hex.
+//!
+//! Any number of keys derive from the seed of a key along paths like `m/0'/5'`, as SLIP-0010
+//! does for ed25519, which BIP32 wallets use: hardened children only, as ed25519 keys do not
+//! add up like those of secp256k1.
+
+use crypto::ed25519;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha512;
+use serde::{Deserialize, Serialize};
+
+/// The keypair of a node, derived from a 32 byte seed.
+#[derive(Clone)]
+pub struct NodeKey {
+    seed: [u8; 32],
+    secret: [u8; 64],
+    public: [u8; 32],
+}
+
+impl NodeKey {
+    /// Generates a fresh random key.
+    pub fn generate() -> Self {
+        NodeKey::from_seed(rand::random())
+    }
+
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        let (secret, public) = ed25519::keypair(&seed);
+        NodeKey {
+            seed,
+            secret,
+            public,
+        }
+    }
+
+    /// Parses a seed written by `to_hex`.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let bytes = from_hex(hex)?;
+        if bytes.len() != 32 {
+            return None;
+        }
+        let mut seed = [0; 32];
+        seed.copy_from_slice(&bytes);
+        Some(NodeKey::from_seed(seed))
+    }
+
+    /// The seed of the key, which must be kept secret.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.seed)
+    }
+
+    pub fn public_key(&self) -> String {
+        to_hex(&self.public)
+    }
+
+    pub fn sign(&self, message: &[u8]) -> String {
+        to_hex(&ed25519::signature(message, &self.secret))
+    }
+
+    /// The key at `path` below this one, see `parse_path`.
+    pub fn derive(&self, path: &[u32]) -> NodeKey {
+        NodeKey::from_seed(derive(&self.seed, path).0)
+    }
+}
+
+// never log the secret half
+impl std::fmt::Debug for NodeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NodeKey").field(&self.public_key()).finish()
+    }
+}
+
+/// A node's statement that it replaced its key `old` with `new` at `at`, in milliseconds since the
+/// Unix epoch. Signed by both keys, so that only the holder of both can make it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyRotation {
+    pub old: String,
+    pub new: String,
+    pub at: u128,
+    pub old_signature: String,
+    pub new_signature: String,
+}
+
+impl KeyRotation {
+    pub fn new(old: &NodeKey, new: &NodeKey, at: u128) -> Self {
+        let message = KeyRotation::message(&old.public_key(), &new.public_key(), at);
+        KeyRotation {
+            old: old.public_key(),
+            new: new.public_key(),
+            at,
+            old_signature: old.sign(&message),
+            new_signature: new.sign(&message),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let message = KeyRotation::message(&self.old, &self.new, self.at);
+        self.old != self.new
+            && verify(&self.old, &message, &self.old_signature)
+            && verify(&self.new, &message, &self.new_signature)
+    }
+
+    fn message(old: &str, new: &str, at: u128) -> Vec<u8> {
+        format!("nb key rotation {} {} {}", old, new, at).into_bytes()
+    }
+}
+
+/// Whether `key` looks like a public key, i.e. 32 bytes of hex.
+pub fn is_public_key(key: &str) -> bool {
+    from_hex(key).is_some_and(|bytes| bytes.len() == 32)
+}
+
+/// Checks that `signature` was made for `message` by the holder of `public_key`.
+pub fn verify(public_key: &str, message: &[u8], signature: &str) -> bool {
+    match (from_hex(public_key), from_hex(signature)) {
+        (Some(public), Some(signature)) if public.len() == 32 && signature.len() == 64 => {
+            ed25519::verify(message, &public, &signature)
+        }
+        _ => false,
+    }
+}
+
+/// The first index of hardened children, which are the only ones ed25519 keys have.
+const HARDENED: u32 = 1 << 31;
+
+/// Parses a path of hardened children like `m/44'/0'` or `m/44h/0h` into the indexes of the
+/// children, `m` alone being the key itself.
+pub fn parse_path(path: &str) -> Option<Vec<u32>> {
+    let mut steps = path.split('/');
+    if steps.next() != Some("m") {
+        return None;
+    }
+    steps
+        .map(|step| {
+            let index = step.strip_suffix(&['\'', 'h'][..])?;
+            if index.starts_with('+') {
+                return None;
+            }
+            index.parse::<u32>().ok().filter(|index| *index < HARDENED)
+        })
+        .collect()
+}
+
+/// Writes `path` as `parse_path` reads it.
+pub fn path_to_string(path: &[u32]) -> String {
+    std::iter::once("m".to_owned())
+        .chain(path.iter().map(|index| format!("{}'", index)))
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// The secret key and chain code at `path` below the master key of `seed`, see SLIP-0010.
+fn derive(seed: &[u8], path: &[u32]) -> ([u8; 32], [u8; 32]) {
+    let split = |mac: &mut Hmac<Sha512>| {
+        let mut output = [0; 64];
+        mac.raw_result(&mut output);
+        let (mut key, mut chain_code) = ([0; 32], [0; 32]);
+        key.copy_from_slice(&output[..32]);
+        chain_code.copy_from_slice(&output[32..]);
+        (key, chain_code)
+    };
+    let mut mac = Hmac::new(Sha512::new(), b"ed25519 seed");
+    mac.input(seed);
+    let mut node = split(&mut mac);
+    for index in path {
+        let mut mac = Hmac::new(Sha512::new(), &node.1);
+        mac.input(&[0]);
+        mac.input(&node.0);
+        mac.input(&(index | HARDENED).to_be_bytes());
+        node = split(&mut mac);
+    }
+    node
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_key() {
+        let key = NodeKey::generate();
+        assert!(is_public_key(&key.public_key()));
+        assert!(!is_public_key("abc"));
+        assert_eq!(
+            NodeKey::from_hex(&key.to_hex()).unwrap().public_key(),
+            key.public_key()
+        );
+
+        let signature = key.sign(b"block");
+        assert!(verify(&key.public_key(), b"block", &signature));
+        assert!(!verify(&key.public_key(), b"other block", &signature));
+        let other = NodeKey::generate();
+        assert!(!verify(&other.public_key(), b"block", &signature));
+        assert!(!verify(&key.public_key(), b"block", "00"));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let (old, new) = (NodeKey::generate(), NodeKey::generate());
+        let rotation = KeyRotation::new(&old, &new, 42);
+        assert!(rotation.is_valid());
+        assert_eq!(rotation.new, new.public_key());
+
+        // neither key can be swapped out, nor the time changed
+        let forged = NodeKey::generate();
+        let mut hijacked = rotation.clone();
+        hijacked.new = forged.public_key();
+        assert!(!hijacked.is_valid());
+        let mut sneaked = KeyRotation::new(&forged, &new, 42);
+        sneaked.old = old.public_key();
+        assert!(!sneaked.is_valid());
+        let mut backdated = rotation;
+        backdated.at = 41;
+        assert!(!backdated.is_valid());
+        assert!(!KeyRotation::new(&old, &old, 42).is_valid());
+    }
+
+    #[test]
+    fn test_derivation() {
+        // test vector 1 for ed25519 of SLIP-0010
+        let seed = from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        let (key, chain_code) = derive(&seed, &[]);
+        assert_eq!(
+            to_hex(&key),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            to_hex(&chain_code),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+        let (key, chain_code) = derive(&seed, &parse_path("m/0'").unwrap());
+        assert_eq!(
+            to_hex(&key),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        assert_eq!(
+            to_hex(&chain_code),
+            "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69"
+        );
+
+        let key = NodeKey::generate();
+        let path = parse_path("m/0'/5h").unwrap();
+        assert_eq!(path, [0, 5]);
+        assert_eq!(path_to_string(&path), "m/0'/5'");
+        assert_eq!(
+            key.derive(&path).public_key(),
+            NodeKey::from_hex(&key.to_hex())
+                .unwrap()
+                .derive(&path)
+                .public_key()
+        );
+        assert_ne!(
+            key.derive(&path).public_key(),
+            key.derive(&[0, 6]).public_key()
+        );
+        assert_ne!(key.derive(&[]).public_key(), key.public_key());
+        for path in ["0'", "m/0", "m/-1'", "m/+1'", "m/2147483648'", "m/"] {
+            assert_eq!(parse_path(path), None, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,613 @@
+//! Wallet keys
+//!
+//! A wallet is a named ed25519 key, whose public key is the address it receives coins on and
+//! which signs the transactions spending them, see `Transaction::sign`. The keystore keeps
+//! every wallet in a file of its own in the `wallets` directory of the data directory, or in
+//! memory for ephemeral nodes.
+//!
+//! The seed of the key is encrypted under the passphrase of the wallet: Argon2id stretches the
+//! passphrase into the key of ChaCha20-Poly1305, which authenticates the address along with the
+//! seed. Only the address is kept in the clear, so that wallets are listed without their
+//! passphrases, which are asked for to sign. Wallets of older versions, whose seeds were kept in
+//! the clear or stretched by scrypt, are still read.
+//!
+//! A wallet receives on more addresses than its own, with keys derived from its seed along
+//! paths, see `NodeKey::derive`, so that one backup of the seed covers them all. The addresses
+//! derived so far are kept in the wallet in the clear, and their keys derived again to sign.
+
+use crate::argon2::{self, argon2id};
+use crate::key::{from_hex, parse_path, path_to_string, to_hex};
+use crate::{is_valid_address, NodeKey, Result};
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// The extension of the file of a wallet.
+const WALLET_EXTENSION: &str = "json";
+
+/// The extension of the file of a wallet of older versions, holding its seed in hex.
+const PLAIN_EXTENSION: &str = "key";
+
+/// The Argon2id costs new wallets are encrypted with: 19 MiB of memory and two passes per try
+/// of a passphrase. Wallets naming higher costs are not read, so that reading one cannot
+/// exhaust the memory.
+const ARGON2_COSTS: argon2::Params = argon2::Params {
+    m_cost: 19 * 1024,
+    t_cost: 2,
+    p_cost: 1,
+};
+
+/// The scrypt cost wallets of older versions were encrypted with, the log2 of N, with r = 8
+/// and p = 1: 32 MiB of memory per try of a passphrase. Wallets naming other costs are not read.
+const SCRYPT_LOG_N: u8 = 15;
+
+/// The first step of the paths of receive addresses, `m/0'/n'` for the nth.
+const RECEIVE_BRANCH: u32 = 0;
+
+/// A passphrase, which never shows in logs.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    pub fn new(passphrase: &str) -> Self {
+        Passphrase(passphrase.to_owned())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Passphrase(..)")
+    }
+}
+
+/// How the passphrase of a wallet is stretched into the key of its cipher.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Kdf {
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+    /// of wallets of older versions only
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+impl Kdf {
+    /// The key of the cipher, `None` if the costs are not ones this version reads.
+    fn stretch(self, passphrase: &Passphrase, salt: &[u8]) -> Option<[u8; 32]> {
+        let mut key = [0; 32];
+        let passphrase = passphrase.0.as_bytes();
+        match self {
+            Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                if !(8..=ARGON2_COSTS.m_cost).contains(&m_cost)
+                    || !(1..=ARGON2_COSTS.t_cost).contains(&t_cost)
+                    || p_cost != ARGON2_COSTS.p_cost
+                {
+                    return None;
+                }
+                let params = argon2::Params {
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                };
+                argon2id(passphrase, salt, params, &mut key);
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                if !(1..=SCRYPT_LOG_N).contains(&log_n) || r != 8 || p != 1 {
+                    return None;
+                }
+                scrypt(passphrase, salt, &ScryptParams::new(log_n, r, p), &mut key);
+            }
+        }
+        Some(key)
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Argon2id {
+            m_cost: ARGON2_COSTS.m_cost,
+            t_cost: ARGON2_COSTS.t_cost,
+            p_cost: ARGON2_COSTS.p_cost,
+        }
+    }
+}
+
+/// A wallet as it is stored, see the module docs.
+#[derive(Clone, Serialize, Deserialize)]
+struct EncryptedKey {
+    address: String,
+    #[serde(flatten)]
+    kdf: Kdf,
+    salt: String,
+    nonce: String,
+    /// the encrypted seed
+    ciphertext: String,
+    tag: String,
+    /// the addresses derived from the seed, see `Keystore::receive`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    derived: Vec<DerivedAddress>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct DerivedAddress {
+    path: String,
+    address: String,
+}
+
+impl EncryptedKey {
+    fn seal(key: &NodeKey, passphrase: &Passphrase, kdf: Kdf) -> Self {
+        let salt: [u8; 16] = rand::random();
+        let nonce: [u8; 8] = rand::random();
+        let address = key.public_key();
+        let seed = from_hex(&key.to_hex()).expect("a seed is hex");
+        let mut ciphertext = vec![0; seed.len()];
+        let mut tag = [0; 16];
+        let cipher_key = kdf
+            .stretch(passphrase, &salt)
+            .expect("wallets are sealed at costs they are read at");
+        ChaCha20Poly1305::new(&cipher_key, &nonce, address.as_bytes()).encrypt(
+            &seed,
+            &mut ciphertext,
+            &mut tag,
+        );
+        EncryptedKey {
+            address,
+            kdf,
+            salt: to_hex(&salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+            tag: to_hex(&tag),
+            derived: Vec::new(),
+        }
+    }
+
+    /// The key, `None` if the passphrase is wrong or the wallet was tampered with.
+    fn open(&self, passphrase: &Passphrase) -> Option<NodeKey> {
+        let (salt, nonce, ciphertext, tag) = (
+            from_hex(&self.salt)?,
+            from_hex(&self.nonce)?,
+            from_hex(&self.ciphertext)?,
+            from_hex(&self.tag)?,
+        );
+        if nonce.len() != 8 || tag.len() != 16 {
+            return None;
+        }
+        let cipher_key = self.kdf.stretch(passphrase, &salt)?;
+        let mut seed = vec![0; ciphertext.len()];
+        if !ChaCha20Poly1305::new(&cipher_key, &nonce, self.address.as_bytes()).decrypt(
+            &ciphertext,
+            &mut seed,
+            &tag,
+        ) {
+            return None;
+        }
+        NodeKey::from_hex(&to_hex(&seed)).filter(|key| key.public_key() == self.address)
+    }
+}
+
+pub struct Keystore {
+    // `None` for wallets kept in memory
+    dir: Option<PathBuf>,
+    memory: BTreeMap<String, EncryptedKey>,
+    // the costs new wallets are sealed at
+    kdf: Kdf,
+}
+
+impl Keystore {
+    /// The keystore in `dir`, which is created with the first wallet.
+    pub fn open(dir: &Path) -> Self {
+        Keystore {
+            dir: Some(dir.to_owned()),
+            memory: BTreeMap::new(),
+            kdf: Kdf::default(),
+        }
+    }
+
+    /// A keystore losing its wallets once dropped.
+    pub fn memory() -> Self {
+        Keystore {
+            dir: None,
+            memory: BTreeMap::new(),
+            kdf: Kdf::default(),
+        }
+    }
+
+    /// Creates the wallet `name` with a fresh key encrypted under `passphrase`, failing if it
+    /// exists.
+    pub fn create(&mut self, name: &str, passphrase: &Passphrase) -> Result<NodeKey> {
+        if !is_valid_address(name) {
+            return Err(failure::err_msg(format!(
+                "{:?} is not a valid wallet name, use 1 to 64 letters, digits, - or _",
+                name
+            )));
+        }
+        if passphrase.is_empty() {
+            return Err(failure::err_msg("A wallet needs a passphrase"));
+        }
+        let exists = || failure::err_msg(format!("The wallet {} exists already", name));
+        if self.stored(name)?.is_some() {
+            return Err(exists());
+        }
+        let key = NodeKey::generate();
+        let sealed = EncryptedKey::seal(&key, passphrase, self.kdf);
+        match &self.dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                match write_new(&path(dir, name, WALLET_EXTENSION), &sealed) {
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(exists()),
+                    written => written?,
+                }
+            }
+            None => {
+                self.memory.insert(name.to_owned(), sealed);
+            }
+        }
+        Ok(key)
+    }
+
+    /// Derives a receive address of the wallet `name` at the path `at`, by default the first
+    /// `m/0'/n'` not derived yet, and keeps it in the wallet.
+    pub fn receive(
+        &mut self,
+        name: &str,
+        passphrase: &Passphrase,
+        at: Option<&[u32]>,
+    ) -> Result<String> {
+        let mut sealed = match self.stored(name)? {
+            Some(Stored::Encrypted(sealed)) => sealed,
+            Some(Stored::Plain(_)) => {
+                return Err(failure::err_msg(format!(
+                    "The wallet {} is not encrypted, move its coins to a new wallet to derive addresses",
+                    name
+                )))
+            }
+            None => return Err(missing(name)),
+        };
+        let key = sealed
+            .open(passphrase)
+            .ok_or_else(|| wrong_passphrase(name))?;
+        let is_derived = |path: &[u32]| {
+            let path = path_to_string(path);
+            sealed.derived.iter().any(|derived| derived.path == path)
+        };
+        let at = match at {
+            Some(path) if is_derived(path) => {
+                return Err(failure::err_msg(format!(
+                    "The wallet {} derived {} already",
+                    name,
+                    path_to_string(path)
+                )))
+            }
+            Some(path) => path.to_vec(),
+            None => (0..)
+                .map(|n| vec![RECEIVE_BRANCH, n])
+                .find(|path| !is_derived(path))
+                .expect("a wallet derives fewer than 2^31 addresses"),
+        };
+        let address = key.derive(&at).public_key();
+        sealed.derived.push(DerivedAddress {
+            path: path_to_string(&at),
+            address: address.clone(),
+        });
+        match &self.dir {
+            Some(dir) => {
+                // replaced whole, so that a crash leaves either wallet
+                let file = path(dir, name, WALLET_EXTENSION);
+                let temporary = file.with_extension("tmp");
+                let _ = fs::remove_file(&temporary);
+                write_new(&temporary, &sealed)?;
+                fs::rename(&temporary, &file)?;
+            }
+            None => {
+                self.memory.insert(name.to_owned(), sealed);
+            }
+        }
+        Ok(address)
+    }
+
+    /// The key of the wallet `name`, decrypted with `passphrase`.
+    pub fn key(&self, name: &str, passphrase: &Passphrase) -> Result<NodeKey> {
+        match self.stored(name)? {
+            Some(Stored::Encrypted(sealed)) => sealed
+                .open(passphrase)
+                .ok_or_else(|| wrong_passphrase(name)),
+            Some(Stored::Plain(key)) => Ok(key),
+            None => Err(missing(name)),
+        }
+    }
+
+    /// The keys of the wallet `name`, its own first, then those of the addresses it derived.
+    pub fn keys(&self, name: &str, passphrase: &Passphrase) -> Result<Vec<NodeKey>> {
+        let key = self.key(name, passphrase)?;
+        let mut keys = vec![key.clone()];
+        if let Some(Stored::Encrypted(sealed)) = self.stored(name)? {
+            for derived in &sealed.derived {
+                let path = parse_path(&derived.path).ok_or_else(|| {
+                    failure::err_msg(format!("The wallet {} holds a bad path", name))
+                })?;
+                keys.push(key.derive(&path));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// The address of the wallet `name`.
+    pub fn address(&self, name: &str) -> Result<String> {
+        match self.stored(name)? {
+            Some(Stored::Encrypted(sealed)) => Ok(sealed.address),
+            Some(Stored::Plain(key)) => Ok(key.public_key()),
+            None => Err(missing(name)),
+        }
+    }
+
+    /// The addresses of the wallet `name`, its own first, labelled `key`, then those it derived,
+    /// labelled by their paths.
+    pub fn addresses(&self, name: &str) -> Result<Vec<(String, String)>> {
+        let mut addresses = vec![("key".to_owned(), self.address(name)?)];
+        if let Some(Stored::Encrypted(sealed)) = self.stored(name)? {
+            addresses.extend(
+                sealed
+                    .derived
+                    .into_iter()
+                    .map(|derived| (derived.path, derived.address)),
+            );
+        }
+        Ok(addresses)
+    }
+
+    /// The wallets by name, with their own addresses.
+    pub fn list(&self) -> Result<Vec<(String, String)>> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                return Ok(self
+                    .memory
+                    .iter()
+                    .map(|(name, sealed)| (name.clone(), sealed.address.clone()))
+                    .collect())
+            }
+        };
+        let entries = match fs::read_dir(dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            entries => entries?,
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension == Some(WALLET_EXTENSION) || extension == Some(PLAIN_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| Ok((name.clone(), self.address(&name)?)))
+            .collect()
+    }
+
+    /// The wallet `name` as it is stored, if any.
+    fn stored(&self, name: &str) -> Result<Option<Stored>> {
+        let dir = match &self.dir {
+            Some(dir) if is_valid_address(name) => dir,
+            Some(_) => return Ok(None),
+            None => return Ok(self.memory.get(name).cloned().map(Stored::Encrypted)),
+        };
+        let invalid =
+            |path: &Path| failure::err_msg(format!("{} does not hold a wallet", path.display()));
+        let path = path(dir, name, WALLET_EXTENSION);
+        match fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            json => {
+                let sealed = serde_json::from_str(&json?).map_err(|_| invalid(&path))?;
+                return Ok(Some(Stored::Encrypted(sealed)));
+            }
+        }
+        let path = path.with_extension(PLAIN_EXTENSION);
+        match fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            seed => {
+                warn!(
+                    target: crate::logging::STORAGE,
+                    "The wallet {} is not encrypted, move its coins to a new wallet",
+                    name
+                );
+                let key = NodeKey::from_hex(seed?.trim()).ok_or_else(|| invalid(&path))?;
+                Ok(Some(Stored::Plain(key)))
+            }
+        }
+    }
+}
+
+enum Stored {
+    Encrypted(EncryptedKey),
+    // of older versions
+    Plain(NodeKey),
+}
+
+fn path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    dir.join(name).with_extension(extension)
+}
+
+fn missing(name: &str) -> failure::Error {
+    failure::err_msg(format!("No wallet {}, see wallet new", name))
+}
+
+fn wrong_passphrase(name: &str) -> failure::Error {
+    failure::err_msg(format!("Wrong passphrase for the wallet {}", name))
+}
+
+/// Writes `sealed` into a new file at `path`, which only its owner may read.
+fn write_new(path: &Path, sealed: &EncryptedKey) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        // only the owner tries passphrases on it
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", serde_json::to_string_pretty(sealed)?)?;
+    file.sync_all()
+}
+
+/// Asks the user for a passphrase on the terminal, without echoing it, or reads a line of
+/// stdin if it is not a terminal.
+pub fn read_passphrase(prompt: &str) -> Result<Passphrase> {
+    use std::io::IsTerminal;
+
+    let stdin = io::stdin();
+    let hidden = stdin.is_terminal() && stty("-echo");
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    let read = stdin.lock().read_line(&mut line);
+    if hidden {
+        stty("echo");
+        eprintln!();
+    }
+    if read? == 0 {
+        return Err(failure::err_msg("No passphrase was entered"));
+    }
+    Ok(Passphrase::new(line.trim_end_matches(&['\r', '\n'][..])))
+}
+
+/// Asks for the passphrase of a new wallet twice.
+pub fn read_new_passphrase() -> Result<Passphrase> {
+    let passphrase = read_passphrase("Passphrase of the new wallet: ")?;
+    if read_passphrase("Repeat it: ")? != passphrase {
+        return Err(failure::err_msg("The passphrases differ"));
+    }
+    Ok(passphrase)
+}
+
+// switches the echo of the terminal
+fn stty(arg: &str) -> bool {
+    cfg!(unix)
+        && std::process::Command::new("stty")
+            .arg(arg)
+            .status()
+            .is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore() {
+        let dir = std::env::temp_dir().join(format!("nb-keystore-{}", std::process::id()));
+        let passphrase = Passphrase::new("correct horse");
+        for mut keystore in [Keystore::open(&dir), Keystore::memory()] {
+            // cheap to try passphrases on, for the test only
+            keystore.kdf = Kdf::Argon2id {
+                m_cost: 8,
+                t_cost: 1,
+                p_cost: 1,
+            };
+            assert!(keystore.list().unwrap().is_empty());
+            let alice = keystore.create("alice", &passphrase).unwrap();
+            assert!(keystore.create("alice", &passphrase).is_err());
+            assert!(keystore.create("../alice", &passphrase).is_err());
+            assert!(keystore.create("bob", &Passphrase::default()).is_err());
+            let bob = keystore.create("bob", &passphrase).unwrap();
+            assert_eq!(
+                keystore.key("alice", &passphrase).unwrap().public_key(),
+                alice.public_key()
+            );
+            assert!(keystore.key("alice", &Passphrase::new("wrong")).is_err());
+            assert!(keystore.key("carol", &passphrase).is_err());
+            assert_eq!(keystore.address("bob").unwrap(), bob.public_key());
+
+            // receive addresses, derived from the seed of the wallet
+            let first = keystore.receive("alice", &passphrase, None).unwrap();
+            assert_eq!(first, alice.derive(&[0, 0]).public_key());
+            let custom = keystore
+                .receive("alice", &passphrase, Some(&[44, 1]))
+                .unwrap();
+            let second = keystore.receive("alice", &passphrase, None).unwrap();
+            assert_eq!(second, alice.derive(&[0, 1]).public_key());
+            assert!(keystore
+                .receive("alice", &passphrase, Some(&[44, 1]))
+                .is_err());
+            let wrong = Passphrase::new("wrong");
+            assert!(keystore.receive("alice", &wrong, None).is_err());
+            assert_eq!(
+                keystore.addresses("alice").unwrap(),
+                [
+                    ("key".to_owned(), alice.public_key()),
+                    ("m/0'/0'".to_owned(), first),
+                    ("m/44'/1'".to_owned(), custom),
+                    ("m/0'/1'".to_owned(), second.clone()),
+                ]
+            );
+            let keys = keystore.keys("alice", &passphrase).unwrap();
+            assert_eq!(keys.len(), 4);
+            assert_eq!(keys[3].public_key(), second);
+            assert_eq!(
+                keystore.list().unwrap(),
+                [
+                    ("alice".to_owned(), alice.public_key()),
+                    ("bob".to_owned(), bob.public_key())
+                ]
+            );
+        }
+
+        // the seed is nowhere in the file, and the address cannot be swapped
+        let file = path(&dir, "alice", WALLET_EXTENSION);
+        let json = fs::read_to_string(&file).unwrap();
+        let mut sealed: EncryptedKey = serde_json::from_str(&json).unwrap();
+        let keystore = Keystore::open(&dir);
+        let alice = keystore.key("alice", &passphrase).unwrap();
+        assert!(!json.contains(&alice.to_hex()));
+        sealed.address = keystore.address("bob").unwrap();
+        assert!(sealed.open(&passphrase).is_none());
+
+        // wallets of older versions, read at their costs only
+        let scrypt = |log_n, r| Kdf::Scrypt { log_n, r, p: 1 };
+        let bob = keystore.key("bob", &passphrase).unwrap();
+        let sealed = EncryptedKey::seal(&bob, &passphrase, scrypt(4, 8));
+        assert_eq!(
+            sealed.open(&passphrase).unwrap().public_key(),
+            bob.public_key()
+        );
+        let json = serde_json::to_string(&sealed).unwrap();
+        let sealed: EncryptedKey = serde_json::from_str(&json).unwrap();
+        assert_eq!(sealed.kdf, scrypt(4, 8));
+        assert!(sealed.open(&passphrase).is_some());
+        assert!(scrypt(20, 8).stretch(&passphrase, &[]).is_none());
+        assert!(scrypt(4, 16).stretch(&passphrase, &[]).is_none());
+        let costly = Kdf::Argon2id {
+            m_cost: ARGON2_COSTS.m_cost * 2,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        assert!(costly.stretch(&passphrase, &[]).is_none());
+        let plain = NodeKey::generate();
+        fs::write(path(&dir, "carol", PLAIN_EXTENSION), plain.to_hex()).unwrap();
+        assert_eq!(keystore.address("carol").unwrap(), plain.public_key());
+        assert_eq!(keystore.list().unwrap().len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
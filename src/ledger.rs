@@ -0,0 +1,802 @@
+//! Account balances
+//!
+//! Coins only come into being through mining rewards, and every other transaction moves coins
+//! its sender holds, applied in the order of the chain. On UTXO chains the coins an address
+//! holds are the unspent outputs it received, which transactions spend as a whole.
+//!
+//! The fees senders pay go to the miner of the block, whose rewards may claim them on top of
+//! the reward of the schedule. Only what they claim beyond the fees is minted.
+//!
+//! Within a block, transactions apply in the order they are listed, each on top of the ones
+//! before it: a transaction may spend what an earlier one of the block paid its sender, or the
+//! outputs it created, but never what a later one will. Transactions without such a dependency
+//! keep whatever order the miner chose, and the mining node lists rewards first, then the
+//! others by fee, dependents after what they depend on. `Ledger::misordered` tells a block
+//! listing a transaction before one it depends on apart from one spending coins that do not
+//! exist at all.
+//!
+//! A transaction spending coins that an earlier one already spent is a double spend. Checking
+//! affordability rejects it like any other overspending, and `Ledger::double_spend` tells it
+//! apart, naming the transaction that spent the coins first.
+//!
+//! Chains may set a coinbase maturity, see `RewardSchedule::coinbase_maturity`: what the rewards
+//! of a block pay, fees included, may only be spent by blocks that many after it. On account
+//! chains the immature rewards of an address are held back from its balance, on UTXO chains
+//! their outputs cannot be spent yet. The allocations of the genesis block are spendable right
+//! away.
+//!
+//! On account chains, transactions with an asset id move tokens of that asset instead of coins,
+//! each address holding a balance of every asset apart from its coins, while the fee is still
+//! paid in coins. Tokens only come into being through issuances, which the issuer of the asset
+//! makes up to its max supply, see `Issuance`.
+
+use crate::logging::MEMPOOL;
+use crate::{
+    Block, Issuance, OutPoint, Output, Transaction, TransactionModel, Utxo, REWARD_SENDER,
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// What a block minted, collected and moved, as `Ledger::record` applied it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BlockAccounting {
+    /// the coins the block minted, what its rewards claimed beyond the fees
+    pub subsidy: i64,
+    /// the fees its transactions paid to the miner
+    pub fees: i64,
+    /// where its first reward went, `None` if it has none
+    pub miner: Option<String>,
+    /// the coins its transactions but the rewards paid, leaving out fees and change
+    pub transferred: i64,
+}
+
+/// A token issued on the chain, see `Transaction::issue`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Asset {
+    /// the sender of its first issuance, the only one issuing more
+    pub issuer: String,
+    pub max_supply: i64,
+    /// how many tokens were issued so far, at most `max_supply`
+    pub issued: i64,
+}
+
+/// The balance of every address, the coins minted and the work done, after the blocks recorded
+/// so far.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ledger {
+    model: TransactionModel,
+    balances: HashMap<String, i64>,
+    // the outputs no transaction spent yet, and the transactions that spent the others, on
+    // UTXO chains
+    unspent: HashMap<OutPoint, Output>,
+    spent_by: HashMap<OutPoint, String>,
+    // the last transaction of every sender and its balance before, on account chains
+    last_spend: HashMap<String, (String, i64)>,
+    // the assets issued so far, and the tokens of them every address holds, by (asset, address)
+    assets: HashMap<String, Asset>,
+    holdings: HashMap<(String, String), i64>,
+    // set by the genesis block, see `RewardSchedule::coinbase_maturity`
+    maturity: u64,
+    // the index of the next block
+    next_index: u64,
+    // the rewards not spendable yet, by the index of their block: what they paid to whom on
+    // account chains, and the outputs they created on UTXO chains
+    immature: VecDeque<(u64, String, i64)>,
+    immature_outputs: HashMap<OutPoint, u64>,
+    minted: i64,
+    // in expected header hashes, see `Block::work`
+    work: u128,
+}
+
+impl Ledger {
+    /// An empty ledger of a chain moving coins by `model`.
+    pub fn new(model: TransactionModel) -> Self {
+        Ledger {
+            model,
+            ..Ledger::default()
+        }
+    }
+
+    pub fn balance(&self, address: &str) -> i64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// The tokens of `asset` that `address` holds.
+    pub fn holding(&self, asset: &str, address: &str) -> i64 {
+        self.holdings
+            .get(&(asset.to_owned(), address.to_owned()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn asset(&self, asset: &str) -> Option<&Asset> {
+        self.assets.get(asset)
+    }
+
+    pub fn minted(&self) -> i64 {
+        self.minted
+    }
+
+    pub fn work(&self) -> u128 {
+        self.work
+    }
+
+    /// Whether `block` mints exactly `reward` and the fees of its transactions, or nothing, and
+    /// every sender can afford what it sends, fee included.
+    pub fn admits(&self, block: &Block, reward: i64) -> bool {
+        let mut changes = Changes::new(self, Some(reward));
+        block.get_transactions().iter().all(|t| changes.transfer(t)) && changes.settles()
+    }
+
+    /// Whether every sender of `block` can afford what it sends, fee included, whatever the
+    /// block mints.
+    pub fn affords_block(&self, block: &Block) -> bool {
+        let mut changes = Changes::new(self, None);
+        block.get_transactions().iter().all(|t| changes.transfer(t))
+    }
+
+    /// The first transaction of `block` that cannot be made where it is listed but could after
+    /// a later one of the block, with the id of that one.
+    pub fn misordered(&self, block: &Block) -> Option<(String, String)> {
+        let transactions = block.get_transactions();
+        let mut changes = Changes::new(self, None);
+        for (i, t) in transactions.iter().enumerate() {
+            if changes.transfer(t) {
+                continue;
+            }
+            return transactions[i + 1..].iter().find_map(|later| {
+                let mut reordered = changes.clone();
+                if reordered.transfer(later) && reordered.transfer(t) {
+                    Some((t.get_id().to_owned(), later.get_id().to_owned()))
+                } else {
+                    None
+                }
+            });
+        }
+        None
+    }
+
+    /// Whether `transaction` can still be made after the `pending` ones. Rewards are not
+    /// limited here, only by the blocks minting them.
+    pub fn affords(&self, pending: &[Transaction], transaction: &Transaction) -> bool {
+        let mut changes = Changes::new(self, None);
+        for t in pending {
+            changes.transfer(t);
+        }
+        changes.transfer(transaction)
+    }
+
+    /// Whether `transaction` could be made after the `pending` ones if every reward were
+    /// spendable, see `affords`.
+    pub fn affords_once_mature(&self, pending: &[Transaction], transaction: &Transaction) -> bool {
+        let mut changes = Changes::new(self, None);
+        changes.maturity = 0;
+        for t in pending {
+            changes.transfer(t);
+        }
+        changes.transfer(transaction)
+    }
+
+    /// The balance of `address` after those of the `pending` transactions that can be made.
+    pub fn balance_after(&self, pending: &[Transaction], address: &str) -> i64 {
+        let mut changes = Changes::new(self, None);
+        for t in pending {
+            changes.transfer(t);
+        }
+        changes.balance(address)
+    }
+
+    /// The asset `asset` after those of the `pending` transactions that can be made, `None` if
+    /// it is not issued yet.
+    pub fn asset_after(&self, pending: &[Transaction], asset: &str) -> Option<Asset> {
+        let mut changes = Changes::new(self, None);
+        for t in pending {
+            changes.transfer(t);
+        }
+        changes.asset(asset).cloned()
+    }
+
+    /// The first of `transactions` spending coins that the chain or an earlier one of them
+    /// already spent, with the id of the transaction that spent them first.
+    ///
+    /// On account chains the coins of a sender are its balance, which the last transaction of
+    /// the sender spent if the balance before it covered the transaction but the one after does
+    /// not.
+    pub fn double_spend(&self, transactions: &[Transaction]) -> Option<(String, String)> {
+        let mut changes = Changes::new(self, None);
+        let mut spent_by: HashMap<&OutPoint, &str> = HashMap::new();
+        let mut last_spend: HashMap<&str, (&str, i64)> = HashMap::new();
+        for t in transactions {
+            let sender = t.get_sender();
+            let before = changes.balance(sender);
+            let first = match t.get_utxo() {
+                // tokens are not coins
+                None if t.get_asset_id().is_some() => {
+                    changes.transfer(t);
+                    None
+                }
+                Some(utxo) => utxo.inputs.iter().find_map(|point| {
+                    spent_by
+                        .get(point)
+                        .copied()
+                        .or_else(|| self.spent_by.get(point).map(String::as_str))
+                }),
+                None if changes.transfer(t) => {
+                    if sender != REWARD_SENDER {
+                        last_spend.insert(sender, (t.get_id(), before));
+                    }
+                    None
+                }
+                None => {
+                    let cost = t.get_amount().saturating_add(t.get_fee());
+                    last_spend
+                        .get(sender)
+                        .copied()
+                        .or_else(|| {
+                            let (id, balance) = self.last_spend.get(sender)?;
+                            Some((id.as_str(), *balance))
+                        })
+                        .filter(|(_, balance)| t.get_amount() > 0 && cost <= *balance)
+                        .map(|(id, _)| id)
+                }
+            };
+            if let Some(first) = first {
+                return Some((t.get_id().to_owned(), first.to_owned()));
+            }
+            for point in t.get_utxo().map_or(&[][..], |utxo| &utxo.inputs) {
+                spent_by.insert(point, t.get_id());
+            }
+        }
+        None
+    }
+
+    /// Orders `transactions` to fill a block, rewards first, then the others by fee, highest
+    /// first, keeping their order among equal fees. Those that cannot be made are left out, but a
+    /// transaction spending what another one receives still makes it after that one.
+    pub fn prioritized(&self, mut transactions: Vec<Transaction>) -> Vec<Transaction> {
+        transactions.sort_by_key(|t| (t.get_sender() != REWARD_SENDER, Reverse(t.get_fee())));
+        let mut changes = Changes::new(self, None);
+        let mut chosen = Vec::new();
+        loop {
+            let before = chosen.len();
+            transactions.retain(|t| {
+                let affordable = changes.transfer(t);
+                if affordable {
+                    chosen.push(t.clone());
+                }
+                !affordable
+            });
+            if chosen.len() == before {
+                break;
+            }
+        }
+        for t in transactions {
+            debug!(
+                target: MEMPOOL,
+                "Leaving out transaction {}, its sender cannot afford it",
+                t.get_id()
+            );
+        }
+        chosen
+    }
+
+    /// Picks unspent outputs of `sender` worth at least `amount` that none of the `pending`
+    /// transactions spends, oldest first. Returns them with what they are worth.
+    pub fn select(
+        &self,
+        pending: &[Transaction],
+        sender: &str,
+        amount: i64,
+    ) -> Option<(Vec<OutPoint>, i64)> {
+        if amount <= 0 {
+            return None;
+        }
+        let mut changes = Changes::new(self, None);
+        for t in pending {
+            changes.transfer(t);
+        }
+        let mut owned: Vec<(&OutPoint, &Output)> = self
+            .unspent
+            .iter()
+            .filter(|(point, _)| !changes.spent.contains(*point))
+            .chain(changes.created.iter())
+            .filter(|(point, output)| output.recipient == sender && !changes.is_immature(point))
+            .collect();
+        owned.sort_by(|a, b| a.0.cmp(b.0));
+        let (mut inputs, mut total) = (Vec::new(), 0i64);
+        for (point, output) in owned {
+            if total >= amount {
+                break;
+            }
+            inputs.push(point.clone());
+            total = total.saturating_add(output.amount);
+        }
+        if total >= amount {
+            Some((inputs, total))
+        } else {
+            None
+        }
+    }
+
+    /// Applies the transactions of `block` as they are, checked or not, returning what the block
+    /// minted, collected and moved.
+    pub fn record(&mut self, block: &Block) -> BlockAccounting {
+        let index = block.get_index();
+        if index == 0 {
+            self.maturity = block.reward_schedule().coinbase_maturity;
+        }
+        self.next_index = index.saturating_add(1);
+        let (mut claimed, mut fees, mut transferred) = (0i64, 0i64, 0i64);
+        for t in block.get_transactions() {
+            let paid = match (self.model, t.get_utxo(), t.get_asset_id()) {
+                (TransactionModel::Utxo, Some(utxo), _) => self.record_utxo(t, utxo),
+                (_, None, Some(asset)) => {
+                    self.record_asset(t, asset);
+                    0
+                }
+                _ => self.record_transfer(t),
+            };
+            if t.get_sender() == REWARD_SENDER {
+                if index > 0 && self.maturity > 0 {
+                    self.hold_back(index, t);
+                }
+                claimed = claimed.saturating_add(paid);
+            } else {
+                fees = fees.saturating_add(t.get_fee());
+                // the payment, not the change going back to the sender, nor tokens
+                if t.get_asset_id().is_none() {
+                    transferred = transferred.saturating_add(t.get_amount());
+                }
+            }
+        }
+        let (maturity, next_index) = (self.maturity, self.next_index);
+        let matured = |height: u64| height.saturating_add(maturity) <= next_index;
+        while self
+            .immature
+            .front()
+            .is_some_and(|(height, _, _)| matured(*height))
+        {
+            self.immature.pop_front();
+        }
+        self.immature_outputs.retain(|_, height| !matured(*height));
+        // the fees were paid by senders, only the rest of the rewards is new
+        let subsidy = claimed.saturating_sub(fees).max(0);
+        self.minted = self.minted.saturating_add(subsidy);
+        self.work = self.work.saturating_add(block.work());
+        BlockAccounting {
+            subsidy,
+            fees,
+            miner: block.get_miner().map(str::to_owned),
+            transferred,
+        }
+    }
+
+    /// Applies `t`, returning what it pays out.
+    fn record_transfer(&mut self, t: &Transaction) -> i64 {
+        // unchecked blocks may hold any amounts
+        let amount = t.get_amount();
+        if t.get_sender() != REWARD_SENDER {
+            let before = (t.get_id().to_owned(), self.balance(t.get_sender()));
+            self.last_spend.insert(t.get_sender().to_owned(), before);
+            let cost = amount.saturating_add(t.get_fee());
+            self.add(t.get_sender(), cost.saturating_neg());
+        }
+        self.add(t.get_recipient(), amount);
+        amount
+    }
+
+    /// Holds back what the reward `t` of the block at `index` pays until it matures.
+    fn hold_back(&mut self, index: u64, t: &Transaction) {
+        match t.get_utxo() {
+            Some(utxo) => {
+                for point in outpoints(t).take(utxo.outputs.len()) {
+                    self.immature_outputs.insert(point, index);
+                }
+            }
+            None => {
+                let paid = (index, t.get_recipient().to_owned(), t.get_amount());
+                self.immature.push_back(paid);
+            }
+        }
+    }
+
+    /// Applies `t` moving, or issuing, tokens of `asset`, its sender paying the fee in coins.
+    fn record_asset(&mut self, t: &Transaction, asset: &str) {
+        let (sender, amount) = (t.get_sender(), t.get_amount());
+        self.add(sender, t.get_fee().saturating_neg());
+        match t.get_issuance() {
+            Some(Issuance { max_supply }) => {
+                let state = self.assets.entry(asset.to_owned()).or_insert(Asset {
+                    issuer: sender.to_owned(),
+                    max_supply,
+                    issued: 0,
+                });
+                state.issued = state.issued.saturating_add(amount);
+            }
+            None => self.hold(asset, sender, amount.saturating_neg()),
+        }
+        self.hold(asset, t.get_recipient(), amount);
+    }
+
+    fn hold(&mut self, asset: &str, address: &str, amount: i64) {
+        let key = (asset.to_owned(), address.to_owned());
+        let holding = self.holdings.entry(key).or_insert(0);
+        *holding = holding.saturating_add(amount);
+    }
+
+    fn record_utxo(&mut self, t: &Transaction, utxo: &Utxo) -> i64 {
+        for point in &utxo.inputs {
+            if let Some(output) = self.unspent.remove(point) {
+                self.add(&output.recipient, output.amount.saturating_neg());
+                self.spent_by.insert(point.clone(), t.get_id().to_owned());
+            }
+        }
+        let mut total = 0i64;
+        for (point, output) in outpoints(t).zip(&utxo.outputs) {
+            total = total.saturating_add(output.amount);
+            self.add(&output.recipient, output.amount);
+            self.unspent.insert(point, output.clone());
+        }
+        total
+    }
+
+    fn add(&mut self, address: &str, amount: i64) {
+        let balance = self.balances.entry(address.to_owned()).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+}
+
+/// The outputs `t` creates, numbered in order.
+fn outpoints(t: &Transaction) -> impl Iterator<Item = OutPoint> + '_ {
+    (0..).map(move |index| OutPoint {
+        transaction: t.get_id().to_owned(),
+        index,
+    })
+}
+
+/// Transactions tried on top of a ledger without touching it.
+#[derive(Clone)]
+struct Changes<'a> {
+    ledger: &'a Ledger,
+    balances: HashMap<String, i64>,
+    // what the rewards claimed and the fees the other transactions paid
+    claimed: i64,
+    fees: i64,
+    // how much the transactions may mint, if limited
+    reward: Option<i64>,
+    // the outputs the transactions spent and created, on UTXO chains
+    spent: HashSet<OutPoint>,
+    created: HashMap<OutPoint, Output>,
+    // the assets the transactions issued and the tokens they moved, on account chains
+    assets: HashMap<String, Asset>,
+    holdings: HashMap<(String, String), i64>,
+    // the coinbase maturity enforced, and what the rewards of the block paid, not spendable in it
+    // unless 0
+    maturity: u64,
+    rewarded: HashMap<String, i64>,
+    rewarded_outputs: HashSet<OutPoint>,
+}
+
+impl<'a> Changes<'a> {
+    fn new(ledger: &'a Ledger, reward: Option<i64>) -> Self {
+        Changes {
+            ledger,
+            balances: HashMap::new(),
+            claimed: 0,
+            fees: 0,
+            reward,
+            spent: HashSet::new(),
+            created: HashMap::new(),
+            assets: HashMap::new(),
+            holdings: HashMap::new(),
+            maturity: ledger.maturity,
+            rewarded: HashMap::new(),
+            rewarded_outputs: HashSet::new(),
+        }
+    }
+
+    /// What `address` holds but may not spend in the next block, as rewards not matured by then.
+    fn immature(&self, address: &str) -> i64 {
+        if self.maturity == 0 {
+            return 0;
+        }
+        let index = self.ledger.next_index;
+        self.ledger
+            .immature
+            .iter()
+            .filter(|(height, recipient, _)| {
+                recipient == address && height.saturating_add(self.maturity) > index
+            })
+            .fold(
+                self.rewarded.get(address).copied().unwrap_or(0),
+                |total, (_, _, amount)| total.saturating_add(*amount),
+            )
+    }
+
+    /// Whether the output at `point` is a reward that may not be spent in the next block yet.
+    fn is_immature(&self, point: &OutPoint) -> bool {
+        if self.maturity == 0 {
+            return false;
+        }
+        let index = self.ledger.next_index;
+        self.rewarded_outputs.contains(point)
+            || self
+                .ledger
+                .immature_outputs
+                .get(point)
+                .is_some_and(|height| height.saturating_add(self.maturity) > index)
+    }
+
+    fn balance(&self, address: &str) -> i64 {
+        self.balances
+            .get(address)
+            .copied()
+            .unwrap_or_else(|| self.ledger.balance(address))
+    }
+
+    fn holding(&self, asset: &str, address: &str) -> i64 {
+        self.holdings
+            .get(&(asset.to_owned(), address.to_owned()))
+            .copied()
+            .unwrap_or_else(|| self.ledger.holding(asset, address))
+    }
+
+    fn asset(&self, asset: &str) -> Option<&Asset> {
+        self.assets.get(asset).or_else(|| self.ledger.asset(asset))
+    }
+
+    fn unspent(&self, point: &OutPoint) -> Option<&Output> {
+        match self.created.get(point) {
+            Some(output) => Some(output),
+            None if self.spent.contains(point) => None,
+            None => self.ledger.unspent.get(point),
+        }
+    }
+
+    /// What the rewards claimed after claiming `amount` more, if they may. Whether they claimed
+    /// exactly the reward and the fees is only known once all transactions are made, see
+    /// `settles`.
+    fn claim(&self, amount: i64) -> Option<i64> {
+        self.claimed.checked_add(amount).filter(|_| amount >= 0)
+    }
+
+    /// What the transactions paid in fees after paying `fee` more, if they may.
+    fn pay(&self, fee: i64) -> Option<i64> {
+        self.fees.checked_add(fee).filter(|_| fee >= 0)
+    }
+
+    /// Whether the rewards claimed exactly the reward and the fees paid, or nothing, which
+    /// forfeits both.
+    fn settles(&self) -> bool {
+        self.reward.is_none_or(|reward| {
+            self.claimed == 0 || reward.checked_add(self.fees) == Some(self.claimed)
+        })
+    }
+
+    /// Makes `t` if it is affordable and follows the transaction model, returning whether it
+    /// was.
+    fn transfer(&mut self, t: &Transaction) -> bool {
+        match (self.ledger.model, t.get_utxo(), t.get_asset_id()) {
+            (TransactionModel::Account, None, None) => self.move_amount(t),
+            (TransactionModel::Account, None, Some(asset)) => self.move_asset(t, asset),
+            (TransactionModel::Utxo, Some(utxo), None) => self.spend(t, utxo),
+            _ => false,
+        }
+    }
+
+    /// Moves, or issues, the tokens of `asset`, see `Issuance`.
+    fn move_asset(&mut self, t: &Transaction, asset: &str) -> bool {
+        let (sender, amount, fee) = (t.get_sender(), t.get_amount(), t.get_fee());
+        let balance = self.balance(sender);
+        let fees = match self.pay(fee) {
+            Some(fees) if sender != REWARD_SENDER && amount > 0 && balance >= fee => fees,
+            _ => return false,
+        };
+        let mut holdings = HashMap::new();
+        let mut issued = None;
+        match t.get_issuance() {
+            Some(Issuance { max_supply }) => {
+                let state = self.asset(asset).cloned().unwrap_or(Asset {
+                    issuer: sender.to_owned(),
+                    max_supply,
+                    issued: 0,
+                });
+                let total = state.issued.checked_add(amount);
+                match total {
+                    Some(total)
+                        if state.issuer == sender
+                            && state.max_supply == max_supply
+                            && total <= max_supply =>
+                    {
+                        issued = Some(Asset {
+                            issued: total,
+                            ..state
+                        })
+                    }
+                    _ => return false,
+                }
+            }
+            None => match self.holding(asset, sender) {
+                held if held >= amount => {
+                    holdings.insert(sender, held - amount);
+                }
+                _ => return false,
+            },
+        }
+        let recipient = t.get_recipient();
+        let held = holdings
+            .get(recipient)
+            .copied()
+            .unwrap_or_else(|| self.holding(asset, recipient));
+        match held.checked_add(amount) {
+            Some(held) => holdings.insert(recipient, held),
+            None => return false,
+        };
+
+        for (address, held) in holdings {
+            self.holdings
+                .insert((asset.to_owned(), address.to_owned()), held);
+        }
+        if let Some(issued) = issued {
+            self.assets.insert(asset.to_owned(), issued);
+        }
+        let mut balances = HashMap::new();
+        balances.insert(sender, balance - fee);
+        self.commit(balances, self.claimed, fees);
+        true
+    }
+
+    fn move_amount(&mut self, t: &Transaction) -> bool {
+        let (amount, fee) = (t.get_amount(), t.get_fee());
+        let mut balances = HashMap::new();
+        let (claimed, fees) = if t.get_sender() == REWARD_SENDER {
+            match self.claim(amount) {
+                Some(claimed) if fee == 0 => (claimed, self.fees),
+                _ => return false,
+            }
+        } else {
+            let balance = self.balance(t.get_sender());
+            let spendable = balance.saturating_sub(self.immature(t.get_sender()));
+            match (amount.checked_add(fee), self.pay(fee)) {
+                (Some(cost), Some(fees)) if amount > 0 && spendable >= cost => {
+                    balances.insert(t.get_sender(), balance - cost);
+                    (self.claimed, fees)
+                }
+                _ => return false,
+            }
+        };
+        let balance = balances
+            .get(t.get_recipient())
+            .copied()
+            .unwrap_or_else(|| self.balance(t.get_recipient()));
+        match balance.checked_add(amount) {
+            Some(balance) => balances.insert(t.get_recipient(), balance),
+            None => return false,
+        };
+        if t.get_sender() == REWARD_SENDER && self.maturity > 0 {
+            let rewarded = self
+                .rewarded
+                .entry(t.get_recipient().to_owned())
+                .or_insert(0);
+            *rewarded = rewarded.saturating_add(amount);
+        }
+        self.commit(balances, claimed, fees);
+        true
+    }
+
+    fn spend(&mut self, t: &Transaction, utxo: &Utxo) -> bool {
+        // the payment the transaction shows must be its first output
+        let shown = utxo.outputs.first().is_some_and(|payment| {
+            payment.recipient == t.get_recipient() && payment.amount == t.get_amount()
+        });
+        let total = utxo.outputs.iter().try_fold(0i64, |total, output| {
+            Some(output.amount)
+                .filter(|amount| *amount > 0)
+                .and_then(|amount| total.checked_add(amount))
+        });
+        // a reused id would overwrite outputs
+        let fresh = outpoints(t)
+            .take(utxo.outputs.len())
+            .all(|point| self.unspent(&point).is_none());
+        let total = match total {
+            Some(total) if shown && fresh => total,
+            _ => return false,
+        };
+
+        let mut balances = HashMap::new();
+        let (mut claimed, mut fees) = (self.claimed, self.fees);
+        if t.get_sender() == REWARD_SENDER {
+            claimed = match self.claim(total) {
+                Some(claimed) if utxo.inputs.is_empty() && t.get_fee() == 0 => claimed,
+                _ => return false,
+            };
+        } else {
+            let distinct: HashSet<&OutPoint> = utxo.inputs.iter().collect();
+            let spent = utxo.inputs.iter().try_fold(0i64, |spent, point| {
+                self.unspent(point)
+                    .filter(|output| output.recipient == t.get_sender())
+                    .filter(|_| !self.is_immature(point))
+                    .and_then(|output| spent.checked_add(output.amount))
+            });
+            let paid = total.checked_add(t.get_fee());
+            match (spent, self.pay(t.get_fee())) {
+                (Some(spent), Some(paid_fees))
+                    if Some(spent) == paid && distinct.len() == utxo.inputs.len() =>
+                {
+                    balances.insert(t.get_sender(), self.balance(t.get_sender()) - spent);
+                    fees = paid_fees;
+                }
+                _ => return false,
+            }
+        }
+        for output in &utxo.outputs {
+            let balance = balances
+                .get(output.recipient.as_str())
+                .copied()
+                .unwrap_or_else(|| self.balance(&output.recipient));
+            match balance.checked_add(output.amount) {
+                Some(balance) => balances.insert(&output.recipient, balance),
+                None => return false,
+            };
+        }
+
+        for point in &utxo.inputs {
+            self.created.remove(point);
+            self.spent.insert(point.clone());
+        }
+        for (point, output) in outpoints(t).zip(&utxo.outputs) {
+            if t.get_sender() == REWARD_SENDER && self.maturity > 0 {
+                self.rewarded_outputs.insert(point.clone());
+            }
+            self.created.insert(point, output.clone());
+        }
+        self.commit(balances, claimed, fees);
+        true
+    }
+
+    fn commit(&mut self, balances: HashMap<&str, i64>, claimed: i64, fees: i64) {
+        for (address, balance) in balances {
+            self.balances.insert(address.to_owned(), balance);
+        }
+        self.claimed = claimed;
+        self.fees = fees;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger() {
+        let mut chain = crate::Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        chain.mine_new_block();
+        let mut ledger = Ledger::default();
+        assert!(!ledger.admits(chain.last_block(), 0));
+        assert!(ledger.admits(chain.last_block(), 1));
+        assert!(!ledger.admits(chain.last_block(), 2));
+        ledger.record(chain.last_block());
+        assert_eq!((ledger.balance("alice"), ledger.minted()), (1, 1));
+
+        let pay = |amount| Transaction::new("alice", "bob", amount);
+        assert!(ledger.affords(&[], &pay(1)));
+        assert!(!ledger.affords(&[], &pay(2)));
+        assert!(!ledger.affords(&[], &pay(0)));
+        assert!(!ledger.affords(&[], &pay(-1)));
+        // pending transactions are made in order
+        assert!(!ledger.affords(&[pay(1)], &pay(1)));
+        let refund = Transaction::new("bob", "alice", 1);
+        assert!(ledger.affords(&[pay(1)], &refund));
+        // a refund is put after the payment it spends, which makes the other payment affordable
+        let kept = ledger.prioritized(vec![refund.clone(), pay(1), pay(1), refund.clone()]);
+        let senders: Vec<&str> = kept.iter().map(|t| t.get_sender()).collect();
+        assert_eq!(senders, ["alice", "bob", "alice", "bob"]);
+        let kept = ledger.prioritized(vec![refund.clone(), pay(1), pay(1)]);
+        assert_eq!(kept.len(), 3);
+        assert!(ledger.prioritized(vec![refund]).is_empty());
+    }
+}
@@ -6,10 +6,53 @@ extern crate log;
 use colored::*;
 
 // list all modules
+mod address;
+mod argon2;
+pub mod args;
+mod attestation;
 mod blockchain;
+mod config;
+mod consensus;
+mod encoding;
+mod genesis;
+mod key;
+mod keystore;
+mod ledger;
+pub mod logging;
+mod merkle;
 mod node;
+mod script;
+pub mod service;
+pub mod storage;
+pub mod testkit;
+mod validator;
 
-pub use blockchain::{Block, Blockchain, Transaction};
-pub use node::Node; // make it public for main.rs
+pub use address::{is_address_prefix, key_of, Address, AddressError, MAX_PREFIX_LEN};
+pub use attestation::Attestation;
+pub use blockchain::{
+    is_valid_address, AuthoritySeal, Block, BlockHeader, BlockId, BlockRejection, Blockchain,
+    BlocksView, Eviction, Expiry, Issuance, OutPoint, Output, PrefixError, Readiness, Rejection,
+    RewardSchedule, Transaction, TransactionInclusion, TransactionModel, Utxo, BLOCK_VERSION,
+    INITIAL_DIFFICULTY, MAX_AMOUNT, MAX_DIFFICULTY, MEMPOOL_CAPACITY, MINER_SIGNATURE_BIT,
+    MIN_PREFIX_LEN, REWARD_SENDER, SIGNAL_THRESHOLD, SIGNAL_WINDOW, SIGNED_BLOCK_VERSION,
+};
+pub use config::{
+    default_control_addr, ConfigProblem, ConsensusMode, GossipStrategy, Latency, LinkLatencies,
+    NodeConfig,
+};
+pub use consensus::{Consensus, ProofOfAuthority, ProofOfStake, ProofOfWork};
+pub use genesis::{Allocation, GenesisSpec, DEFAULT_ADDRESS_PREFIX};
+pub use key::{is_public_key, parse_path, KeyRotation, NodeKey};
+pub use keystore::{read_new_passphrase, read_passphrase, Keystore, Passphrase};
+pub use ledger::{Asset, BlockAccounting};
+pub use merkle::{MerkleProof, Sibling};
+pub use script::{Op, MAX_SCRIPT_OPS};
+// make them public for the binaries, and programs running nodes
+pub use node::{
+    client, control, wire_schema, BlockSummary, Broadcast, ChainStats, Node, NodeHandle,
+    ShutdownToken, Submission,
+};
+pub use storage::{DataDir, Storage, Store};
+pub use validator::TxValidator;
 
 pub type Result<T> = std::result::Result<T, failure::Error>;
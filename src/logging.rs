@@ -0,0 +1,50 @@
+//! Log targets, to filter the logs of one part of the node by
+//!
+//! Log lines go to the target of what they are about rather than to the module they come from,
+//! and `RUST_LOG` filters them as usual, e.g.
+//!
+//! - `RUST_LOG=debug,nb::net=warn` to debug everything but the chatter between peers
+//! - `RUST_LOG=info,nb::consensus=trace` to follow how every block is validated
+//! - `RUST_LOG=warn,nb::mempool=debug` to see what becomes of transactions
+//! - `RUST_LOG=nb::storage=info` to hear only about the data directory
+//!
+//! The `log_status` command shows what the filters let through.
+
+use log::{Level, Metadata};
+
+/// Peers, handshakes, gossip, connections and syncing.
+pub const NET: &str = "nb::net";
+/// Blocks: mining, validating, forks and reorganizations.
+pub const CONSENSUS: &str = "nb::consensus";
+/// Pending transactions, and what the wallet hears about them.
+pub const MEMPOOL: &str = "nb::mempool";
+/// The data directory: blocks, peers and keys persisted, migrations and repairs.
+pub const STORAGE: &str = "nb::storage";
+/// The command line.
+pub const REPL: &str = "nb::repl";
+
+pub const TARGETS: [&str; 5] = [NET, CONSENSUS, MEMPOOL, STORAGE, REPL];
+
+/// The most verbose level logged for every target, `None` for those that are off.
+pub fn levels() -> Vec<(&'static str, Option<Level>)> {
+    TARGETS
+        .iter()
+        .map(|&target| {
+            let level = [
+                Level::Trace,
+                Level::Debug,
+                Level::Info,
+                Level::Warn,
+                Level::Error,
+            ]
+            .iter()
+            .copied()
+            .find(|&level| {
+                level <= log::max_level()
+                    && log::logger()
+                        .enabled(&Metadata::builder().target(target).level(level).build())
+            });
+            (target, level)
+        })
+        .collect()
+}
@@ -1,34 +1,1085 @@
 #[macro_use]
 extern crate log;
 
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use env_logger::Env;
-use nb::Node;
-use tokio::runtime::Runtime;
+use nb::args::{self, difficulty, node_args, node_config, number, set_genesis_spec};
+use nb::control::{self, Control};
+use nb::logging::REPL;
+use nb::service::{Service, ServiceAction, DEFAULT_SERVICE_NAME};
+use nb::storage::{backup, blkdat};
+use nb::testkit::{DoubleSpend, MinerStrategy, SelfishMining, TestVectors, Tutorial};
+use nb::{
+    client, default_control_addr, is_address_prefix, is_public_key, parse_path,
+    read_new_passphrase, read_passphrase, wire_schema, Address, Allocation, Block, BlockId,
+    ConsensusMode, DataDir, GenesisSpec, Keystore, NodeConfig, RewardSchedule, TransactionModel,
+    DEFAULT_ADDRESS_PREFIX, INITIAL_DIFFICULTY, MAX_DIFFICULTY,
+};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 fn main() {
     let matches = App::new("nb")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
-        .about("A simple blockchain node")
+        .about("A simple blockchain node: a console attached to the node nbd runs")
         .setting(AppSettings::DisableHelpSubcommand)
+        .args(&args::chain_args())
+        .arg(args::control_addr_arg())
         .arg(
-            Arg::with_name("addr")
-                .long("addr")
-                .takes_value(true)
-                .value_name("IP-PORT")
-                .default_value("127.0.0.1:4000")
-                .help("the node's address"),
+            Arg::with_name("standalone")
+                .long("standalone")
+                .help("runs the node in an nbd of its own, attached to this console, and stops it on exit"),
+        )
+        .args(&node_args(true))
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs the node in an nbd of its own with this console attached, as nb --standalone does")
+                .args(&node_args(false)),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("Sets up the data directory of a node: its chain with the genesis block, and its key")
+                .arg(
+                    Arg::with_name("interactive")
+                        .long("interactive")
+                        .help("asks what the genesis block of a new network sets up, writing it as a genesis spec into the data directory, and prints how peers join the network"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Validates the stored chain of a stopped node from its genesis block on"),
+        )
+        .subcommand(
+            SubCommand::with_name("localnet")
+                .about("Runs a network of nodes on this machine, on consecutive ports from --addr, all peers of the first, whose console this is")
+                .arg(
+                    Arg::with_name("nodes")
+                        .long("nodes")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("3")
+                        .help("how many nodes to run"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cli")
+                .about("Asks the running node at --addr what it knows")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("balance")
+                        .about("Prints the balance of an address")
+                        .arg(Arg::with_name("address").required(true).help("the address"))
+                        .arg(
+                            Arg::with_name("height")
+                                .long("height")
+                                .takes_value(true)
+                                .value_name("N")
+                                .help("the balance after block N [default: after the tip]"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("block")
+                        .about("Prints a block of the chain of the node")
+                        .arg(
+                            Arg::with_name("block")
+                                .required(true)
+                                .help("the height or the hash of the block"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("tx")
+                        .about("Prints a transaction, and the block confirming it if any")
+                        .arg(Arg::with_name("id").required(true).help("the transaction id")),
+                )
+                .subcommand(
+                    SubCommand::with_name("chain-stats").about(
+                        "Prints the height, transactions, mempool, difficulty, estimated hashrate, block interval and peers of the node",
+                    ),
+                )
+                .subcommand(
+                    SubCommand::with_name("stats")
+                        .about("Prints what the chain of the node confirmed recently")
+                        .arg(
+                            Arg::with_name("window")
+                                .long("window")
+                                .takes_value(true)
+                                .value_name("SECS")
+                                .help("the last SECS seconds [default: the node's --stats-window]"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("submit-block")
+                        .about("Hands a block to the node to extend its chain with, printing the code of the rule it breaks if the node turns it down")
+                        .arg(Arg::with_name("file").required(true).help("the block as JSON, - for stdin")),
+                )
+                .subcommand(
+                    SubCommand::with_name("validate-block")
+                        .about("Asks the node whether a block would extend its chain, without adding it")
+                        .arg(Arg::with_name("file").required(true).help("the block as JSON, - for stdin")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Archives the data directory of a stopped node into a single file")
+                .arg(Arg::with_name("path").required(true).help("the archive to create")),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restores a data directory from an archive, validating its chain")
+                .arg(Arg::with_name("path").required(true).help("the archive to restore"))
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("overwrites a data directory that already holds data"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes the chain of a stopped node into a single file")
+                .arg(Arg::with_name("path").required(true).help("the file to create"))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["blkdat"])
+                        .default_value("blkdat")
+                        .help("writes the blocks as length-prefixed records, one after the other"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Imports a chain written by export into the data directory, validating it")
+                .arg(Arg::with_name("path").required(true).help("the file to import"))
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("overwrites the chain of the data directory if it already holds data"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("key")
+                .about("Prints the public key of the node, generating its key on first use"),
+        )
+        .subcommand(
+            SubCommand::with_name("wallet")
+                .about("Manages the wallets of a stopped node, keys in its data directory whose public keys are the addresses they receive on, encoded under the address prefix of a --genesis spec setting one; a running node does the same in its console")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("new")
+                        .about("Creates a wallet with a fresh key, encrypted under a passphrase it asks for, and prints its address")
+                        .arg(Arg::with_name("name").required(true).help("the name of the wallet")),
+                )
+                .subcommand(SubCommand::with_name("list").about("Prints the wallets with their addresses"))
+                .subcommand(
+                    SubCommand::with_name("address")
+                        .about("Prints the addresses of a wallet, its own and those derived from it")
+                        .arg(Arg::with_name("name").required(true).help("the name of the wallet")),
+                )
+                .subcommand(
+                    SubCommand::with_name("receive")
+                        .about("Derives a fresh address of a wallet from its seed, asking for its passphrase, and prints it")
+                        .arg(Arg::with_name("name").required(true).help("the name of the wallet"))
+                        .arg(Arg::with_name("path").help("the path to derive at, like m/0'/1', by default the next m/0'/n'")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Prints the JSON Schema of the messages nodes and clients exchange"),
+        )
+        .subcommand(
+            SubCommand::with_name("genvectors")
+                .about("Writes blocks and transactions as they are serialized, with their hashes and proofs of work, mined with --difficulty, as JSON files for the golden tests and other implementations")
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .default_value("vectors")
+                        .help("the directory to write the files into"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("double-spend")
+                .about("Simulates a double-spend attack with a private fork, without touching any node")
+                .arg(
+                    Arg::with_name("share")
+                        .long("share")
+                        .takes_value(true)
+                        .default_value("0.3")
+                        .help("the attacker's share of the hash power"),
+                )
+                .arg(
+                    Arg::with_name("confirmations")
+                        .long("confirmations")
+                        .takes_value(true)
+                        .default_value("3")
+                        .help("how many blocks the merchant waits for"),
+                )
+                .arg(
+                    Arg::with_name("release-after")
+                        .long("release-after")
+                        .takes_value(true)
+                        .value_name("N")
+                        .default_value("4")
+                        .help("releases the private fork once it is N blocks long"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("seeds who finds each block"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("selfish-mining")
+                .about("Simulates a mining pool withholding its blocks and compares its revenue with honest mining")
+                .arg(
+                    Arg::with_name("share")
+                        .long("share")
+                        .takes_value(true)
+                        .default_value("0.35")
+                        .help("the pool's share of the hash power"),
+                )
+                .arg(
+                    Arg::with_name("gamma")
+                        .long("gamma")
+                        .takes_value(true)
+                        .default_value("0.5")
+                        .help("the share of honest miners building on the pool's block during a tie"),
+                )
+                .arg(
+                    Arg::with_name("blocks")
+                        .long("blocks")
+                        .takes_value(true)
+                        .default_value("50")
+                        .help("how many blocks are found"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("seeds who finds each block"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("service")
+                .about("Keeps the node running in the background, as a systemd unit or a Windows task at startup, installed with the flags given here")
+                .arg(
+                    Arg::with_name("action")
+                        .required(true)
+                        .possible_values(&["install", "uninstall", "start", "stop"])
+                        .help("what to do with the service"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .default_value(DEFAULT_SERVICE_NAME)
+                        .help("the name of the unit or task, one per node"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .help("installs a systemd unit of the user, started at login, instead of one of the system"),
+                )
+                .arg(
+                    Arg::with_name("print")
+                        .long("print")
+                        .help("prints the unit, or the command of the task, instead of installing it"),
+                )
+                .args(&node_args(false)),
+        )
+        .subcommand(
+            SubCommand::with_name("tutorial")
+                .about("Walks through mining, sending coins, adding a peer and resolving a fork, on chains of its own, checking the commands entered at each step"),
         )
         .get_matches();
 
+    // the tutorial and the vectors speak for themselves, without the log of their chains
+    let default_filter = match matches.subcommand_name() {
+        Some("tutorial") | Some("genvectors") => "warn",
+        _ => "debug,sled=info",
+    };
+    env_logger::from_env(Env::default().default_filter_or(default_filter)).init();
+
+    let result = match matches.subcommand() {
+        ("run", Some(matches)) => run(matches),
+        ("init", Some(matches)) => init(matches),
+        ("verify", Some(matches)) => verify(matches),
+        ("localnet", Some(matches)) => localnet(matches),
+        ("cli", Some(matches)) => cli(matches),
+        ("backup", Some(matches)) => backup(matches),
+        ("restore", Some(matches)) => restore(matches),
+        ("export", Some(matches)) => export(matches),
+        ("import", Some(matches)) => import(matches),
+        ("key", Some(matches)) => key(matches),
+        ("wallet", Some(matches)) => wallet(matches),
+        ("schema", Some(_)) => schema(),
+        ("genvectors", Some(matches)) => genvectors(matches),
+        ("double-spend", Some(matches)) => double_spend(matches),
+        ("selfish-mining", Some(matches)) => selfish_mining(matches),
+        ("service", Some(matches)) => service(matches),
+        ("tutorial", Some(_)) => tutorial(&mut io::stdin().lock()),
+        _ if matches.is_present("standalone") => run(&matches),
+        _ => control::console(
+            &args::control_addr(&matches),
+            &args::control_cookie(&matches),
+        ),
+    };
+    if let Err(e) = result {
+        error!(target: REPL, "{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the node in an nbd of its own, with the console attached, and stops it once the console
+/// exits.
+fn run(matches: &ArgMatches) -> nb::Result<()> {
+    // the flags are checked before nbd is bothered with them
+    node_config(matches)?;
+    let control_addr = args::control_addr(matches);
+    let cookie = args::control_cookie(matches);
+    let mut command = nbd();
+    args::forward(matches, args::CHAIN_FLAGS, &mut command);
+    args::forward(matches, args::NODE_FLAGS, &mut command);
+    let mut daemon = spawn(command.stdin(Stdio::null()))?;
+    let result = wait_for_control(&control_addr, &cookie, &mut daemon)
+        .and_then(|_| control::console(&control_addr, &cookie));
+    let stopped = stop(&control_addr, &cookie, &mut daemon);
+    result.and(stopped)
+}
+
+/// The nbd next to this nb.
+fn nbd() -> Command {
+    let exe = std::env::current_exe().unwrap_or_default();
+    Command::new(exe.with_file_name(format!("nbd{}", std::env::consts::EXE_SUFFIX)))
+}
+
+fn spawn(command: &mut Command) -> nb::Result<Child> {
+    command.spawn().map_err(|e| {
+        failure::err_msg(format!(
+            "cannot run {}: {}",
+            command.get_program().to_string_lossy(),
+            e
+        ))
+    })
+}
+
+/// Waits until `daemon` takes commands on `control_addr` and wrote its `cookie`.
+fn wait_for_control(control_addr: &str, cookie: &Path, daemon: &mut Child) -> nb::Result<()> {
+    for _ in 0..100 {
+        if cookie.exists() && TcpStream::connect(control_addr).is_ok() {
+            return Ok(());
+        }
+        if daemon.try_wait()?.is_some() {
+            return Err(failure::err_msg(format!(
+                "nbd exited before taking commands on {}",
+                control_addr
+            )));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(failure::err_msg(format!(
+        "nbd did not start taking commands on {}",
+        control_addr
+    )))
+}
+
+/// Stops `daemon`, unless it stopped already, e.g. by the shutdown command.
+fn stop(control_addr: &str, cookie: &Path, daemon: &mut Child) -> nb::Result<()> {
+    if let Ok(control) = Control::connect(control_addr, cookie) {
+        // a node stopping on its own may hang up first
+        let _ = control.shutdown();
+    }
+    if !daemon.wait()?.success() {
+        return Err(failure::err_msg(format!(
+            "the nbd of {} did not exit cleanly",
+            control_addr
+        )));
+    }
+    Ok(())
+}
+
+fn init(matches: &ArgMatches) -> nb::Result<()> {
+    let mut config = node_config(matches)?;
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let spec_path = if matches.is_present("interactive") {
+        let path = data_dir.genesis_file();
+        if path.exists() {
+            return Err(failure::err_msg(format!(
+                "{} already exists",
+                path.display()
+            )));
+        }
+        let (spec, consensus) = init_wizard(&config, &mut io::stdin().lock())?;
+        fs::write(&path, serde_json::to_string_pretty(&spec)?)?;
+        config.consensus = consensus;
+        set_genesis_spec(&mut config, spec);
+        Some(path)
+    } else {
+        None
+    };
+    let genesis = config.genesis();
+    let store = data_dir.store(&genesis)?;
+    let key = store.node_key()?;
+    if let ConsensusMode::ProofOfAuthority(authorities) | ConsensusMode::ProofOfStake(authorities) =
+        &mut config.consensus
+    {
+        // the wizard leaves them out for this node alone to produce the blocks
+        if authorities.is_empty() {
+            authorities.push(key.public_key().to_owned());
+        }
+    }
+    let chain = config.open_chain(store.clone(), None)?;
+    store.flush()?;
+    println!("Chain:   {}", data_dir.chain_dir(&genesis).display());
+    println!("Blocks:  {}", chain.len());
+    println!("Genesis: {}", genesis.get_hash());
+    println!("Key:     {}", key.public_key());
+
+    if let Some(path) = spec_path {
+        let mut command = format!("nb run --genesis {}", path.display());
+        match &config.consensus {
+            ConsensusMode::ProofOfWork => {}
+            ConsensusMode::ProofOfAuthority(authorities) => {
+                command += " --consensus poa";
+                for authority in authorities {
+                    command += &format!(" --authority {}", authority);
+                }
+            }
+            ConsensusMode::ProofOfStake(authorities) => {
+                command += " --consensus pos";
+                for authority in authorities {
+                    command += &format!(" --authority {}", authority);
+                }
+            }
+        }
+        println!();
+        println!(
+            "Wrote the genesis spec of the chain {} to {}",
+            chain.chain_id(),
+            path.display()
+        );
+        println!("Run this node with");
+        println!(
+            "    {} --addr {} --data-dir {}",
+            command,
+            config.addr,
+            config.data_dir.display()
+        );
+        println!("Peers join with a copy of the spec, running");
+        println!("    {} --addr <IP-PORT>", command);
+        println!("and entering `add_peer {}` in their console.", config.addr);
+    }
+    Ok(())
+}
+
+/// Asks on `input` what the genesis block of a new network sets up, and how it reaches
+/// consensus. The authorities are left out if this node is to be the only one.
+fn init_wizard(
+    config: &NodeConfig,
+    input: &mut impl BufRead,
+) -> nb::Result<(GenesisSpec, ConsensusMode)> {
+    println!("Setting up a new network, press enter to take the default in brackets.");
+    let name = ask(input, "Name of the network", "testnet", |name| {
+        Some(name.to_owned()).filter(|name| !name.contains(char::is_whitespace))
+    })?;
+    let difficulty = ask(
+        input,
+        "Initial difficulty, in leading zero bits",
+        &config.difficulty.unwrap_or(INITIAL_DIFFICULTY).to_string(),
+        |bits| bits.parse().ok().filter(|&bits| bits <= MAX_DIFFICULTY),
+    )?;
+    let defaults = config.reward_schedule.unwrap_or_default();
+    let initial_reward = ask(
+        input,
+        "Block reward",
+        &defaults.initial_reward.to_string(),
+        |coins| coins.parse().ok().filter(|&coins: &i64| coins >= 0),
+    )?;
+    let halving_interval = ask(
+        input,
+        "Halving interval, in blocks, 0 for never",
+        &defaults.halving_interval.to_string(),
+        |blocks| blocks.parse().ok(),
+    )?;
+    let max_supply = ask(
+        input,
+        "Maximum supply, none for no limit",
+        &defaults
+            .max_supply
+            .map_or("none".to_owned(), |coins| coins.to_string()),
+        |coins| match coins {
+            "none" => Some(None),
+            coins => coins
+                .parse()
+                .ok()
+                .filter(|&coins: &i64| coins > 0)
+                .map(Some),
+        },
+    )?;
+    let coinbase_maturity = ask(
+        input,
+        "Blocks before a mining reward may be spent, 0 for right away",
+        &defaults.coinbase_maturity.to_string(),
+        |blocks| blocks.parse().ok(),
+    )?;
+    let transaction_model = ask(
+        input,
+        "Transaction model, account or utxo",
+        match config.transaction_model {
+            TransactionModel::Account => "account",
+            TransactionModel::Utxo => "utxo",
+        },
+        |model| match model {
+            "account" => Some(TransactionModel::Account),
+            "utxo" => Some(TransactionModel::Utxo),
+            _ => None,
+        },
+    )?;
+    let address_prefix = ask(
+        input,
+        "Prefix of the addresses, which encode public keys",
+        DEFAULT_ADDRESS_PREFIX,
+        |prefix| Some(prefix.to_owned()).filter(|prefix| is_address_prefix(prefix)),
+    )?;
+    let mut allocations = Vec::new();
+    while let Some(allocation) = ask(
+        input,
+        "Premine, as ADDRESS AMOUNT, empty when done",
+        "",
+        |allocation| {
+            if allocation.is_empty() {
+                return Some(None);
+            }
+            let mut words = allocation.split_whitespace();
+            let address = words.next().filter(|address| {
+                address
+                    .parse::<Address>()
+                    .is_ok_and(|address| address.prefix() == address_prefix)
+            })?;
+            let amount = words.next()?.parse().ok().filter(|&amount| amount > 0)?;
+            match words.next() {
+                None => Some(Some(Allocation {
+                    address: address.to_owned(),
+                    amount,
+                })),
+                Some(_) => None,
+            }
+        },
+    )? {
+        allocations.push(allocation);
+    }
+    let consensus = ask(input, "Consensus, pow, poa or pos", "pow", |consensus| {
+        Some(consensus.to_owned())
+            .filter(|consensus| ["pow", "poa", "pos"].contains(&&consensus[..]))
+    })?;
+    let consensus = match &consensus[..] {
+        "pow" => ConsensusMode::ProofOfWork,
+        consensus => {
+            let authorities = ask(
+                input,
+                "Public keys of the authorities, separated by spaces",
+                "this node",
+                |keys| match keys {
+                    "this node" => Some(Vec::new()),
+                    keys => keys
+                        .split_whitespace()
+                        .map(|key| Some(key.to_owned()).filter(|key| is_public_key(key)))
+                        .collect(),
+                },
+            )?;
+            if consensus == "poa" {
+                ConsensusMode::ProofOfAuthority(authorities)
+            } else {
+                ConsensusMode::ProofOfStake(authorities)
+            }
+        }
+    };
+
+    let spec = GenesisSpec {
+        name: Some(name),
+        // tells the network apart from others set up with the same answers
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+        difficulty: Some(difficulty),
+        reward_schedule: Some(RewardSchedule {
+            initial_reward,
+            halving_interval,
+            max_supply,
+            coinbase_maturity,
+        }),
+        transaction_model: Some(transaction_model),
+        address_prefix: Some(address_prefix),
+        legacy_genesis: None,
+        allocations,
+    };
+    spec.check()?;
+    Ok((spec, consensus))
+}
+
+/// Asks `question` until the answer, or `default` if there is none, `parse`s.
+fn ask<T>(
+    input: &mut impl BufRead,
+    question: &str,
+    default: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> nb::Result<T> {
+    loop {
+        if default.is_empty() {
+            print!("{}: ", question);
+        } else {
+            print!("{} [{}]: ", question, default);
+        }
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Err(failure::err_msg(
+                "the input ended before the network was set up",
+            ));
+        }
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match parse(answer) {
+            Some(value) => return Ok(value),
+            None => println!("{:?} does not do, try again", answer),
+        }
+    }
+}
+
+fn verify(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let genesis = config.genesis();
+    if !data_dir.chain_dir(&genesis).exists() {
+        return Err(failure::err_msg(format!(
+            "there is no chain at {}",
+            data_dir.chain_dir(&genesis).display()
+        )));
+    }
+    // opening the chain validates it
+    let chain = config.open_chain(data_dir.store(&genesis)?, None)?;
+    println!("Verified {} blocks, tip {}", chain.len(), chain.tip_hash());
+    Ok(())
+}
+
+fn localnet(matches: &ArgMatches) -> nb::Result<()> {
+    let count = number(matches, "nodes")?;
+    if count == 0 {
+        return Err(failure::err_msg("--nodes must be at least 1"));
+    }
+    let first: SocketAddr = matches
+        .value_of("addr")
+        .unwrap()
+        .parse()
+        .map_err(|_| failure::err_msg("--addr must be an IP-PORT"))?;
+    let mut nodes = Vec::new();
+    for i in 0..count {
+        let port = u16::try_from(u64::from(first.port()) + i)
+            .map_err(|_| failure::err_msg("the ports of the localnet run out"))?;
+        let addr = SocketAddr::new(first.ip(), port).to_string();
+        let mut command = nbd();
+        command.arg("--addr").arg(&addr).stdin(Stdio::null());
+        let mut config = NodeConfig::new(&addr);
+        if let Some(dir) = matches.value_of("data-dir") {
+            config.data_dir = Path::new(dir).join(&addr);
+            command.arg("--data-dir").arg(&config.data_dir);
+        }
+        args::forward(matches, args::CHAIN_FLAGS, &mut command);
+        if i > 0 {
+            let log = format!("localnet-{}.log", addr);
+            println!("Running {}, logging to {}", addr, log);
+            command.stdout(Stdio::null()).stderr(File::create(log)?);
+        }
+        let control_addr = default_control_addr(&addr);
+        let cookie = config.control_cookie();
+        nodes.push((addr, control_addr, cookie, spawn(&mut command)?));
+    }
+
+    // the others join the first once they all take commands
+    let mut result = nodes
+        .iter_mut()
+        .try_for_each(|(_, control_addr, cookie, daemon)| {
+            wait_for_control(control_addr, cookie, daemon)
+        });
+    if result.is_ok() {
+        let (first, rest) = nodes.split_first().unwrap();
+        result = rest.iter().try_for_each(|(_, control_addr, cookie, _)| {
+            Control::connect(control_addr, cookie)?.run(&format!("add_peer {}", first.0))?;
+            Ok(())
+        });
+    }
+    if result.is_ok() {
+        result = control::console(&nodes[0].1, &nodes[0].2);
+    }
+    for (i, (addr, control_addr, cookie, daemon)) in nodes.iter_mut().enumerate() {
+        match stop(control_addr, cookie, daemon) {
+            Err(e) if i == 0 => result = result.and(Err(e)),
+            Err(_) => warn!(target: REPL, "{} did not exit cleanly", addr),
+            Ok(()) => {}
+        }
+    }
+    result
+}
+
+fn cli(matches: &ArgMatches) -> nb::Result<()> {
     let addr = matches.value_of("addr").unwrap();
-    let addr = addr.to_owned();
-    env_logger::from_env(Env::default().default_filter_or("debug")).init();
+    match matches.subcommand() {
+        ("balance", Some(matches)) => {
+            let address = matches.value_of("address").unwrap();
+            let height = match matches.value_of("height") {
+                Some(_) => Some(number(matches, "height")?),
+                None => None,
+            };
+            match client::get_balance(addr, address, height)? {
+                Some(balance) => println!("{}", balance),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "the chain of {} is not that long yet",
+                        addr
+                    )))
+                }
+            }
+        }
+        ("block", Some(matches)) => {
+            let id: BlockId = matches.value_of("block").unwrap().parse()?;
+            match client::get_block(addr, id.clone())? {
+                Some(block) => println!("{}", serde_json::to_string_pretty(&block)?),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "the chain of {} holds no block {}",
+                        addr, id
+                    )))
+                }
+            }
+        }
+        ("tx", Some(matches)) => {
+            let id = matches.value_of("id").unwrap();
+            match client::get_transaction(addr, id)? {
+                Some(found) => {
+                    println!("{}", serde_json::to_string_pretty(&found.transaction)?);
+                    println!("{}", found);
+                }
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "{} knows no transaction {}",
+                        addr, id
+                    )))
+                }
+            }
+        }
+        ("chain-stats", Some(_)) => {
+            let stats = client::get_chain_stats(addr)?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        ("stats", Some(matches)) => {
+            let window = match matches.value_of("window") {
+                Some(_) => Some(number(matches, "window")?),
+                None => None,
+            };
+            let throughput = client::get_stats(addr, window)?;
+            println!("{}", serde_json::to_string_pretty(&throughput)?);
+        }
+        (command @ ("submit-block" | "validate-block"), Some(matches)) => {
+            let file = matches.value_of("file").unwrap();
+            let json = if file == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                fs::read_to_string(file)?
+            };
+            let block: Block = serde_json::from_str(&json)
+                .map_err(|e| failure::err_msg(format!("{} holds no block: {}", file, e)))?;
+            let verdict = if command == "submit-block" {
+                client::submit_block(addr, &block)?
+            } else {
+                client::validate_block(addr, &block)?
+            };
+            match verdict {
+                Ok(()) if command == "submit-block" => println!("accepted"),
+                Ok(()) => println!("valid"),
+                Err(rejection) => return Err(failure::err_msg(format!("rejected, {}", rejection))),
+            }
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+    Ok(())
+}
+
+fn backup(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let path = Path::new(matches.value_of("path").unwrap());
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let chain_dir = data_dir.chain_dir(&config.genesis());
+    let count = backup::backup_data_dir(config.db, &chain_dir, path)?;
+    println!(
+        "Backed up {} entries of {} to {}",
+        count,
+        chain_dir.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn restore(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let path = Path::new(matches.value_of("path").unwrap());
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let (count, len, chain_dir) = backup::restore_data_dir(
+        path,
+        &data_dir,
+        matches.is_present("force"),
+        &config.consensus,
+    )?;
+    println!(
+        "Restored {} entries ({} blocks) to {}",
+        count,
+        len,
+        chain_dir.display()
+    );
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let path = Path::new(matches.value_of("path").unwrap());
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let genesis = config.genesis();
+    if !data_dir.chain_dir(&genesis).exists() {
+        return Err(failure::err_msg(format!(
+            "there is no chain at {}",
+            data_dir.chain_dir(&genesis).display()
+        )));
+    }
+    let count = blkdat::export(&data_dir.store(&genesis)?, path)?;
+    println!("Exported {} blocks to {}", count, path.display());
+    Ok(())
+}
 
-    info!("nb {}", env!("CARGO_PKG_VERSION"));
-    info!("Listening on {}", addr);
+fn import(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let path = Path::new(matches.value_of("path").unwrap());
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let (len, chain_dir) = blkdat::import_data_dir(
+        path,
+        &data_dir,
+        matches.is_present("force"),
+        &config.consensus,
+    )?;
+    println!("Imported {} blocks to {}", len, chain_dir.display());
+    Ok(())
+}
+
+fn key(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let store = data_dir.store(&config.genesis())?;
+    println!("{}", store.node_key()?.public_key());
+    store.flush()
+}
+
+fn wallet(matches: &ArgMatches) -> nb::Result<()> {
+    let config = node_config(matches)?;
+    let data_dir = DataDir::open(&config.data_dir, config.db)?;
+    let mut keystore = Keystore::open(&data_dir.wallets_dir());
+    let prefix = config
+        .genesis_spec
+        .and_then(|spec| spec.address_prefix)
+        .unwrap_or_default();
+    // the public key itself on chains without a prefix
+    let address_of = |key: &str| {
+        Address::new(&prefix, key).map_or_else(|| key.to_owned(), |address| address.to_string())
+    };
+    match matches.subcommand() {
+        ("new", Some(matches)) => {
+            let passphrase = read_new_passphrase()?;
+            let key = keystore.create(matches.value_of("name").unwrap(), &passphrase)?;
+            println!("{}", address_of(&key.public_key()));
+        }
+        ("address", Some(matches)) => {
+            for (path, key) in keystore.addresses(matches.value_of("name").unwrap())? {
+                println!("{}: {}", path, address_of(&key));
+            }
+        }
+        ("receive", Some(matches)) => {
+            let name = matches.value_of("name").unwrap();
+            let path = match matches.value_of("path") {
+                Some(path) => Some(parse_path(path).ok_or_else(|| {
+                    failure::err_msg(format!(
+                        "{} is no path of hardened children like m/0'/1'",
+                        path
+                    ))
+                })?),
+                None => None,
+            };
+            let passphrase = read_passphrase(&format!("Passphrase of the wallet {}: ", name))?;
+            let key = keystore.receive(name, &passphrase, path.as_deref())?;
+            println!("{}", address_of(&key));
+        }
+        _ => {
+            for (name, key) in keystore.list()? {
+                println!("{}: {}", name, address_of(&key));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn schema() -> nb::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&wire_schema())?);
+    Ok(())
+}
+
+fn genvectors(matches: &ArgMatches) -> nb::Result<()> {
+    let dir = Path::new(matches.value_of("out").unwrap());
+    let vectors = TestVectors::generate(difficulty(matches)?);
+    vectors.write(dir)?;
+    println!(
+        "Wrote {} transactions and {} blocks to {}",
+        vectors.transactions.len(),
+        vectors.blocks.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+fn service(matches: &ArgMatches) -> nb::Result<()> {
+    let action = match matches.value_of("action").unwrap() {
+        "install" => ServiceAction::Install,
+        "uninstall" => ServiceAction::Uninstall,
+        "start" => ServiceAction::Start,
+        _ => ServiceAction::Stop,
+    };
+    let config = node_config(matches)?;
+    let mut command = nbd();
+    args::forward(matches, args::CHAIN_FLAGS, &mut command);
+    args::forward(matches, args::NODE_FLAGS, &mut command);
+    if matches.occurrences_of("data-dir") == 0 {
+        // the default is relative to where nb runs
+        command.arg("--data-dir").arg(&config.data_dir);
+    }
+    let mut service = Service::new(
+        matches.value_of("name").unwrap(),
+        command.get_program().into(),
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        matches.is_present("user"),
+    )?;
+    service.run_as_installer()?;
+    if matches.is_present("print") {
+        if cfg!(windows) {
+            println!("{}", service.task_command());
+        } else {
+            print!("{}", service.systemd_unit());
+        }
+        return Ok(());
+    }
+    service.run(action)
+}
+
+fn share(matches: &ArgMatches, name: &str) -> nb::Result<f64> {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .ok()
+        .filter(|share| (0.0..=1.0).contains(share))
+        .ok_or_else(|| failure::err_msg(format!("--{} must be between 0 and 1", name)))
+}
+
+fn double_spend(matches: &ArgMatches) -> nb::Result<()> {
+    let simulation = DoubleSpend {
+        attacker_share: share(matches, "share")?,
+        confirmations: number(matches, "confirmations")?,
+        release_after: number(matches, "release-after")?,
+        seed: number(matches, "seed")?,
+        difficulty: difficulty(matches)?,
+        ..Default::default()
+    };
+    let report = simulation.run();
+    println!(
+        "honest chain: {} blocks, private fork: {} blocks, payment confirmations: {}",
+        report.honest_blocks, report.fork_blocks, report.confirmations
+    );
+    if !report.fork_released {
+        println!("The fork never got long enough to be released");
+    } else if !report.fork_adopted {
+        println!("The fork was released but the honest chain is longer, the attack failed");
+    } else if !report.succeeded {
+        println!(
+            "The fork was adopted before the merchant accepted the payment, nothing was stolen"
+        );
+    } else {
+        println!("The attack succeeded: the merchant's payment was reverted");
+    }
+    Ok(())
+}
+
+fn selfish_mining(matches: &ArgMatches) -> nb::Result<()> {
+    let simulation = SelfishMining {
+        pool_share: share(matches, "share")?,
+        gamma: share(matches, "gamma")?,
+        blocks: number(matches, "blocks")?,
+        seed: number(matches, "seed")?,
+        difficulty: difficulty(matches)?,
+    };
+    for strategy in &[MinerStrategy::Honest, MinerStrategy::Selfish] {
+        let report = simulation.run(*strategy);
+        println!(
+            "{} pool: mined {} of {} blocks, {} made it into the chain, {} orphaned in total, relative revenue {:.3} for {:.3} of the hash power",
+            strategy,
+            report.pool_mined,
+            report.pool_mined + report.honest_mined,
+            report.pool_blocks,
+            report.orphaned(),
+            report.relative_revenue(),
+            report.pool_share
+        );
+    }
+    Ok(())
+}
 
-    let rt = Runtime::new().expect("tokio runtime can be initialized");
-    rt.block_on(async move { Node::handle_events(addr).await.unwrap() });
+/// Runs the steps of the tutorial on the commands read from `input`, until the last step is
+/// done, the user exits or the input ends.
+fn tutorial(input: &mut impl BufRead) -> nb::Result<()> {
+    let mut tutorial = Tutorial::default();
+    println!(
+        "Welcome to nb! Enter the commands asked for, 'help' repeats the step, 'exit' leaves."
+    );
+    while let Some(instructions) = tutorial.instructions() {
+        println!("\n{}", instructions);
+        loop {
+            print!("> ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match line.trim() {
+                "" => continue,
+                "help" => println!("{}", instructions),
+                "exit" => return Ok(()),
+                line => match tutorial.enter(line) {
+                    Ok(output) => {
+                        print!("{}", output);
+                        break;
+                    }
+                    Err(e) => println!("{}", e),
+                },
+            }
+        }
+    }
+    println!("\nThat is the tour. Run nb --standalone for a node of your own, where 'help' lists every command.");
+    Ok(())
 }
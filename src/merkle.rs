@@ -0,0 +1,106 @@
+//! Merkle trees over the transactions of a block
+//!
+//! Leaves are transaction hashes and every parent is the SHA-256 of its children's hex hashes
+//! concatenated. A level with an odd number of nodes pairs its last node with itself.
+//!
+//! So the leaves with the last ones repeated, e.g. `[a, b, c, c]` and `[a, b, c]`, have the
+//! same root, and a block with a transaction repeated the hash of the block without. Blocks
+//! must list their transactions once, which `Block::has_valid_merkle_root` checks, so that the
+//! repeating copy is turned down rather than taken for the block it mimics.
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::{Deserialize, Serialize};
+
+/// The root of a block without transactions.
+pub const EMPTY_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A sibling on the path from a leaf to the root.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Sibling {
+    Left(String),
+    Right(String),
+}
+
+/// Proves that a transaction is in the block at `block_index`, given the block's Merkle root.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MerkleProof {
+    pub block_index: u64,
+    pub path: Vec<Sibling>,
+}
+
+fn hash_pair(left: &str, right: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(left);
+    hasher.input_str(right);
+    hasher.result_str()
+}
+
+fn parent_level(level: &[String]) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
+}
+
+/// Computes the root of the tree over `leaves`.
+pub fn merkle_root(leaves: &[String]) -> String {
+    if leaves.is_empty() {
+        return EMPTY_ROOT.to_owned();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level.remove(0)
+}
+
+/// Returns the path from the leaf at `position` to the root, `None` if there is no such leaf.
+pub fn merkle_path(leaves: &[String], mut position: usize) -> Option<Vec<Sibling>> {
+    if position >= leaves.len() {
+        return None;
+    }
+    let mut path = vec![];
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = match position % 2 {
+            0 => Sibling::Right(level.get(position + 1).unwrap_or(&level[position]).clone()),
+            _ => Sibling::Left(level[position - 1].clone()),
+        };
+        path.push(sibling);
+        level = parent_level(&level);
+        position /= 2;
+    }
+    Some(path)
+}
+
+/// Checks that `path` leads from `leaf` to `root`.
+pub fn verify_path(leaf: &str, path: &[Sibling], root: &str) -> bool {
+    let computed = path
+        .iter()
+        .fold(leaf.to_owned(), |hash, sibling| match sibling {
+            Sibling::Left(left) => hash_pair(left, &hash),
+            Sibling::Right(right) => hash_pair(&hash, right),
+        });
+    computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_path() {
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+        for n in 1..=7 {
+            let leaves: Vec<String> = (0..n).map(|i| format!("{:064}", i)).collect();
+            let root = merkle_root(&leaves);
+            for (position, leaf) in leaves.iter().enumerate() {
+                let path = merkle_path(&leaves, position).unwrap();
+                assert!(verify_path(leaf, &path, &root));
+                assert!(!verify_path(&format!("{:064}", 42), &path, &root));
+            }
+            assert!(merkle_path(&leaves, n).is_none());
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Listeners passed to the node by the process starting it
+//!
+//! Instead of binding its addresses itself, the node can take over listening sockets its parent
+//! bound, see `NodeConfig::listen_fd` and `NodeConfig::control_fd`, or those systemd passes by
+//! socket activation, see sd_listen_fds(3). The parent keeps the sockets open across restarts of
+//! the node, queueing the connections meanwhile, and may bind ports the node has no right to.
+//! Without names, systemd passes the peer listener first and the control listener second;
+//! `FileDescriptorName=control` marks the control listener in any position.
+
+use super::*;
+use std::env;
+use std::net::TcpListener;
+
+/// The first descriptor systemd passes, following stdin, stdout and stderr.
+const LISTEN_FDS_START: i32 = 3;
+
+/// The name marking the control listener among those systemd passes.
+const CONTROL_FD_NAME: &str = "control";
+
+/// The listener for peers and the one for consoles: those passed by the parent or by systemd,
+/// else bound to `addr` and `control_addr`.
+pub(super) fn listeners(config: &NodeConfig) -> Result<(TcpListener, TcpListener)> {
+    let (activated, activated_control) = activated_fds();
+    let listener = match config.listen_fd.or(activated) {
+        Some(fd) => {
+            let listener = inherit(fd)?;
+            info!(
+                target: NET,
+                "Listening on the inherited socket {}, bound to {}",
+                fd,
+                listener.local_addr()?
+            );
+            listener
+        }
+        None => TcpListener::bind(&config.addr)?,
+    };
+    let control_listener = match config.control_fd.or(activated_control) {
+        Some(fd) => {
+            let listener = inherit(fd)?;
+            let addr = listener.local_addr()?;
+            if !addr.ip().is_loopback() {
+                return Err(failure::err_msg(format!(
+                    "The inherited control socket {} is bound to {}, not a loopback address, anyone reaching it would control the node",
+                    fd, addr
+                )));
+            }
+            info!(
+                target: REPL,
+                "Taking commands on the inherited socket {}, bound to {}",
+                fd, addr
+            );
+            listener
+        }
+        None => TcpListener::bind(&config.control_addr)?,
+    };
+    Ok((listener, control_listener))
+}
+
+/// The peer and the control listener systemd passed to this process, if it did.
+fn activated_fds() -> (Option<i32>, Option<i32>) {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = match env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+    {
+        Some(count) if for_us => count,
+        _ => return (None, None),
+    };
+    let fds = LISTEN_FDS_START..LISTEN_FDS_START + count;
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let names: Vec<&str> = names.split(':').collect();
+    let is_control =
+        |fd: &i32| names.get((fd - LISTEN_FDS_START) as usize) == Some(&CONTROL_FD_NAME);
+    match fds.clone().find(is_control) {
+        Some(control) => (fds.clone().find(|fd| !is_control(fd)), Some(control)),
+        None => (fds.clone().next(), fds.clone().nth(1)),
+    }
+}
+
+/// Takes over the listening socket `fd`, which nothing else in the process may use.
+#[cfg(unix)]
+fn inherit(fd: i32) -> Result<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    // the parent passed the descriptor for the node alone, which `NodeConfig::check` makes sure
+    // is not taken twice
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    listener.local_addr().map_err(|e| {
+        failure::err_msg(format!(
+            "Descriptor {} is not a listening socket: {}",
+            fd, e
+        ))
+    })?;
+    listener.set_nonblocking(false)?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn inherit(fd: i32) -> Result<TcpListener> {
+    Err(failure::err_msg(format!(
+        "Cannot take over descriptor {}, inherited listeners need Unix",
+        fd
+    )))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn test_inherited_listeners() {
+        let bound = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = (bound.local_addr().unwrap(), control.local_addr().unwrap());
+        let mut config = NodeConfig::new("127.0.0.1:4000");
+        config.listen_fd = Some(bound.into_raw_fd());
+        config.control_fd = Some(control.into_raw_fd());
+        let (listener, control_listener) = listeners(&config).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), addrs.0);
+        assert_eq!(control_listener.local_addr().unwrap(), addrs.1);
+
+        // a file is no listener
+        let file = std::fs::File::open("Cargo.toml").unwrap();
+        config.listen_fd = Some(file.into_raw_fd());
+        config.control_fd = None;
+        config.control_addr = "127.0.0.1:0".to_owned();
+        assert!(listeners(&config).is_err());
+    }
+}
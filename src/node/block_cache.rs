@@ -0,0 +1,114 @@
+//! Blocks downloaded from peers but not applied yet
+//!
+//! A sync that does not adopt a chain it downloaded, because it lost to another one, failed
+//! validation or the peer hung up halfway, used to throw its blocks away, so that the next
+//! attempt downloaded them again, from the same peer or another one. The cache keeps recent ones
+//! by their hash, which tells them apart whoever sent them, shared with the threads downloading
+//! chains: a sync only fetches the blocks neither the chain nor the cache holds, and an orphan
+//! finds its parent in it before asking a peer.
+
+use super::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// How many blocks are cached at most, the oldest go first.
+const BLOCK_CACHE_CAPACITY: usize = 1000;
+
+/// Blocks by their hash, see the module docs. Cheap to clone, clones share the blocks.
+#[derive(Clone)]
+pub struct BlockCache {
+    inner: Arc<Mutex<Cached>>,
+}
+
+struct Cached {
+    blocks: HashMap<String, Block>,
+    // oldest first
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new(BLOCK_CACHE_CAPACITY)
+    }
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            inner: Arc::new(Mutex::new(Cached {
+                blocks: HashMap::new(),
+                order: VecDeque::new(),
+                capacity,
+            })),
+        }
+    }
+
+    /// Keeps `block`, unless its transactions do not match its hash, which it would shadow the
+    /// block of in the cache.
+    pub fn insert(&self, block: Block) {
+        if !block.has_valid_merkle_root() {
+            return;
+        }
+        let mut cached = self.inner.lock().unwrap();
+        let hash = block.get_hash();
+        if cached.blocks.contains_key(&hash) {
+            return;
+        }
+        if cached.order.len() >= cached.capacity {
+            if let Some(evicted) = cached.order.pop_front() {
+                cached.blocks.remove(&evicted);
+            }
+        }
+        cached.order.push_back(hash.clone());
+        cached.blocks.insert(hash, block);
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Block> {
+        self.inner.lock().unwrap().blocks.get(hash).cloned()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.inner.lock().unwrap().blocks.contains_key(hash)
+    }
+
+    /// Lets go of the block with `hash`, once it is applied.
+    pub fn remove(&self, hash: &str) {
+        let mut cached = self.inner.lock().unwrap();
+        if cached.blocks.remove(hash).is_some() {
+            cached.order.retain(|kept| kept != hash);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().blocks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_cache() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let blocks: Vec<Block> = (0..3).map(|_| chain.mine_new_block().clone()).collect();
+        let hash = |i: usize| blocks[i].get_hash();
+
+        let cache = BlockCache::new(2);
+        let shared = cache.clone();
+        cache.insert(blocks[0].clone());
+        cache.insert(blocks[0].clone());
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared.get(&hash(0)).unwrap().get_index(), 1);
+        // the oldest goes first
+        cache.insert(blocks[1].clone());
+        cache.insert(blocks[2].clone());
+        assert!(!shared.contains(&hash(0)));
+        assert!(shared.contains(&hash(1)) && shared.contains(&hash(2)));
+        shared.remove(&hash(1));
+        assert_eq!(cache.len(), 1);
+        cache.insert(blocks[0].clone());
+        assert!(cache.contains(&hash(2)));
+    }
+}
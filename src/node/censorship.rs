@@ -0,0 +1,284 @@
+//! Detecting miners that keep leaving out transactions they could have included
+//!
+//! The node remembers at which height every transaction entered its mempool. Every later block
+//! that does not include a transaction, though it had room for it or included one paying a lower
+//! fee, counts as an exclusion by that block's miner. Exclusions can be innocent (the miner had
+//! not received the transaction yet), so only transactions that a miner skipped repeatedly are
+//! flagged.
+
+use super::*;
+use crate::blockchain::Block;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// A transaction some miner repeatedly left out.
+#[derive(Serialize, Debug, Clone)]
+pub struct Exclusion {
+    pub tx_id: String,
+    pub miner: String,
+    pub excluded: usize,
+    // when the transaction entered our mempool
+    pub pending_since: u64,
+    pub included_in: Option<u64>,
+}
+
+/// How many blocks back the history reaches. Transactions that entered the mempool earlier are
+/// forgotten, confirmed or not.
+const HISTORY_WINDOW: u64 = 1000;
+
+/// What the history keeps of a pending transaction.
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    // the index of the first block it was eligible for
+    entered: u64,
+    fee: i64,
+    size: usize,
+}
+
+impl Pending {
+    /// Whether the miner of `block` had reason to include the transaction: the block had room for
+    /// it or included one paying a lower fee.
+    fn passed_over_by(&self, block: &Block) -> bool {
+        self.size.saturating_add(1) < block.room()
+            || block
+                .get_transactions()
+                .iter()
+                .any(|t| t.get_sender() != REWARD_SENDER && t.get_fee() < self.fee)
+    }
+}
+
+#[derive(Default)]
+pub struct MempoolHistory {
+    // pending transaction id -> what is kept of it
+    entered: HashMap<String, Pending>,
+    // how often transactions confirmed since were left out, by each miner
+    settled: Vec<Exclusion>,
+}
+
+impl MempoolHistory {
+    /// Notes that `transaction` entered the mempool of `chain`.
+    pub fn record(&mut self, transaction: &Transaction, chain: &Blockchain) {
+        self.entered
+            .entry(transaction.get_id().to_owned())
+            .or_insert(Pending {
+                entered: chain.len() as u64,
+                fee: transaction.get_fee(),
+                size: transaction.size(),
+            });
+    }
+
+    /// Settles the transactions `chain` confirmed, counting their exclusions once and for all,
+    /// and forgets those older than `HISTORY_WINDOW` blocks.
+    pub fn prune(&mut self, chain: &Blockchain) {
+        let start = (chain.len() as u64).saturating_sub(HISTORY_WINDOW);
+        self.entered.retain(|_, pending| pending.entered >= start);
+        self.settled.retain(|e| e.pending_since >= start);
+        let confirmed: Vec<_> = self
+            .entered
+            .iter()
+            .filter_map(|(id, pending)| {
+                let height = chain.find_transaction(id)?.height?;
+                Some((id.as_str(), *pending, Some(height)))
+            })
+            .collect();
+        let confirmed_ids: Vec<String> = confirmed.iter().map(|(id, ..)| id.to_string()).collect();
+        let settled = count_exclusions(chain, confirmed);
+        for id in confirmed_ids {
+            self.entered.remove(&id);
+        }
+        self.settled.extend(settled);
+    }
+
+    /// Returns every transaction some miner left out at least `min_exclusions` times.
+    pub fn find_exclusions(&self, chain: &Blockchain, min_exclusions: usize) -> Vec<Exclusion> {
+        let pending = self
+            .entered
+            .iter()
+            .map(|(id, pending)| {
+                let height = chain.find_transaction(id).and_then(|t| t.height);
+                (id.as_str(), *pending, height)
+            })
+            .collect();
+        let mut exclusions = count_exclusions(chain, pending);
+        exclusions.extend(self.settled.iter().cloned());
+        exclusions.retain(|e| e.excluded >= min_exclusions);
+        exclusions.sort_by(|a, b| (&a.tx_id, &a.miner).cmp(&(&b.tx_id, &b.miner)));
+        exclusions
+    }
+
+    /// Returns the transactions some miner left out at least `min_exclusions` times.
+    pub fn report(&self, chain: &Blockchain, min_exclusions: usize) -> CensorshipReport {
+        CensorshipReport {
+            min_exclusions,
+            exclusions: self.find_exclusions(chain, min_exclusions),
+        }
+    }
+}
+
+/// Counts how often each miner of `chain` passed over each of `transactions`: its id, what is
+/// kept of it and the index of the block including it, if any.
+fn count_exclusions(
+    chain: &Blockchain,
+    mut transactions: Vec<(&str, Pending, Option<u64>)>,
+) -> Vec<Exclusion> {
+    let first = match transactions.iter().map(|(_, p, _)| p.entered).min() {
+        Some(first) => first,
+        None => return vec![],
+    };
+    transactions.sort_unstable_by_key(|(id, ..)| *id);
+    let mut excluded: BTreeMap<(usize, String), usize> = BTreeMap::new();
+    for index in first..chain.len() as u64 {
+        let block = match chain.get_block_by_index(index) {
+            Some(block) => block,
+            None => break,
+        };
+        let miner = block.get_miner().unwrap_or("unknown");
+        for (i, (_, pending, included_in)) in transactions.iter().enumerate() {
+            if pending.entered <= index
+                && included_in.is_none_or(|included| index < included)
+                && pending.passed_over_by(&block)
+            {
+                *excluded.entry((i, miner.to_owned())).or_default() += 1;
+            }
+        }
+    }
+    excluded
+        .into_iter()
+        .map(|((i, miner), count)| {
+            let (id, pending, included_in) = transactions[i];
+            Exclusion {
+                tx_id: id.to_owned(),
+                miner,
+                excluded: count,
+                pending_since: pending.entered,
+                included_in,
+            }
+        })
+        .collect()
+}
+
+/// The transactions flagged as censored, with the threshold they were flagged by.
+#[derive(Serialize, Debug, Clone)]
+pub struct CensorshipReport {
+    pub min_exclusions: usize,
+    pub exclusions: Vec<Exclusion>,
+}
+
+/// Shows the flagged transactions and how many of them each miner left out.
+impl fmt::Display for CensorshipReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exclusions.is_empty() {
+            return writeln!(
+                f,
+                "{}",
+                format!(
+                    "no transaction was left out {} times or more by a miner",
+                    self.min_exclusions
+                )
+                .color(MSG_COLOR)
+            );
+        }
+        let mut per_miner: BTreeMap<&str, usize> = BTreeMap::new();
+        for e in &self.exclusions {
+            let status = match e.included_in {
+                Some(index) => format!("finally included in block {}", index),
+                None => "still pending".to_owned(),
+            };
+            writeln!(
+                f,
+                "tx {}: eligible since block {}, left out {} times by {}, {}",
+                e.tx_id, e.pending_since, e.excluded, e.miner, status
+            )?;
+            *per_miner.entry(&e.miner).or_default() += 1;
+        }
+        for (miner, count) in per_miner {
+            writeln!(
+                f,
+                "{}",
+                format!("miner {} repeatedly left out {} transactions", miner, count)
+                    .color(PROMINENT_COLOR)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::MAX_BLOCK_SIZE;
+
+    fn mine(chain: &mut Blockchain, miner: &str, transactions: &[&Transaction]) {
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, miner, 1))
+            .unwrap();
+        for t in transactions {
+            chain.add_new_transaction(t).unwrap();
+        }
+        let hash = chain.last_block().get_hash();
+        chain.create_new_block(0, hash);
+    }
+
+    #[test]
+    fn test_find_exclusions() {
+        let mut chain = Blockchain::new();
+        let mut history = MempoolHistory::default();
+        let censored = Transaction::new("alice", "bob", 1);
+        // paid with the reward of the block it ends up in
+        let delayed = Transaction::new("honest", "dave", 1);
+        history.record(&censored, &chain);
+        history.record(&delayed, &chain);
+        for _ in 0..3 {
+            mine(&mut chain, "evil", &[]);
+        }
+        mine(&mut chain, "honest", &[&delayed]);
+
+        let exclusions = history.find_exclusions(&chain, 3);
+        assert_eq!(exclusions.len(), 2);
+        assert!(exclusions
+            .iter()
+            .all(|e| e.miner == "evil" && e.excluded == 3));
+        let delayed = exclusions
+            .iter()
+            .find(|e| e.tx_id == delayed.get_id())
+            .unwrap();
+        assert_eq!(delayed.included_in, Some(4));
+        assert!(history.find_exclusions(&chain, 4).is_empty());
+
+        // the confirmed transaction is settled, with its exclusions kept
+        history.prune(&chain);
+        assert_eq!(history.entered.len(), 1);
+        assert_eq!(history.find_exclusions(&chain, 3).len(), 2);
+        // and the pending one forgotten once out of the window
+        for _ in 0..HISTORY_WINDOW {
+            mine(&mut chain, "honest", &[]);
+        }
+        history.prune(&chain);
+        assert!(history.entered.is_empty() && history.settled.is_empty());
+    }
+
+    #[test]
+    fn test_full_blocks() {
+        let mut chain = Blockchain::new();
+        let mut history = MempoolHistory::default();
+        let paying = Transaction::new("alice", "bob", 1).with_fee(1);
+        let free = Transaction::new("alice", "carol", 1);
+        history.record(&paying, &chain);
+        history.record(&free, &chain);
+        // more than a block holds, none paying a fee
+        chain.push_pending(Transaction::new(REWARD_SENDER, "busy", 1));
+        for i in 0..MAX_BLOCK_SIZE / 100 {
+            chain.push_pending(Transaction::new("dave", "erin", i as i64 + 1));
+        }
+        let hash = chain.last_block().get_hash();
+        chain.create_new_block(0, hash);
+        assert!(chain.last_block().room() <= free.size() + 1);
+
+        // a full block only passed over the one paying more than what it included
+        let exclusions = history.find_exclusions(&chain, 1);
+        assert_eq!(exclusions.len(), 1);
+        assert_eq!(exclusions[0].tx_id, paying.get_id());
+        assert_eq!(exclusions[0].miner, "busy");
+    }
+}
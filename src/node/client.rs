@@ -0,0 +1,83 @@
+//! Queries of clients, which ask a node what it knows without becoming its peer
+
+use super::node::{connect, read_response};
+use super::*;
+use std::io::Write;
+use std::time::Duration;
+
+/// How long a client waits for the node.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Asks the node at `addr` for the balance of `address` after the block at `height`, or after
+/// its tip. `None` if its chain is not that long yet.
+pub fn get_balance(addr: &str, address: &str, height: Option<u64>) -> Result<Option<i64>> {
+    match query(addr, &Request::GetBalance(address.to_owned(), height))? {
+        Response::Balance(balance) => Ok(balance),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+/// Asks the node at `addr` what its chain confirmed during the last `window` seconds, or its
+/// default window.
+pub fn get_stats(addr: &str, window: Option<u64>) -> Result<Throughput> {
+    match query(addr, &Request::GetStats(window))? {
+        Response::Stats(throughput) => Ok(throughput),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+/// Asks the node at `addr` for the stats of its chain, e.g. its height and hashrate.
+pub fn get_chain_stats(addr: &str) -> Result<ChainStats> {
+    match query(addr, &Request::GetChainStats)? {
+        Response::ChainStats(stats) => Ok(stats),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+/// Asks the node at `addr` for the block `id` of its chain, `None` if it holds no such block.
+pub fn get_block(addr: &str, id: BlockId) -> Result<Option<Block>> {
+    match query(addr, &Request::GetBlock(id))? {
+        Response::Block(block) => Ok(block.map(|block| *block)),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+/// Asks the node at `addr` for the transaction with `id`, and how far it made it, `None` if
+/// the node does not know it.
+pub fn get_transaction(addr: &str, id: &str) -> Result<Option<TransactionInclusion>> {
+    match query(addr, &Request::GetTransaction(id.to_owned()))? {
+        Response::Transaction(found) => Ok(found.map(|found| *found)),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+/// Hands `block` to the node at `addr` to extend its chain with, returning why the node turned
+/// it down, if it did.
+pub fn submit_block(addr: &str, block: &Block) -> Result<std::result::Result<(), BlockRejection>> {
+    verdict(query(addr, &Request::SubmitBlock(block.clone()))?)
+}
+
+/// Asks the node at `addr` whether `block` would extend its chain, and why not.
+pub fn validate_block(
+    addr: &str,
+    block: &Block,
+) -> Result<std::result::Result<(), BlockRejection>> {
+    verdict(query(addr, &Request::ValidateBlock(block.clone()))?)
+}
+
+fn verdict(response: Response) -> Result<std::result::Result<(), BlockRejection>> {
+    match response {
+        Response::Ack(_) => Ok(Ok(())),
+        Response::Error(rejection) => Ok(Err(rejection)),
+        _ => Err(failure::err_msg("Invalid response")),
+    }
+}
+
+fn query(addr: &str, request: &Request) -> Result<Response> {
+    let addr = parse_addr(addr.to_owned())
+        .map_err(|_| failure::err_msg(format!("Invalid node address {}", addr)))?;
+    let mut stream = connect(addr, None, TIMEOUT)?;
+    serde_json::to_writer(stream.try_clone()?, request)?;
+    stream.flush()?;
+    read_response(stream)
+}
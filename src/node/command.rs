@@ -1,115 +1,708 @@
 use super::*;
+use crate::address::invalid_address;
+use crate::storage::AuditEntry;
 use colored::Colorize;
-use std::io::{stdin, stdout, Write};
-use tokio::sync::mpsc::Sender;
+use connections::ConnectionMetrics;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tokio::sync::oneshot;
 
+/// Where the outcome of a command goes back to the frontend that issued it.
+pub type Responder = oneshot::Sender<Result<CommandOutput>>;
+
+/// A command of the console, sent to the node over its control interface.
+#[derive(Serialize, Deserialize, Debug)]
 pub enum Command {
-    NewTrans(String, String, i64), // sender, receiver, amount
+    NewTrans(String, String, i64, i64, Option<Expiry>), // sender, receiver, amount, fee, expiry
+    Replace(String, i64),                               // id, fee
+    Issue(String, String, String, i64, i64, i64), // issuer, receiver, asset, amount, max supply, fee
+    SendAsset(String, String, String, i64, i64),  // sender, receiver, asset, amount, fee
+    Asset(String, Option<String>),                // asset, address
     Display,
+    GetBlock(BlockId),
+    FindTransaction(String), // id
+    History(String),         // address
     AddPeer(String),
+    Repin(String), // address
     DisplayPeers,
     Resolve,
     Mine,
+    PreviewBlock,
+    Telemetry,
+    Gossip(Option<(GossipStrategy, Option<usize>)>), // strategy, fanout
+    Censorship(usize),                               // min exclusions
+    GetBalance(String, Option<u64>),                 // address, height
+    Balance(String, bool),                           // address, whether to include pending
+    Stats(Option<u64>),                              // window in seconds
+    ChainStats,
+    Signals,
+    SetRewardAddress(String),
+    RotateKey,
+    Connections,
+    AuditLog,
+    Wallet,
+    WalletNew(String, Passphrase), // name, passphrase
+    WalletList,
+    WalletAddress(String),                               // name
+    WalletReceive(String, Option<Vec<u32>>, Passphrase), // name, path, passphrase
+    Send(String, String, i64, i64, Passphrase), // wallet, receiver, amount, fee, passphrase
+    TxStatus(String),                           // id
+    Notarize,
+    VerifyAttestation(Attestation),
+    LogStatus,
+    Exit,
+}
+
+/// What a command results in, for the frontend that issued it to show or to process.
+#[derive(Debug)]
+pub enum CommandOutput {
+    Unit,
+    Submitted(Submission),
+    BlockSummary(BlockSummary),
+    Blocks(BlocksView),
+    Block(Box<BlockDetails>),
+    Transaction(Box<TransactionInclusion>),
+    History(Vec<TransactionInclusion>),
+    PeerList(Vec<PeerInfo>),
+    Balance(i64),
+    Asset(AssetStatus),
+    Synced(bool), // whether the chain was replaced
+    AlreadySyncing,
+    Gossip(GossipStatus),
+    Propagation(PropagationReport),
+    Censorship(CensorshipReport),
+    Stats(Throughput),
+    ChainStats(ChainStats),
+    Signals(Vec<Readiness>, Vec<u8>), // the readiness by bit, the bits this node signals
+    Connections(ConnectionMetrics),
+    AuditLog(Vec<AuditEntry>),
+    Wallet(Vec<SentTransaction>),
+    Wallets(Vec<(String, String)>),   // names and addresses
+    Addresses(Vec<(String, String)>), // paths and addresses
+    Address(String),
+    TxStatus(Box<SentTransaction>),
+    Attestation(Attestation),
+    Attested(Attestation), // checked against the chain
+    LogLevels(Vec<(&'static str, Option<log::Level>)>), // the most verbose per target
+}
+
+/// Shows the output to the user of the console.
+impl fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandOutput::Unit => Ok(()),
+            CommandOutput::Submitted(submission) => writeln!(f, "{}", submission),
+            CommandOutput::BlockSummary(summary) => write!(f, "{}", summary),
+            CommandOutput::Blocks(blocks) => writeln!(f, "{}", pretty(blocks)?),
+            CommandOutput::Block(details) => writeln!(f, "{}", pretty(details)?),
+            CommandOutput::Transaction(found) => {
+                writeln!(f, "{}", pretty(&found.transaction)?)?;
+                writeln!(f, "{}", found.to_string().color(MSG_COLOR))
+            }
+            CommandOutput::PeerList(peers) => writeln!(f, "{}", pretty(peers)?),
+            CommandOutput::Balance(balance) => writeln!(f, "{}", balance),
+            CommandOutput::Asset(status) => write!(f, "{}", status),
+            CommandOutput::Synced(true) => writeln!(f, "node updated"),
+            CommandOutput::Synced(false) => writeln!(f, "node stays unchanged"),
+            CommandOutput::AlreadySyncing => writeln!(f, "already syncing"),
+            CommandOutput::Gossip(status) => write!(f, "{}", status),
+            CommandOutput::Propagation(report) => write!(f, "{}", report),
+            CommandOutput::Censorship(report) => write!(f, "{}", report),
+            CommandOutput::Stats(throughput) => write!(f, "{}", throughput),
+            CommandOutput::ChainStats(stats) => write!(f, "{}", stats),
+            CommandOutput::Signals(readiness, ours) => {
+                if readiness.is_empty() {
+                    return writeln!(f, "no block signals readiness for a rule change");
+                }
+                for r in readiness {
+                    write!(
+                        f,
+                        "bit {}: {} of the last {} blocks",
+                        r.bit, r.blocks, r.window
+                    )?;
+                    if r.is_locked_in() {
+                        write!(f, ", {}", "locked in".color(MSG_COLOR))?;
+                    }
+                    if ours.contains(&r.bit) {
+                        write!(f, ", signalled by this node")?;
+                    }
+                    writeln!(f)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Connections(metrics) => write!(f, "{}", metrics),
+            CommandOutput::AuditLog(entries) => {
+                for entry in entries {
+                    writeln!(f, "{} {}", entry.at, entry.event)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Attestation(attestation) => writeln!(f, "{}", pretty(attestation)?),
+            CommandOutput::Attested(a) => writeln!(
+                f,
+                "{}",
+                format!(
+                    "block {} ({}) is attested by {} at {}, and on our chain",
+                    a.index, a.hash, a.key, a.at
+                )
+                .color(MSG_COLOR)
+            ),
+            CommandOutput::LogLevels(levels) => {
+                match std::env::var("RUST_LOG") {
+                    Ok(filters) => writeln!(f, "RUST_LOG={}", filters)?,
+                    Err(_) => writeln!(f, "RUST_LOG is not set, the default filters apply")?,
+                }
+                for (target, level) in levels {
+                    match level {
+                        Some(level) => writeln!(f, "{}: {} and above", target, level)?,
+                        None => writeln!(f, "{}: off", target)?,
+                    }
+                }
+                Ok(())
+            }
+            CommandOutput::Wallet(sent) => {
+                for s in sent {
+                    writeln!(f, "{}", s)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Wallets(wallets) => {
+                if wallets.is_empty() {
+                    return writeln!(f, "no wallet yet, create one with wallet new [name]");
+                }
+                for (name, address) in wallets {
+                    writeln!(f, "{}: {}", name, address)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Addresses(addresses) => {
+                for (path, address) in addresses {
+                    writeln!(f, "{}: {}", path, address)?;
+                }
+                Ok(())
+            }
+            CommandOutput::Address(address) => writeln!(f, "{}", address),
+            CommandOutput::TxStatus(sent) => writeln!(f, "{}", sent),
+            CommandOutput::History(history) => {
+                for found in history {
+                    let t = &found.transaction;
+                    writeln!(
+                        f,
+                        "tx {}: {} -> {}, amount: {}, fee: {}, {}",
+                        t.get_id(),
+                        t.get_sender(),
+                        t.get_recipient(),
+                        t.amount_label(),
+                        t.get_fee(),
+                        found
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl CommandOutput {
+    /// The hash of the block or the id of the transaction the output is about, if any, which
+    /// the console remembers as `$last`.
+    pub fn last(&self) -> Option<String> {
+        match self {
+            CommandOutput::Submitted(submission) => Some(submission.id.clone()),
+            CommandOutput::BlockSummary(summary) => Some(summary.hash.clone()),
+            CommandOutput::Block(details) => Some(details.block.get_hash()),
+            CommandOutput::Transaction(found) => Some(found.transaction.get_id().to_owned()),
+            CommandOutput::TxStatus(sent) => Some(sent.transaction.get_id().to_owned()),
+            _ => None,
+        }
+    }
+}
+
+/// What a console remembers between the commands of its user: the variables set with `set`,
+/// and `last`, the block hash or transaction id the last command output that had one. A word
+/// `$name` of a command stands for the value of the variable.
+#[derive(Debug, Default)]
+pub struct Session {
+    variables: BTreeMap<String, String>,
+}
+
+impl Session {
+    /// Replaces every word `$name` of `input` by the value of the variable.
+    pub fn expand(&self, input: &str) -> std::result::Result<String, String> {
+        let words: std::result::Result<Vec<&str>, String> = input
+            .split_whitespace()
+            .map(|word| match word.strip_prefix('$') {
+                Some(name) => {
+                    self.variables
+                        .get(name)
+                        .map(String::as_str)
+                        .ok_or_else(|| match name {
+                            LAST => {
+                                "no command output a block hash or transaction id yet".to_owned()
+                            }
+                            _ => format!("${} is not set, see set", name),
+                        })
+                }
+                None => Ok(word),
+            })
+            .collect();
+        Ok(words?.join(" "))
+    }
+
+    /// Runs `set [name] [value]`, which sets a variable, or lists them all without arguments.
+    /// Returns what to print.
+    pub fn set(&mut self, args: &[&str]) -> std::result::Result<String, String> {
+        match args {
+            [] => Ok(self
+                .variables
+                .iter()
+                .map(|(name, value)| format!("${} = {}\n", name, value))
+                .collect()),
+            [LAST, _] => Err("$last is set by the commands".to_owned()),
+            [name, value] if is_valid_address(name) => {
+                self.variables
+                    .insert((*name).to_owned(), (*value).to_owned());
+                Ok(String::new())
+            }
+            [_, _] => Err("illegal variable name!".to_owned()),
+            _ => Err("usage: set [name] [value]".to_owned()),
+        }
+    }
+
+    /// Remembers `last` as `$last`.
+    pub fn remember(&mut self, last: String) {
+        self.variables.insert(LAST.to_owned(), last);
+    }
+}
+
+/// The pretty JSON of `value`.
+fn pretty(value: &impl Serialize) -> std::result::Result<String, fmt::Error> {
+    serde_json::to_string_pretty(value).map_err(|_| fmt::Error)
+}
+
+/// A block with what it minted, collected and moved.
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockDetails {
+    pub block: Block,
+    pub accounting: Option<BlockAccounting>,
+}
+
+/// An asset as of the last block, with the tokens of it an address holds, if asked for.
+#[derive(Debug, Clone)]
+pub struct AssetStatus {
+    pub id: String,
+    /// `None` until it is issued
+    pub asset: Option<Asset>,
+    pub holding: Option<(String, i64)>,
+}
+
+impl fmt::Display for AssetStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.asset {
+            Some(asset) => writeln!(
+                f,
+                "asset {}: {} of {} issued by {}",
+                self.id, asset.issued, asset.max_supply, asset.issuer
+            )?,
+            None => writeln!(f, "asset {} is not issued yet", self.id)?,
+        }
+        match &self.holding {
+            Some((address, held)) => writeln!(f, "{} holds {}", address, held),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The gist of a block, e.g. one just mined.
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockSummary {
+    pub index: u64,
+    pub hash: String,
+    pub transactions: usize,
+    pub miner: Option<String>,
+}
+
+impl BlockSummary {
+    pub fn of(block: &Block) -> Self {
+        BlockSummary {
+            index: block.get_index(),
+            hash: block.get_hash(),
+            transactions: block.get_transactions().len(),
+            miner: block.get_miner().map(str::to_owned),
+        }
+    }
+}
+
+impl fmt::Display for BlockSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "block {} ({}) with {} transactions",
+                self.index,
+                abbreviate(&self.hash),
+                self.transactions
+            )
+            .color(MSG_COLOR)
+        )
+    }
 }
 
 const NEW_TRANS: &str = "new_trans";
+const REPLACE: &str = "replace";
+const ISSUE: &str = "issue";
+const SEND_ASSET: &str = "send_asset";
+const ASSET: &str = "asset";
 const SEE_BLOCKCHAIN: &str = "list_blocks";
+const GET_BLOCK: &str = "get_block";
+const FIND_TX: &str = "find_tx";
+const HISTORY: &str = "history";
 const ADD_PEER: &str = "add_peer";
 const LIST_PEERS: &str = "list_peers";
+const REPIN: &str = "repin";
 const RESOLVE_CONFLICTS: &str = "resolve";
-const EXIT: &str = "exit";
-const HELP: &str = "help";
+pub(super) const EXIT: &str = "exit";
+pub(super) const HELP: &str = "help";
+pub(super) const SET: &str = "set";
+const LAST: &str = "last";
+const SHUTDOWN: &str = "shutdown";
 const MINE: &str = "mine";
+const PREVIEW_BLOCK: &str = "preview_block";
+const TELEMETRY: &str = "telemetry";
+const GOSSIP: &str = "gossip";
+const CENSORSHIP: &str = "censorship";
+const GET_BALANCE: &str = "get_balance";
+const BALANCE: &str = "balance";
+const STATS: &str = "stats";
+const CHAIN_STATS: &str = "chain_stats";
+const SIGNALS: &str = "signals";
+const SET_REWARD_ADDRESS: &str = "set_reward_address";
+const ROTATE_KEY: &str = "rotate_key";
+const CONNECTIONS: &str = "connections";
+const AUDIT_LOG: &str = "audit_log";
+const WALLET: &str = "wallet";
+const SEND: &str = "send";
+const TX_STATUS: &str = "tx_status";
+const NOTARIZE: &str = "notarize";
+const VERIFY_ATTESTATION: &str = "verify_attestation";
+const LOG_STATUS: &str = "log_status";
 
-pub async fn handle_input_commands(sender: Sender<Event>) {
-    loop {
-        let mut input = String::new();
-        // a prompt for input
-        print!("{}", "> ".color(PROMPT_COLOR).bold());
-        stdout().flush().expect("flush error");
-        stdin().read_line(&mut input).expect("cannot read input");
-        let input = input.trim();
-        let args: Vec<&str> = input.split_whitespace().collect();
-        let command = match args.get(0) {
-            Some(value) => *value,
-            None => {
-                continue;
-            }
-        };
-        let mut event_cmd = None;
-        match command {
-            NEW_TRANS => {
-                if args.len() < 4 {
-                    eprintln!("{}", "not enough arguments!".color(ERR_COLOR));
-                    continue;
-                }
-                let sender = *args.get(1).unwrap();
-                let receiver = *args.get(2).unwrap();
-                let amount: i64;
-                match (*args.get(3).unwrap()).parse() {
-                    Ok(num) => amount = num,
-                    Err(_) => {
-                        eprintln!("{}", "illegal amount!".color(ERR_COLOR));
-                        continue;
-                    }
-                };
-                event_cmd = Some(Command::NewTrans(
-                    sender.to_owned(),
-                    receiver.to_owned(),
-                    amount,
-                ))
+/// Parses a line of the console into the command it stands for, or tells what is wrong with it.
+/// The console handles `help` and `exit` itself, as they do not reach the node.
+pub fn parse(input: &str) -> std::result::Result<Command, String> {
+    let args: Vec<&str> = input.split_whitespace().collect();
+    let not_enough = || "not enough arguments!".to_owned();
+    let command = match args.first() {
+        Some(value) => *value,
+        None => return Err(not_enough()),
+    };
+    let command = match command {
+        NEW_TRANS => {
+            if args.len() < 4 {
+                return Err(not_enough());
             }
-            MINE => {
-                event_cmd = Some(Command::Mine);
-                debug!("{}", "Ready to mine".color(MSG_COLOR))
+            let sender = args[1];
+            let receiver = args[2];
+            if sender != REWARD_SENDER {
+                check_address(sender)?;
             }
-            SEE_BLOCKCHAIN => {
-                event_cmd = Some(Command::Display);
-            }
-            ADD_PEER => {
-                if args.len() < 2 {
-                    eprintln!("{}", "not enough arguments!".color(ERR_COLOR));
-                    continue;
+            check_address(receiver)?;
+            let amount: i64 = args[3].parse().map_err(|_| "illegal amount!".to_owned())?;
+            let fee: i64 = args
+                .get(4)
+                .map_or(Ok(0), |fee| fee.parse())
+                .map_err(|_| "illegal fee!".to_owned())?;
+            let expiry = match (args.get(5), args.get(6).map(|s| s.parse::<u64>())) {
+                (None, _) => None,
+                (Some(&"--expire-height"), Some(Ok(height))) => Some(Expiry::Height(height)),
+                (Some(&"--expire-in"), Some(Ok(secs))) => Some(Expiry::Time(
+                    crate::blockchain::get_time() + u128::from(secs) * 1000,
+                )),
+                _ => {
+                    return Err("usage: new_trans [sender] [receiver] [amount] [fee] [--expire-height N | --expire-in SECS]".to_owned())
                 }
-                let peer = *args.get(1).unwrap();
-                event_cmd = Some(Command::AddPeer(peer.to_owned()));
-            }
-            LIST_PEERS => {
-                event_cmd = Some(Command::DisplayPeers);
+            };
+            Command::NewTrans(sender.to_owned(), receiver.to_owned(), amount, fee, expiry)
+        }
+        REPLACE => {
+            if args.len() < 3 {
+                return Err(not_enough());
             }
-            RESOLVE_CONFLICTS => {
-                event_cmd = Some(Command::Resolve);
+            let fee: i64 = args[2].parse().map_err(|_| "illegal fee!".to_owned())?;
+            Command::Replace(args[1].to_owned(), fee)
+        }
+        ISSUE => {
+            let usage = "usage: issue [issuer] [receiver] [asset] [amount] [max_supply] [fee]";
+            let numbers: std::result::Result<Vec<i64>, _> =
+                args.iter().skip(4).map(|s| s.parse::<i64>()).collect();
+            match (&args[1..], numbers.as_deref()) {
+                ([issuer, receiver, asset, ..], Ok([amount, max_supply, fee @ ..]))
+                    if fee.len() <= 1 =>
+                {
+                    Command::Issue(
+                        (*issuer).to_owned(),
+                        (*receiver).to_owned(),
+                        (*asset).to_owned(),
+                        *amount,
+                        *max_supply,
+                        fee.first().copied().unwrap_or(0),
+                    )
+                }
+                _ => return Err(usage.to_owned()),
             }
-            HELP => {
-                list_commands();
+        }
+        SEND_ASSET => {
+            let usage = "usage: send_asset [sender] [receiver] [asset] [amount] [fee]";
+            let numbers: std::result::Result<Vec<i64>, _> =
+                args.iter().skip(4).map(|s| s.parse::<i64>()).collect();
+            match (&args[1..], numbers.as_deref()) {
+                ([sender, receiver, asset, ..], Ok([amount, fee @ ..])) if fee.len() <= 1 => {
+                    Command::SendAsset(
+                        (*sender).to_owned(),
+                        (*receiver).to_owned(),
+                        (*asset).to_owned(),
+                        *amount,
+                        fee.first().copied().unwrap_or(0),
+                    )
+                }
+                _ => return Err(usage.to_owned()),
             }
-            EXIT => {
-                break;
+        }
+        ASSET => match &args[1..] {
+            [asset] => Command::Asset((*asset).to_owned(), None),
+            [asset, address] => Command::Asset((*asset).to_owned(), Some((*address).to_owned())),
+            _ => return Err("usage: asset [asset] [address]".to_owned()),
+        },
+        MINE => {
+            debug!(target: REPL, "{}", "Ready to mine".color(MSG_COLOR));
+            Command::Mine
+        }
+        PREVIEW_BLOCK => Command::PreviewBlock,
+        SEE_BLOCKCHAIN => Command::Display,
+        GET_BLOCK => match args.get(1).map(|s| s.parse::<BlockId>()) {
+            Some(Ok(id)) => Command::GetBlock(id),
+            _ => return Err(not_enough()),
+        },
+        FIND_TX => match args.get(1) {
+            Some(id) => Command::FindTransaction((*id).to_owned()),
+            None => return Err(not_enough()),
+        },
+        HISTORY => match args.get(1) {
+            Some(address) => Command::History((*address).to_owned()),
+            None => return Err(not_enough()),
+        },
+        ADD_PEER => match args.get(1) {
+            Some(peer) => Command::AddPeer((*peer).to_owned()),
+            None => return Err(not_enough()),
+        },
+        REPIN => match args.get(1) {
+            Some(peer) => Command::Repin((*peer).to_owned()),
+            None => return Err(not_enough()),
+        },
+        LIST_PEERS => Command::DisplayPeers,
+        RESOLVE_CONFLICTS => Command::Resolve,
+        TELEMETRY => Command::Telemetry,
+        GOSSIP => {
+            let strategy = match args.get(1).map(|s| s.parse::<GossipStrategy>()) {
+                Some(Ok(strategy)) => Some(strategy),
+                Some(Err(e)) => return Err(e.to_string()),
+                None => None,
+            };
+            let fanout = match args.get(2).map(|s| s.parse::<usize>()) {
+                Some(Ok(fanout)) => Some(fanout),
+                Some(Err(_)) => return Err("illegal fanout!".to_owned()),
+                None => None,
+            };
+            Command::Gossip(strategy.map(|s| (s, fanout)))
+        }
+        CENSORSHIP => match args.get(1).map(|s| s.parse::<usize>()) {
+            Some(Ok(min_exclusions)) => Command::Censorship(min_exclusions),
+            Some(Err(_)) => return Err("illegal number of exclusions!".to_owned()),
+            None => Command::Censorship(3),
+        },
+        GET_BALANCE => {
+            let address = match args.get(1) {
+                Some(address) => *address,
+                None => return Err(not_enough()),
+            };
+            let height = match (args.get(2), args.get(3).map(|s| s.parse::<u64>())) {
+                (None, _) => None,
+                (Some(&"--height"), Some(Ok(height))) => Some(height),
+                _ => return Err("usage: get_balance [address] --height [N]".to_owned()),
+            };
+            Command::GetBalance(address.to_owned(), height)
+        }
+        BALANCE => match &args[1..] {
+            [address] => Command::Balance((*address).to_owned(), false),
+            [address, "--pending"] => Command::Balance((*address).to_owned(), true),
+            _ => return Err("usage: balance [address] [--pending]".to_owned()),
+        },
+        STATS => match args.get(1).map(|s| s.parse::<u64>()) {
+            Some(Ok(window)) => Command::Stats(Some(window)),
+            Some(Err(_)) => return Err("illegal window!".to_owned()),
+            None => Command::Stats(None),
+        },
+        CHAIN_STATS => Command::ChainStats,
+        SIGNALS => Command::Signals,
+        SET_REWARD_ADDRESS => {
+            let address = match args.get(1) {
+                Some(address) => *address,
+                None => return Err(not_enough()),
+            };
+            check_address(address)?;
+            Command::SetRewardAddress(address.to_owned())
+        }
+        ROTATE_KEY => Command::RotateKey,
+        CONNECTIONS => Command::Connections,
+        AUDIT_LOG => Command::AuditLog,
+        WALLET => match &args[1..] {
+            [] => Command::Wallet,
+            ["new", name] => Command::WalletNew((*name).to_owned(), Passphrase::default()),
+            ["list"] => Command::WalletList,
+            ["address", name] => Command::WalletAddress((*name).to_owned()),
+            ["receive", name] => {
+                Command::WalletReceive((*name).to_owned(), None, Passphrase::default())
             }
+            ["receive", name, path] => match parse_path(path) {
+                Some(path) => {
+                    Command::WalletReceive((*name).to_owned(), Some(path), Passphrase::default())
+                }
+                None => {
+                    return Err(format!(
+                        "{} is no path of hardened children like m/0'/1'",
+                        path
+                    ))
+                }
+            },
             _ => {
-                eprintln!(
-                    "{}",
-                    "Command not found. Type 'help' to list commands.".color(ERR_COLOR)
-                );
+                return Err(
+                    "usage: wallet [new [name] | list | address [name] | receive [name] [path]]"
+                        .to_owned(),
+                )
+            }
+        },
+        SEND => {
+            let usage = "usage: send [wallet] [receiver] [amount] [fee]";
+            let numbers: std::result::Result<Vec<i64>, _> =
+                args.iter().skip(3).map(|s| s.parse::<i64>()).collect();
+            match (&args[1..], numbers.as_deref()) {
+                ([wallet, receiver, ..], Ok([amount, fee @ ..])) if fee.len() <= 1 => {
+                    check_address(receiver)?;
+                    Command::Send(
+                        (*wallet).to_owned(),
+                        (*receiver).to_owned(),
+                        *amount,
+                        fee.first().copied().unwrap_or(0),
+                        Passphrase::default(),
+                    )
+                }
+                _ => return Err(usage.to_owned()),
             }
         }
-        if let Some(event_cmd) = event_cmd {
-            sender.send(Event::Command(event_cmd)).await;
+        TX_STATUS => match args.get(1) {
+            Some(id) => Command::TxStatus((*id).to_owned()),
+            None => return Err(not_enough()),
+        },
+        NOTARIZE => Command::Notarize,
+        VERIFY_ATTESTATION => {
+            let path = match args.get(1) {
+                Some(path) => *path,
+                None => return Err(not_enough()),
+            };
+            // read by the console, whose user named the file
+            let attestation = std::fs::read_to_string(path)
+                .map_err(failure::Error::from)
+                .and_then(|json| Ok(serde_json::from_str(&json)?))
+                .map_err(|e| format!("cannot read an attestation from {}: {}", path, e))?;
+            Command::VerifyAttestation(attestation)
         }
+        LOG_STATUS => Command::LogStatus,
+        SHUTDOWN => Command::Exit,
+        _ => return Err("Command not found. Type 'help' to list commands.".to_owned()),
+    };
+    Ok(command)
+}
+
+/// Turns down `address` unless it is valid, telling what is wrong with it.
+fn check_address(address: &str) -> std::result::Result<(), String> {
+    if is_valid_address(address) {
+        Ok(())
+    } else {
+        Err(format!("illegal address! {}", invalid_address(address)))
     }
 }
 
-fn list_commands() {
+pub(super) fn list_commands() {
     println!(
         "{}",
         concat!("blockchain node commands:\n",
         "  mine - mines a new block\n",
-        "  new_trans [sender] [receiver] [amount] - adds a new transaction into the local blockchain\n",
+        "  preview_block - show the block mine would make now, with its reward, fees and transactions, without mining it\n",
+        "  set_reward_address [address] - send the rewards of mined blocks to another address\n",
+        "  new_trans [sender] [receiver] [amount] [fee] [--expire-height N | --expire-in SECS] - adds a new transaction into the local blockchain, paying [fee] (default 0) to its miner, dropped unless mined by block N or within SECS\n",
+        "  replace [id] [fee] - replaces a pending transaction with the same one paying a higher [fee]\n",
+        "  issue [issuer] [receiver] [asset] [amount] [max_supply] [fee] - issues new tokens of an asset, making the issuer the only one to issue more, up to [max_supply]\n",
+        "  send_asset [sender] [receiver] [asset] [amount] [fee] - sends tokens of an asset, paying [fee] (default 0) in coins\n",
+        "  asset [asset] [address] - show who issued an asset and how much of it, and how much of it an address holds\n",
+        "  send [wallet] [receiver] [amount] [fee] - sends coins from the address of a wallet, signed with its key, paying [fee] (default 0) to its miner; asks for the passphrase of the wallet\n",
+        "  wallet - list the transactions this node sent, and whether they need re-sending or re-signing\n",
+        "  wallet new [name] - create a wallet with a fresh key in the data directory, encrypted under a passphrase it asks for, and show its address\n",
+        "  wallet list - list the wallets with their addresses\n",
+        "  wallet address [name] - show the addresses of a wallet, its own and those derived from it\n",
+        "  wallet receive [name] [path] - derive a fresh address of a wallet from its seed, at [path] like m/0'/1' (default the next m/0'/n'), covered by the backup of the wallet\n",
+        "  tx_status [id] - show what became of a transaction this node sent, and how many peers it was relayed to\n",
         "  list_blocks - list the local chain blocks\n",
+        "  get_block [index|hash] - show a block with its subsidy, fees, miner and transferred value, the hash may be cut to a prefix of 4 or more characters naming one block\n",
+        "  find_tx [id] - show a transaction, and the block confirming it with how many confirmations, or that it is pending, the id may be cut to a prefix of 4 or more characters naming one transaction\n",
+        "  history [address] - list the transactions sending to or from an address, oldest first, then the pending ones\n",
         "  add_peer [addr:port] - add one node as a peer\n",
         "  list_peers - list the node's peers\n",
+        "  repin [addr:port] - trust the key the peer at the address tells now, after it changed without a rotation\n",
         "  resolve - apply the consensus algorithm to resolve conflicts\n",
-        "  exit - quit the program")
+        "  gossip [flood|random|push-pull] [fanout] - show or switch the gossip strategy\n",
+        "  get_balance [address] [--height N] - show the balance of an address, as of block N if given\n",
+        "  balance [address] [--pending] - show the confirmed balance of an address, or with the pending transactions\n",
+        "  stats [window_secs] - show blocks per hour and confirmed transactions per second\n",
+        "  chain_stats - show the height, transactions, mempool, difficulty, estimated hashrate, block interval and peers\n",
+        "  signals - show how many of the last 100 blocks signal readiness for each rule change, and which are locked in by 95 of them\n",
+        "  rotate_key - replace the node key, and so its id, telling peers\n",
+        "  connections - show how many incoming connections are open, closed, reaped and refused\n",
+        "  notarize - print the hash of the last block, attested with the node key, to publish elsewhere\n",
+        "  verify_attestation [file] - check an attestation printed by notarize against the local chain\n",
+        "  audit_log - show what happened to the stored data, e.g. corrupt blocks and their repairs\n",
+        "  log_status - show which levels the log filters let through per target, e.g. nb::net; set RUST_LOG=debug,nb::net=warn to quiet the peers\n",
+        "  censorship [min_exclusions] - report pending transactions miners keep leaving out (default 3)\n",
+        "  telemetry - show block propagation delays (collector of the experiment only)\n",
+        "  set [name] [value] - set a variable of the console, which $name stands for in later commands, $last for the block hash or transaction id the last command output, e.g. get_block $last; without arguments, list them\n",
+        "  exit - leave the console, which stops the node only if nb started it\n",
+        "  shutdown - stop the node")
             .color(MSG_COLOR)
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let mut session = Session::default();
+        assert!(session.expand("get_block $last").is_err());
+        assert_eq!(session.set(&["me", "alice"]), Ok(String::new()));
+        assert!(session.set(&["last", "abc"]).is_err());
+        assert!(session.set(&["$me", "alice"]).is_err());
+        session.remember("abcd1234".to_owned());
+        assert_eq!(
+            session.expand("new_trans  $me bob 3").unwrap(),
+            "new_trans alice bob 3"
+        );
+        assert_eq!(
+            session.expand("get_block $last").unwrap(),
+            "get_block abcd1234"
+        );
+        assert!(session.expand("new_trans $you bob 3").is_err());
+        assert_eq!(session.set(&[]).unwrap(), "$last = abcd1234\n$me = alice\n");
+    }
+}
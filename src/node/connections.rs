@@ -0,0 +1,275 @@
+use super::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Counts of incoming connections since the node started.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionMetrics {
+    pub open: usize,
+    pub opened: u64,
+    pub closed: u64,
+    // neither read from nor written to for the idle timeout
+    pub reaped: u64,
+    // turned away because too many connections were open
+    pub refused: u64,
+}
+
+impl fmt::Display for ConnectionMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "connections: {} open, {} opened, {} closed, {} reaped, {} refused",
+                self.open, self.opened, self.closed, self.reaped, self.refused
+            )
+            .color(PROMINENT_COLOR)
+        )
+    }
+}
+
+/// The incoming connections of a node, bounded in number and in how long they may stay idle.
+///
+/// Every request comes on a connection of its own, which stays open until the event loop has
+/// answered it. A peer vanishing silently, or a busy event loop, would otherwise keep sockets
+/// open for good.
+pub struct Connections {
+    max_open: usize,
+    idle_timeout: Duration,
+    // what the times of the last activity on connections count from
+    started: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    // a handle of every open connection, to shut it down when it is reaped, and when it was last
+    // read from or written to, see `Connections::elapsed`
+    open: HashMap<u64, (Arc<AtomicU64>, TcpStream)>,
+    metrics: ConnectionMetrics,
+}
+
+impl Connections {
+    pub fn new(max_open: usize, idle_timeout: Duration) -> Arc<Self> {
+        Arc::new(Connections {
+            max_open,
+            idle_timeout,
+            started: Instant::now(),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// The milliseconds since the connections were set up.
+    fn elapsed(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    /// Admits `stream`, unless too many connections are open already.
+    pub fn accept(self: &Arc<Self>, stream: TcpStream) -> Result<Option<Connection>> {
+        let mut state = self.state.lock().unwrap();
+        if state.open.len() >= self.max_open {
+            state.metrics.refused += 1;
+            return Ok(None);
+        }
+        // a silent peer gives up its connection after the idle timeout
+        stream.set_read_timeout(Some(self.idle_timeout))?;
+        stream.set_write_timeout(Some(self.idle_timeout))?;
+        let id = state.next_id;
+        state.next_id += 1;
+        let active_at = Arc::new(AtomicU64::new(self.elapsed()));
+        state
+            .open
+            .insert(id, (active_at.clone(), stream.try_clone()?));
+        state.metrics.opened += 1;
+        Ok(Some(Connection {
+            id,
+            stream,
+            active_at,
+            connections: self.clone(),
+        }))
+    }
+
+    /// Shuts down the connections not read from nor written to for the idle timeout, returning
+    /// how many.
+    pub fn reap(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let now = self.elapsed();
+        let idle_timeout = self.idle_timeout.as_millis() as u64;
+        let idle: Vec<u64> = state
+            .open
+            .iter()
+            .filter(|(_, (active_at, _))| {
+                now.saturating_sub(active_at.load(Ordering::Relaxed)) >= idle_timeout
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &idle {
+            if let Some((_, stream)) = state.open.remove(id) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        state.metrics.reaped += idle.len() as u64;
+        idle.len()
+    }
+
+    pub fn metrics(&self) -> ConnectionMetrics {
+        let state = self.state.lock().unwrap();
+        ConnectionMetrics {
+            open: state.open.len(),
+            ..state.metrics
+        }
+    }
+
+    pub fn max_open(&self) -> usize {
+        self.max_open
+    }
+
+    fn close(&self, id: u64, timed_out: bool) {
+        let mut state = self.state.lock().unwrap();
+        // reaped connections are already gone
+        if state.open.remove(&id).is_some() {
+            if timed_out {
+                state.metrics.reaped += 1;
+            } else {
+                state.metrics.closed += 1;
+            }
+        }
+    }
+}
+
+/// An admitted incoming connection, which is closed when dropped.
+pub struct Connection {
+    id: u64,
+    stream: TcpStream,
+    // see `State::open`
+    active_at: Arc<AtomicU64>,
+    connections: Arc<Connections>,
+}
+
+impl Connection {
+    // records that the connection is in use, so that it is not reaped as idle
+    fn touch<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if result.is_ok() {
+            self.active_at
+                .store(self.connections.elapsed(), Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// The address the peer connects from.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Closes the connection of a peer that stayed silent for the idle timeout.
+    pub fn time_out(self) {
+        self.connections.close(self.id, true);
+    }
+}
+
+/// Whether `kind` is what reading from a socket with a timeout fails with when it runs out.
+pub fn is_timeout(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.stream.read(buf);
+        self.touch(result)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.stream.write(buf);
+        self.touch(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.close(self.id, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Connections::new(2, Duration::from_millis(50));
+        let accept = || {
+            let _client = TcpStream::connect(addr).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            connections.accept(stream).unwrap()
+        };
+        let first = accept().unwrap();
+        let second = accept().unwrap();
+        assert!(accept().is_none());
+        drop(first);
+        let third = accept().unwrap();
+        assert_eq!(
+            connections.metrics(),
+            ConnectionMetrics {
+                open: 2,
+                opened: 3,
+                closed: 1,
+                reaped: 0,
+                refused: 1,
+            }
+        );
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(connections.reap(), 2);
+        drop((second, third));
+        let metrics = connections.metrics();
+        assert_eq!((metrics.open, metrics.closed, metrics.reaped), (0, 1, 2));
+    }
+
+    #[test]
+    fn test_reap_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Connections::new(2, Duration::from_millis(100));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut active = connections
+            .accept(listener.accept().unwrap().0)
+            .unwrap()
+            .unwrap();
+        let _silent = TcpStream::connect(addr).unwrap();
+        let idle = connections
+            .accept(listener.accept().unwrap().0)
+            .unwrap()
+            .unwrap();
+
+        // open for longer than the idle timeout, but never idle for that long
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(40));
+            client.write_all(b"?").unwrap();
+            let mut byte = [0];
+            active.read_exact(&mut byte).unwrap();
+            active.write_all(b"!").unwrap();
+        }
+        assert_eq!(connections.reap(), 1);
+        assert_eq!(connections.metrics().open, 1);
+        std::thread::sleep(Duration::from_millis(110));
+        assert_eq!(connections.reap(), 1);
+        drop((active, idle));
+        assert_eq!(connections.metrics().reaped, 2);
+    }
+}
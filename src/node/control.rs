@@ -0,0 +1,341 @@
+//! The control interface, which consoles drive a running node through
+//!
+//! The node listens on a loopback address of its own, see `NodeConfig::control_addr`, for the
+//! commands of consoles. A console parses what its user enters, sends the command as JSON and
+//! prints the output the node rendered, or the error, which come back as JSON too. The console
+//! never runs in the process of the node, so that nothing it does can stall consensus.
+//!
+//! Other users of the machine reach the loopback address too, so a console first sends the
+//! secret the node wrote to its control cookie, see `ControlCookie`, as a JSON string.
+
+use super::*;
+use serde_json::Deserializer;
+use std::fs::{self, OpenOptions};
+use std::io::{stdin, stdout, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use tokio::runtime;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// What a command results in, rendered for the console with the hash or id it is about, see
+/// `CommandOutput::last`, or the error.
+type Reply = std::result::Result<(String, Option<String>), String>;
+
+/// The secret of the control interface of a running node, in a file only the owner of the data
+/// directory may read, see `NodeConfig::control_cookie`. The file is removed once dropped, unless
+/// another node took it over since.
+pub(super) struct ControlCookie {
+    path: PathBuf,
+    secret: String,
+}
+
+impl ControlCookie {
+    /// Writes a new random secret to `path`, replacing the cookie of an earlier run.
+    pub(super) fn create(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // a new file, so that the permissions of an old one do not carry over
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let secret = crate::key::to_hex(&rand::random::<[u8; 32]>());
+        let mut file = options.open(path)?;
+        file.write_all(secret.as_bytes())?;
+        file.sync_all()?;
+        Ok(ControlCookie {
+            path: path.to_owned(),
+            secret,
+        })
+    }
+
+    pub(super) fn secret(&self) -> &str {
+        &self.secret
+    }
+}
+
+impl Drop for ControlCookie {
+    fn drop(&mut self) {
+        if fs::read_to_string(&self.path).is_ok_and(|secret| secret == self.secret) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Whether `given` is `secret`, taking as long whichever byte they differ in.
+fn is_secret(given: &str, secret: &str) -> bool {
+    given.len() == secret.len()
+        && given
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Serves the consoles connecting to `listener` that know `secret`, each in a thread of its own.
+pub(super) fn handle_control_connections(
+    listener: TcpListener,
+    sender: UnboundedSender<Event>,
+    secret: String,
+) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!(target: REPL, "Console connection failed: {}", e);
+                continue;
+            }
+        };
+        let sender = sender.clone();
+        let secret = secret.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_console(stream, sender, &secret) {
+                error!(target: REPL, "{}", e);
+            }
+        });
+    }
+}
+
+/// Runs the commands of one console, one at a time, until it disconnects, once it sent `secret`.
+fn serve_console(stream: TcpStream, sender: UnboundedSender<Event>, secret: &str) -> Result<()> {
+    // only to wait for the outcomes
+    let runtime = runtime::Builder::new_current_thread()
+        .build()
+        .expect("tokio runtime can be initialized");
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let given = Deserializer::from_reader(&mut reader)
+        .into_iter::<String>()
+        .next();
+    if !matches!(given, Some(Ok(given)) if is_secret(&given, secret)) {
+        let reply: Reply = Err("Wrong control cookie".to_owned());
+        serde_json::to_writer(&mut writer, &reply)?;
+        return Err(failure::err_msg(
+            "A console without the control cookie was turned away",
+        ));
+    }
+    for command in Deserializer::from_reader(reader).into_iter::<Command>() {
+        let command =
+            command.map_err(|e| failure::err_msg(format!("Deserializing error {}", e)))?;
+        debug!(target: REPL, "command received {:?}", command);
+        let exit = matches!(command, Command::Exit);
+        if exit {
+            // answered first, as the node may be gone before it gets to
+            serde_json::to_writer(&mut writer, &Reply::Ok((String::new(), None)))?;
+            writer.flush()?;
+        }
+        let (responder, outcome) = oneshot::channel();
+        if sender.send(Event::Command(command, responder)).is_err() {
+            return Err(failure::err_msg("The event loop is gone"));
+        }
+        if exit {
+            break;
+        }
+        let reply: Reply = match runtime.block_on(outcome) {
+            Ok(Ok(output)) => Ok((output.to_string(), output.last())),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => return Err(failure::err_msg("The event loop is gone")),
+        };
+        serde_json::to_writer(&mut writer, &reply)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// A connection to the control interface of a node.
+pub struct Control {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Control {
+    /// Connects to the node taking commands on `control_addr`, authenticating with the secret
+    /// in the file `cookie`, see `NodeConfig::control_cookie`.
+    pub fn connect(control_addr: &str, cookie: &Path) -> Result<Self> {
+        let secret = fs::read_to_string(cookie).map_err(|e| {
+            failure::err_msg(format!(
+                "Cannot read the control cookie {}, is nbd running with this data directory? {}",
+                cookie.display(),
+                e
+            ))
+        })?;
+        let mut stream = TcpStream::connect(control_addr).map_err(|e| {
+            failure::err_msg(format!(
+                "Cannot reach a node at {}, is nbd running? {}",
+                control_addr, e
+            ))
+        })?;
+        serde_json::to_writer(&mut stream, secret.trim())?;
+        stream.flush()?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok(Control { stream, reader })
+    }
+
+    /// Runs the console line `input` on the node, returning what it printed.
+    pub fn run(&mut self, input: &str) -> Result<String> {
+        let command = command::parse(input).map_err(failure::err_msg)?;
+        self.send(&command)?
+            .map(|(output, _)| output)
+            .map_err(failure::err_msg)
+    }
+
+    /// Stops the node.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.send(&Command::Exit).map(|_| ())
+    }
+
+    fn send(&mut self, command: &Command) -> Result<Reply> {
+        serde_json::to_writer(&mut self.stream, command)?;
+        self.stream.flush()?;
+        match Deserializer::from_reader(&mut self.reader)
+            .into_iter::<Reply>()
+            .next()
+        {
+            Some(reply) => Ok(reply?),
+            None => Err(failure::err_msg("The node closed the console")),
+        }
+    }
+}
+
+/// Reads commands from stdin, runs them on the node at `control_addr`, authenticating with
+/// `cookie`, and prints what they result in, one at a time, until the user exits, the input ends
+/// or the node stops. The variables of the user are kept by the console, see `Session`.
+pub fn console(control_addr: &str, cookie: &Path) -> Result<()> {
+    let mut control = Control::connect(control_addr, cookie)?;
+    let mut session = command::Session::default();
+    loop {
+        let mut input = String::new();
+        // a prompt for input
+        print!("{}", "> ".color(PROMPT_COLOR).bold());
+        stdout().flush()?;
+        if stdin().read_line(&mut input)? == 0 {
+            return Ok(());
+        }
+        let input = match session.expand(&input) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}", e.color(ERR_COLOR));
+                continue;
+            }
+        };
+        let args: Vec<&str> = input.split_whitespace().collect();
+        if args.first() == Some(&command::SET) {
+            match session.set(&args[1..]) {
+                Ok(output) => print!("{}", output),
+                Err(e) => eprintln!("{}", e.color(ERR_COLOR)),
+            }
+            continue;
+        }
+        let command = match input.as_str() {
+            "" => continue,
+            command::HELP => {
+                command::list_commands();
+                continue;
+            }
+            command::EXIT => return Ok(()),
+            input => match command::parse(input)
+                .map_err(failure::err_msg)
+                .and_then(with_passphrase)
+            {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("{}", e.to_string().color(ERR_COLOR));
+                    continue;
+                }
+            },
+        };
+        let exit = matches!(command, Command::Exit);
+        match control.send(&command)? {
+            Ok((output, last)) => {
+                print!("{}", output);
+                if let Some(last) = last {
+                    session.remember(last);
+                }
+            }
+            Err(e) => eprintln!("{}", e.color(ERR_COLOR)),
+        }
+        if exit {
+            return Ok(());
+        }
+    }
+}
+
+/// Asks the user for the passphrase of the wallet `command` uses, if it does.
+fn with_passphrase(command: Command) -> Result<Command> {
+    Ok(match command {
+        Command::WalletNew(name, _) => Command::WalletNew(name, read_new_passphrase()?),
+        Command::WalletReceive(name, path, _) => {
+            let prompt = format!("Passphrase of the wallet {}: ", name);
+            Command::WalletReceive(name, path, read_passphrase(&prompt)?)
+        }
+        Command::Send(wallet, receiver, amount, fee, _) => {
+            let prompt = format!("Passphrase of the wallet {}: ", wallet);
+            Command::Send(wallet, receiver, amount, fee, read_passphrase(&prompt)?)
+        }
+        command => command,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn test_control_cookie() {
+        let dir = std::env::temp_dir().join(format!("nb-control-{}", std::process::id()));
+        let path = dir.join(crate::storage::CONTROL_COOKIE_FILE);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control_addr = listener.local_addr().unwrap().to_string();
+        let (sender, mut receiver) = unbounded_channel();
+
+        let cookie = ControlCookie::create(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), cookie.secret());
+        // a later run takes the cookie over, which the earlier one leaves in place on exit
+        let earlier = cookie;
+        let cookie = ControlCookie::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), cookie.secret());
+        drop(earlier);
+        assert_eq!(fs::read_to_string(&path).unwrap(), cookie.secret());
+
+        let secret = cookie.secret().to_owned();
+        thread::spawn(move || handle_control_connections(listener, sender, secret));
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_current_thread().build().unwrap();
+            while let Some(event) = runtime.block_on(receiver.recv()) {
+                if let Event::Command(_, responder) = event {
+                    let _ = responder.send(Ok(CommandOutput::Balance(7)));
+                }
+            }
+        });
+        let mut control = Control::connect(&control_addr, &path).unwrap();
+        assert_eq!(control.run("list_peers").unwrap(), "7\n");
+
+        let forged = dir.join("forged.cookie");
+        fs::write(&forged, "0".repeat(cookie.secret().len())).unwrap();
+        let mut control = Control::connect(&control_addr, &forged).unwrap();
+        let e = control.run("list_peers").unwrap_err();
+        assert!(e.to_string().contains("Wrong control cookie"));
+        assert!(Control::connect(&control_addr, &dir.join("missing")).is_err());
+
+        drop(cookie);
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Relaying blocks and transactions to peers
+
+use super::*;
+use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Identifies an item that can be announced and fetched under the push-pull strategy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Inventory {
+    Block(u64, String), // index, hash
+    Transaction(String),
+}
+
+/// What a node has sent to its peers since the gossip strategy was last set.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+pub struct Gossip {
+    strategy: GossipStrategy,
+    fanout: usize,
+    traffic: Traffic,
+}
+
+impl Gossip {
+    pub fn new(strategy: GossipStrategy, fanout: usize) -> Self {
+        Gossip {
+            strategy,
+            fanout,
+            traffic: Traffic::default(),
+        }
+    }
+
+    pub fn get_strategy(&self) -> GossipStrategy {
+        self.strategy
+    }
+
+    pub fn get_traffic(&self) -> Traffic {
+        self.traffic
+    }
+
+    /// Switches the strategy and resets the traffic counters, so each experiment run only
+    /// measures one strategy.
+    pub fn set_strategy(&mut self, strategy: GossipStrategy, fanout: Option<usize>) {
+        self.strategy = strategy;
+        if let Some(fanout) = fanout {
+            self.fanout = fanout;
+        }
+        self.traffic = Traffic::default();
+    }
+
+    /// Counts one message of `bytes` sent to a peer.
+    pub fn count(&mut self, bytes: usize) {
+        self.traffic.messages += 1;
+        self.traffic.bytes += bytes as u64;
+    }
+
+    /// Picks the peers a message is relayed to.
+    pub fn targets<'a>(&self, peers: &'a HashSet<PeerInfo>) -> Vec<&'a PeerInfo> {
+        match self.strategy {
+            GossipStrategy::Flood => peers.iter().collect(),
+            GossipStrategy::RandomSubset | GossipStrategy::PushPull => peers
+                .iter()
+                .choose_multiple(&mut rand::thread_rng(), self.fanout),
+        }
+    }
+
+    /// Returns what to announce instead of relaying `request` itself, if anything.
+    pub fn announcement(&self, request: &Request) -> Option<Inventory> {
+        if self.strategy != GossipStrategy::PushPull {
+            return None;
+        }
+        match request {
+            Request::NewBlock(_, block) => {
+                Some(Inventory::Block(block.get_index(), block.get_hash()))
+            }
+            Request::NewTransaction(_, transaction) => {
+                Some(Inventory::Transaction(transaction.get_id().to_owned()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> GossipStatus {
+        GossipStatus {
+            strategy: self.strategy,
+            fanout: self.fanout,
+            traffic: self.traffic,
+        }
+    }
+}
+
+/// The gossip strategy of a node and what it sent with it.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct GossipStatus {
+    pub strategy: GossipStrategy,
+    pub fanout: usize,
+    pub traffic: Traffic,
+}
+
+impl fmt::Display for GossipStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "gossip: {} (fanout {}), sent {} messages, {} bytes",
+                self.strategy, self.fanout, self.traffic.messages, self.traffic.bytes
+            )
+            .color(MSG_COLOR)
+        )
+    }
+}
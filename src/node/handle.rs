@@ -0,0 +1,145 @@
+//! Nodes running inside another program
+//!
+//! `Node::spawn` runs a node on a thread of its own and hands back a `NodeHandle`, whose methods
+//! send the node commands and await what they result in, as a console does over the control
+//! interface. Any number of nodes can run in one process, each with its own addresses and data
+//! directory.
+
+use super::*;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// A node spawned by `Node::spawn`, which keeps running until shut down.
+pub struct NodeHandle {
+    addr: String,
+    sender: UnboundedSender<Event>,
+    // with the error that stopped the node, if any
+    stopped: oneshot::Receiver<Result<()>>,
+}
+
+/// Stops a node from anywhere, e.g. another task, without its handle.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    sender: UnboundedSender<Event>,
+}
+
+impl ShutdownToken {
+    /// Tells the node to stop, unless it stopped already.
+    pub fn shutdown(&self) {
+        let (responder, _) = oneshot::channel();
+        let _ = self.sender.send(Event::Command(Command::Exit, responder));
+    }
+}
+
+impl NodeHandle {
+    pub(super) fn new(
+        addr: String,
+        sender: UnboundedSender<Event>,
+        stopped: oneshot::Receiver<Result<()>>,
+    ) -> Self {
+        NodeHandle {
+            addr,
+            sender,
+            stopped,
+        }
+    }
+
+    /// The address the node listens on for its peers.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        ShutdownToken {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Makes the node at `addr` a peer.
+    pub async fn add_peer(&self, addr: &str) -> Result<()> {
+        match self.command(Command::AddPeer(addr.to_owned())).await? {
+            CommandOutput::Unit => Ok(()),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Sends `amount` from `sender` to `recipient`, paying `fee`, returning the transaction id
+    /// and the peers it was relayed to.
+    pub async fn new_transaction(
+        &self,
+        sender: &str,
+        recipient: &str,
+        amount: i64,
+        fee: i64,
+    ) -> Result<Submission> {
+        let command = Command::NewTrans(sender.to_owned(), recipient.to_owned(), amount, fee, None);
+        match self.command(command).await? {
+            CommandOutput::Submitted(submission) => Ok(submission),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Mines a block of the pending transactions.
+    pub async fn mine(&self) -> Result<BlockSummary> {
+        match self.command(Command::Mine).await? {
+            CommandOutput::BlockSummary(summary) => Ok(summary),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Syncs with the peers, returning whether the node took over a chain of theirs.
+    pub async fn resolve(&self) -> Result<bool> {
+        match self.command(Command::Resolve).await? {
+            CommandOutput::Synced(updated) => Ok(updated),
+            CommandOutput::AlreadySyncing => Ok(false),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Returns the balance of `address` as of the last block.
+    pub async fn balance(&self, address: &str) -> Result<i64> {
+        match self
+            .command(Command::Balance(address.to_owned(), false))
+            .await?
+        {
+            CommandOutput::Balance(balance) => Ok(balance),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Returns the block `id` of the chain, failing if the chain holds no such block.
+    pub async fn block(&self, id: BlockId) -> Result<Block> {
+        match self.command(Command::GetBlock(id)).await? {
+            CommandOutput::Block(details) => Ok(details.block),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    pub async fn stats(&self) -> Result<ChainStats> {
+        match self.command(Command::ChainStats).await? {
+            CommandOutput::ChainStats(stats) => Ok(stats),
+            _ => Err(failure::err_msg("Invalid output")),
+        }
+    }
+
+    /// Stops the node and waits until it stopped, failing with what stopped it if not this.
+    ///
+    /// The node lets go of its data directory, but its listeners stay bound until the process
+    /// exits.
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown_token().shutdown();
+        self.stopped
+            .await
+            .map_err(|_| failure::err_msg("The node thread panicked"))?
+    }
+
+    async fn command(&self, command: Command) -> Result<CommandOutput> {
+        let (responder, outcome) = oneshot::channel();
+        self.sender
+            .send(Event::Command(command, responder))
+            .map_err(|_| failure::err_msg("The node stopped"))?;
+        outcome
+            .await
+            .map_err(|_| failure::err_msg("The node stopped"))?
+    }
+}
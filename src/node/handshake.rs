@@ -0,0 +1,141 @@
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// What a node tells about itself in a `Hello`. Its info is flattened, so that older versions,
+/// which send and expect a bare `PeerInfo`, leave the parameters of its chain out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Greeting {
+    #[serde(flatten)]
+    pub peer: PeerInfo,
+    /// the network magic, see `Blockchain::chain_id`, which tells chains apart before anything
+    /// else is compared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<ChainParams>,
+}
+
+impl Greeting {
+    /// Names what the chain of the greeting node differs in from ours, as told here and as told
+    /// the way the greeting node sees it, `None` if the chains are the same or it does not tell.
+    pub fn mismatch(&self, chain_id: &str, ours: &ChainParams) -> Option<(String, String)> {
+        let told =
+            |here: &str, there: &str| format!("the chain id is {} here but {} there", here, there);
+        match (&self.chain_id, &self.chain) {
+            (Some(theirs), _) if theirs != chain_id => {
+                Some((told(chain_id, theirs), told(theirs, chain_id)))
+            }
+            (_, Some(theirs)) => {
+                let mismatch = ours.mismatch(theirs)?;
+                Some((mismatch.clone(), theirs.mismatch(ours).unwrap_or(mismatch)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The parameters two nodes must share to follow the same chain. Nodes that differ in any of
+/// them reject each other's blocks, so they refuse each other as peers in the first place.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainParams {
+    /// the hash of the genesis block, which the parameters below are part of
+    pub genesis: String,
+    pub difficulty: u32,
+    pub reward_schedule: RewardSchedule,
+    pub transaction_model: TransactionModel,
+    pub consensus: ConsensusMode,
+}
+
+impl ChainParams {
+    /// The parameters of `chain`, run by `consensus`.
+    pub fn of(chain: &Blockchain, consensus: &ConsensusMode) -> Self {
+        ChainParams {
+            genesis: chain
+                .get_block_by_index(0)
+                .map(|genesis| genesis.get_hash())
+                .unwrap_or_default(),
+            difficulty: chain.initial_difficulty(),
+            reward_schedule: chain.reward_schedule(),
+            transaction_model: chain.transaction_model(),
+            consensus: consensus.clone(),
+        }
+    }
+
+    /// Names the first parameter `theirs` differs in, with both values, `None` if the chains are
+    /// the same.
+    pub fn mismatch(&self, theirs: &ChainParams) -> Option<String> {
+        let differs = |name: &str, ours: &dyn Debug, theirs: &dyn Debug| {
+            Some(format!(
+                "the {} is {:?} here but {:?} there",
+                name, ours, theirs
+            ))
+        };
+        if self.difficulty != theirs.difficulty {
+            differs("initial difficulty", &self.difficulty, &theirs.difficulty)
+        } else if self.reward_schedule != theirs.reward_schedule {
+            differs(
+                "reward schedule",
+                &self.reward_schedule,
+                &theirs.reward_schedule,
+            )
+        } else if self.transaction_model != theirs.transaction_model {
+            differs(
+                "transaction model",
+                &self.transaction_model,
+                &theirs.transaction_model,
+            )
+        } else if self.consensus != theirs.consensus {
+            differs("consensus", &self.consensus, &theirs.consensus)
+        } else if self.genesis != theirs.genesis {
+            differs("genesis block", &self.genesis, &theirs.genesis)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_params() {
+        let params = |difficulty| {
+            let chain = Blockchain::with_difficulty(difficulty);
+            ChainParams::of(&chain, &ConsensusMode::ProofOfWork)
+        };
+        assert_eq!(params(4).mismatch(&params(4)), None);
+        let mismatch = params(4).mismatch(&params(5)).unwrap();
+        assert!(mismatch.contains("initial difficulty is 4 here but 5 there"));
+        let mut authority = params(4);
+        authority.consensus = ConsensusMode::ProofOfAuthority(vec!["key".to_owned()]);
+        assert!(params(4)
+            .mismatch(&authority)
+            .unwrap()
+            .contains("consensus"));
+
+        // older versions greet with a bare `PeerInfo`, and take a greeting for one
+        let peer = PeerInfo::new("node".to_owned(), "127.0.0.1:4000".to_owned()).unwrap();
+        let greeting: Greeting =
+            serde_json::from_value(serde_json::to_value(&peer).unwrap()).unwrap();
+        assert_eq!((greeting.peer, greeting.chain), (peer.clone(), None));
+        let greeting = Greeting {
+            peer: peer.clone(),
+            chain_id: Some("testnet".to_owned()),
+            chain: Some(params(4)),
+        };
+        let taken: PeerInfo =
+            serde_json::from_value(serde_json::to_value(&greeting).unwrap()).unwrap();
+        assert_eq!(taken, peer);
+
+        // the chain id is compared first
+        assert_eq!(greeting.mismatch("testnet", &params(4)), None);
+        let (here, there) = greeting.mismatch("mainnet", &params(5)).unwrap();
+        assert!(here.contains("chain id is mainnet here but testnet there"));
+        assert!(there.contains("chain id is testnet here but mainnet there"));
+        let (here, there) = greeting.mismatch("testnet", &params(5)).unwrap();
+        assert!(here.contains("is 5 here but 4 there"));
+        assert!(there.contains("is 4 here but 5 there"));
+    }
+}
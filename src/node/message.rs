@@ -2,60 +2,125 @@ use super::*;
 use serde::{Deserialize, Serialize};
 use serde_json::Deserializer;
 use std::net::TcpListener;
-use tokio::sync::mpsc::Sender;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Request {
-    Hello(PeerInfo),
+    Hello(Greeting),
     HowAreYou(PeerInfo),
     NewTransaction(PeerInfo, Transaction),
     NewBlock(PeerInfo, Block),
     NewPeer(PeerInfo, PeerInfo),
+    Telemetry(PeerInfo, BlockSighting),
+    Announce(PeerInfo, Inventory),
+    Fetch(PeerInfo, Inventory),
+    RotateKey(PeerInfo, PeerInfo, KeyRotation), // sender, the rotated node under its new id
+    GetMempool(PeerInfo, usize),                // sender, how many transactions at most
+    GetHeaders(PeerInfo, u64, usize),           // sender, first index, how many headers at most
+    // queries from clients, which are not peers
+    GetBalance(String, Option<u64>), // address, height
+    GetStats(Option<u64>),           // window in seconds
+    GetChainStats,
+    GetBlock(BlockId),
+    GetTransaction(String), // id
+    SubmitBlock(Block),     // to extend the chain of the node with
+    ValidateBlock(Block),   // to check against the tip, without adding it
 }
 
 impl Request {
-    /// Get the `PeerInfo` of the request sender
-    pub fn get_sender_peer_info(&self) -> &PeerInfo {
-        match self {
-            Request::Hello(p)
+    /// Get the `PeerInfo` of the request sender, `None` for client queries
+    pub fn get_sender_peer_info(&self) -> Option<&PeerInfo> {
+        let peer_info = match self {
+            Request::Hello(Greeting { peer: p, .. })
             | Request::HowAreYou(p)
             | Request::NewTransaction(p, _)
             | Request::NewBlock(p, _)
-            | Request::NewPeer(p, _) => p,
-        }
+            | Request::NewPeer(p, _)
+            | Request::Telemetry(p, _)
+            | Request::Announce(p, _)
+            | Request::Fetch(p, _)
+            | Request::RotateKey(p, ..)
+            | Request::GetMempool(p, _)
+            | Request::GetHeaders(p, ..) => p,
+            Request::GetBalance(..)
+            | Request::GetStats(_)
+            | Request::GetChainStats
+            | Request::GetBlock(_)
+            | Request::GetTransaction(_)
+            | Request::SubmitBlock(_)
+            | Request::ValidateBlock(_) => return None,
+        };
+        Some(peer_info)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Response {
-    Ack(PeerInfo),                  // for Hello, NewTransaction, NewBlock
+    Ack(PeerInfo), // for Hello, NewTransaction, NewBlock, and blocks passing SubmitBlock or ValidateBlock
     MyBlocks(PeerInfo, Vec<Block>), // for HowAreYou
+    Balance(Option<i64>), // for GetBalance, `None` if the height is not reached yet
+    Stats(Throughput), // for GetStats
+    ChainStats(ChainStats), // for GetChainStats
+    Block(Option<Box<Block>>), // for GetBlock, `None` if the chain holds no such block
+    Transaction(Option<Box<TransactionInclusion>>), // for GetTransaction, `None` if unknown
+    IncompatibleChain(String), // for Hello, naming the parameter the chains differ in
+    MempoolTxs(PeerInfo, Vec<Transaction>), // for GetMempool, highest fees first
+    Headers(PeerInfo, Vec<BlockHeader>), // for GetHeaders
+    KeyNotPinned(String), // for Hello, the key pinned to the address of the sender instead
+    Error(BlockRejection), // for SubmitBlock and ValidateBlock, why the block is turned down
 }
 
-pub async fn handle_incoming_connections(
+pub fn handle_incoming_connections(
     listener: TcpListener,
-    sender: Sender<Event>,
-) -> Result<()> {
+    sender: UnboundedSender<Event>,
+    connections: Arc<Connections>,
+) {
+    // requests are read one at a time, so those of a peer reach the event loop in order
     for stream in listener.incoming() {
-        debug!("new incoming connection");
-        match stream {
-            Ok(stream) => {
-                // There should be only one request, but we have to deserialize from a stream in this way
-                let mut request = None;
-                for _request in
-                    Deserializer::from_reader(stream.try_clone()?).into_iter::<Request>()
-                {
-                    request = Some(
-                        _request
-                            .map_err(|e| failure::err_msg(format!("Deserializing error {}", e)))?,
-                    );
-                    debug!("request received {:?}", request);
-                    break;
-                }
-                sender.send(Event::Request(stream, request.unwrap())).await;
+        debug!(target: NET, "new incoming connection");
+        let connection = match stream
+            .map_err(failure::Error::from)
+            .and_then(|stream| connections.accept(stream))
+        {
+            Ok(Some(connection)) => connection,
+            Ok(None) => {
+                warn!(
+                    target: NET,
+                    "Refused a connection, {} are open already",
+                    connections.max_open()
+                );
+                continue;
+            }
+            Err(e) => {
+                error!(target: NET, "Connection failed: {}", e);
+                continue;
             }
-            Err(e) => error!("Connection failed: {}", e),
+        };
+        if let Err(e) = read_request(connection, &sender) {
+            error!(target: NET, "{}", e);
         }
     }
-    Ok(())
+}
+
+fn read_request(mut connection: Connection, sender: &UnboundedSender<Event>) -> Result<()> {
+    // There should be only one request, but we have to deserialize from a stream in this way
+    let next = Deserializer::from_reader(&mut connection)
+        .into_iter::<Request>()
+        .next();
+    let request = match next {
+        Some(Ok(request)) => request,
+        Some(Err(e)) if e.io_error_kind().is_some_and(is_timeout) => {
+            connection.time_out();
+            return Err(failure::err_msg(
+                "The peer stayed silent, closed its connection",
+            ));
+        }
+        Some(Err(e)) => return Err(failure::err_msg(format!("Deserializing error {}", e))),
+        None => return Err(failure::err_msg("No request")),
+    };
+    debug!(target: NET, "request received {:?}", request);
+    sender
+        .send(Event::Request(connection, request))
+        .map_err(|_| failure::err_msg("The event loop is gone"))
 }
@@ -1,8 +1,25 @@
-mod command;
+mod activation;
+mod block_cache;
+mod censorship;
+pub mod client;
+pub(crate) mod command;
+mod connections;
+pub mod control;
+mod gossip;
+mod handle;
+mod handshake;
 mod message;
+#[allow(clippy::module_inception)]
 mod node;
+mod orphans;
 mod peer;
+mod pins;
+mod schema;
+mod stats;
+mod telemetry;
 mod utils;
+mod verifier;
+mod wallet;
 
 // color values for pretty console output
 const PROMINENT_COLOR: &str = "cyan";
@@ -11,11 +28,30 @@ const ERR_COLOR: &str = "red";
 const PROMPT_COLOR: &str = "blue";
 
 // bring some inner components out for convenience
+use crate::config::GossipStrategy;
+use crate::logging::{CONSENSUS, MEMPOOL, NET, REPL, STORAGE};
 use crate::*;
-use command::Command;
+use block_cache::BlockCache;
+use censorship::{CensorshipReport, MempoolHistory};
+pub use command::BlockSummary;
+use command::{AssetStatus, BlockDetails, Command, CommandOutput, Responder};
+use connections::{is_timeout, Connection, Connections};
+use control::ControlCookie;
+use gossip::{Gossip, GossipStatus, Inventory, Traffic};
+pub use handle::{NodeHandle, ShutdownToken};
+use handshake::{ChainParams, Greeting};
 use message::{Request, Response};
 use node::Event;
+use orphans::OrphanPool;
 use peer::PeerInfo;
+use pins::KeyPins;
+pub use schema::wire_schema;
+pub use stats::ChainStats;
+use stats::{StallDetector, Throughput};
+use telemetry::{BlockSighting, PropagationReport, Telemetry};
 use utils::*;
+use verifier::Verifier;
+pub use wallet::{Broadcast, Submission};
+use wallet::{SentTransaction, Wallet};
 
 pub use node::Node;
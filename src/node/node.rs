@@ -1,72 +1,436 @@
 //! The blockchain node
 use super::*;
+use rand::Rng;
 use serde_json::Deserializer;
-use std::collections::HashSet;
-use std::io::{stdout, Write};
-use std::net::{TcpListener, TcpStream};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::mem;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
 use std::thread;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+use tokio::runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 
 pub enum Event {
-    Request(TcpStream, Request),
+    Request(Connection, Request),
     _Response(Response),
     Broadcast(Request),
-    Command(Command),
+    Command(Command, Responder),
+    // periodic housekeeping
+    Tick,
+    // the stored data is not valid anymore
+    Alarm(String),
+    // the chains of peers, downloaded by a sync
+    Synced(Vec<Download>),
+    // a persisted peer acked our greeting after a restart, with how it knows itself now
+    Regreeted(PeerInfo, PeerInfo),
 }
 
+/// Whether the node is downloading the chains of its peers.
+enum SyncState {
+    Idle,
+    // with whoever waits for the outcome
+    Syncing(Option<Responder>),
+}
+
+/// The chain of a peer, as a sync downloaded it.
+pub enum Download {
+    /// all of its blocks
+    Blocks(Vec<Block>),
+    /// the hashes of its blocks, which the chain or the block cache hold
+    Hashes(Vec<String>),
+}
+
+/// Hands the chains a sync downloaded back in an `Event::Synced` once dropped, so that the node
+/// stops syncing even if the download panics.
+struct SyncGuard {
+    sender: UnboundedSender<Event>,
+    chains: Vec<Download>,
+}
+
+impl SyncGuard {
+    fn new(sender: UnboundedSender<Event>) -> Self {
+        SyncGuard {
+            sender,
+            chains: Vec::new(),
+        }
+    }
+
+    fn downloaded(&mut self, chains: Vec<Download>) {
+        self.chains = chains;
+    }
+}
+
+impl Drop for SyncGuard {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Event::Synced(mem::take(&mut self.chains)));
+    }
+}
+
+/// How many blocks a sync fetches one at a time, missing them in the chain and the block cache,
+/// before it rather downloads the whole chain of the peer.
+const FETCH_LIMIT: usize = 100;
+
+/// How often `Event::Tick` fires.
+const TICK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many pending transactions a node asks a new peer for, and hands out when asked.
+const MEMPOOL_SYNC_LIMIT: usize = 1000;
+
+/// How many headers a node asks a peer for at a time, and hands out when asked.
+const HEADERS_LIMIT: usize = 2000;
+
+/// The pause between greeting two persisted peers again after a restart.
+const REGREET_PACE: Duration = Duration::from_millis(200);
+
 // TODO: add consensus protocol specification
 pub struct Node {
     basic_info: PeerInfo,
     chain: Blockchain,
     peers: HashSet<PeerInfo>,
-    broadcast_sender: Sender<Event>,
+    store: Store,
+    // locked while the node runs, none if ephemeral
+    _data_dir: Option<DataDir>,
+    // removed once the node stops
+    _cookie: ControlCookie,
+    // stops the ticks once dropped
+    _stop_ticks: std::sync::mpsc::Sender<()>,
+    broadcast_sender: UnboundedSender<Event>,
+    telemetry: Option<Telemetry>,
+    gossip: Gossip,
+    history: MempoolHistory,
+    // the transactions this node sent
+    wallet: Wallet,
+    // the keys of the wallets of the user, in the data directory
+    keystore: Keystore,
+    latency: LinkLatencies,
+    // the most the first relay of a transaction of the node is delayed by
+    diffusion: Option<Duration>,
+    stats_window: Duration,
+    stall_detector: StallDetector,
+    // where mining rewards go
+    reward_address: String,
+    min_peers_to_mine: usize,
+    skip_empty_blocks: bool,
+    connections: Arc<Connections>,
+    // of outgoing connections too
+    idle_timeout: Duration,
+    // the id of the node, and the key sealing its blocks
+    key: NodeKey,
+    consensus: ConsensusMode,
+    // the rotations of this node and its peers, by the retired key
+    rotations: HashMap<String, KeyRotation>,
+    // the keys of the peers shaken hands with, by their address
+    pins: KeyPins,
+    key_grace_period: Duration,
+    // at most one sync runs at a time
+    sync_state: SyncState,
+    // blocks waiting for their parent
+    orphans: OrphanPool,
+    // blocks downloaded by syncs, shared with their threads
+    block_cache: BlockCache,
 }
 
-const EVENT_BUFFER_SIZE: usize = 10;
-
 impl Node {
-    pub async fn handle_events(addr: String) -> Result<()> {
-        let listener = TcpListener::bind(&addr)?;
+    /// Runs a node with `config` until a command stops it.
+    pub async fn handle_events(config: NodeConfig) -> Result<()> {
+        let (sender, receiver) = unbounded_channel();
+        Node::start(config, sender)?.run(receiver).await
+    }
+
+    /// Runs a node with `config` on a thread of its own, returning once it listens.
+    pub fn spawn(config: NodeConfig) -> Result<NodeHandle> {
+        let (sender, receiver) = unbounded_channel();
+        let (started, start) = std::sync::mpsc::channel();
+        let (stopped, stop) = oneshot::channel();
+        let addr = config.addr.clone();
+        let events = sender.clone();
+        thread::spawn(move || {
+            let node = match Node::start(config, events) {
+                Ok(node) => {
+                    let _ = started.send(Ok(()));
+                    node
+                }
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                    return;
+                }
+            };
+            // only to wait for events
+            let runtime = runtime::Builder::new_current_thread()
+                .build()
+                .expect("tokio runtime can be initialized");
+            let _ = stopped.send(runtime.block_on(node.run(receiver)));
+        });
+        start
+            .recv()
+            .map_err(|_| failure::err_msg("The node failed to start"))??;
+        Ok(NodeHandle::new(addr, sender, stop))
+    }
+
+    /// Sets up the node: binds its listeners, opens its chain and spawns the threads sending
+    /// it events through `sender`.
+    fn start(config: NodeConfig, sender: UnboundedSender<Event>) -> Result<Self> {
+        config.check()?;
+        let (listener, control_listener) = activation::listeners(&config)?;
+        let genesis = config.genesis();
+        let (data_dir, store) = if config.ephemeral {
+            warn!(
+                target: STORAGE,
+                "Ephemeral node: the chain, the peers and the node key are kept in memory only, and lost on exit"
+            );
+            (None, Store::memory())
+        } else {
+            let data_dir = DataDir::open(&config.data_dir, config.db)?;
+            let store = data_dir.store(&genesis)?;
+            info!(
+                target: STORAGE,
+                "Data directory: {} ({})",
+                data_dir.chain_dir(&genesis).display(),
+                config.db
+            );
+            (Some(data_dir), store)
+        };
+
+        let cookie = ControlCookie::create(&config.control_cookie())?;
+        let secret = cookie.secret().to_owned();
 
-        let (sender, mut receiver) = channel(EVENT_BUFFER_SIZE);
         let sender1 = sender.clone();
         let sender2 = sender.clone();
-        thread::spawn(move || message::handle_incoming_connections(listener, sender1));
-        thread::spawn(move || command::handle_input_commands(sender2));
+        let connections = Connections::new(config.max_connections, config.idle_timeout);
+        let connections1 = connections.clone();
+        thread::spawn(move || {
+            message::handle_incoming_connections(listener, sender1, connections1)
+        });
+        thread::spawn(move || {
+            control::handle_control_connections(control_listener, sender2, secret)
+        });
+        let sender3 = sender.clone();
+        // the node hangs up `stop_ticks` when it stops
+        let (stop_ticks, ticks_stopped) = std::sync::mpsc::channel::<()>();
+        thread::spawn(move || {
+            while sender3.send(Event::Tick).is_ok() {
+                if ticks_stopped.recv_timeout(TICK_INTERVAL)
+                    != Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+                {
+                    break;
+                }
+            }
+        });
 
-        let mut node = Node {
-            basic_info: PeerInfo::new(addr)?,
-            chain: Blockchain::new(),
-            peers: HashSet::new(),
+        let key = store.node_key()?;
+        info!(target: NET, "Node key: {}", key.public_key());
+        let basic_info = PeerInfo::new(key.public_key(), config.addr.clone())?;
+        let telemetry = match config.telemetry_collector.clone() {
+            Some(collector) => {
+                let telemetry = Telemetry::new(parse_addr(collector)?, &basic_info);
+                if telemetry.is_collector() {
+                    info!(target: NET, "Collecting block propagation telemetry");
+                } else {
+                    info!(
+                        target: NET,
+                        "Reporting block propagation telemetry to {}",
+                        telemetry.get_collector()
+                    );
+                }
+                Some(telemetry)
+            }
+            None => None,
+        };
+        if let Some(hash) = &config.assume_valid {
+            info!(target: CONSENSUS, "Assuming block {} and its ancestors valid", hash);
+        }
+        if let Some(spec) = &config.genesis_spec {
+            info!(target: CONSENSUS, "Genesis spec: {:?}", spec);
+        }
+        if !config.checkpoints.is_empty() {
+            info!(target: CONSENSUS, "Checkpoints: {:?}", config.checkpoints);
+        }
+        if !config.signals.is_empty() {
+            info!(target: CONSENSUS, "Signalling readiness for bits {:?}", config.signals);
+        }
+        let chain = config.open_chain(store.clone(), Some(key.clone()))?;
+        if !chain.can_seal() {
+            info!(
+                target: CONSENSUS,
+                "The consensus engine does not let this node produce the next block"
+            );
+        }
+        if let Some(pace) = config.verify_pace {
+            info!(target: STORAGE, "Re-verifying the stored chain in the background");
+            Verifier::new(store.clone(), pace).spawn(sender.clone());
+        }
+        // stake is only counted for public keys, which can sign the blocks they are drawn for
+        let reward_address = config
+            .reward_address
+            .unwrap_or_else(|| chain.address_of(&key.public_key()));
+        if !chain.is_address(&reward_address) {
+            return Err(failure::err_msg(format!(
+                "The reward address {} is not an address of the chain, see its address prefix",
+                reward_address
+            )));
+        }
+        let rotations: HashMap<String, KeyRotation> = store
+            .load_key_rotations()?
+            .into_iter()
+            .map(|rotation| (rotation.old.clone(), rotation))
+            .collect();
+        let peers = store
+            .load_peers::<PeerInfo>()?
+            .into_iter()
+            .filter(|peer| !rotations.contains_key(peer.get_id()))
+            .collect();
+        let pins = KeyPins::new(store.load_pinned_keys()?);
+        let keystore = match &data_dir {
+            Some(data_dir) => Keystore::open(&data_dir.wallets_dir()),
+            None => Keystore::memory(),
+        };
+        let node = Node {
+            basic_info,
+            chain,
+            peers,
+            store,
+            _data_dir: data_dir,
+            _cookie: cookie,
+            _stop_ticks: stop_ticks,
             broadcast_sender: sender,
+            telemetry,
+            gossip: Gossip::new(config.gossip, config.fanout),
+            history: MempoolHistory::default(),
+            wallet: Wallet::default(),
+            keystore,
+            latency: config.latency,
+            diffusion: config.diffusion,
+            stats_window: config.stats_window,
+            stall_detector: StallDetector::new(config.stall_warning),
+            reward_address,
+            min_peers_to_mine: config.min_peers_to_mine,
+            skip_empty_blocks: config.skip_empty_blocks,
+            connections,
+            idle_timeout: config.idle_timeout,
+            key,
+            consensus: config.consensus,
+            rotations,
+            pins,
+            key_grace_period: config.key_grace_period,
+            sync_state: SyncState::Idle,
+            orphans: OrphanPool::default(),
+            block_cache: BlockCache::default(),
         };
+        info!(
+            target: STORAGE,
+            "Loaded {} blocks and {} peers",
+            node.chain.len(),
+            node.peers.len()
+        );
+        node.regreet_peers();
+        Ok(node)
+    }
 
-        loop {
-            let event = receiver.recv().await.unwrap();
-            // TODO: result not used
-            let _result = match event {
+    /// Handles the events of `receiver` until a command stops the node.
+    async fn run(mut self, mut receiver: UnboundedReceiver<Event>) -> Result<()> {
+        let node = &mut self;
+        while let Some(event) = receiver.recv().await {
+            let result = match event {
                 Event::Request(stream, request) => node.serve_request(stream, request),
                 Event::_Response(_response) => unimplemented!(),
-                Event::Broadcast(request) => node.broadcast_request(&request),
-                Event::Command(command) => node.serve_command(command),
+                Event::Broadcast(request) => node.broadcast_request(request),
+                Event::Command(Command::Exit, _) => break,
+                // answered once the sync is done
+                Event::Command(Command::Resolve, responder) => {
+                    node.sync(Some(responder));
+                    Ok(())
+                }
+                Event::Command(command, responder) => {
+                    let _ = responder.send(node.serve_command(command));
+                    Ok(())
+                }
+                Event::Tick => {
+                    node.stall_detector
+                        .check(&node.chain, crate::blockchain::get_time());
+                    node.repair_corrupt_blocks();
+                    node.history.prune(&node.chain);
+                    let reaped = node.connections.reap();
+                    if reaped > 0 {
+                        info!(target: NET, "Reaped {} idle connections", reaped);
+                    }
+                    Ok(())
+                }
+                Event::Alarm(alarm) => Err(failure::err_msg(alarm)),
+                Event::Synced(chains) => {
+                    node.finish_sync(chains);
+                    Ok(())
+                }
+                Event::Regreeted(known, acked) => {
+                    node.finish_regreet(known, acked);
+                    Ok(())
+                }
             };
+            if let Err(e) = result {
+                error!(target: NET, "{}", e);
+            }
+            node.notify_wallet();
         }
+        node.store.flush()
     }
 
-    fn serve_request(&mut self, mut stream: TcpStream, request: Request) -> Result<()> {
-        let peer_info = request.get_sender_peer_info();
-        if self.add_peer(peer_info) {
-            info!("Add one new peer: {:?}", peer_info);
+    fn serve_request(&mut self, mut stream: Connection, request: Request) -> Result<()> {
+        if let Request::Hello(greeting) = &request {
+            let ours = self.chain_params();
+            if let Some((mismatch, told)) = greeting.mismatch(&self.chain.chain_id(), &ours) {
+                warn!(
+                    target: NET,
+                    "Refusing the peer {} at {}, it runs another chain: {}",
+                    greeting.peer.get_id(),
+                    greeting.peer.get_address(),
+                    mismatch
+                );
+                // told the way the peer sees it
+                let response = Response::IncompatibleChain(told);
+                serde_json::to_writer(&mut stream, &response)?;
+                stream.flush()?;
+                return Ok(());
+            }
+            if !self.pin_key(&greeting.peer) {
+                let pinned = self.pins.conflict(&greeting.peer).unwrap_or_default();
+                let response = Response::KeyNotPinned(pinned.to_owned());
+                serde_json::to_writer(&mut stream, &response)?;
+                stream.flush()?;
+                return Ok(());
+            }
+        }
+        if let Some(peer_info) = request.get_sender_peer_info() {
+            // a peer that rotated its key is known by its new id from then on
+            let peer_info = match self.rotations.get(peer_info.get_id()) {
+                Some(rotation) if self.is_retired(rotation) => {
+                    debug!(
+                        target: NET,
+                        "Dropping a request from the retired id {}",
+                        peer_info.get_id()
+                    );
+                    return Ok(());
+                }
+                Some(rotation) => peer_info.with_id(&rotation.new),
+                None => peer_info.clone(),
+            };
+            if self.add_peer(&peer_info) {
+                info!(target: NET, "Add one new peer: {:?}", peer_info);
+            }
         }
         let my_info = self.get_basic_info();
         let mut response = None;
         match request {
-            Request::Hello(peer_info) => {
-                info!("Get Hello from {:?}, simply ack it", peer_info);
+            Request::Hello(Greeting {
+                peer: peer_info, ..
+            }) => {
+                info!(target: NET, "Get Hello from {:?}, simply ack it", peer_info);
                 response = Some(Response::Ack(my_info));
             }
             Request::HowAreYou(peer_info) => {
                 info!(
+                    target: NET,
                     "Get HowAreYou from {:?}, will respond with all my blocks",
                     peer_info
                 );
@@ -74,6 +438,7 @@ impl Node {
             }
             Request::NewTransaction(peer_info, transaction) => {
                 info!(
+                    target: NET,
                     "Get NewTransaction from {:?}, add the transaction and ack it",
                     peer_info
                 );
@@ -81,54 +446,338 @@ impl Node {
             }
             Request::NewBlock(peer_info, new_block) => {
                 info!(
+                    target: NET,
                     "Get NewBlock from {:?}, validate it and possibly add it to our chain",
                     peer_info
                 );
-                self.handle_incoming_block(new_block);
+                self.handle_incoming_block(&peer_info, new_block);
             }
             Request::NewPeer(peer_info, new_peer) => {
                 info!(
+                    target: NET,
                     "Get NewPeer from {:?}, new peer is {:?}",
                     peer_info, new_peer
                 );
                 self.handle_incoming_peer(new_peer);
             }
+            Request::Announce(peer_info, inventory) => {
+                debug!(target: NET, "Get Announce from {:?}: {:?}", peer_info, inventory);
+                if is_connected_from(&stream, &peer_info) {
+                    self.handle_announcement(&peer_info, inventory);
+                }
+            }
+            Request::Fetch(peer_info, inventory) => {
+                debug!(target: NET, "Get Fetch from {:?}: {:?}", peer_info, inventory);
+                if is_connected_from(&stream, &peer_info) {
+                    self.handle_fetch(&peer_info, inventory);
+                }
+            }
+            Request::RotateKey(peer_info, rotated, rotation) => {
+                debug!(target: NET, "Get RotateKey from {:?}: {:?}", peer_info, rotation);
+                self.handle_key_rotation(rotated, rotation);
+            }
+            Request::GetMempool(peer_info, limit) => {
+                info!(
+                    target: NET,
+                    "Get GetMempool from {:?}, will respond with up to {} pending transactions",
+                    peer_info, limit
+                );
+                let transactions = self
+                    .chain
+                    .get_prioritized_transactions(limit.min(MEMPOOL_SYNC_LIMIT));
+                response = Some(Response::MempoolTxs(my_info, transactions));
+            }
+            Request::GetHeaders(peer_info, start, limit) => {
+                debug!(
+                    target: NET,
+                    "Get GetHeaders from {:?}, will respond with up to {} headers from block {} on",
+                    peer_info, limit, start
+                );
+                let headers = self.chain.get_headers(start, limit.min(HEADERS_LIMIT));
+                response = Some(Response::Headers(my_info, headers));
+            }
+            Request::GetBalance(address, height) => {
+                debug!(target: NET, "Get GetBalance of {} at {:?}", address, height);
+                response = Some(Response::Balance(self.get_balance_at(&address, height)));
+            }
+            Request::GetBlock(id) => {
+                debug!(target: NET, "Get GetBlock {}", id);
+                let block = self
+                    .chain
+                    .get_block(&id)
+                    .map(|block| Box::new(block.into_owned()));
+                response = Some(Response::Block(block));
+            }
+            Request::GetTransaction(id) => {
+                debug!(target: NET, "Get GetTransaction {}", id);
+                response = Some(Response::Transaction(
+                    self.find_transaction(&id).map(Box::new),
+                ));
+            }
+            Request::SubmitBlock(block) => {
+                debug!(target: NET, "Get SubmitBlock {}", block.get_hash());
+                response = Some(match self.submit_block(&block) {
+                    Ok(()) => Response::Ack(my_info),
+                    Err(rejection) => Response::Error(rejection),
+                });
+            }
+            Request::ValidateBlock(block) => {
+                debug!(target: NET, "Get ValidateBlock {}", block.get_hash());
+                response = Some(match self.chain.validate_block(&block) {
+                    Ok(()) => Response::Ack(my_info),
+                    Err(rejection) => Response::Error(rejection),
+                });
+            }
+            Request::GetStats(window) => {
+                debug!(target: NET, "Get GetStats over {:?}s", window);
+                response = Some(Response::Stats(self.get_throughput(window)));
+            }
+            Request::GetChainStats => {
+                debug!(target: NET, "Get GetChainStats");
+                response = Some(Response::ChainStats(self.stats()));
+            }
+            Request::Telemetry(peer_info, sighting) => {
+                debug!(target: NET, "Get Telemetry from {:?}: {:?}", peer_info, sighting);
+                if let Some(telemetry) = &mut self.telemetry {
+                    if !telemetry.record(&peer_info, sighting) {
+                        debug!(target: NET, "Dropped the telemetry of {:?}", peer_info);
+                    }
+                }
+            }
         };
         if let Some(response) = response {
             serde_json::to_writer(&mut stream, &response)?;
             stream.flush()?;
-            debug!("response sent {:?}", response);
+            debug!(target: NET, "response sent {:?}", response);
         };
         Ok(())
     }
 
-    fn serve_command(&mut self, command: Command) -> Result<()> {
-        match command {
-            Command::NewTrans(sender, receiver, amount) => {
-                self.create_and_add_new_transaction(&sender, &receiver, amount);
+    fn serve_command(&mut self, command: Command) -> Result<CommandOutput> {
+        let output = match command {
+            Command::NewTrans(sender, receiver, amount, fee, expiry) => CommandOutput::Submitted(
+                self.create_and_add_new_transaction(&sender, &receiver, amount, fee, expiry)?,
+            ),
+            Command::Replace(id, fee) => {
+                CommandOutput::Submitted(self.replace_transaction(&id, fee)?)
+            }
+            Command::Issue(issuer, receiver, asset, amount, max_supply, fee) => {
+                let issue = Transaction::issue(&issuer, &receiver, &asset, amount, max_supply);
+                CommandOutput::Submitted(self.add_asset_transaction(issue.with_fee(fee))?)
+            }
+            Command::SendAsset(sender, receiver, asset, amount, fee) => {
+                let transfer = Transaction::new(&sender, &receiver, amount).with_asset(&asset);
+                CommandOutput::Submitted(self.add_asset_transaction(transfer.with_fee(fee))?)
+            }
+            Command::Asset(id, address) => CommandOutput::Asset(AssetStatus {
+                asset: self.chain.get_asset(&id),
+                holding: address.map(|address| {
+                    let held = self.chain.get_asset_balance(&id, &address);
+                    (address, held)
+                }),
+                id,
+            }),
+            Command::Display => CommandOutput::Blocks(self.chain.view()),
+            Command::GetBlock(id) => {
+                let id = match id {
+                    BlockId::Hash(prefix) => {
+                        let missing = format!("the chain holds no block {}", prefix);
+                        let resolution = self.chain.resolve_block(&prefix);
+                        BlockId::Hash(resolved(resolution, &prefix, missing)?)
+                    }
+                    index => index,
+                };
+                match self.chain.get_block(&id) {
+                    Some(block) => CommandOutput::Block(Box::new(BlockDetails {
+                        accounting: self.chain.get_block_accounting(block.get_index()),
+                        block: block.into_owned(),
+                    })),
+                    None => {
+                        return Err(failure::err_msg(match id {
+                            BlockId::Index(_) => {
+                                format!("the chain has only {} blocks", self.chain.len())
+                            }
+                            BlockId::Hash(hash) => format!("the chain holds no block {}", hash),
+                        }))
+                    }
+                }
             }
-            Command::Display => self.display(),
             Command::AddPeer(peer) => {
                 // BLOCKING
-                if !self.greet_and_add_peer(&peer) {
-                    eprintln!("{}", "fail to add peer".color(ERR_COLOR));
-                }
+                self.greet_and_add_peer(&peer)?;
+                CommandOutput::Unit
             }
-            Command::DisplayPeers => self.display_peers(),
-            Command::Resolve => {
+            Command::Repin(peer) => {
                 // BLOCKING
-                if self.resolve_conflicts() {
-                    println!("node updated");
-                } else {
-                    println!("node stays unchanged")
+                self.repin(&peer)?;
+                CommandOutput::Unit
+            }
+            Command::DisplayPeers => CommandOutput::PeerList(self.get_peers()),
+            Command::Mine => CommandOutput::BlockSummary(self.mine()?),
+            Command::Gossip(None) => CommandOutput::Gossip(self.gossip.status()),
+            Command::Gossip(Some((strategy, fanout))) => {
+                self.gossip.set_strategy(strategy, fanout);
+                CommandOutput::Gossip(self.gossip.status())
+            }
+            Command::Telemetry => match &self.telemetry {
+                Some(telemetry) if telemetry.is_collector() => {
+                    CommandOutput::Propagation(telemetry.report())
+                }
+                _ => return Err(failure::err_msg("this node is not a telemetry collector")),
+            },
+            Command::Censorship(min_exclusions) => {
+                CommandOutput::Censorship(self.history.report(&self.chain, min_exclusions))
+            }
+            Command::GetBalance(address, height) => match self.get_balance_at(&address, height) {
+                Some(balance) => CommandOutput::Balance(balance),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "the chain has only {} blocks",
+                        self.chain.len()
+                    )))
+                }
+            },
+            Command::Balance(address, include_pending) => {
+                CommandOutput::Balance(self.get_balance(&address, include_pending))
+            }
+            Command::Stats(window) => CommandOutput::Stats(self.get_throughput(window)),
+            Command::ChainStats => CommandOutput::ChainStats(self.stats()),
+            Command::Signals => {
+                CommandOutput::Signals(self.chain.signalling(), self.chain.signals())
+            }
+            Command::Connections => CommandOutput::Connections(self.connections.metrics()),
+            Command::AuditLog => CommandOutput::AuditLog(self.store.load_audit_log()?),
+            Command::FindTransaction(prefix) => {
+                let missing = format!("no transaction {} is pending or on the chain", prefix);
+                let resolution = self.chain.resolve_transaction(&prefix);
+                let id = resolved(resolution, &prefix, missing.clone())?;
+                match self.find_transaction(&id) {
+                    Some(found) => CommandOutput::Transaction(Box::new(found)),
+                    None => return Err(failure::err_msg(missing)),
+                }
+            }
+            Command::History(address) => CommandOutput::History(self.get_history(&address)),
+            Command::Wallet => CommandOutput::Wallet(self.wallet.history(&self.chain)),
+            Command::WalletNew(name, passphrase) => {
+                let key = self.keystore.create(&name, &passphrase)?;
+                let address = self.chain.address_of(&key.public_key());
+                info!(target: MEMPOOL, "Created the wallet {}: {}", name, address);
+                CommandOutput::Address(address)
+            }
+            Command::WalletList => CommandOutput::Wallets(self.addresses_of(self.keystore.list()?)),
+            Command::WalletAddress(name) => {
+                CommandOutput::Addresses(self.addresses_of(self.keystore.addresses(&name)?))
+            }
+            Command::WalletReceive(name, path, passphrase) => {
+                let key = self.keystore.receive(&name, &passphrase, path.as_deref())?;
+                let address = self.chain.address_of(&key);
+                info!(target: MEMPOOL, "The wallet {} receives on {}", name, address);
+                CommandOutput::Address(address)
+            }
+            Command::Send(wallet, receiver, amount, fee, passphrase) => {
+                // from the first address of the wallet affording it, else its own to tell why not
+                let keys = self.keystore.keys(&wallet, &passphrase)?;
+                let key = keys
+                    .iter()
+                    .find(|key| {
+                        self.chain
+                            .new_transaction(
+                                &self.chain.address_of(&key.public_key()),
+                                &receiver,
+                                amount,
+                                fee,
+                            )
+                            .is_some_and(|transaction| self.chain.is_affordable(&transaction))
+                    })
+                    .unwrap_or(&keys[0]);
+                CommandOutput::Submitted(
+                    self.create_and_add_signed_transaction(key, &receiver, amount, fee)?,
+                )
+            }
+            Command::TxStatus(id) => match self.wallet.get(&id, &self.chain) {
+                Some(sent) => CommandOutput::TxStatus(Box::new(sent)),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "this node did not send transaction {}, find_tx looks up any",
+                        id
+                    )))
                 }
+            },
+            Command::Notarize => CommandOutput::Attestation(Attestation::new(
+                &self.key,
+                &self.chain,
+                crate::blockchain::get_time(),
+            )),
+            Command::PreviewBlock => CommandOutput::Block(Box::new(self.build_candidate_block())),
+            Command::LogStatus => CommandOutput::LogLevels(crate::logging::levels()),
+            Command::VerifyAttestation(attestation) => {
+                attestation.verify(&self.chain)?;
+                CommandOutput::Attested(attestation)
             }
-            Command::Mine => {
-                self.mine();
-                debug!("{}", "Mined!!!".color(MSG_COLOR))
+            Command::RotateKey => {
+                self.rotate_key()?;
+                CommandOutput::Unit
+            }
+            Command::SetRewardAddress(address) => {
+                if !self.chain.is_address(&address) {
+                    return Err(failure::err_msg(format!(
+                        "{} is not an address of the chain, see its address prefix",
+                        address
+                    )));
+                }
+                info!(target: REPL, "Mining rewards go to {} from now on", address);
+                self.reward_address = address;
+                CommandOutput::Unit
             }
+            // handled by the event loop
+            Command::Resolve | Command::Exit => CommandOutput::Unit,
+        };
+        Ok(output)
+    }
+
+    /// Measures the throughput over the last `window` seconds, or the configured window.
+    pub fn get_throughput(&self, window: Option<u64>) -> Throughput {
+        let window = window.map_or(self.stats_window, Duration::from_secs);
+        Throughput::measure(&self.chain, window, crate::blockchain::get_time())
+    }
+
+    /// The addresses on the chain of the public keys labelled by `keys`, e.g. of wallets.
+    fn addresses_of(&self, keys: Vec<(String, String)>) -> Vec<(String, String)> {
+        keys.into_iter()
+            .map(|(label, key)| (label, self.chain.address_of(&key)))
+            .collect()
+    }
+
+    /// Takes the stats of the chain and the node.
+    pub fn stats(&self) -> ChainStats {
+        ChainStats::measure(&self.chain, self.peers.len())
+    }
+
+    /// Returns the balance of `address` as of the last block, or once the pending transactions
+    /// are confirmed too if `include_pending`.
+    pub fn get_balance(&self, address: &str, include_pending: bool) -> i64 {
+        if include_pending {
+            self.chain.get_pending_balance(address)
+        } else {
+            self.chain.get_balance(address)
         }
-        Ok(())
+    }
+
+    /// Returns the transaction with `id`, pending or on the chain, with how far it made it.
+    pub fn find_transaction(&self, id: &str) -> Option<TransactionInclusion> {
+        self.chain.find_transaction(id)
+    }
+
+    /// Returns the transactions touching `address`, confirmed and pending, see
+    /// `Blockchain::get_history`.
+    pub fn get_history(&self, address: &str) -> Vec<TransactionInclusion> {
+        self.chain.get_history(address)
+    }
+
+    /// Returns the balance of `address` as of the block at `height`, or the last block.
+    pub fn get_balance_at(&self, address: &str, height: Option<u64>) -> Option<i64> {
+        let height = height.unwrap_or(self.chain.len() as u64 - 1);
+        self.chain.get_balance_at(address, height)
     }
 
     pub fn get_basic_info(&self) -> PeerInfo {
@@ -140,269 +789,1402 @@ impl Node {
         self.chain.get_blocks()
     }
 
-    /// Displays the full blockchain
-    pub fn display(&self) {
-        self.chain.display();
-        println!();
-    }
-
-    /// Displays the peers
-    pub fn display_peers(&self) {
-        serde_json::to_writer_pretty(stdout(), &self.peers).expect("fail to display peers");
-        println!();
+    /// Returns the peers the node knows
+    pub fn get_peers(&self) -> Vec<PeerInfo> {
+        self.peers.iter().cloned().collect()
     }
 
     /// Mines a new block
-    pub fn mine(&mut self) {
-        let last_block = self.chain.last_block();
-        let proof = self.chain.run_pow();
-        let last_hash = last_block.get_hash();
-        // receive a reward for finding the proof.
-        // The sender is "0" to signify that this node has mined a new coin.
-        let bonus_trans = Transaction::new("0", self.basic_info.get_id(), 1);
-        self.chain.add_new_transaction(&bonus_trans);
-
-        let block = self.chain.create_new_block(proof, last_hash);
+    pub fn mine(&mut self) -> Result<BlockSummary> {
+        if !self.chain.can_seal() {
+            return Err(failure::err_msg(
+                "The consensus engine does not let this node produce the next block",
+            ));
+        }
+        let own_address = self.chain.address_of(&self.key.public_key());
+        if self.chain.requires_miner_signatures() && self.reward_address != own_address {
+            return Err(failure::err_msg(format!(
+                "The blocks of the chain are signed by their miner, whose address their rewards \
+                 must pay: set the reward address to {} to mine",
+                own_address
+            )));
+        }
+        if self.peers.len() < self.min_peers_to_mine {
+            return Err(failure::err_msg(format!(
+                "Mining is deferred until the node has {} peers, it has {}: a block mined alone \
+                 forks off the chain of the network once the node connects. Add a peer, or run \
+                 the node with --min-peers-to-mine 0 to mine solo",
+                self.min_peers_to_mine,
+                self.peers.len()
+            )));
+        }
+        // the block is made now, so that the fees claimed are those of the transactions that
+        // have not expired by then
+        let now = crate::blockchain::get_time();
+        self.chain.drop_expired(now);
+        if self.skip_empty_blocks && self.chain.pending_count() == 0 {
+            return Err(failure::err_msg(
+                "No transaction is pending, and the node skips empty blocks: send one first, or \
+                 run the node without --skip-empty-blocks",
+            ));
+        }
+        if let Some(bonus_trans) = self.mining_reward(now) {
+            if let Err(rejection) = self.chain.add_new_transaction(&bonus_trans) {
+                warn!(
+                    target: MEMPOOL,
+                    "The reward for the new block is rejected: {}",
+                    rejection
+                );
+            }
+        }
+
+        // the proof commits to the reward, so it is found after adding it
+        let block = self.chain.mine_new_block_at(now).clone();
         info!(
+            target: CONSENSUS,
             "A new block {} is forged, will broadcast it to all peers",
             block.get_index()
         );
+        self.see_block(&block);
         // broadcast the newly mined block
         self.async_broadcast_latest_block();
+        Ok(BlockSummary::of(&block))
     }
 
-    /// Adds a new transaction
-    pub fn create_and_add_new_transaction(&mut self, sender: &str, receiver: &str, amount: i64) {
-        let transaction = Transaction::new(sender, receiver, amount);
-        if !self.chain.add_new_transaction(&transaction) {
-            info!("Transaction already exists");
-            return;
+    /// The reward for finding the proof of a block made at `now`, along with the fees of the
+    /// transactions mined, `None` once the schedule has run out of coins and there are no fees.
+    /// The sender is "0" to signify that this node has mined a new coin.
+    fn mining_reward(&self, now: u128) -> Option<Transaction> {
+        let reward = self.chain.claimable_reward(now);
+        if reward <= 0 {
+            return None;
+        }
+        self.chain
+            .new_transaction(REWARD_SENDER, &self.reward_address, reward, 0)
+    }
+
+    /// The block `mine` would seal right now, with the reward, fees and pending transactions it
+    /// would claim and take, in order, and its difficulty. Nothing changes, and no work is done.
+    pub fn build_candidate_block(&self) -> BlockDetails {
+        let now = crate::blockchain::get_time();
+        let block = self.chain.candidate_block(self.mining_reward(now), now);
+        BlockDetails {
+            accounting: Some(self.chain.candidate_accounting(&block)),
+            block,
+        }
+    }
+
+    /// Adds a new transaction paying `fee` to its miner and expiring after `expiry`, if given,
+    /// returning its id
+    pub fn create_and_add_new_transaction(
+        &mut self,
+        sender: &str,
+        receiver: &str,
+        amount: i64,
+        fee: i64,
+        expiry: Option<Expiry>,
+    ) -> Result<Submission> {
+        self.add_transaction_from(sender, None, receiver, amount, fee, expiry)
+    }
+
+    /// Adds a new transaction from the address of `key`, signed with it, e.g. the key of a
+    /// wallet
+    pub fn create_and_add_signed_transaction(
+        &mut self,
+        key: &NodeKey,
+        receiver: &str,
+        amount: i64,
+        fee: i64,
+    ) -> Result<Submission> {
+        let sender = self.chain.address_of(&key.public_key());
+        self.add_transaction_from(&sender, Some(key), receiver, amount, fee, None)
+    }
+
+    // signs with `key` if given, else as `sign_own` does
+    fn add_transaction_from(
+        &mut self,
+        sender: &str,
+        key: Option<&NodeKey>,
+        receiver: &str,
+        amount: i64,
+        fee: i64,
+        expiry: Option<Expiry>,
+    ) -> Result<Submission> {
+        if sender == REWARD_SENDER {
+            return Err(failure::err_msg("Only mining mints coins"));
+        }
+        let transaction = self
+            .chain
+            .new_transaction(sender, receiver, amount, fee)
+            .map(|transaction| match expiry {
+                Some(expiry) => transaction.with_expiry(expiry),
+                None => transaction,
+            })
+            .map(|transaction| match key {
+                Some(key) => transaction.sign(key),
+                None => self.sign_own(transaction),
+            });
+        let transaction = match transaction {
+            Some(transaction) if self.chain.is_affordable(&transaction) => transaction,
+            _ => {
+                let maturity = self.chain.reward_schedule().coinbase_maturity;
+                let immature = if maturity > 0 {
+                    format!(
+                        ", though rewards are only spendable {} blocks after their own",
+                        maturity
+                    )
+                } else {
+                    String::new()
+                };
+                return Err(failure::err_msg(format!(
+                    "{} cannot afford to send {} for a fee of {}, it holds {}{}",
+                    sender,
+                    amount,
+                    fee,
+                    self.chain.get_balance(sender),
+                    immature
+                )));
+            }
+        };
+        if transaction.is_expired_at(self.chain.len() as u64, crate::blockchain::get_time()) {
+            return Err(failure::err_msg(
+                "Transaction expires before the next block",
+            ));
         }
+        self.chain.add_new_transaction(&transaction)?;
+        self.history.record(&transaction, &self.chain);
+        self.wallet.record(&transaction);
         info!(
-            "A new transaction is added: {} -> {}, amount: {}",
-            sender, receiver, amount
+            target: MEMPOOL,
+            "A new transaction is added: {} -> {}, amount: {}, fee: {}",
+            sender, receiver, amount, fee
         );
-        self.async_broadcast_transaction(transaction);
+        Ok(self.submit(transaction))
+    }
+
+    /// Adds `transaction` moving, or issuing, tokens of its asset into the local blockchain and
+    /// relays it, see `Transaction::issue`
+    pub fn add_asset_transaction(&mut self, transaction: Transaction) -> Result<Submission> {
+        if self.chain.transaction_model() != TransactionModel::Account {
+            return Err(failure::err_msg("Assets only move on account chains"));
+        }
+        let transaction = self.sign_own(transaction);
+        self.chain.add_new_transaction(&transaction)?;
+        self.history.record(&transaction, &self.chain);
+        self.wallet.record(&transaction);
+        info!(
+            target: MEMPOOL,
+            "A new transaction is added: {} -> {}, amount: {}, fee: {}",
+            transaction.get_sender(),
+            transaction.get_recipient(),
+            transaction.amount_label(),
+            transaction.get_fee()
+        );
+        Ok(self.submit(transaction))
+    }
+
+    /// Replaces the pending transaction `id` with the same one paying a higher `fee`, before it is
+    /// mined, returning the id of the replacement
+    pub fn replace_transaction(&mut self, id: &str, fee: i64) -> Result<Submission> {
+        let replacement = match self.chain.new_replacement(id, fee) {
+            Some(replacement) => self.sign_own(replacement),
+            None => {
+                return Err(failure::err_msg(format!(
+                    "No transaction {} is pending, or its sender cannot afford a fee of {}",
+                    id, fee
+                )))
+            }
+        };
+        if let Err(rejection) = self.chain.add_new_transaction(&replacement) {
+            return Err(failure::err_msg(format!(
+                "The replacement of transaction {} is rejected, {}",
+                id, rejection
+            )));
+        }
+        self.history.record(&replacement, &self.chain);
+        self.wallet.record(&replacement);
+        info!(target: MEMPOOL, "Transaction {} is replaced, fee: {}", id, fee);
+        Ok(self.submit(replacement))
+    }
+
+    /// Signs `transaction` with the node key if it is sent from it, e.g. from the rewards of the
+    /// node, see `Transaction::verify`. Others are left for their senders to sign, wallets with
+    /// the passphrase `send` asks for.
+    fn sign_own(&self, transaction: Transaction) -> Transaction {
+        if key_of(transaction.get_sender()) == Some(self.key.public_key()) {
+            transaction.sign(&self.key)
+        } else {
+            transaction
+        }
+    }
+
+    /// Relays a transaction the node sent to its peers right away, rather than once the command
+    /// is done, so that the user learns how many peers got it.
+    fn submit(&mut self, transaction: Transaction) -> Submission {
+        let id = transaction.get_id().to_owned();
+        let request = Request::NewTransaction(self.get_basic_info(), transaction);
+        let broadcast = self.diffuse(request, self.diffusion);
+        if broadcast.delivered.is_empty() && broadcast.scheduled.is_empty() {
+            warn!(target: MEMPOOL, "Transaction {} {}", id, broadcast);
+        }
+        self.wallet.record_broadcast(&id, broadcast.clone());
+        Submission { id, broadcast }
+    }
+
+    /// Tells the wallet about the transactions that left the mempool other than into a block.
+    fn notify_wallet(&mut self) {
+        for (transaction, eviction) in self.chain.take_evictions() {
+            self.wallet.notify(&transaction, eviction);
+        }
     }
 
     pub fn handle_incoming_peer(&mut self, peer: PeerInfo) {
         if !self.add_peer(&peer) {
-            debug!("Redundant incoming peer, simply drop it");
+            debug!(target: NET, "Redundant incoming peer, simply drop it");
             return;
         }
         self.async_broadcast_peer(peer);
     }
 
     /// Take an incoming transaction and try to add it.
-    /// If it already exists, drop it and do nothing.
+    /// If it is rejected, e.g. because it already exists, drop it and do nothing.
     /// Else, add and broadcast it.
     pub fn handle_incoming_transaction(&mut self, transaction: Transaction) {
-        if !self.chain.add_new_transaction(&transaction) {
-            debug!("Redundant incoming transaction, simply drop it");
+        // rewards only come with the blocks that mint them
+        if transaction.get_sender() == REWARD_SENDER {
+            debug!(target: MEMPOOL, "Incoming transaction minting coins, simply drop it");
+            return;
+        }
+        if let Err(rejection) = self.chain.add_new_transaction(&transaction) {
+            debug!(
+                target: MEMPOOL,
+                "Dropping incoming transaction {}: {}",
+                transaction.get_id(),
+                rejection
+            );
             return;
         }
+        self.history.record(&transaction, &self.chain);
         self.async_broadcast_transaction(transaction);
     }
 
-    /// When a new block comes, check its index:
+    /// When a new block comes, check what it links to:
     ///
-    /// If its index is lower than or equal to that of out latest block, drop it and do nothing.
+    /// If it links to our latest block, then append it to the end of my chain.
     ///
-    /// If its index is exactly one plus our latest block's index and its previous block is our
-    /// latest block, then append it to the end of my chain.
+    /// If it links to another block we hold, it forks off our chain. Hold it as a side block, with
+    /// the orphans following it, and reorganize onto its branch once that beats our chain.
     ///
-    /// Else, do nothing to this block but then we need to resolve conflicts.
-    pub fn handle_incoming_block(&mut self, block: Block) {
+    /// Else, we miss its parent. Keep it as an orphan and fetch the parent from `peer`, which
+    /// comes in as a block again, until one links to our tip. If we miss too many blocks for
+    /// that, resolve conflicts instead.
+    pub fn handle_incoming_block(&mut self, peer: &PeerInfo, block: Block) {
+        self.see_block(&block);
+        if self.chain.is_foreign(&block) {
+            warn!(
+                target: CONSENSUS,
+                "Dropping block {} from {:?}, it was made for the chain {:?}",
+                block.get_index(),
+                peer,
+                block.get_chain_id()
+            );
+            return;
+        }
+        if self
+            .chain
+            .repair_block(&block, &peer.get_address().to_string())
+        {
+            return;
+        }
         if self.chain.add_new_block(&block) {
+            self.attach_orphans();
             // broadcast this good news to my friends~
             self.async_broadcast_latest_block();
+        } else if self.chain.is_orphan(&block) {
+            let horizon = (self.chain.len() + self.orphans.capacity()) as u64;
+            if block.get_index() >= horizon {
+                debug!(
+                    target: CONSENSUS,
+                    "The incoming block is too far ahead of our chain, syncing with peers"
+                );
+                self.sync(None);
+                return;
+            }
+            let parent =
+                Inventory::Block(block.get_index() - 1, block.get_previous_hash().to_owned());
+            let fetching = self.orphans.contains(block.get_previous_hash());
+            let cached = self.block_cache.get(block.get_previous_hash());
+            if !self.orphans.insert(block) || fetching {
+                return;
+            }
+            if let Some(parent) = cached {
+                debug!(
+                    target: CONSENSUS,
+                    "The incoming block misses its parent, taking it from the block cache"
+                );
+                self.handle_incoming_block(peer, parent);
+                return;
+            }
+            debug!(
+                target: CONSENSUS,
+                "The incoming block misses its parent, keeping it with {} orphans and fetching the parent from {:?}",
+                self.orphans.len(),
+                peer
+            );
+            let request = Request::Fetch(self.get_basic_info(), parent);
+            if let Err(e) = self.send_to(peer.get_address(), &request) {
+                debug!(target: NET, "Failed to fetch from {:?}: {}, syncing with peers", peer, e);
+                self.sync(None);
+            }
         } else {
-            // TODO: asynchronously resolve conflicts
+            self.add_side_blocks(block);
+        }
+    }
+
+    /// Adds `block` a client submitted, which must extend the tip, and relays it.
+    fn submit_block(&mut self, block: &Block) -> std::result::Result<(), BlockRejection> {
+        self.chain.try_add_block(block)?;
+        info!(
+            target: CONSENSUS,
+            "Block {} submitted by a client is added",
+            block.get_index()
+        );
+        self.attach_orphans();
+        self.async_broadcast_latest_block();
+        Ok(())
+    }
+
+    /// Fetches the stored blocks found corrupt from the peers again, each from all of them, since
+    /// whichever answers first repairs it. A block whose successor is corrupt too waits for the
+    /// successor, which tells the hash it must have.
+    fn repair_corrupt_blocks(&mut self) {
+        for index in self.chain.corrupt_blocks() {
+            let hash = match self.chain.expected_hash(index) {
+                Some(hash) => hash,
+                None => continue,
+            };
+            debug!(target: STORAGE, "Fetching the corrupt block {} from peers", index);
+            let request = Request::Fetch(self.get_basic_info(), Inventory::Block(index, hash));
+            for peer in self.get_peers() {
+                if let Err(e) = self.send_to(peer.get_address(), &request) {
+                    debug!(target: NET, "Failed to fetch from {:?}: {}", peer, e);
+                }
+            }
+        }
+    }
+
+    /// Holds `block` and the orphans following it as side blocks, see
+    /// `Blockchain::add_side_block`. Orphans may extend our tip again once we reorganized.
+    fn add_side_blocks(&mut self, block: Block) {
+        let mut advanced = false;
+        let mut pending = vec![block];
+        while let Some(block) = pending.pop() {
+            pending.extend(self.orphans.take_children(&block.get_hash()));
+            if self.chain.add_new_block(&block) || self.chain.add_side_block(&block).is_some() {
+                advanced = true;
+            }
+        }
+        if advanced {
+            self.attach_orphans();
+            self.async_broadcast_latest_block();
+        }
+    }
+
+    /// Adds the orphans following our tip, and those following them in turn. Orphans competing
+    /// for the same parent are dropped once one of them is added.
+    fn attach_orphans(&mut self) {
+        loop {
+            let children = self.orphans.take_children(self.chain.tip_hash());
+            let attached = children.iter().any(|child| self.chain.add_new_block(child));
+            if !attached {
+                break;
+            }
+            debug!(
+                target: CONSENSUS,
+                "Attached orphan block {}",
+                self.chain.last_block().get_index()
+            );
+        }
+    }
+
+    /// Reports the first sighting of `block` to the telemetry collector, if any.
+    fn see_block(&mut self, block: &Block) {
+        let telemetry = match &mut self.telemetry {
+            Some(telemetry) => telemetry,
+            None => return,
+        };
+        let sighting = match telemetry.see_block(block, &self.gossip) {
+            Some(sighting) => sighting,
+            None => return,
+        };
+        if telemetry.is_collector() {
+            telemetry.record(&self.basic_info, sighting);
+            return;
+        }
+        let collector = telemetry.get_collector();
+        let request = Request::Telemetry(self.get_basic_info(), sighting);
+        if let Err(e) = send_request(collector, &request) {
+            debug!(target: NET, "Failed to report telemetry to {}: {}", collector, e);
+        }
+    }
+
+    /// Fetches an announced item from the announcer unless we already have it.
+    fn handle_announcement(&mut self, peer: &PeerInfo, inventory: Inventory) {
+        let known = match &inventory {
+            Inventory::Block(index, hash) => {
+                self.chain.contains_block(*index, hash) || self.chain.contains_side_block(hash)
+            }
+            Inventory::Transaction(id) => self.chain.contains_transaction(id),
+        };
+        if known {
+            debug!(target: NET, "Announced item is already known, ignore it");
+            return;
+        }
+        let request = Request::Fetch(self.get_basic_info(), inventory);
+        if let Err(e) = self.send_to(peer.get_address(), &request) {
+            debug!(target: NET, "Failed to fetch from {:?}: {}", peer, e);
+        }
+    }
+
+    /// Pushes a fetched item to the peer asking for it, if we still have it.
+    fn handle_fetch(&mut self, peer: &PeerInfo, inventory: Inventory) {
+        let request = match inventory {
+            Inventory::Block(index, hash) => match self.chain.get_block_by_index(index) {
+                Some(block) if block.get_hash() == hash => {
+                    Request::NewBlock(self.get_basic_info(), block.into_owned())
+                }
+                _ => return,
+            },
+            Inventory::Transaction(id) => match self.chain.get_pending_transaction(&id) {
+                Some(transaction) => {
+                    Request::NewTransaction(self.get_basic_info(), transaction.clone())
+                }
+                None => return,
+            },
         };
+        if let Err(e) = self.send_to(peer.get_address(), &request) {
+            debug!(target: NET, "Failed to serve fetch of {:?}: {}", peer, e);
+        }
     }
 
-    async fn async_broadcast_transaction(&self, transaction: Transaction) {
+    fn async_broadcast_transaction(&self, transaction: Transaction) {
         // add this transaction to broadcast channel
         // which will then send it asynchronously
-        self.broadcast_sender
-            .send(Event::Broadcast(Request::NewTransaction(
-                self.basic_info.clone(),
-                transaction,
-            )))
-            .await;
+        self.async_broadcast(Request::NewTransaction(
+            self.basic_info.clone(),
+            transaction,
+        ));
     }
 
-    async fn async_broadcast_block(&self, block: Block) {
-        self.broadcast_sender
-            .send(Event::Broadcast(Request::NewBlock(
-                self.get_basic_info(),
-                block,
-            )))
-            .await;
+    fn async_broadcast_block(&self, block: Block) {
+        self.async_broadcast(Request::NewBlock(self.get_basic_info(), block));
     }
 
-    async fn async_broadcast_latest_block(&self) {
+    fn async_broadcast_latest_block(&self) {
         self.async_broadcast_block(self.chain.last_block().to_owned())
-            .await
     }
 
-    async fn async_broadcast_peer(&self, peer: PeerInfo) {
-        self.broadcast_sender
-            .send(Event::Broadcast(Request::NewPeer(
-                self.get_basic_info(),
-                peer,
-            )))
-            .await;
+    fn async_broadcast_peer(&self, peer: PeerInfo) {
+        self.async_broadcast(Request::NewPeer(self.get_basic_info(), peer));
+    }
+
+    fn async_broadcast(&self, request: Request) {
+        if self
+            .broadcast_sender
+            .send(Event::Broadcast(request))
+            .is_err()
+        {
+            error!(target: NET, "The event loop is gone, cannot broadcast");
+        }
+    }
+
+    fn broadcast_request(&mut self, req: Request) -> Result<()> {
+        self.broadcast(req);
+        Ok(())
+    }
+
+    /// Sends `req` to the peers the gossip strategy picks, returning which of them it reached.
+    fn broadcast(&mut self, req: Request) -> Broadcast {
+        self.diffuse(req, None)
     }
 
-    fn broadcast_request(&self, req: &Request) -> Result<()> {
-        debug!("{}", "broadcast begins".color(PROMINENT_COLOR));
+    /// Broadcasts `req`, delaying it to every peer by a random time up to `diffusion`, if given,
+    /// so that the peers do not all hear of it at once from this node. The peers it is delayed to
+    /// are reported as scheduled rather than delivered.
+    fn diffuse(&mut self, req: Request, diffusion: Option<Duration>) -> Broadcast {
+        debug!(target: NET, "{}", "broadcast begins".color(PROMINENT_COLOR));
+        let mut broadcast = Broadcast::default();
         let peers = self.peers.clone();
-        debug!("broadcasts request {:?} to peers :{:?}", req, peers);
-        for peer in peers.iter() {
-            debug!("Connecting {:?}", peer);
-            match TcpStream::connect(peer.get_address()) {
-                Ok(mut stream) => {
-                    serde_json::to_writer(stream.try_clone()?, req)?;
-                    stream.flush()?;
-                    debug!("Request broadcast");
+        let targets = self.gossip.targets(&peers);
+        let req = match self.gossip.announcement(&req) {
+            Some(inventory) => Request::Announce(self.get_basic_info(), inventory),
+            None => req,
+        };
+        debug!(target: NET, "broadcasts request {:?} to peers :{:?}", req, targets);
+        for peer in targets {
+            debug!(target: NET, "Connecting {:?}", peer);
+            let delay = diffusion.map(|max| {
+                Duration::from_millis(rand::thread_rng().gen_range(0, max.as_millis() as u64 + 1))
+            });
+            match self.send_after(peer.get_address(), &req, delay) {
+                Ok(()) if delay.is_some() => {
+                    debug!(target: NET, "Request scheduled");
+                    broadcast.scheduled.push(peer.get_address());
+                }
+                Ok(()) => {
+                    debug!(target: NET, "Request broadcast");
+                    broadcast.delivered.push(peer.get_address());
                 }
                 Err(e) => {
-                    debug!("Connection to {:?} failed: {}", peer, e);
-                    // Err(failure::err_msg("Failed to connect"))
+                    debug!(target: NET, "Connection to {:?} failed: {}", peer, e);
+                    broadcast.failed.push((peer.get_address(), e.to_string()));
                 }
             };
-            debug!("broadcast to one peer finished");
+            debug!(target: NET, "broadcast to one peer finished");
         }
-        // Err(failure::err_msg("No peer to connect"))
-        debug!("{}", "broadcast finished".color(PROMINENT_COLOR));
+        debug!(target: NET, "{}", "broadcast finished".color(PROMINENT_COLOR));
+        broadcast
+    }
+
+    /// Sends a gossip message to `addr`, counting the traffic.
+    fn send_to(&mut self, addr: SocketAddr, request: &Request) -> Result<()> {
+        self.send_after(addr, request, None)
+    }
+
+    /// Sends a gossip message to `addr` once `delay`, if given, has passed, counting the traffic.
+    ///
+    /// A delayed message, or one on a link with artificial latency, is sent from another thread
+    /// once the delays have passed, so failures to deliver it are only logged.
+    fn send_after(
+        &mut self,
+        addr: SocketAddr,
+        request: &Request,
+        delay: Option<Duration>,
+    ) -> Result<()> {
+        let delay = match (self.link_delay(&addr), delay) {
+            (None, None) => {
+                let bytes = send_request(addr, request)?;
+                self.gossip.count(bytes);
+                return Ok(());
+            }
+            (latency, delay) => latency.unwrap_or_default() + delay.unwrap_or_default(),
+        };
+        self.gossip.count(serde_json::to_vec(request)?.len());
+        let request = request.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            if let Err(e) = send_request(addr, &request) {
+                debug!(target: NET, "Delayed message to {} failed: {}", addr, e);
+            }
+        });
         Ok(())
     }
 
+    /// Draws the artificial delay of the next message to `addr`, if the link has any.
+    fn link_delay(&self, addr: &SocketAddr) -> Option<Duration> {
+        let latency = self.latency.get(addr)?;
+        let jitter = latency.jitter.as_millis() as i64;
+        let offset = rand::thread_rng().gen_range(-jitter, jitter + 1);
+        Some(Duration::from_millis(
+            (latency.delay.as_millis() as i64 + offset).max(0) as u64,
+        ))
+    }
+
+    /// Opens a connection for a request awaiting a response, after the link's artificial delay.
+    fn connect(&self, addr: SocketAddr) -> std::io::Result<TcpStream> {
+        connect(addr, self.link_delay(&addr), self.idle_timeout)
+    }
+
     /// Tries to greet and add a new peer at the given address.
     /// Returns false if `addr` is not a valid socket addr
-    pub fn greet_and_add_peer(&mut self, addr: &str) -> bool {
-        if let Ok(addr) = parse_addr(addr.to_owned()) {
-            match TcpStream::connect(addr) {
-                Ok(stream) => {
-                    if let Ok(true) = self.say_hello(stream) {
-                        true
-                    } else {
-                        false
-                    }
+    /// Greets the node at `addr` and adds it as a peer, unless it runs another chain.
+    pub fn greet_and_add_peer(&mut self, addr: &str) -> Result<()> {
+        let addr = parse_addr(addr.to_owned())
+            .map_err(|_| failure::err_msg(format!("Invalid peer address {}", addr)))?;
+        let stream = self.connect(addr).map_err(|e| {
+            failure::err_msg(format!("Error when communicating with {:?}: {}", addr, e))
+        })?;
+        if !self.say_hello(stream)? {
+            return Err(failure::err_msg("fail to add peer"));
+        }
+        Ok(())
+    }
+
+    /// The parameters of the chain this node follows, which peers must share.
+    fn chain_params(&self) -> ChainParams {
+        ChainParams::of(&self.chain, &self.consensus)
+    }
+
+    /// How this node introduces itself to peers.
+    fn greeting(&self) -> Greeting {
+        Greeting {
+            peer: self.basic_info.clone(),
+            chain_id: Some(self.chain.chain_id()),
+            chain: Some(self.chain_params()),
+        }
+    }
+
+    fn say_hello(&mut self, stream: TcpStream) -> Result<bool> {
+        match greet(stream, &self.greeting())? {
+            Response::Ack(peer_info) => {
+                debug!(target: NET, "Ack for Hello received from: {:?}", peer_info);
+                if !self.pin_key(&peer_info) {
+                    return Err(failure::err_msg(format!(
+                        "the peer at {} tells another key than the one pinned, use repin if it changed for good",
+                        peer_info.get_address()
+                    )));
                 }
-                Err(e) => {
-                    error!("Error when communicating with {:?}: {}", addr, e);
-                    false
+                self.async_broadcast_peer(peer_info.clone());
+                let added = self.add_peer(&peer_info);
+                if let Err(e) = self.sync_mempool(&peer_info) {
+                    debug!(
+                        target: NET,
+                        "Failed to get the pending transactions of {:?}: {}",
+                        peer_info, e
+                    );
                 }
+                Ok(added)
             }
-        } else {
-            error!("Invalid peer address {}", addr);
-            false
+            Response::IncompatibleChain(mismatch) => {
+                warn!(target: NET, "The peer runs another chain: {}", mismatch);
+                Err(failure::err_msg(format!(
+                    "incompatible chain, {}",
+                    mismatch
+                )))
+            }
+            Response::KeyNotPinned(pinned) => {
+                warn!(
+                    target: NET,
+                    "The peer pinned the key {} to our address, not ours {}",
+                    pinned,
+                    self.basic_info.get_id()
+                );
+                Err(failure::err_msg(format!(
+                    "the peer pinned another key to our address, {}",
+                    pinned
+                )))
+            }
+            _ => Err(failure::err_msg("Invalid response")),
         }
     }
 
-    fn say_hello(&mut self, mut stream: TcpStream) -> Result<bool> {
-        serde_json::to_writer(
-            stream.try_clone()?,
-            &Request::Hello(self.basic_info.clone()),
-        )?;
+    /// Asks a newly connected peer for its pending transactions, those paying the highest fees
+    /// first, and adds those we miss. They are not broadcast again, the other peers likely have
+    /// them already.
+    fn sync_mempool(&mut self, peer: &PeerInfo) -> Result<()> {
+        let mut stream = self.connect(peer.get_address())?;
+        let request = Request::GetMempool(self.get_basic_info(), MEMPOOL_SYNC_LIMIT);
+        serde_json::to_writer(stream.try_clone()?, &request)?;
         stream.flush()?;
-        debug!("Request sent");
-        // There should be only one response, but we have to deserialize from a stream in this way
-        for response in Deserializer::from_reader(stream.try_clone()?).into_iter::<Response>() {
-            let response =
-                response.map_err(|e| failure::err_msg(format!("Deserializing error {}", e)))?;
-            return if let Response::Ack(peer_info) = response {
-                debug!("Ack for Hello received from: {:?}", peer_info);
-                self.async_broadcast_peer(peer_info.clone());
-                Ok(self.add_peer(&peer_info))
-            } else {
-                Err(failure::err_msg("Invalid response"))
-            };
+        let transactions = match read_response(stream)? {
+            Response::MempoolTxs(_, transactions) => transactions,
+            _ => return Err(failure::err_msg("Invalid response")),
+        };
+        let total = transactions.len().min(MEMPOOL_SYNC_LIMIT);
+        let mut added = 0;
+        for transaction in transactions.into_iter().take(MEMPOOL_SYNC_LIMIT) {
+            // rewards only come with the blocks that mint them
+            if transaction.get_sender() != REWARD_SENDER
+                && self.chain.add_new_transaction(&transaction).is_ok()
+            {
+                self.history.record(&transaction, &self.chain);
+                added += 1;
+            }
         }
-        Err(failure::err_msg("No response"))
+        info!(
+            target: MEMPOOL,
+            "Got {} new of {} pending transactions from {:?}",
+            added, total, peer
+        );
+        Ok(())
+    }
+
+    /// Greets the persisted peers again after a restart, so that they learn what changed about us
+    /// while we were gone, and we about them. The greetings go out one every `REGREET_PACE` from
+    /// another thread, the acks come back in `Event::Regreeted`s.
+    fn regreet_peers(&self) {
+        let greeting = self.greeting();
+        let peers: Vec<(PeerInfo, Option<Duration>)> = self
+            .peers
+            .iter()
+            .map(|peer| (peer.clone(), self.link_delay(&peer.get_address())))
+            .collect();
+        let timeout = self.idle_timeout;
+        let sender = self.broadcast_sender.clone();
+        thread::spawn(move || {
+            for (peer, delay) in peers {
+                let response = connect(peer.get_address(), delay, timeout)
+                    .map_err(failure::Error::from)
+                    .and_then(|stream| greet(stream, &greeting));
+                match response {
+                    Ok(Response::Ack(acked)) => {
+                        if sender.send(Event::Regreeted(peer, acked)).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(Response::IncompatibleChain(mismatch)) => {
+                        warn!(
+                            target: NET,
+                            "The peer {:?} runs another chain now: {}",
+                            peer,
+                            mismatch
+                        )
+                    }
+                    Ok(Response::KeyNotPinned(pinned)) => warn!(target: NET,
+                        "The peer {:?} pinned the key {} to our address instead",
+                        peer, pinned
+                    ),
+                    Ok(_) => debug!(target: NET, "Invalid response to greeting {:?} again", peer),
+                    Err(e) => debug!(target: NET, "Failed to greet {:?} again: {}", peer, e),
+                }
+                thread::sleep(REGREET_PACE);
+            }
+        });
+    }
+
+    /// Takes the ack of a peer we knew as `known` and greeted again after a restart.
+    fn finish_regreet(&mut self, known: PeerInfo, acked: PeerInfo) {
+        debug!(
+            target: NET,
+            "Persisted peer {:?} acked our greeting as {:?}",
+            known, acked
+        );
+        if !self.pin_key(&acked) {
+            return;
+        }
+        if acked != known {
+            // the peer moved or rotated its key while we were gone
+            self.peers.remove(&known);
+            if let Err(e) = self.store.remove_peer(known.get_id()) {
+                error!(target: STORAGE, "Failed to remove peer {}: {}", known.get_id(), e);
+            }
+            self.add_peer(&acked);
+        }
+        if let Err(e) = self.sync_mempool(&acked) {
+            debug!(
+                target: NET,
+                "Failed to get the pending transactions of {:?}: {}",
+                acked, e
+            );
+        }
+    }
+
+    /// Replaces the key of the node, and with it its id, and tells the peers. They keep
+    /// accepting the old id for the grace period, while the news spreads.
+    pub fn rotate_key(&mut self) -> Result<()> {
+        let key = NodeKey::generate();
+        let rotation = KeyRotation::new(&self.key, &key, crate::blockchain::get_time());
+        self.store.put_node_key(&key)?;
+        self.store.put_key_rotation(&rotation)?;
+        self.rotations
+            .insert(rotation.old.clone(), rotation.clone());
+        self.chain
+            .set_consensus(self.consensus.engine(Some(key.clone())));
+        self.chain.set_miner_key(Some(key.clone()));
+        if !self.chain.can_seal() {
+            warn!(
+                target: CONSENSUS,
+                "The consensus engine does not let the new key produce the next block"
+            );
+        }
+        self.basic_info = self.basic_info.with_id(&rotation.new);
+        self.key = key;
+        // spending needs the signature of the key, see `Transaction::verify`
+        let stranded = self.chain.get_balance(&rotation.old);
+        if stranded > 0 {
+            warn!(
+                target: MEMPOOL,
+                "The {} coins of the retired key {} can no longer be spent by the node",
+                stranded, rotation.old
+            );
+        }
+        info!(
+            target: NET,
+            "Rotated the node key from {} to {}, peers accept the old one for {:?}",
+            rotation.old, rotation.new, self.key_grace_period
+        );
+        let me = self.get_basic_info();
+        self.async_broadcast(Request::RotateKey(me.clone(), me, rotation));
+        Ok(())
+    }
+
+    /// Whether the grace period of the id retired by `rotation` is over.
+    fn is_retired(&self, rotation: &KeyRotation) -> bool {
+        let end = rotation.at + self.key_grace_period.as_millis();
+        crate::blockchain::get_time() > end
+    }
+
+    /// Replaces the peer that rotated its key to become `rotated`, and passes the news on.
+    pub fn handle_key_rotation(&mut self, rotated: PeerInfo, rotation: KeyRotation) {
+        if !rotation.is_valid() || rotated.get_id() != rotation.new {
+            debug!(target: NET, "Ignoring an invalid key rotation: {:?}", rotation);
+            return;
+        }
+        if self.rotations.contains_key(&rotation.old) {
+            debug!(target: NET, "Key rotation already known: {:?}", rotation);
+            return;
+        }
+        info!(
+            target: NET,
+            "Peer {} rotated its key, its id is {} now",
+            rotation.old, rotation.new
+        );
+        if let Err(e) = self.store.put_key_rotation(&rotation) {
+            error!(target: STORAGE, "Failed to persist the key rotation {:?}: {}", rotation, e);
+        }
+        let retired: Vec<PeerInfo> = self
+            .peers
+            .iter()
+            .filter(|peer| peer.get_id() == rotation.old)
+            .cloned()
+            .collect();
+        for peer in retired {
+            self.peers.remove(&peer);
+        }
+        if let Err(e) = self.store.remove_peer(&rotation.old) {
+            error!(target: STORAGE, "Failed to remove peer {}: {}", rotation.old, e);
+        }
+        self.rotations
+            .insert(rotation.old.clone(), rotation.clone());
+        for address in self.pins.rotate(&rotation.old, &rotation.new) {
+            let pinned = PeerInfo::new(rotation.new.clone(), address.to_string())
+                .and_then(|peer| self.store.put_pinned_key(&address.to_string(), &peer));
+            if let Err(e) = pinned {
+                error!(target: STORAGE, "Failed to persist the key pinned to {}: {}", address, e);
+            }
+        }
+        self.add_peer(&rotated);
+        self.async_broadcast(Request::RotateKey(self.get_basic_info(), rotated, rotation));
+    }
+
+    /// Pins the key of `peer` to its address on the first handshake with it. Returns `false`,
+    /// warning loudly, if another key is pinned there: the peer may be an impostor.
+    fn pin_key(&mut self, peer: &PeerInfo) -> bool {
+        if let Some(pinned) = self.pins.conflict(peer) {
+            error!(
+                target: NET,
+                "{}",
+                format!(
+                    "The peer at {} tells the key {}, but {} is pinned to it. It may be an impostor! Refusing it, use `repin {}` if its key changed for good",
+                    peer.get_address(),
+                    peer.get_id(),
+                    pinned,
+                    peer.get_address()
+                )
+                .color(ERR_COLOR)
+            );
+            return false;
+        }
+        if self.pins.pin(peer) {
+            debug!(target: NET, "Pinned the key {} to {}", peer.get_id(), peer.get_address());
+            if let Err(e) = self
+                .store
+                .put_pinned_key(&peer.get_address().to_string(), peer)
+            {
+                error!(
+                    target: STORAGE,
+                    "Failed to persist the key pinned to {}: {}",
+                    peer.get_address(),
+                    e
+                );
+            }
+        }
+        true
+    }
+
+    /// Forgets the key pinned to the peer at `addr` and greets it again, pinning the key it tells
+    /// now. The peers known at the address under the old key are dropped.
+    pub fn repin(&mut self, addr: &str) -> Result<()> {
+        let address = parse_addr(addr.to_owned())
+            .map_err(|_| failure::err_msg(format!("Invalid peer address {}", addr)))?;
+        match self.pins.unpin(address) {
+            Some(old) => {
+                warn!(target: NET, "Unpinned the key {} from {}", old, address);
+                let stale: Vec<PeerInfo> = self
+                    .peers
+                    .iter()
+                    .filter(|peer| peer.get_address() == address)
+                    .cloned()
+                    .collect();
+                for peer in stale {
+                    self.peers.remove(&peer);
+                    if let Err(e) = self.store.remove_peer(peer.get_id()) {
+                        error!(target: STORAGE, "Failed to remove peer {}: {}", peer.get_id(), e);
+                    }
+                }
+            }
+            None => debug!(target: NET, "No key is pinned to {}", address),
+        }
+        self.store.remove_pinned_key(&address.to_string())?;
+        self.greet_and_add_peer(addr)
     }
 
     /// Adds a given `PeerInfo` to the peer list. Returns `false` if the peer already exists.
     pub fn add_peer(&mut self, peer: &PeerInfo) -> bool {
         if &self.basic_info == peer {
-            debug!("Peer is myself");
+            debug!(target: NET, "Peer is myself");
+            false
+        } else if self.rotations.contains_key(peer.get_id()) {
+            debug!(target: NET, "Peer rotated its key away from {}", peer.get_id());
             false
         } else if self.peers.contains(peer) {
-            debug!("Peer already exists: {:?}", peer);
+            debug!(target: NET, "Peer already exists: {:?}", peer);
             false
         } else {
-            debug!("New peer added: {:?}", peer);
+            debug!(target: NET, "New peer added: {:?}", peer);
+            if let Err(e) = self.store.put_peer(peer.get_id(), peer) {
+                error!(target: STORAGE, "Failed to persist peer {:?}: {}", peer, e);
+            }
             self.peers.insert(peer.clone());
             true
         }
     }
 
+    /// Replaces our chain with `new_blocks` if they win the fork choice of our consensus engine,
+    /// which is not always the longer chain, e.g. under proof of work the one with the most work.
     pub fn update_chain(&mut self, new_blocks: Vec<Block>) -> bool {
-        if new_blocks.len() <= self.chain.len() {
+        let mut new_chain = Blockchain::from_blocks(new_blocks);
+        if !self.chain.prefers(&new_chain) {
             return false;
         }
-        let mut new_chain = Blockchain::from_blocks(new_blocks);
-        if !Blockchain::valid_chain(&new_chain) {
+        new_chain.set_assume_valid(self.chain.assume_valid().map(str::to_owned));
+        new_chain
+            .set_signals(&self.chain.signals())
+            .expect("the signals of our chain are in range");
+        new_chain.set_miner_key(self.chain.miner_key().cloned());
+        for validator in self.chain.validators() {
+            new_chain.add_validator(validator.clone());
+        }
+        // the new chain passed them when it was validated
+        if let Err(e) = new_chain.set_checkpoints(self.chain.checkpoints().clone()) {
+            error!(target: CONSENSUS, "{}", e);
             return false;
         }
         // add current transactions that are not on the chain yet
         // otherwise, these transaction would be lost!
         for t in self.chain.get_current_transactions() {
-            new_chain.add_new_transaction(&t);
+            if let Err(rejection) = new_chain.add_new_transaction(&t) {
+                debug!(
+                    target: MEMPOOL,
+                    "Dropping pending transaction {}: {}",
+                    t.get_id(),
+                    rejection
+                );
+            }
+        }
+        if let Err(e) = new_chain.attach_store(self.store.clone()) {
+            error!(target: STORAGE, "Failed to persist the new chain: {}", e);
         }
         self.chain = new_chain;
+        self.attach_orphans();
         // broadcast only the latest block
         self.async_broadcast_latest_block();
         true
     }
 
-    /// This is our Consensus Algorithm, it resolves conflicts (explicitly)
-    /// by replacing our chain with the longest one in the network.
-    /// Returns `true` if the chain is replaced
-    pub fn resolve_conflicts(&mut self) -> bool {
-        let mut ret = false;
-        let peers = self.peers.clone();
-        debug!("Resolve conflict with peers :{:?}", peers);
-        for peer in peers.iter() {
-            debug!("Connecting {:?}", peer);
-            match TcpStream::connect(peer.get_address()) {
-                Ok(stream) => {
-                    debug!("Resolve conflict with peer :{:?}", peer);
-                    match self.resolve_conflict(stream) {
-                        Ok(flag) => {
-                            ret = ret || flag;
-                        }
+    /// This is our Consensus Algorithm, it resolves conflicts by replacing our chain with the
+    /// best one in the network, see `update_chain`. The chains of all peers are downloaded in the background and
+    /// handed back in an `Event::Synced`, while the node keeps serving requests.
+    ///
+    /// Only one download runs at a time, a `requester` of another one is told that the node is
+    /// already syncing. The one that started it gets the outcome.
+    fn sync(&mut self, requester: Option<Responder>) {
+        if let SyncState::Syncing(_) = self.sync_state {
+            debug!(target: NET, "Already syncing");
+            if let Some(requester) = requester {
+                let _ = requester.send(Ok(CommandOutput::AlreadySyncing));
+            }
+            return;
+        }
+        self.sync_state = SyncState::Syncing(requester);
+        let me = self.get_basic_info();
+        let peers: Vec<(PeerInfo, Option<Duration>)> = self
+            .peers
+            .iter()
+            .map(|peer| (peer.clone(), self.link_delay(&peer.get_address())))
+            .collect();
+        debug!(target: NET, "Resolve conflict with peers :{:?}", peers);
+        let timeout = self.idle_timeout;
+        let ours = (self.chain.len(), self.chain.total_work());
+        let mut guard = SyncGuard::new(self.broadcast_sender.clone());
+        let store = self.store.clone();
+        let cache = self.block_cache.clone();
+        thread::spawn(move || {
+            let chains = peers
+                .iter()
+                .filter_map(|(peer, delay)| {
+                    debug!(target: NET, "Resolve conflict with peer :{:?}", peer);
+                    let download = Downloader {
+                        me: &me,
+                        addr: peer.get_address(),
+                        delay: *delay,
+                        timeout,
+                    };
+                    match download.chain(ours, &store, &cache) {
+                        Ok(blocks) => blocks,
                         Err(e) => {
-                            error!("Error when communicating with {:?}: {}", peer, e);
+                            error!(target: NET, "Error when communicating with {:?}: {}", peer, e);
+                            None
+                        }
+                    }
+                })
+                .collect();
+            guard.downloaded(chains);
+        });
+    }
+
+    /// Adopts the best of the `chains` a sync downloaded, if it beats ours.
+    fn finish_sync(&mut self, chains: Vec<Download>) {
+        let requester = match mem::replace(&mut self.sync_state, SyncState::Idle) {
+            SyncState::Syncing(requester) => requester,
+            SyncState::Idle => None,
+        };
+        let mut updated = false;
+        for download in chains {
+            let blocks = match download {
+                Download::Blocks(blocks) => blocks,
+                Download::Hashes(hashes) => match self.assemble(&hashes) {
+                    Some(blocks) => blocks,
+                    None => {
+                        debug!(
+                            target: NET,
+                            "Blocks of a downloaded chain left the block cache, skipping it"
+                        );
+                        continue;
+                    }
+                },
+            };
+            let hashes: Vec<String> = blocks.iter().map(Block::get_hash).collect();
+            if self.update_chain(blocks) {
+                updated = true;
+                for hash in hashes {
+                    self.block_cache.remove(&hash);
+                }
+            }
+        }
+        debug!(
+            target: NET,
+            "{} downloaded blocks stay in the block cache",
+            self.block_cache.len()
+        );
+        if let Some(requester) = requester {
+            let _ = requester.send(Ok(CommandOutput::Synced(updated)));
+        }
+    }
+}
+
+impl Node {
+    /// The blocks with `hashes`, from our chain where it holds them at the same index, else from
+    /// the block cache, `None` if one is in neither.
+    fn assemble(&self, hashes: &[String]) -> Option<Vec<Block>> {
+        hashes
+            .iter()
+            .enumerate()
+            .map(
+                |(index, hash)| match self.chain.get_block_by_index(index as u64) {
+                    Some(block) if block.get_hash() == *hash => Some(block.into_owned()),
+                    _ => self.block_cache.get(hash),
+                },
+            )
+            .collect()
+    }
+}
+
+/// Opens a connection for a request awaiting a response, after waiting `delay`.
+pub(super) fn connect(
+    addr: SocketAddr,
+    delay: Option<Duration>,
+    timeout: Duration,
+) -> std::io::Result<TcpStream> {
+    if let Some(delay) = delay {
+        thread::sleep(delay);
+    }
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// Downloads from the peer at `addr`, connecting after `delay`.
+struct Downloader<'a> {
+    me: &'a PeerInfo,
+    addr: SocketAddr,
+    delay: Option<Duration>,
+    timeout: Duration,
+}
+
+impl Downloader<'_> {
+    /// Downloads the chain of the peer, unless its headers show that it can beat neither the
+    /// length nor the work of `ours`, which no consensus engine would prefer. Only the blocks
+    /// neither `store` nor `cache` hold are fetched, unless there are too many of them, and all
+    /// blocks fetched go to `cache`.
+    fn chain(
+        &self,
+        ours: (usize, u128),
+        store: &Store,
+        cache: &BlockCache,
+    ) -> Result<Option<Download>> {
+        let addr = self.addr;
+        match self.headers() {
+            Ok(headers) => match BlockHeader::chain_work(&headers) {
+                Some(work) if headers.len() > ours.0 || work > ours.1 => {
+                    let hashes: Vec<String> = headers.iter().map(BlockHeader::get_hash).collect();
+                    let mut missing = Vec::new();
+                    for (index, hash) in hashes.iter().enumerate() {
+                        if store.find_block(hash)? != Some(index as u64) && !cache.contains(hash) {
+                            missing.push(hash);
                         }
                     }
+                    if missing.len() <= FETCH_LIMIT {
+                        debug!(
+                            target: NET,
+                            "Fetching {} of the {} blocks of {}, the others are known",
+                            missing.len(),
+                            hashes.len(),
+                            addr
+                        );
+                        let fetched = missing
+                            .into_iter()
+                            .try_for_each(|hash| self.block(hash).map(|block| cache.insert(block)));
+                        match fetched {
+                            Ok(()) => return Ok(Some(Download::Hashes(hashes))),
+                            // older versions do not hand out blocks by hash
+                            Err(e) => debug!(target: NET, "Failed to fetch from {}: {}", addr, e),
+                        }
+                    }
+                }
+                Some(_) => {
+                    debug!(target: NET, "The chain of {} cannot beat ours, keeping ours", addr);
+                    return Ok(None);
+                }
+                None => {
+                    debug!(target: NET, "The headers of {} do not link up, ignoring its chain", addr);
+                    return Ok(None);
+                }
+            },
+            // older versions do not hand out headers
+            Err(e) => debug!(target: NET, "Failed to get the headers of {}: {}", addr, e),
+        }
+        match self.request(&Request::HowAreYou(self.me.clone()))? {
+            Response::MyBlocks(_, blocks) => {
+                debug!(target: NET, "Response received");
+                for block in &blocks {
+                    if store.find_block(&block.get_hash())? != Some(block.get_index()) {
+                        cache.insert(block.clone());
+                    }
+                }
+                Ok(Some(Download::Blocks(blocks)))
+            }
+            _ => Err(failure::err_msg("Invalid response")),
+        }
+    }
+
+    /// Downloads the headers of the chain of the peer, `HEADERS_LIMIT` at a time.
+    fn headers(&self) -> Result<Vec<BlockHeader>> {
+        let mut headers = Vec::new();
+        loop {
+            let request = Request::GetHeaders(self.me.clone(), headers.len() as u64, HEADERS_LIMIT);
+            match self.request(&request)? {
+                Response::Headers(_, batch) => {
+                    let done = batch.len() < HEADERS_LIMIT;
+                    headers.extend(batch);
+                    if done {
+                        return Ok(headers);
+                    }
                 }
-                Err(e) => error!("Connection to {:?} failed: {}", peer, e),
+                _ => return Err(failure::err_msg("Invalid response")),
             }
         }
-        ret
     }
 
-    fn resolve_conflict(&mut self, mut stream: TcpStream) -> Result<bool> {
-        serde_json::to_writer(
-            stream.try_clone()?,
-            &Request::HowAreYou(self.basic_info.clone()),
-        )?;
+    /// Fetches the block with `hash`.
+    fn block(&self, hash: &str) -> Result<Block> {
+        match self.request(&Request::GetBlock(BlockId::Hash(hash.to_owned())))? {
+            Response::Block(Some(block)) if block.get_hash() == hash => Ok(*block),
+            Response::Block(_) => Err(failure::err_msg(format!(
+                "{} does not hand out its block {}",
+                self.addr, hash
+            ))),
+            _ => Err(failure::err_msg("Invalid response")),
+        }
+    }
+
+    fn request(&self, request: &Request) -> Result<Response> {
+        let mut stream = connect(self.addr, self.delay, self.timeout)?;
+        serde_json::to_writer(stream.try_clone()?, request)?;
         stream.flush()?;
-        debug!("Request sent");
-        // There should be only one response, but we have to deserialize from a stream in this way
-        for response in Deserializer::from_reader(stream.try_clone()?).into_iter::<Response>() {
-            let response =
-                response.map_err(|e| failure::err_msg(format!("Deserializing error {}", e)))?;
-            return if let Response::MyBlocks(_, blocks) = response {
-                debug!("Response received");
-                Ok(self.update_chain(blocks))
-            } else {
-                Err(failure::err_msg("Invalid response"))
-            };
+        debug!(target: NET, "Request sent");
+        read_response(stream)
+    }
+}
+
+/// Says hello on `stream` with `greeting` and reads the answer.
+fn greet(mut stream: TcpStream, greeting: &Greeting) -> Result<Response> {
+    serde_json::to_writer(stream.try_clone()?, &Request::Hello(greeting.clone()))?;
+    stream.flush()?;
+    debug!(target: NET, "Request sent");
+    read_response(stream)
+}
+
+/// Sends `request` to `addr` without waiting for a response. Returns the bytes sent.
+fn send_request(addr: SocketAddr, request: &Request) -> Result<usize> {
+    let mut stream = TcpStream::connect(addr)?;
+    let bytes = serde_json::to_vec(request)?;
+    stream.write_all(&bytes)?;
+    stream.flush()?;
+    Ok(bytes.len())
+}
+
+/// Whether `peer` connects from the host it claims to listen on. Announcements and fetches are
+/// answered by connecting to the address the peer claims, so that nobody aims those answers at
+/// a third party.
+fn is_connected_from(stream: &Connection, peer: &PeerInfo) -> bool {
+    let from = stream.peer_addr().map(|addr| addr.ip());
+    if from.as_ref().ok() == Some(&peer.get_address().ip()) {
+        return true;
+    }
+    warn!(
+        target: NET,
+        "Not answering {:?}, which connects from {:?}",
+        peer, from
+    );
+    false
+}
+
+/// Reads the single response the peer sends back on `stream`.
+pub(super) fn read_response(stream: TcpStream) -> Result<Response> {
+    // There should be only one response, but we have to deserialize from a stream in this way
+    match Deserializer::from_reader(stream)
+        .into_iter::<Response>()
+        .next()
+    {
+        Some(response) => {
+            response.map_err(|e| failure::err_msg(format!("Deserializing error {}", e)))
         }
-        Err(failure::err_msg("No response"))
+        None => Err(failure::err_msg("No response")),
+    }
+}
+
+/// The hash a console gave the `prefix` of, or why it names no single one, `missing` if nothing
+/// starts with it.
+fn resolved(
+    resolution: std::result::Result<String, PrefixError>,
+    prefix: &str,
+    missing: String,
+) -> Result<String> {
+    match resolution {
+        Ok(hash) => Ok(hash),
+        Err(PrefixError::NotFound) => Err(failure::err_msg(missing)),
+        Err(e) => Err(failure::err_msg(format!("{} {}", prefix, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::os::unix::io::IntoRawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn ephemeral_node(sender: UnboundedSender<Event>) -> Node {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let control = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = NodeConfig::new(&listener.local_addr().unwrap().to_string());
+        config.control_addr = control.local_addr().unwrap().to_string();
+        config.listen_fd = Some(listener.into_raw_fd());
+        config.control_fd = Some(control.into_raw_fd());
+        config.ephemeral = true;
+        config.difficulty = Some(1);
+        Node::start(config, sender).unwrap()
+    }
+
+    #[test]
+    fn test_sync() {
+        let (sender, mut receiver) = unbounded_channel();
+        let mut node = ephemeral_node(sender);
+        // a peer hanging up on every request, counting the downloads starting
+        let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = peer.local_addr().unwrap().to_string();
+        node.peers
+            .insert(PeerInfo::new("peer".to_owned(), addr).unwrap());
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let counted = downloads.clone();
+        thread::spawn(move || {
+            for stream in peer.incoming() {
+                let request = Deserializer::from_reader(stream.unwrap())
+                    .into_iter::<Request>()
+                    .next();
+                if let Some(Ok(Request::GetHeaders(_, 0, _))) = request {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        let (first, mut synced) = oneshot::channel();
+        let (second, mut coalesced) = oneshot::channel();
+        node.sync(Some(first));
+        node.sync(Some(second));
+        assert!(matches!(
+            coalesced.try_recv(),
+            Ok(Ok(CommandOutput::AlreadySyncing))
+        ));
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+        while let Some(event) = runtime.block_on(receiver.recv()) {
+            if let Event::Synced(chains) = event {
+                node.finish_sync(chains);
+                break;
+            }
+        }
+        assert!(matches!(
+            synced.try_recv(),
+            Ok(Ok(CommandOutput::Synced(false)))
+        ));
+        // only the first sync downloaded
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+
+        // a download panicking still ends the sync
+        let (sender, mut receiver) = unbounded_channel();
+        let guard = SyncGuard::new(sender);
+        assert!(thread::spawn(move || {
+            let _guard = guard;
+            panic!("the download failed");
+        })
+        .join()
+        .is_err());
+        assert!(matches!(receiver.try_recv(), Ok(Event::Synced(chains)) if chains.is_empty()));
+    }
+
+    #[test]
+    fn test_diffusion() {
+        let (sender, _receiver) = unbounded_channel();
+        let mut node = ephemeral_node(sender);
+        let listening = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listening.local_addr().unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        for (name, addr) in &[("open", open), ("closed", closed)] {
+            node.peers
+                .insert(PeerInfo::new(name.to_string(), addr.to_string()).unwrap());
+        }
+
+        // delayed, neither peer is known to have got it
+        node.diffusion = Some(Duration::from_millis(10));
+        let transaction = Transaction::new("alice", "bob", 1);
+        node.wallet.record(&transaction);
+        let delayed = node.submit(transaction);
+        assert!(delayed.broadcast.delivered.is_empty() && delayed.broadcast.failed.is_empty());
+        assert_eq!(delayed.broadcast.scheduled.len(), 2);
+        assert!(delayed.to_string().ends_with("scheduled for 2 of 2 peers"));
+        let sent = node.wallet.get(&delayed.id, &node.chain).unwrap();
+        assert!(sent.to_string().ends_with("scheduled for 2 of 2 peers"));
+
+        node.diffusion = None;
+        let relayed = node.submit(Transaction::new("alice", "carol", 1));
+        assert_eq!(relayed.broadcast.delivered, [open]);
+        assert!(relayed.broadcast.scheduled.is_empty());
+        assert_eq!(relayed.broadcast.failed.len(), 1);
+        assert!(relayed.to_string().contains("relayed to 1 of 2 peers"));
     }
 }
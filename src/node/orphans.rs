@@ -0,0 +1,108 @@
+//! Blocks whose parent has not arrived yet
+//!
+//! A block can overtake its parent on the way, or come in while the node missed a few blocks.
+//! Such an orphan is kept until its parent is added, and attached right after it, instead of
+//! being dropped and fetched again by a sync.
+
+use super::*;
+use std::collections::VecDeque;
+use std::mem;
+
+/// How many orphans are kept at most, the oldest go first.
+const ORPHAN_CAPACITY: usize = 100;
+
+pub struct OrphanPool {
+    // oldest first
+    blocks: VecDeque<Block>,
+    capacity: usize,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        OrphanPool::new(ORPHAN_CAPACITY)
+    }
+}
+
+impl OrphanPool {
+    pub fn new(capacity: usize) -> Self {
+        OrphanPool {
+            blocks: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Keeps `block` until its parent arrives, returning `false` if it is kept already or its
+    /// transactions do not match its hash.
+    pub fn insert(&mut self, block: Block) -> bool {
+        if !block.has_valid_merkle_root() || self.contains(&block.get_hash()) {
+            return false;
+        }
+        if self.blocks.len() >= self.capacity {
+            if let Some(evicted) = self.blocks.pop_front() {
+                debug!(
+                    target: CONSENSUS,
+                    "Too many orphan blocks, dropping block {}",
+                    evicted.get_index()
+                );
+            }
+        }
+        self.blocks.push_back(block);
+        true
+    }
+
+    /// Whether the block with `hash` is kept.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.blocks.iter().any(|orphan| orphan.get_hash() == hash)
+    }
+
+    /// Removes and returns the orphans linking to the block with `hash`, oldest first.
+    pub fn take_children(&mut self, hash: &str) -> Vec<Block> {
+        let (children, others) = mem::take(&mut self.blocks)
+            .into_iter()
+            .partition(|orphan| orphan.get_previous_hash() == hash);
+        self.blocks = others;
+        children.into()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orphan_pool() {
+        let mut chain = Blockchain::with_difficulty(0);
+        let genesis = chain.last_block().get_hash();
+        let first = chain.mine_new_block().clone();
+        let second = chain.mine_new_block().clone();
+        let mut other = Blockchain::with_difficulty(0);
+        other
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+            .unwrap();
+        let rival = other.mine_new_block().clone();
+
+        let mut pool = OrphanPool::new(2);
+        assert!(pool.insert(second.clone()));
+        assert!(!pool.insert(second.clone()));
+        assert!(pool.contains(&second.get_hash()));
+        assert!(pool.insert(rival.clone()));
+        let children = pool.take_children(&genesis);
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].get_hash(), rival.get_hash());
+        assert_eq!(pool.len(), 1);
+
+        // the oldest goes first
+        pool.insert(rival);
+        pool.insert(first);
+        assert!(pool.take_children(second.get_previous_hash()).is_empty());
+        assert_eq!(pool.len(), 2);
+    }
+}
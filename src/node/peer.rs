@@ -1,7 +1,6 @@
 use super::*;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
-use uuid::Uuid;
 
 #[derive(Hash, Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct PeerInfo {
@@ -10,13 +9,22 @@ pub struct PeerInfo {
 }
 
 impl PeerInfo {
-    pub fn new(address: String) -> Result<Self> {
+    /// The info of the node with `id`, usually its public key, listening at `address`.
+    pub fn new(id: String, address: String) -> Result<Self> {
         Ok(PeerInfo {
-            id: Uuid::new_v4().to_string(),
+            id,
             address: parse_addr(address)?,
         })
     }
 
+    /// The same node under another id, after it rotated its key.
+    pub fn with_id(&self, id: &str) -> Self {
+        PeerInfo {
+            id: id.to_owned(),
+            address: self.address,
+        }
+    }
+
     pub fn get_address(&self) -> SocketAddr {
         self.address
     }
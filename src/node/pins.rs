@@ -0,0 +1,96 @@
+//! The keys of peers, pinned to their addresses
+//!
+//! A peer is known by the address it listens at, and tells its public key as its id. The first
+//! handshake with the peer at an address pins the key it tells, trusting it on first use. A peer
+//! telling another key from then on may be an impostor: it is refused until the key is pinned
+//! again with `repin`, unless the peer rotated its key, which its old key signs.
+
+use super::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+#[derive(Default)]
+pub struct KeyPins {
+    keys: HashMap<SocketAddr, String>,
+}
+
+impl KeyPins {
+    /// The pins of the `peers` shaken hands with before.
+    pub fn new(peers: Vec<PeerInfo>) -> Self {
+        KeyPins {
+            keys: peers
+                .into_iter()
+                .map(|peer| (peer.get_address(), peer.get_id().to_owned()))
+                .collect(),
+        }
+    }
+
+    /// The key pinned to the address of `peer`, if it is not the key of `peer`.
+    pub fn conflict(&self, peer: &PeerInfo) -> Option<&str> {
+        self.keys
+            .get(&peer.get_address())
+            .map(String::as_str)
+            .filter(|pinned| *pinned != peer.get_id())
+    }
+
+    /// Pins the key of `peer` to its address, returning `false` if a key is pinned already.
+    pub fn pin(&mut self, peer: &PeerInfo) -> bool {
+        if self.keys.contains_key(&peer.get_address()) {
+            return false;
+        }
+        self.keys
+            .insert(peer.get_address(), peer.get_id().to_owned());
+        true
+    }
+
+    /// Pins `new` wherever `old` is pinned, after a peer rotated its key. Returns the addresses
+    /// of the pins moved.
+    pub fn rotate(&mut self, old: &str, new: &str) -> Vec<SocketAddr> {
+        let mut moved = Vec::new();
+        for (address, key) in self.keys.iter_mut() {
+            if key == old {
+                *key = new.to_owned();
+                moved.push(*address);
+            }
+        }
+        moved
+    }
+
+    /// Forgets the key pinned to `address`, returning it.
+    pub fn unpin(&mut self, address: SocketAddr) -> Option<String> {
+        self.keys.remove(&address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_pins() {
+        let peer = |id: &str| PeerInfo::new(id.to_owned(), "127.0.0.1:4000".to_owned()).unwrap();
+        let mut pins = KeyPins::default();
+        assert_eq!(pins.conflict(&peer("alice")), None);
+        assert!(pins.pin(&peer("alice")));
+        // another key at the same address is refused, whatever it claims
+        assert!(!pins.pin(&peer("mallory")));
+        assert_eq!(pins.conflict(&peer("alice")), None);
+        assert_eq!(pins.conflict(&peer("mallory")), Some("alice"));
+        let elsewhere = PeerInfo::new("mallory".to_owned(), "127.0.0.1:4001".to_owned()).unwrap();
+        assert_eq!(pins.conflict(&elsewhere), None);
+
+        // a rotated key takes the place of the old one
+        assert_eq!(
+            pins.rotate("alice", "alice2"),
+            vec![peer("alice2").get_address()]
+        );
+        assert_eq!(pins.conflict(&peer("alice")), Some("alice2"));
+
+        let mut pins = KeyPins::new(vec![peer("alice2")]);
+        assert_eq!(
+            pins.unpin(peer("bob").get_address()),
+            Some("alice2".to_owned())
+        );
+        assert!(pins.pin(&peer("bob")));
+    }
+}
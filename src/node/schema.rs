@@ -0,0 +1,703 @@
+//! JSON Schema of the wire format
+//!
+//! Every message is a single JSON value, as serde writes `Request` and `Response`: enums are
+//! externally tagged (`{"Variant": payload}`, with tuple payloads as arrays) and structs are
+//! objects. Whenever a wire type changes, its definition here must change with it, the tests
+//! check the serialized messages against the schema, and that the schema defines exactly the
+//! variants serde derives for `Request`, `Response` and `BlockRejection`, each with a sample.
+
+use serde_json::{json, Map, Value};
+
+/// The JSON Schema (draft 7) of `Request`, `Response` and the types they carry.
+pub fn wire_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "nb wire format",
+        "description": "A message sent over a TCP connection between nodes, or a client and a node",
+        "oneOf": [reference("Request"), reference("Response")],
+        "definitions": {
+            "Request": tagged(vec![
+                ("Hello", vec![reference("Greeting")]),
+                ("HowAreYou", vec![reference("PeerInfo")]),
+                ("NewTransaction", vec![reference("PeerInfo"), reference("Transaction")]),
+                ("NewBlock", vec![reference("PeerInfo"), reference("Block")]),
+                ("NewPeer", vec![reference("PeerInfo"), reference("PeerInfo")]),
+                ("Telemetry", vec![reference("PeerInfo"), reference("BlockSighting")]),
+                ("Announce", vec![reference("PeerInfo"), reference("Inventory")]),
+                ("Fetch", vec![reference("PeerInfo"), reference("Inventory")]),
+                (
+                    "RotateKey",
+                    vec![reference("PeerInfo"), reference("PeerInfo"), reference("KeyRotation")],
+                ),
+                ("GetMempool", vec![reference("PeerInfo"), unsigned()]),
+                ("GetHeaders", vec![reference("PeerInfo"), unsigned(), unsigned()]),
+                ("GetBalance", vec![string(), optional(unsigned())]),
+                ("GetStats", vec![optional(unsigned())]),
+                ("GetChainStats", vec![]),
+                ("GetTransaction", vec![string()]),
+                (
+                    "GetBlock",
+                    vec![tagged(vec![("Index", vec![unsigned()]), ("Hash", vec![string()])])],
+                ),
+                ("SubmitBlock", vec![reference("Block")]),
+                ("ValidateBlock", vec![reference("Block")]),
+            ]),
+            "Response": tagged(vec![
+                ("Ack", vec![reference("PeerInfo")]),
+                ("MyBlocks", vec![reference("PeerInfo"), array(reference("Block"))]),
+                ("Balance", vec![optional(integer())]),
+                ("Stats", vec![reference("Throughput")]),
+                ("ChainStats", vec![reference("ChainStats")]),
+                ("Block", vec![optional(reference("Block"))]),
+                ("Transaction", vec![optional(reference("TransactionInclusion"))]),
+                ("IncompatibleChain", vec![string()]),
+                ("MempoolTxs", vec![reference("PeerInfo"), array(reference("Transaction"))]),
+                ("Headers", vec![reference("PeerInfo"), array(reference("BlockHeader"))]),
+                ("KeyNotPinned", vec![string()]),
+                ("Error", vec![reference("BlockRejection")]),
+            ]),
+            "BlockRejection": tagged(vec![
+                ("BadParent", vec![]),
+                ("BadIndex", vec![object(vec![("expected", unsigned())])]),
+                ("ForeignChain", vec![]),
+                ("CheckpointMismatch", vec![object(vec![("hash", string())])]),
+                ("UnsupportedVersion", vec![]),
+                ("BadTimestamp", vec![]),
+                ("OversizedBlock", vec![]),
+                ("BadDifficulty", vec![object(vec![("expected", unsigned())])]),
+                ("BadProof", vec![]),
+                ("BadSeal", vec![]),
+                ("BadMerkleRoot", vec![]),
+                ("DuplicateTransaction", vec![object(vec![("id", string())])]),
+                ("InvalidTxSignature", vec![object(vec![("id", string())])]),
+                (
+                    "InvalidTransaction",
+                    vec![object(vec![("id", string()), ("reason", string())])],
+                ),
+                ("ConfirmedTransaction", vec![object(vec![("id", string())])]),
+                ("ExpiredTransaction", vec![object(vec![("id", string())])]),
+                ("DoubleSpend", vec![object(vec![("id", string()), ("first", string())])]),
+                (
+                    "MisorderedTransaction",
+                    vec![object(vec![("id", string()), ("later", string())])],
+                ),
+                ("InvalidCoinbase", vec![]),
+                ("Overspend", vec![]),
+                ("BadMinerSignature", vec![]),
+                ("RewardNotToMiner", vec![object(vec![("id", string())])]),
+                ("UnsignedBlock", vec![]),
+            ]),
+            "Block": with_optional(
+                object(vec![
+                    ("header", reference("BlockHeader")),
+                    ("transactions", array(reference("Transaction"))),
+                ]),
+                "seal",
+                object(vec![("authority", string()), ("signature", string())]),
+            ),
+            "BlockHeader": with_optional(with_optional(with_optional(with_optional(with_optional(with_optional(with_optional(with_optional(with_optional(with_optional(
+                object(vec![
+                    ("index", unsigned()),
+                    ("timestamp", unsigned()),
+                    ("previous_hash", string()),
+                    ("merkle_root", string()),
+                    ("difficulty_target", unsigned()),
+                    ("nonce", unsigned()),
+                ]),
+                "reward_schedule",
+                with_optional(
+                    object(vec![
+                        ("initial_reward", integer()),
+                        ("halving_interval", unsigned()),
+                        ("max_supply", optional(integer())),
+                    ]),
+                    "coinbase_maturity",
+                    unsigned(),
+                ),
+            ), "transaction_model", json!({"enum": ["Account", "Utxo"]})), "name", string()), "address_prefix", string()), "chain_id", string()), "encoding", unsigned()), "version", unsigned()), "signals", unsigned()), "miner", string()), "miner_signature", string()),
+            "Transaction": (vec![
+                ("fee", integer()),
+                ("nonce", unsigned()),
+                (
+                    "expiry",
+                    tagged(vec![("Height", vec![unsigned()]), ("Time", vec![unsigned()])]),
+                ),
+                ("replaces", string()),
+                (
+                    "utxo",
+                    object(vec![
+                        (
+                            "inputs",
+                            array(object(vec![("transaction", string()), ("index", unsigned())])),
+                        ),
+                        (
+                            "outputs",
+                            array(object(vec![("recipient", string()), ("amount", integer())])),
+                        ),
+                    ]),
+                ),
+                ("script", array(reference("Op"))),
+                ("asset_id", string()),
+                ("issuance", object(vec![("max_supply", integer())])),
+                ("public_key", string()),
+                ("signature", string()),
+            ]
+            .into_iter()
+            .fold(
+                object(vec![
+                    ("id", string()),
+                    ("sender", string()),
+                    ("recipient", string()),
+                    ("amount", integer()),
+                ]),
+                |object, (name, schema)| with_optional(object, name, schema),
+            )),
+            "Op": tagged(
+                [
+                    "Dup",
+                    "Drop",
+                    "Swap",
+                    "Equal",
+                    "EqualVerify",
+                    "Less",
+                    "Greater",
+                    "Not",
+                    "Verify",
+                    "Sha256",
+                    "CheckSig",
+                    "CheckSigVerify",
+                ]
+                .iter()
+                .map(|&name| (name, vec![]))
+                .chain(vec![("Push", vec![string()])])
+                .collect(),
+            ),
+            "TransactionInclusion": object(vec![
+                ("transaction", reference("Transaction")),
+                ("height", optional(unsigned())),
+                ("confirmations", unsigned()),
+            ]),
+            "Greeting": with_optional(with_optional(
+                object(vec![
+                    ("id", string()),
+                    ("address", json!({"type": "string", "description": "IP:PORT"})),
+                ]),
+                "chain",
+                object(vec![
+                    ("genesis", string()),
+                    ("difficulty", unsigned()),
+                    (
+                        "reward_schedule",
+                        with_optional(
+                            object(vec![
+                                ("initial_reward", integer()),
+                                ("halving_interval", unsigned()),
+                                ("max_supply", optional(integer())),
+                            ]),
+                            "coinbase_maturity",
+                            unsigned(),
+                        ),
+                    ),
+                    ("transaction_model", json!({"enum": ["Account", "Utxo"]})),
+                    (
+                        "consensus",
+                        json!({"oneOf": [
+                            {"enum": ["ProofOfWork"]},
+                            object(vec![("ProofOfAuthority", array(string()))]),
+                            object(vec![("ProofOfStake", array(string()))]),
+                        ]}),
+                    ),
+                ]),
+            ), "chain_id", string()),
+            "PeerInfo": object(vec![
+                ("id", string()),
+                ("address", json!({"type": "string", "description": "IP:PORT"})),
+            ]),
+            "KeyRotation": object(vec![
+                ("old", string()),
+                ("new", string()),
+                ("at", unsigned()),
+                ("old_signature", string()),
+                ("new_signature", string()),
+            ]),
+            "BlockSighting": object(vec![
+                ("hash", string()),
+                ("index", unsigned()),
+                ("seen_at", unsigned()),
+                ("strategy", json!({"enum": ["Flood", "RandomSubset", "PushPull"]})),
+                ("traffic", object(vec![("messages", unsigned()), ("bytes", unsigned())])),
+            ]),
+            "Inventory": tagged(vec![
+                ("Block", vec![unsigned(), string()]),
+                ("Transaction", vec![string()]),
+            ]),
+            "Throughput": object(vec![
+                ("window_secs", unsigned()),
+                ("blocks", unsigned()),
+                ("transactions", unsigned()),
+                ("tps", json!({"type": "number"})),
+                ("blocks_per_hour", json!({"type": "number"})),
+            ]),
+            "ChainStats": object(vec![
+                ("height", unsigned()),
+                ("transactions", unsigned()),
+                ("mempool", unsigned()),
+                ("difficulty", unsigned()),
+                ("hashrate", optional(json!({"type": "number"}))),
+                ("block_interval", optional(unsigned())),
+                ("peers", unsigned()),
+            ]),
+        }
+    })
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/definitions/{}", name) })
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn integer() -> Value {
+    json!({"type": "integer"})
+}
+
+fn unsigned() -> Value {
+    json!({"type": "integer", "minimum": 0})
+}
+
+fn optional(schema: Value) -> Value {
+    json!({"oneOf": [schema, {"type": "null"}]})
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    let required: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+    let properties: Map<String, Value> = fields
+        .into_iter()
+        .map(|(name, schema)| (name.to_owned(), schema))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Adds the field `name`, which may be left out, to the `object` schema.
+fn with_optional(mut object: Value, name: &str, schema: Value) -> Value {
+    object["properties"][name] = schema;
+    object
+}
+
+/// An externally tagged enum whose variants carry the given fields.
+fn tagged(variants: Vec<(&str, Vec<Value>)>) -> Value {
+    let variants: Vec<Value> = variants
+        .into_iter()
+        .map(|(name, mut fields)| {
+            // unit variants are written as their bare name
+            if fields.is_empty() {
+                return json!({ "enum": [name] });
+            }
+            let payload = if fields.len() == 1 {
+                fields.remove(0)
+            } else {
+                json!({
+                    "type": "array",
+                    "minItems": fields.len(),
+                    "maxItems": fields.len(),
+                    "items": fields,
+                })
+            };
+            object(vec![(name, payload)])
+        })
+        .collect();
+    json!({ "oneOf": variants })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use serde::de::{self, DeserializeOwned, Visitor};
+    use serde::{forward_to_deserialize_any, Serialize};
+    use std::collections::BTreeSet;
+    use std::fmt;
+    use std::time::Duration;
+
+    // just enough of JSON Schema to check the definitions above
+    fn conforms(schema: &Value, value: &Value, root: &Value) -> bool {
+        if let Some(path) = schema["$ref"].as_str() {
+            let name = path.trim_start_matches("#/definitions/");
+            return conforms(&root["definitions"][name], value, root);
+        }
+        if let Some(options) = schema["oneOf"].as_array() {
+            return options
+                .iter()
+                .filter(|option| conforms(option, value, root))
+                .count()
+                == 1;
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            return options.contains(value);
+        }
+        match schema["type"].as_str() {
+            Some("null") => value.is_null(),
+            Some("string") => value.is_string(),
+            Some("number") => value.is_number(),
+            Some("integer") if schema["minimum"] == 0 => value.is_u64(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("array") => {
+                let values = match value.as_array() {
+                    Some(values) => values,
+                    None => return false,
+                };
+                match &schema["items"] {
+                    Value::Array(items) => {
+                        items.len() == values.len()
+                            && items
+                                .iter()
+                                .zip(values)
+                                .all(|(item, value)| conforms(item, value, root))
+                    }
+                    item => values.iter().all(|value| conforms(item, value, root)),
+                }
+            }
+            Some("object") => {
+                let fields = match value.as_object() {
+                    Some(fields) => fields,
+                    None => return false,
+                };
+                let properties = schema["properties"].as_object().unwrap();
+                let required = schema["required"].as_array().unwrap();
+                required
+                    .iter()
+                    .all(|name| fields.contains_key(name.as_str().unwrap()))
+                    && fields.iter().all(|(name, field)| {
+                        properties
+                            .get(name)
+                            .is_some_and(|property| conforms(property, field, root))
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    // the variants serde derives for an enum, which it hands the deserializer
+    #[derive(Debug)]
+    struct Variants(&'static [&'static str]);
+
+    impl fmt::Display for Variants {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+
+    impl std::error::Error for Variants {}
+
+    impl de::Error for Variants {
+        fn custom<T: fmt::Display>(_msg: T) -> Self {
+            Variants(&[])
+        }
+    }
+
+    struct EnumOf;
+
+    impl<'de> de::Deserializer<'de> for EnumOf {
+        type Error = Variants;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _visitor: V,
+        ) -> std::result::Result<V::Value, Variants> {
+            Err(Variants(&[]))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> std::result::Result<V::Value, Variants> {
+            Err(Variants(variants))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+            ignored_any
+        }
+    }
+
+    /// The names of the variants of the enum `T` on the wire, as derived.
+    fn variants<T: DeserializeOwned>() -> BTreeSet<String> {
+        match T::deserialize(EnumOf) {
+            Err(Variants(variants)) => variants.iter().map(|&v| v.to_owned()).collect(),
+            Ok(_) => unreachable!("no value is deserialized"),
+        }
+    }
+
+    /// The names of the variants the schema defines for the enum `definition`.
+    fn schema_variants(definition: &str) -> BTreeSet<String> {
+        wire_schema()["definitions"][definition]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|option| match option["enum"].get(0) {
+                Some(name) => name.as_str().unwrap().to_owned(),
+                None => option["properties"]
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .next()
+                    .unwrap()
+                    .clone(),
+            })
+            .collect()
+    }
+
+    /// The name of the variant an externally tagged enum is written as.
+    fn tag(value: &Value) -> Option<&str> {
+        match value {
+            Value::String(name) => Some(name),
+            Value::Object(fields) if fields.len() == 1 => fields.keys().next().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// Checks messages against the schema, remembering which variants conform.
+    #[derive(Default)]
+    struct Checker {
+        seen: BTreeSet<String>,
+        rejections: BTreeSet<String>,
+    }
+
+    impl Checker {
+        fn check(&mut self, message: impl Serialize) -> bool {
+            let value = serde_json::to_value(message).unwrap();
+            let schema = wire_schema();
+            if !conforms(&schema, &value, &schema) {
+                return false;
+            }
+            self.seen.extend(tag(&value).map(str::to_owned));
+            if let Some(rejection) = value.get("Error").and_then(tag) {
+                self.rejections.insert(rejection.to_owned());
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_wire_schema() {
+        let mut checker = Checker::default();
+        let peer = PeerInfo::new("node".to_owned(), "127.0.0.1:4000".to_owned()).unwrap();
+        let mut chain = Blockchain::with_difficulty(0);
+        let transaction = Transaction::new("alice", "bob", 5);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 5))
+            .unwrap();
+        chain.add_new_transaction(&transaction).unwrap();
+        chain.mine_new_block();
+        let block = chain.last_block().clone();
+        let sighting = BlockSighting {
+            hash: block.get_hash(),
+            index: 1,
+            seen_at: 42,
+            strategy: GossipStrategy::PushPull,
+            traffic: Traffic::default(),
+        };
+
+        let greeting = Greeting {
+            peer: peer.clone(),
+            chain_id: Some(chain.chain_id()),
+            chain: Some(ChainParams::of(
+                &chain,
+                &ConsensusMode::ProofOfAuthority(vec!["key".to_owned()]),
+            )),
+        };
+        assert!(checker.check(Request::Hello(greeting)));
+        assert!(checker.check(Request::Hello(Greeting {
+            peer: peer.clone(),
+            chain_id: None,
+            chain: None
+        })));
+        assert!(checker.check(Request::NewTransaction(peer.clone(), transaction.clone())));
+        assert!(checker.check(Request::NewTransaction(
+            peer.clone(),
+            Transaction::new("alice", "bob", 1).with_expiry(Expiry::Time(42))
+        )));
+        let key = NodeKey::generate();
+        assert!(checker.check(Request::NewTransaction(
+            peer.clone(),
+            Transaction::new(&key.public_key(), "bob", 1).sign(&key)
+        )));
+        assert!(checker.check(Request::NewBlock(peer.clone(), block)));
+        let mut signalling = Blockchain::with_difficulty(0);
+        signalling.set_signals(&[0, 31]).unwrap();
+        assert!(checker.check(Request::NewBlock(
+            peer.clone(),
+            signalling.mine_new_block().clone()
+        )));
+        let mut authority = Blockchain::with_difficulty(0);
+        let key = NodeKey::generate();
+        let mode = ConsensusMode::ProofOfAuthority(vec![key.public_key()]);
+        authority.set_consensus(mode.engine(Some(key)));
+        let signed = authority.mine_new_block().clone();
+        assert!(checker.check(Request::NewBlock(peer.clone(), signed)));
+        assert!(checker.check(Request::NewPeer(peer.clone(), peer.clone())));
+        assert!(checker.check(Request::HowAreYou(peer.clone())));
+        assert!(checker.check(Response::Ack(peer.clone())));
+        assert!(checker.check(Request::Telemetry(peer.clone(), sighting)));
+        assert!(checker.check(Request::Announce(
+            peer.clone(),
+            Inventory::Block(1, "abc".to_owned())
+        )));
+        assert!(checker.check(Request::Fetch(
+            peer.clone(),
+            Inventory::Transaction("abc".to_owned())
+        )));
+        let rotation = KeyRotation::new(&NodeKey::generate(), &NodeKey::generate(), 42);
+        assert!(checker.check(Request::RotateKey(peer.clone(), peer.clone(), rotation)));
+        assert!(checker.check(Request::GetMempool(peer.clone(), 10)));
+        assert!(checker.check(Request::GetHeaders(peer.clone(), 0, 10)));
+        assert!(checker.check(Response::KeyNotPinned("key".to_owned())));
+        assert!(checker.check(Request::SubmitBlock(chain.last_block().clone())));
+        assert!(checker.check(Request::ValidateBlock(chain.last_block().clone())));
+        let id = || "a".to_owned();
+        for rejection in vec![
+            BlockRejection::BadParent,
+            BlockRejection::BadIndex { expected: 1 },
+            BlockRejection::ForeignChain,
+            BlockRejection::CheckpointMismatch { hash: id() },
+            BlockRejection::UnsupportedVersion,
+            BlockRejection::BadTimestamp,
+            BlockRejection::OversizedBlock,
+            BlockRejection::BadDifficulty { expected: 1 },
+            BlockRejection::BadProof,
+            BlockRejection::BadSeal,
+            BlockRejection::BadMerkleRoot,
+            BlockRejection::DuplicateTransaction { id: id() },
+            BlockRejection::InvalidTxSignature { id: id() },
+            BlockRejection::InvalidTransaction {
+                id: id(),
+                reason: "b".to_owned(),
+            },
+            BlockRejection::ConfirmedTransaction { id: id() },
+            BlockRejection::ExpiredTransaction { id: id() },
+            BlockRejection::DoubleSpend {
+                id: id(),
+                first: "b".to_owned(),
+            },
+            BlockRejection::MisorderedTransaction {
+                id: id(),
+                later: "b".to_owned(),
+            },
+            BlockRejection::InvalidCoinbase,
+            BlockRejection::Overspend,
+            BlockRejection::BadMinerSignature,
+            BlockRejection::RewardNotToMiner { id: id() },
+            BlockRejection::UnsignedBlock,
+        ] {
+            assert!(checker.check(Response::Error(rejection)));
+        }
+        let miner = NodeKey::generate();
+        let mut signed = chain.last_block().clone();
+        signed.sign_miner(&miner, &miner.public_key());
+        assert!(checker.check(Request::SubmitBlock(signed)));
+        assert!(checker.check(Response::Headers(peer.clone(), chain.get_headers(0, 10))));
+        assert!(checker.check(Request::GetBalance("bob".to_owned(), Some(1))));
+        assert!(checker.check(Request::GetStats(None)));
+        assert!(checker.check(Request::GetChainStats));
+        assert!(checker.check(Response::ChainStats(ChainStats::measure(&chain, 1))));
+        assert!(checker.check(Request::GetBlock(BlockId::Index(1))));
+        assert!(checker.check(Request::GetBlock(BlockId::Hash("abc".to_owned()))));
+        assert!(checker.check(Response::Block(Some(Box::new(chain.last_block().clone())))));
+        assert!(checker.check(Response::Block(None)));
+        assert!(checker.check(Request::GetTransaction("abc".to_owned())));
+        assert!(checker.check(Response::Transaction(
+            chain.find_transaction(transaction.get_id()).map(Box::new)
+        )));
+        assert!(checker.check(Response::Transaction(None)));
+        assert!(checker.check(Response::MyBlocks(peer.clone(), chain.get_blocks())));
+        assert!(checker.check(Response::IncompatibleChain("reason".to_owned())));
+        assert!(checker.check(Response::MempoolTxs(
+            peer.clone(),
+            vec![Transaction::new("alice", "bob", 1).with_fee(1)]
+        )));
+        let scripted = Transaction::new("alice", "bob", 1).with_script(vec![
+            crate::Op::Push("1".to_owned()),
+            crate::Op::Dup,
+            crate::Op::Equal,
+        ]);
+        assert!(checker.check(Response::MempoolTxs(peer.clone(), vec![scripted])));
+        let tokens = vec![
+            Transaction::issue("alice", "bob", "gold", 5, 100).with_fee(1),
+            Transaction::new("bob", "carol", 2).with_asset("gold"),
+        ];
+        assert!(checker.check(Response::MempoolTxs(peer.clone(), tokens)));
+        let scheduled = Blockchain::with_reward_schedule(
+            0,
+            RewardSchedule {
+                coinbase_maturity: 10,
+                ..RewardSchedule::default()
+            },
+        );
+        assert!(checker.check(Response::MyBlocks(peer.clone(), scheduled.get_blocks())));
+        let named = crate::GenesisSpec {
+            name: Some("testnet".to_owned()),
+            allocations: vec![crate::Allocation {
+                address: "alice".to_owned(),
+                amount: 5,
+            }],
+            ..crate::GenesisSpec::default()
+        };
+        assert!(checker.check(Response::MyBlocks(peer.clone(), vec![named.block()])));
+        let prefixed = crate::GenesisSpec {
+            address_prefix: Some("nb".to_owned()),
+            allocations: vec![crate::Allocation {
+                address: crate::Address::new("nb", &crate::NodeKey::generate().public_key())
+                    .unwrap()
+                    .to_string(),
+                amount: 5,
+            }],
+            ..named
+        };
+        assert!(checker.check(Response::MyBlocks(peer.clone(), vec![prefixed.block()])));
+        let mut utxo = Blockchain::with_transaction_model(0, TransactionModel::Utxo);
+        let reward = utxo.new_transaction(REWARD_SENDER, "alice", 5, 0).unwrap();
+        utxo.add_new_transaction(&reward).unwrap();
+        utxo.mine_new_block();
+        let payment = utxo.new_transaction("alice", "bob", 2, 1).unwrap();
+        utxo.add_new_transaction(&payment).unwrap();
+        utxo.mine_new_block();
+        assert!(checker.check(Response::MyBlocks(peer.clone(), utxo.get_blocks())));
+        assert!(checker.check(Response::Balance(Some(-5))));
+        assert!(checker.check(Response::Stats(Throughput::measure(
+            &chain,
+            Duration::from_secs(1),
+            0
+        ))));
+
+        // every variant conforms, and the schema defines no others
+        let mut messages = variants::<Request>();
+        messages.extend(variants::<Response>());
+        assert_eq!(checker.seen, messages);
+        assert_eq!(schema_variants("Request"), variants::<Request>());
+        assert_eq!(schema_variants("Response"), variants::<Response>());
+        assert_eq!(checker.rejections, variants::<BlockRejection>());
+        assert_eq!(
+            schema_variants("BlockRejection"),
+            variants::<BlockRejection>()
+        );
+
+        // messages off the schema are caught
+        assert!(!checker.check(json!({"Hello": {"id": "x"}})));
+        assert!(!checker.check(json!({"GetBalance": ["bob"]})));
+        assert!(!checker.check(json!({"Ack": [peer]})));
+    }
+}
@@ -0,0 +1,238 @@
+//! Rolling throughput of the chain, and detection of a network that stopped confirming
+//!
+//! Throughput is computed from the timestamps of the blocks, i.e. the miners' clocks.
+
+use super::*;
+use crate::blockchain::RETARGET_INTERVAL;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// What the chain confirmed during the last `window_secs` seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Throughput {
+    pub window_secs: u64,
+    pub blocks: u64,
+    // mining rewards are not counted
+    pub transactions: u64,
+    pub tps: f64,
+    pub blocks_per_hour: f64,
+}
+
+impl Throughput {
+    /// Measures the blocks mined during the `window` before `now`, in milliseconds.
+    pub fn measure(chain: &Blockchain, window: Duration, now: u128) -> Self {
+        let since = now.saturating_sub(window.as_millis());
+        let (mut blocks, mut transactions) = (0, 0);
+        // the genesis block was not mined
+        for index in (1..chain.len() as u64).rev() {
+            let block = match chain.get_block_by_index(index) {
+                Some(block) => block,
+                None => break,
+            };
+            if block.get_timestamp() < since {
+                break;
+            }
+            blocks += 1;
+            transactions += confirmed(&block) as u64;
+        }
+        let secs = window.as_secs_f64().max(1.0);
+        Throughput {
+            window_secs: window.as_secs(),
+            blocks,
+            transactions,
+            tps: transactions as f64 / secs,
+            blocks_per_hour: blocks as f64 * 3600.0 / secs,
+        }
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "last {}s: {} blocks ({:.2} per hour), {} transactions ({:.3} per second)",
+                self.window_secs, self.blocks, self.blocks_per_hour, self.transactions, self.tps
+            )
+            .color(MSG_COLOR)
+        )
+    }
+}
+
+/// A snapshot of the chain and the node, for dashboards and scripts.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainStats {
+    // the index of the last block
+    pub height: u64,
+    // confirmed, mining rewards included
+    pub transactions: u64,
+    // pending transactions
+    pub mempool: usize,
+    // of the next block, in leading zero bits
+    pub difficulty: u32,
+    // hashes per second, `None` without recent blocks to estimate it from
+    pub hashrate: Option<f64>,
+    // the average time between recent blocks, in milliseconds
+    pub block_interval: Option<u64>,
+    pub peers: usize,
+}
+
+impl ChainStats {
+    /// Takes the stats of `chain`, estimating the rates from its last `RETARGET_INTERVAL` mined
+    /// blocks: the work their difficulties call for, over the time they took.
+    pub fn measure(chain: &Blockchain, peers: usize) -> Self {
+        let height = chain.len() as u64 - 1;
+        // the genesis block was not mined, and its timestamp is fixed
+        let first = height.saturating_sub(RETARGET_INTERVAL).max(1);
+        let (mut hashrate, mut block_interval) = (None, None);
+        if let (true, Some(start)) = (height > first, chain.get_block_by_index(first)) {
+            let span = chain.last_block().get_timestamp() - start.get_timestamp();
+            let work: f64 = (first + 1..=height)
+                .filter_map(|index| chain.get_block_by_index(index))
+                .map(|block| 2f64.powi(block.get_difficulty() as i32))
+                .sum();
+            block_interval = Some((span / u128::from(height - first)) as u64);
+            if span > 0 {
+                hashrate = Some(work * 1000.0 / span as f64);
+            }
+        }
+        ChainStats {
+            height,
+            transactions: chain.transaction_count(),
+            mempool: chain.pending_count(),
+            difficulty: chain.next_difficulty(),
+            hashrate,
+            block_interval,
+            peers,
+        }
+    }
+}
+
+impl fmt::Display for ChainStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "height {}, {} transactions, {} pending, difficulty {}, {} peers",
+                self.height, self.transactions, self.mempool, self.difficulty, self.peers
+            )
+            .color(MSG_COLOR)
+        )?;
+        match (self.block_interval, self.hashrate) {
+            (Some(interval), Some(hashrate)) => writeln!(
+                f,
+                "a block every {:.1}s, at {:.0} hashes per second",
+                interval as f64 / 1000.0,
+                hashrate
+            ),
+            (Some(interval), None) => {
+                writeln!(f, "a block every {:.1}s", interval as f64 / 1000.0)
+            }
+            (None, _) => writeln!(f, "too few blocks yet to estimate the hashrate"),
+        }
+    }
+}
+
+/// How many transactions other than the mining reward `block` confirms.
+fn confirmed(block: &Block) -> usize {
+    block
+        .get_transactions()
+        .iter()
+        .filter(|t| t.get_sender() != REWARD_SENDER)
+        .count()
+}
+
+/// Notices when transactions keep waiting while none get confirmed.
+pub struct StallDetector {
+    stall_after: Duration,
+    // since when transactions have been pending without interruption
+    waiting_since: Option<u128>,
+    warned: bool,
+}
+
+impl StallDetector {
+    pub fn new(stall_after: Duration) -> Self {
+        StallDetector {
+            stall_after,
+            waiting_since: None,
+            warned: false,
+        }
+    }
+
+    /// Checks for a stall at `now`, warning once per stall.
+    pub fn check(&mut self, chain: &Blockchain, now: u128) {
+        if chain.get_current_transactions().is_empty() {
+            self.waiting_since = None;
+            self.warned = false;
+            return;
+        }
+        let waiting_since = *self.waiting_since.get_or_insert(now);
+        // the clock may go back
+        if now.saturating_sub(waiting_since) < self.stall_after.as_millis() {
+            return;
+        }
+        let recent = Throughput::measure(chain, self.stall_after, now);
+        if recent.transactions > 0 {
+            self.warned = false;
+        } else if !self.warned {
+            warn!(
+                target: MEMPOOL,
+                "No transaction was confirmed during the last {}s while {} are pending, is the network stuck?",
+                self.stall_after.as_secs(),
+                chain.get_current_transactions().len()
+            );
+            self.warned = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput() {
+        let mut chain = Blockchain::new();
+        for (timestamp, transactions) in &[(1_000, 5), (61_000, 1), (91_000, 0)] {
+            // enough for alice to pay for all of them
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 5))
+                .unwrap();
+            for _ in 0..*transactions {
+                chain
+                    .add_new_transaction(&Transaction::new("alice", "bob", 1))
+                    .unwrap();
+            }
+            // proofs are not checked here, so skip the work
+            chain.create_new_block_at(0, chain.last_block().get_hash(), *timestamp);
+        }
+        let all = Throughput::measure(&chain, Duration::from_secs(100), 100_000);
+        assert_eq!((all.blocks, all.transactions), (3, 6));
+        let recent = Throughput::measure(&chain, Duration::from_secs(60), 100_000);
+        assert_eq!((recent.blocks, recent.transactions), (2, 1));
+        assert_eq!(recent.blocks_per_hour, 120.0);
+
+        // the first mined block starts the window, 90s before the last
+        let stats = ChainStats::measure(&chain, 2);
+        assert_eq!((stats.height, stats.transactions, stats.mempool), (3, 9, 0));
+        assert_eq!(stats.block_interval, Some(45_000));
+        let work = 2f64.powi(chain.last_block().get_difficulty() as i32) * 2.0;
+        assert_eq!(stats.hashrate, Some(work / 90.0));
+        let genesis = Blockchain::from_blocks(chain.get_blocks()[..2].to_vec());
+        assert_eq!(ChainStats::measure(&genesis, 0).hashrate, None);
+
+        let mut detector = StallDetector::new(Duration::from_secs(60));
+        chain
+            .add_new_transaction(&Transaction::new("alice", "bob", 1))
+            .unwrap();
+        detector.check(&chain, 100_000);
+        assert!(!detector.warned);
+        detector.check(&chain, 90_000);
+        assert!(!detector.warned);
+        detector.check(&chain, 170_000);
+        assert!(detector.warned);
+    }
+}
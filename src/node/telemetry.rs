@@ -0,0 +1,232 @@
+//! Experiment mode measuring how fast blocks propagate through the network
+//!
+//! Every node taking part reports the first time it sees each block to a designated collector
+//! node, which then computes the propagation delay of every block relative to its earliest
+//! sighting (normally the miner's). Delays are only meaningful if the clocks of the nodes agree,
+//! e.g. when all nodes run on one machine.
+
+use super::*;
+use crate::config::is_block_hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::net::SocketAddr;
+
+/// The first time a node saw a block.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockSighting {
+    pub hash: String,
+    pub index: u64,
+    pub seen_at: u128,
+    // what the reporting node has gossiped so far
+    pub strategy: GossipStrategy,
+    pub traffic: Traffic,
+}
+
+/// How many blocks the collector keeps the sightings of, dropping those of the lowest index
+/// first.
+const MAX_TRACKED_BLOCKS: usize = 1000;
+
+/// How many nodes the collector takes reports from, the first to report.
+const MAX_TRACKED_PEERS: usize = 256;
+
+pub struct Telemetry {
+    collector: SocketAddr,
+    is_collector: bool,
+    // hashes of the blocks we have already reported
+    seen: HashSet<String>,
+    // (index, hash) -> peer id -> first seen time, only filled on the collector
+    reports: BTreeMap<(u64, String), HashMap<String, u128>>,
+    // peer id -> latest reported gossip traffic, only filled on the collector
+    traffic: HashMap<String, (GossipStrategy, Traffic)>,
+}
+
+impl Telemetry {
+    pub fn new(collector: SocketAddr, my_info: &PeerInfo) -> Self {
+        Telemetry {
+            collector,
+            is_collector: collector == my_info.get_address(),
+            seen: HashSet::new(),
+            reports: BTreeMap::new(),
+            traffic: HashMap::new(),
+        }
+    }
+
+    pub fn get_collector(&self) -> SocketAddr {
+        self.collector
+    }
+
+    pub fn is_collector(&self) -> bool {
+        self.is_collector
+    }
+
+    /// Notes that `block` was just seen. Returns the sighting to report the first time only.
+    pub fn see_block(&mut self, block: &Block, gossip: &Gossip) -> Option<BlockSighting> {
+        let hash = block.get_hash();
+        if !self.seen.insert(hash.clone()) {
+            return None;
+        }
+        Some(BlockSighting {
+            hash,
+            index: block.get_index(),
+            seen_at: crate::blockchain::get_time(),
+            strategy: gossip.get_strategy(),
+            traffic: gossip.get_traffic(),
+        })
+    }
+
+    /// Records a sighting reported by `peer`. Only the first report per peer and block counts,
+    /// and only those of the first `MAX_TRACKED_PEERS` peers. Returns `false` if it is dropped.
+    pub fn record(&mut self, peer: &PeerInfo, sighting: BlockSighting) -> bool {
+        let id = peer.get_id();
+        if !is_block_hash(&sighting.hash)
+            || !self.traffic.contains_key(id) && self.traffic.len() >= MAX_TRACKED_PEERS
+        {
+            return false;
+        }
+        self.traffic
+            .insert(id.to_owned(), (sighting.strategy, sighting.traffic));
+        self.reports
+            .entry((sighting.index, sighting.hash))
+            .or_default()
+            .entry(id.to_owned())
+            .or_insert(sighting.seen_at);
+        while self.reports.len() > MAX_TRACKED_BLOCKS {
+            self.reports.pop_first();
+        }
+        true
+    }
+
+    /// Returns the propagation delays in milliseconds of every reported block, sorted ascending.
+    pub fn delays(&self) -> Vec<(u64, &str, Vec<u128>)> {
+        self.reports
+            .iter()
+            .map(|((index, hash), seen)| {
+                let first = seen.values().min().copied().unwrap_or_default();
+                let mut delays: Vec<u128> = seen.values().map(|t| t - first).collect();
+                delays.sort_unstable();
+                (*index, hash.as_str(), delays)
+            })
+            .collect()
+    }
+
+    /// Returns the propagation delays of every reported block and the traffic per node.
+    pub fn report(&self) -> PropagationReport {
+        PropagationReport {
+            blocks: self
+                .delays()
+                .into_iter()
+                .map(|(index, hash, delays)| (index, hash.to_owned(), delays))
+                .collect(),
+            traffic: self
+                .traffic
+                .iter()
+                .map(|(id, (strategy, traffic))| (id.clone(), *strategy, *traffic))
+                .collect(),
+        }
+    }
+}
+
+/// What the collector learned about block propagation.
+#[derive(Serialize, Debug, Clone)]
+pub struct PropagationReport {
+    // index, hash and sorted delays in milliseconds of every reported block
+    pub blocks: Vec<(u64, String, Vec<u128>)>,
+    // the latest reported gossip traffic per node id
+    pub traffic: Vec<(String, GossipStrategy, Traffic)>,
+}
+
+/// Shows the delay distribution per block and over all blocks, and the traffic per node.
+impl fmt::Display for PropagationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut all = vec![];
+        for (index, hash, delays) in &self.blocks {
+            writeln!(
+                f,
+                "block {} ({}): {} nodes, {}",
+                index,
+                abbreviate(hash),
+                delays.len(),
+                summarize(delays)
+            )?;
+            // the earliest sighting is no propagation
+            all.extend_from_slice(&delays[1..]);
+        }
+        all.sort_unstable();
+        writeln!(
+            f,
+            "{}",
+            format!("all blocks: {} deliveries, {}", all.len(), summarize(&all)).color(MSG_COLOR)
+        )?;
+        for (id, strategy, traffic) in &self.traffic {
+            writeln!(
+                f,
+                "node {}: {}, sent {} messages, {} bytes",
+                abbreviate(id),
+                strategy,
+                traffic.messages,
+                traffic.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `p`-th percentile of sorted `values`.
+fn percentile(values: &[u128], p: usize) -> u128 {
+    if values.is_empty() {
+        return 0;
+    }
+    values[(values.len() - 1) * p / 100]
+}
+
+fn summarize(delays: &[u128]) -> String {
+    format!(
+        "delay ms min {} / median {} / p90 {} / max {}",
+        percentile(delays, 0),
+        percentile(delays, 50),
+        percentile(delays, 90),
+        percentile(delays, 100)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sighting(hash: &str, index: u64, seen_at: u128) -> BlockSighting {
+        BlockSighting {
+            hash: hash.to_owned(),
+            index,
+            seen_at,
+            strategy: GossipStrategy::Flood,
+            traffic: Traffic::default(),
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let collector = PeerInfo::new("collector".to_owned(), "127.0.0.1:4000".to_owned()).unwrap();
+        let mut telemetry = Telemetry::new(collector.get_address(), &collector);
+        let hash = "ab".repeat(32);
+        // ids of peers are theirs to choose, short or not
+        let peer = collector.with_id("é");
+        assert!(telemetry.record(&collector, sighting(&hash, 1, 10)));
+        assert!(telemetry.record(&peer, sighting(&hash, 1, 15)));
+        assert!(telemetry.record(&peer, sighting(&hash, 1, 20)));
+        assert!(!telemetry.record(&peer, sighting("ab", 2, 20)));
+        assert_eq!(telemetry.delays(), [(1, hash.as_str(), vec![0, 5])]);
+        assert!(telemetry.report().to_string().contains("node é: "));
+
+        for i in 0..MAX_TRACKED_PEERS {
+            telemetry.record(&collector.with_id(&i.to_string()), sighting(&hash, 1, 30));
+        }
+        assert!(!telemetry.record(&collector.with_id("late"), sighting(&hash, 1, 30)));
+        assert_eq!(telemetry.traffic.len(), MAX_TRACKED_PEERS);
+        for index in 0..=MAX_TRACKED_BLOCKS as u64 {
+            telemetry.record(&peer, sighting(&hash, index + 2, 30));
+        }
+        assert_eq!(telemetry.reports.len(), MAX_TRACKED_BLOCKS);
+        assert_eq!(telemetry.reports.keys().next().unwrap().0, 3);
+    }
+}
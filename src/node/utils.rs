@@ -8,3 +8,8 @@ pub fn parse_addr(addr: String) -> Result<SocketAddr> {
         addr[0].to_owned()
     })?)
 }
+
+/// The first 8 characters of `s`, e.g. of a hash, or all of it if it is shorter.
+pub fn abbreviate(s: &str) -> &str {
+    s.char_indices().nth(8).map_or(s, |(i, _)| &s[..i])
+}
@@ -0,0 +1,125 @@
+use super::*;
+use crate::ledger::Ledger;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Re-verifies the stored chain block by block, from the genesis to the tip and over again,
+/// to catch data that was fine when it was written but got corrupted on disk since.
+///
+/// The verifier runs on a thread of its own and reads the store directly, not the blocks the
+/// node holds in memory. It waits `pace` before every block, so that it hardly takes any disk
+/// or CPU time away from the node.
+pub struct Verifier {
+    store: Store,
+    pace: Duration,
+}
+
+impl Verifier {
+    pub fn new(store: Store, pace: Duration) -> Self {
+        Verifier { store, pace }
+    }
+
+    /// Verifies the chain until a block fails, then raises an `Event::Alarm` and stops.
+    pub fn spawn(self, sender: UnboundedSender<Event>) {
+        thread::spawn(move || loop {
+            if let Some(alarm) = self.pass() {
+                let _ = sender.send(Event::Alarm(alarm));
+                return;
+            }
+            thread::sleep(self.pace);
+        });
+    }
+
+    /// Verifies the stored chain once, returning what is wrong with it, if anything.
+    fn pass(&self) -> Option<String> {
+        let mut previous = match self.store.get_block(0) {
+            Ok(Some(genesis)) => genesis,
+            Ok(None) => return None,
+            Err(e) => return Some(format!("The stored genesis block cannot be read: {}", e)),
+        };
+        let schedule = previous.reward_schedule();
+        let mut ledger = Ledger::new(previous.transaction_model());
+        let prefix = previous.address_prefix().map(str::to_owned);
+        ledger.record(&previous);
+        let mut index = 1;
+        loop {
+            thread::sleep(self.pace);
+            let block = match self.store.get_block(index) {
+                Ok(Some(block)) => block,
+                // the tip, or the chain was replaced by a shorter one
+                Ok(None) => break,
+                Err(e) => return Some(format!("Stored block {} cannot be read: {}", index, e)),
+            };
+            if let Err(reason) = block
+                .verify_after(&previous, &ledger, schedule.reward(index, ledger.minted()))
+                .and_then(|()| block.check_addresses(prefix.as_deref()))
+            {
+                // blocks that are not stored as checked were replaced by a fork meanwhile
+                if self.is_stored(&previous) && self.is_stored(&block) {
+                    return Some(format!(
+                        "Stored block {} no longer validates: {}",
+                        index, reason
+                    ));
+                }
+                debug!(
+                    target: STORAGE,
+                    "The stored chain changed during verification, starting over"
+                );
+                return None;
+            }
+            ledger.record(&block);
+            previous = block;
+            index += 1;
+        }
+        debug!(target: STORAGE, "Verified the {} stored blocks", index);
+        None
+    }
+
+    fn is_stored(&self, block: &Block) -> bool {
+        match self.store.get_block(block.get_index()) {
+            Ok(Some(stored)) => {
+                serde_json::to_string(&stored).ok() == serde_json::to_string(block).ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::BLOCKS_TREE;
+
+    #[test]
+    fn test_verifier() {
+        let store = Store::memory();
+        let mut chain = Blockchain::open(store.clone(), Some(0)).unwrap();
+        for _ in 0..3 {
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+                .unwrap();
+            chain.mine_new_block();
+            chain
+                .add_new_transaction(&Transaction::new("alice", "bob", 1))
+                .unwrap();
+        }
+        let verifier = Verifier::new(store.clone(), Duration::from_millis(0));
+        assert_eq!(verifier.pass(), None);
+
+        // flip a transaction on disk
+        let mut block = serde_json::to_string(&store.get_block(2).unwrap().unwrap()).unwrap();
+        block = block.replace("\"alice\"", "\"carol\"");
+        let key = 2u64.to_be_bytes();
+        store
+            .backend()
+            .insert(BLOCKS_TREE, &key, block.as_bytes())
+            .unwrap();
+        let alarm = verifier.pass().unwrap();
+        assert!(alarm.contains("block 2"), "{}", alarm);
+
+        store.backend().insert(BLOCKS_TREE, &key, b"{").unwrap();
+        let alarm = verifier.pass().unwrap();
+        assert!(alarm.contains("cannot be read"), "{}", alarm);
+    }
+}
@@ -0,0 +1,298 @@
+//! Keeping track of the transactions the node sent for its user
+//!
+//! The mempool lets go of transactions other than into a block: it evicts them once full,
+//! drops them once expired, replaced or spent by a block, and may not take them back after a
+//! reorganization. The wallet hears about the transactions it sent, so that its history tells
+//! which of them can just be sent again and which must be signed anew.
+//!
+//! Being in the mempool only means the node accepted a transaction, so the wallet also keeps
+//! which peers it was relayed to, telling a transaction nobody else heard of from one that is
+//! on its way through the network.
+
+use super::*;
+use crate::blockchain::Eviction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// What became of a transaction the node sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SentStatus {
+    Pending,
+    Confirmed,
+    /// with the id of the transaction in its place
+    Replaced(String),
+    /// the transaction itself is still good, e.g. evicted from a full mempool
+    NeedsResend(Eviction),
+    /// the transaction cannot make it into a block anymore, e.g. once expired
+    NeedsResign(Eviction),
+    /// it left the mempool, but the wallet missed why
+    Dropped,
+}
+
+impl fmt::Display for SentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SentStatus::Pending => write!(f, "pending"),
+            SentStatus::Confirmed => write!(f, "confirmed"),
+            SentStatus::Replaced(id) => write!(f, "replaced by {}", id),
+            SentStatus::NeedsResend(eviction) => write!(f, "needs re-sending, {}", eviction),
+            SentStatus::NeedsResign(eviction) => write!(f, "needs re-signing, {}", eviction),
+            SentStatus::Dropped => write!(f, "dropped"),
+        }
+    }
+}
+
+/// Which peers the node relayed a transaction it sent to, the gossip strategy picking them.
+///
+/// A delivered transaction was written to the connection to the peer, or queued on a link with
+/// artificial latency; peers do not answer transactions, so whether they took it into their
+/// mempools is up to them. A scheduled one is only sent once its diffusion delay has passed, so
+/// whether it reaches the peer is not known yet.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub delivered: Vec<SocketAddr>,
+    #[serde(default)]
+    pub scheduled: Vec<SocketAddr>,
+    /// with why it failed
+    pub failed: Vec<(SocketAddr, String)>,
+}
+
+impl Broadcast {
+    /// How many peers the transaction was sent to.
+    pub fn tried(&self) -> usize {
+        self.delivered.len() + self.scheduled.len() + self.failed.len()
+    }
+}
+
+impl fmt::Display for Broadcast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tried() == 0 {
+            return write!(f, "accepted locally, no peer to relay it to");
+        }
+        let mut sent = vec![];
+        if !self.delivered.is_empty() {
+            sent.push(format!("relayed to {}", self.delivered.len()));
+        }
+        if !self.scheduled.is_empty() {
+            sent.push(format!("scheduled for {}", self.scheduled.len()));
+        }
+        if sent.is_empty() {
+            write!(f, "accepted locally, relaying failed")?;
+        } else {
+            write!(f, "{} of {} peers", sent.join(" and "), self.tried())?;
+        }
+        for (i, (peer, e)) in self.failed.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            write!(f, "{}{} failed ({})", sep, peer, e)?;
+        }
+        Ok(())
+    }
+}
+
+/// A transaction the node just sent, with the peers it was relayed to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: String,
+    pub broadcast: Broadcast,
+}
+
+impl fmt::Display for Submission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}", self.id, self.broadcast)
+    }
+}
+
+/// A transaction the node sent, with what became of it.
+#[derive(Debug, Clone)]
+pub struct SentTransaction {
+    pub transaction: Transaction,
+    pub status: SentStatus,
+    /// how it was relayed when sent, `None` if not yet
+    pub broadcast: Option<Broadcast>,
+}
+
+impl fmt::Display for SentTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = &self.transaction;
+        write!(
+            f,
+            "tx {}: {} -> {}, amount: {}, fee: {}, {}",
+            t.get_id(),
+            t.get_sender(),
+            t.get_recipient(),
+            t.amount_label(),
+            t.get_fee(),
+            self.status
+        )?;
+        match &self.broadcast {
+            Some(broadcast) => write!(f, ", {}", broadcast),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Wallet {
+    // in the order they were sent
+    sent: Vec<Transaction>,
+    // transaction id -> why it left the mempool last
+    evictions: HashMap<String, Eviction>,
+    // transaction id -> the peers it was relayed to
+    broadcasts: HashMap<String, Broadcast>,
+}
+
+impl Wallet {
+    /// Notes that the node sent `transaction`.
+    pub fn record(&mut self, transaction: &Transaction) {
+        self.sent.push(transaction.clone());
+    }
+
+    /// Tells the wallet that `transaction` left the mempool for `eviction`, which it ignores
+    /// unless the node sent the transaction.
+    pub fn notify(&mut self, transaction: &Transaction, eviction: Eviction) {
+        if !self.sent.iter().any(|t| t.get_id() == transaction.get_id()) {
+            return;
+        }
+        let status = Wallet::status_after(&eviction);
+        match status {
+            SentStatus::Replaced(_) => {
+                info!(target: MEMPOOL, "Transaction {} is {}", transaction.get_id(), status)
+            }
+            _ => warn!(target: MEMPOOL, "Transaction {} {}", transaction.get_id(), status),
+        }
+        self.evictions
+            .insert(transaction.get_id().to_owned(), eviction);
+    }
+
+    /// Notes which peers the node relayed the transaction `id` to.
+    pub fn record_broadcast(&mut self, id: &str, broadcast: Broadcast) {
+        self.broadcasts.insert(id.to_owned(), broadcast);
+    }
+
+    fn status_after(eviction: &Eviction) -> SentStatus {
+        match eviction {
+            Eviction::Replaced(id) => SentStatus::Replaced(id.clone()),
+            Eviction::Evicted | Eviction::RolledBack(Rejection::MempoolFull) => {
+                SentStatus::NeedsResend(eviction.clone())
+            }
+            _ => SentStatus::NeedsResign(eviction.clone()),
+        }
+    }
+
+    /// Returns the transactions the node sent, with what became of them on `chain`, which
+    /// overrules what the wallet was told, e.g. once a transaction is mined after all.
+    pub fn history(&self, chain: &Blockchain) -> Vec<SentTransaction> {
+        self.sent
+            .iter()
+            .map(|transaction| self.sent_transaction(transaction, chain))
+            .collect()
+    }
+
+    /// Returns the transaction `id` with what became of it on `chain`, `None` unless the node
+    /// sent it.
+    pub fn get(&self, id: &str, chain: &Blockchain) -> Option<SentTransaction> {
+        self.sent
+            .iter()
+            .find(|transaction| transaction.get_id() == id)
+            .map(|transaction| self.sent_transaction(transaction, chain))
+    }
+
+    fn sent_transaction(&self, transaction: &Transaction, chain: &Blockchain) -> SentTransaction {
+        let id = transaction.get_id();
+        let status = if chain.get_pending_transaction(id).is_some() {
+            SentStatus::Pending
+        } else if chain.contains_transaction(id) {
+            SentStatus::Confirmed
+        } else {
+            self.evictions
+                .get(id)
+                .map_or(SentStatus::Dropped, Wallet::status_after)
+        };
+        SentTransaction {
+            transaction: transaction.clone(),
+            status,
+            broadcast: self.broadcasts.get(id).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut chain = Blockchain::with_difficulty(0);
+        chain
+            .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 20))
+            .unwrap();
+        chain.mine_new_block();
+        let mut wallet = Wallet::default();
+        let mut send = |chain: &mut Blockchain, transaction: Transaction| {
+            chain.add_new_transaction(&transaction).unwrap();
+            wallet.record(&transaction);
+            transaction
+        };
+        let confirmed = send(&mut chain, Transaction::new("alice", "bob", 1));
+        chain.mine_new_block();
+        let original = send(&mut chain, Transaction::new("alice", "bob", 1));
+        let expiring = send(
+            &mut chain,
+            Transaction::new("alice", "carol", 1)
+                .with_fee(1)
+                .with_expiry(Expiry::Height(3)),
+        );
+        let replacement = chain.new_replacement(original.get_id(), 1).unwrap();
+        let replacement = send(&mut chain, replacement);
+        let cheap = send(&mut chain, Transaction::new("alice", "dave", 1));
+        // somebody else's pays the most, and is not the wallet's business
+        chain
+            .add_new_transaction(&Transaction::new("alice", "erin", 1).with_fee(2))
+            .unwrap();
+        chain.set_mempool_capacity(3);
+        // a block passing the expiry, leaving the rest pending
+        let mut other = Blockchain::from_blocks(chain.get_blocks());
+        assert!(chain.add_new_block(other.mine_new_block()));
+        for (transaction, eviction) in chain.take_evictions() {
+            wallet.notify(&transaction, eviction);
+        }
+
+        let history: Vec<(String, SentStatus)> = wallet
+            .history(&chain)
+            .into_iter()
+            .map(|sent| (sent.transaction.get_id().to_owned(), sent.status))
+            .collect();
+        let id = |t: &Transaction| t.get_id().to_owned();
+        assert_eq!(
+            history,
+            [
+                (id(&confirmed), SentStatus::Confirmed),
+                (id(&original), SentStatus::Replaced(id(&replacement))),
+                (id(&expiring), SentStatus::NeedsResign(Eviction::Expired)),
+                (id(&replacement), SentStatus::Pending),
+                (id(&cheap), SentStatus::NeedsResend(Eviction::Evicted)),
+            ]
+        );
+
+        let peer: SocketAddr = "127.0.0.1:4001".parse().unwrap();
+        let broadcast = Broadcast {
+            delivered: vec![peer],
+            scheduled: vec![],
+            failed: vec![("127.0.0.1:4002".parse().unwrap(), "refused".to_owned())],
+        };
+        wallet.record_broadcast(replacement.get_id(), broadcast.clone());
+        let sent = wallet.get(replacement.get_id(), &chain).unwrap();
+        assert_eq!(sent.broadcast, Some(broadcast));
+        assert!(sent
+            .to_string()
+            .ends_with("pending, relayed to 1 of 2 peers: 127.0.0.1:4002 failed (refused)"));
+        assert_eq!(wallet.get(cheap.get_id(), &chain).unwrap().broadcast, None);
+        assert!(wallet.get("unknown", &chain).is_none());
+        assert_eq!(
+            Broadcast::default().to_string(),
+            "accepted locally, no peer to relay it to"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! A tiny stack machine for the spending conditions of transactions
+//!
+//! A transaction may carry a script, which every node runs when validating it: the transaction
+//! is only valid if the script runs through and leaves a true value on top of the stack, e.g.
+//!
+//! - `Push(signature) Push(public_key) CheckSig` for a payment signed by a key
+//! - `Push(preimage) Sha256 Push(hash) Equal` for a payment revealing a secret
+//!
+//! Values are strings, and numbers are their decimal digits. The empty string and `0` are false,
+//! anything else is true. Signatures are taken over the signature hash of the transaction, see
+//! `Transaction::signature_hash`, which leaves the script out, so that a signature can go into
+//! the script of the transaction it signs.
+
+use crate::key;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::{Deserialize, Serialize};
+
+/// How many operations a script may have.
+pub const MAX_SCRIPT_OPS: usize = 100;
+
+/// How long a value pushed onto the stack may be.
+pub const MAX_VALUE_LEN: usize = 520;
+
+/// An operation of a script.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Op {
+    /// pushes the value
+    Push(String),
+    Dup,
+    Drop,
+    Swap,
+    /// pushes whether the top two values are equal
+    Equal,
+    /// fails unless the top two values are equal
+    EqualVerify,
+    /// pushes whether the second number is less than the top one
+    Less,
+    /// pushes whether the second number is greater than the top one
+    Greater,
+    Not,
+    /// fails unless the top value is true
+    Verify,
+    /// replaces the top value with the hex SHA-256 of it
+    Sha256,
+    /// pushes whether the second value is a signature of the transaction by the public key on top
+    CheckSig,
+    /// fails unless the second value is a signature of the transaction by the public key on top
+    CheckSigVerify,
+}
+
+/// Runs `script` of the transaction whose signature hash is `message`, failing with why the
+/// spending condition does not hold.
+pub fn run(script: &[Op], message: &[u8]) -> Result<(), String> {
+    if script.len() > MAX_SCRIPT_OPS {
+        return Err(format!("it has more than {} operations", MAX_SCRIPT_OPS));
+    }
+    let mut stack: Vec<String> = Vec::new();
+    for op in script {
+        match op {
+            Op::Push(value) if value.len() > MAX_VALUE_LEN => {
+                return Err(format!("it pushes a value longer than {}", MAX_VALUE_LEN))
+            }
+            Op::Push(value) => stack.push(value.clone()),
+            Op::Dup => {
+                let top = pop(&mut stack)?;
+                stack.push(top.clone());
+                stack.push(top);
+            }
+            Op::Drop => {
+                pop(&mut stack)?;
+            }
+            Op::Swap => {
+                let (second, top) = (pop(&mut stack)?, pop(&mut stack)?);
+                stack.push(second);
+                stack.push(top);
+            }
+            Op::Equal | Op::EqualVerify => {
+                let equal = pop(&mut stack)? == pop(&mut stack)?;
+                if *op == Op::EqualVerify && !equal {
+                    return Err("EqualVerify failed".to_owned());
+                }
+                if *op == Op::Equal {
+                    stack.push(boolean(equal));
+                }
+            }
+            Op::Less | Op::Greater => {
+                let (top, second) = (number(&mut stack)?, number(&mut stack)?);
+                stack.push(boolean(if *op == Op::Less {
+                    second < top
+                } else {
+                    second > top
+                }));
+            }
+            Op::Not => {
+                let value = pop(&mut stack)?;
+                stack.push(boolean(!is_true(&value)));
+            }
+            Op::Verify => {
+                if !is_true(&pop(&mut stack)?) {
+                    return Err("Verify failed".to_owned());
+                }
+            }
+            Op::Sha256 => {
+                let value = pop(&mut stack)?;
+                let mut hasher = Sha256::new();
+                hasher.input_str(&value);
+                stack.push(hasher.result_str());
+            }
+            Op::CheckSig | Op::CheckSigVerify => {
+                let (public_key, signature) = (pop(&mut stack)?, pop(&mut stack)?);
+                let signed = key::verify(&public_key, message, &signature);
+                if *op == Op::CheckSigVerify && !signed {
+                    return Err("CheckSigVerify failed".to_owned());
+                }
+                if *op == Op::CheckSig {
+                    stack.push(boolean(signed));
+                }
+            }
+        }
+    }
+    match stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err("it does not end with a true value".to_owned()),
+    }
+}
+
+fn pop(stack: &mut Vec<String>) -> Result<String, String> {
+    stack
+        .pop()
+        .ok_or_else(|| "it pops an empty stack".to_owned())
+}
+
+fn number(stack: &mut Vec<String>) -> Result<i64, String> {
+    let value = pop(stack)?;
+    value
+        .parse()
+        .map_err(|_| format!("{:?} is not a number", value))
+}
+
+fn boolean(value: bool) -> String {
+    if value { "1" } else { "0" }.to_owned()
+}
+
+fn is_true(value: &str) -> bool {
+    !value.is_empty() && value != "0"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::NodeKey;
+
+    fn push(value: &str) -> Op {
+        Op::Push(value.to_owned())
+    }
+
+    #[test]
+    fn test_run() {
+        assert!(run(&[push("1")], b"").is_ok());
+        assert!(run(&[push("0")], b"").is_err());
+        assert!(run(&[], b"").is_err());
+        assert!(run(&[Op::Dup], b"").is_err());
+        assert!(run(&[push("2"), push("3"), Op::Less], b"").is_ok());
+        assert!(run(&[push("2"), push("3"), Op::Swap, Op::Less], b"").is_err());
+        assert!(run(&[push("2"), push("x"), Op::Greater], b"").is_err());
+        assert!(run(
+            &[push("a"), Op::Dup, Op::EqualVerify, push("0"), Op::Not],
+            b""
+        )
+        .is_ok());
+        assert!(run(&vec![push("1"); MAX_SCRIPT_OPS + 1], b"").is_err());
+
+        // a hash lock
+        let mut hasher = Sha256::new();
+        hasher.input_str("secret");
+        let hash = hasher.result_str();
+        let lock = |preimage| vec![push(preimage), Op::Sha256, push(&hash), Op::Equal];
+        assert!(run(&lock("secret"), b"").is_ok());
+        assert!(run(&lock("guess"), b"").is_err());
+
+        // a signature of the message
+        let key = NodeKey::generate();
+        let signed = [
+            push(&key.sign(b"tx")),
+            push(&key.public_key()),
+            Op::CheckSig,
+        ];
+        assert!(run(&signed, b"tx").is_ok());
+        assert!(run(&signed, b"other tx").is_err());
+        let other = NodeKey::generate();
+        let forged = [
+            push(&key.sign(b"tx")),
+            push(&other.public_key()),
+            Op::CheckSigVerify,
+            push("1"),
+        ];
+        assert!(run(&forged, b"tx").is_err());
+    }
+}
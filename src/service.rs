@@ -0,0 +1,337 @@
+//! Keeping a node running in the background, for `nb service`
+//!
+//! On Linux `nbd` becomes a systemd unit, of the system or, with `user`, of the user, started at
+//! boot or login and restarted when it fails. Windows runs it as a scheduled task at startup, as
+//! `nbd` does not speak the protocol of Windows services. Either way the unit runs `nbd` with the
+//! flags it was installed with, its paths made absolute, as it does not start where `nb` ran, and
+//! as the account that installed it rather than as root or SYSTEM.
+
+use crate::Result;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The name of the unit or task if none is given.
+pub const DEFAULT_SERVICE_NAME: &str = "nbd";
+
+/// The flags taking paths, which the unit passes absolute.
+const PATH_FLAGS: &[&str] = &["--data-dir", "--genesis"];
+
+/// What `nb service` does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServiceAction {
+    Install,
+    Uninstall,
+    Start,
+    Stop,
+}
+
+/// The node to keep running.
+#[derive(Clone, Debug)]
+pub struct Service {
+    pub name: String,
+    /// the `nbd` to run
+    pub exe: PathBuf,
+    /// the flags to run it with
+    pub args: Vec<String>,
+    /// a unit of the user rather than of the system, systemd only
+    pub user: bool,
+    /// the account a unit of the system or the task runs as, see `run_as_installer`
+    pub account: Option<String>,
+    /// the primary group of `account`, systemd only
+    pub group: Option<String>,
+}
+
+impl Service {
+    /// Runs `exe` with `args`, making the paths they name absolute from the working directory.
+    pub fn new(name: &str, exe: PathBuf, args: Vec<String>, user: bool) -> Result<Self> {
+        let cwd = env::current_dir()?;
+        let mut args = args;
+        for i in 1..args.len() {
+            if PATH_FLAGS.contains(&args[i - 1].as_str()) {
+                args[i] = cwd.join(&args[i]).display().to_string();
+            }
+        }
+        Ok(Service {
+            name: name.to_owned(),
+            exe,
+            args,
+            user,
+            account: None,
+            group: None,
+        })
+    }
+
+    /// Runs the node as the account installing it, the one that ran sudo if any, which must not
+    /// be root: the node then writes its data directory as that account, not as root or SYSTEM.
+    /// Units of the user run as the user anyway.
+    pub fn run_as_installer(&mut self) -> Result<()> {
+        if self.user && !cfg!(windows) {
+            return Ok(());
+        }
+        let vars: &[&str] = if cfg!(windows) {
+            &["USERNAME"]
+        } else {
+            &["SUDO_USER", "USER", "LOGNAME"]
+        };
+        let account = vars
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|account| !account.is_empty())
+            .filter(|account| account != "root")
+            .ok_or_else(|| {
+                failure::err_msg(
+                    "Cannot tell which account to run the node as: install it through sudo \
+                     from that account, or as a unit of the user with --user",
+                )
+            })?;
+        if cfg!(windows) {
+            self.account = Some(match env::var("USERDOMAIN") {
+                Ok(domain) if !domain.is_empty() => format!("{}\\{}", domain, account),
+                _ => account,
+            });
+        } else {
+            self.group = Command::new("id")
+                .args(["-gn", &account])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+                .filter(|group| !group.is_empty());
+            self.account = Some(account);
+        }
+        Ok(())
+    }
+
+    /// The systemd unit running the node.
+    pub fn systemd_unit(&self) -> String {
+        let exec: Vec<String> = std::iter::once(self.exe.display().to_string())
+            .chain(self.args.iter().cloned())
+            .map(|arg| systemd_quote(&arg))
+            .collect();
+        let mut account = String::new();
+        if !self.user {
+            if let Some(user) = &self.account {
+                account += &format!("User={}\n", user);
+            }
+            if let Some(group) = &self.group {
+                account += &format!("Group={}\n", group);
+            }
+        }
+        format!(
+            "[Unit]\n\
+             Description=nb node {name}\n\
+             Wants=network-online.target\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n\
+             {account}\
+             ExecStart={exec}\n\
+             Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy={target}\n",
+            name = self.name,
+            account = account,
+            exec = exec.join(" "),
+            target = if self.user {
+                "default.target"
+            } else {
+                "multi-user.target"
+            },
+        )
+    }
+
+    /// The command line the scheduled task runs on Windows.
+    pub fn task_command(&self) -> String {
+        std::iter::once(self.exe.display().to_string())
+            .chain(self.args.iter().cloned())
+            .map(|arg| {
+                if arg.contains(' ') {
+                    format!("\"{}\"", arg)
+                } else {
+                    arg
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Where the systemd unit goes.
+    pub fn unit_path(&self) -> Result<PathBuf> {
+        let dir = if self.user {
+            match env::var_os("XDG_CONFIG_HOME") {
+                Some(config) => PathBuf::from(config),
+                None => PathBuf::from(env::var_os("HOME").ok_or_else(|| {
+                    failure::err_msg("HOME is not set, so the user units are nowhere")
+                })?)
+                .join(".config"),
+            }
+            .join("systemd/user")
+        } else {
+            PathBuf::from("/etc/systemd/system")
+        };
+        Ok(dir.join(format!("{}.service", self.name)))
+    }
+
+    pub fn run(&self, action: ServiceAction) -> Result<()> {
+        if cfg!(windows) {
+            self.run_task(action)
+        } else {
+            self.run_unit(action)
+        }
+    }
+
+    fn run_unit(&self, action: ServiceAction) -> Result<()> {
+        let unit = format!("{}.service", self.name);
+        match action {
+            ServiceAction::Install => {
+                let path = self.unit_path()?;
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&path, self.systemd_unit()).map_err(|e| {
+                    failure::err_msg(format!("Cannot write {}: {}", path.display(), e))
+                })?;
+                self.systemctl(&["daemon-reload"])?;
+                self.systemctl(&["enable", &unit])?;
+                println!(
+                    "Installed {}, start it with nb service start",
+                    path.display()
+                );
+            }
+            ServiceAction::Uninstall => {
+                let path = self.unit_path()?;
+                self.systemctl(&["disable", "--now", &unit])?;
+                remove(&path)?;
+                self.systemctl(&["daemon-reload"])?;
+                println!("Uninstalled {}", path.display());
+            }
+            ServiceAction::Start => self.systemctl(&["start", &unit])?,
+            ServiceAction::Stop => self.systemctl(&["stop", &unit])?,
+        }
+        Ok(())
+    }
+
+    fn systemctl(&self, args: &[&str]) -> Result<()> {
+        let mut command = Command::new("systemctl");
+        if self.user {
+            command.arg("--user");
+        }
+        run(command.args(args))
+    }
+
+    fn run_task(&self, action: ServiceAction) -> Result<()> {
+        let mut command = Command::new("schtasks");
+        match action {
+            // schtasks asks for the password of the account, to run the task before it logs on
+            ServiceAction::Install => {
+                let account = self.account.as_deref().ok_or_else(|| {
+                    failure::err_msg("No account to run the task as, see run_as_installer")
+                })?;
+                command.args([
+                    "/Create",
+                    "/TN",
+                    &self.name,
+                    "/TR",
+                    &self.task_command(),
+                    "/SC",
+                    "ONSTART",
+                    "/RU",
+                    account,
+                    "/F",
+                ])
+            }
+            ServiceAction::Uninstall => command.args(["/Delete", "/TN", &self.name, "/F"]),
+            ServiceAction::Start => command.args(["/Run", "/TN", &self.name]),
+            ServiceAction::Stop => command.args(["/End", "/TN", &self.name]),
+        };
+        run(&mut command)
+    }
+}
+
+/// Quotes `arg` for a command line of a systemd unit, see systemd.service(5).
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .map_err(|e| failure::err_msg(format!("Cannot run {}: {}", program, e)))?;
+    if !status.success() {
+        return Err(failure::err_msg(format!("{} failed, {}", program, status)));
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(failure::err_msg(format!(
+            "Cannot remove {}: {}",
+            path.display(),
+            e
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_systemd_unit() {
+        let args = [
+            "--addr",
+            "0.0.0.0:4000",
+            "--data-dir",
+            "nb data",
+            "--ephemeral",
+        ];
+        let service = Service::new(
+            DEFAULT_SERVICE_NAME,
+            PathBuf::from("/usr/bin/nbd"),
+            args.iter().map(|arg| arg.to_string()).collect(),
+            false,
+        )
+        .unwrap();
+        let data_dir = env::current_dir().unwrap().join("nb data");
+        assert_eq!(service.args[3], data_dir.display().to_string());
+        let unit = service.systemd_unit();
+        assert!(unit.contains(&format!(
+            "ExecStart=/usr/bin/nbd --addr 0.0.0.0:4000 --data-dir \"{}\" --ephemeral\n",
+            data_dir.display()
+        )));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+        assert!(!unit.contains("User="));
+        assert_eq!(
+            service.unit_path().unwrap(),
+            Path::new("/etc/systemd/system/nbd.service")
+        );
+
+        // as the account installing it, unless it is a unit of the user
+        let mut service = service;
+        service.account = Some("alice".to_owned());
+        service.group = Some("staff".to_owned());
+        assert!(service
+            .systemd_unit()
+            .contains("[Service]\nUser=alice\nGroup=staff\nExecStart="));
+        service.user = true;
+        assert!(!service.systemd_unit().contains("User="));
+        assert!(service.run_as_installer().is_ok());
+
+        assert_eq!(systemd_quote("100%"), "\"100%%\"");
+        assert_eq!(systemd_quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,224 @@
+//! Single-file archives of a whole store
+//!
+//! An archive starts with `MAGIC` and the archive format version, followed by one record per
+//! entry: the tree name, the key and the value, each prefixed by its length as a big-endian u32.
+
+use super::*;
+use crate::{Blockchain, ConsensusMode};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+const MAGIC: &[u8] = b"NBBACKUP";
+const FORMAT_VERSION: u32 = 1;
+
+/// Writes every tree of `storage` into a new archive at `path`. Returns the number of entries.
+pub fn backup(storage: &dyn Storage, path: &Path) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+    let mut count = 0;
+    for tree in storage.trees()? {
+        for (key, value) in storage.scan(&tree)? {
+            write_field(&mut writer, tree.as_bytes())?;
+            write_field(&mut writer, &key)?;
+            write_field(&mut writer, &value)?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Copies every entry of the archive at `path` into `storage`. Returns the number of entries.
+pub fn restore(path: &Path, storage: &dyn Storage) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(failure::err_msg(format!(
+            "{} is not an nb backup",
+            path.display()
+        )));
+    }
+    let version = read_u32(&mut reader)?;
+    if version != FORMAT_VERSION {
+        return Err(failure::err_msg(format!(
+            "unsupported backup format v{}",
+            version
+        )));
+    }
+    let mut count = 0;
+    let mut len = [0; 4];
+    loop {
+        // a clean end of file can only happen between records
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let tree = String::from_utf8(read_exact(&mut reader, u32::from_be_bytes(len))?)?;
+        let key = read_field(&mut reader)?;
+        let value = read_field(&mut reader)?;
+        storage.insert(&tree, &key, &value)?;
+        count += 1;
+    }
+    storage.flush()?;
+    Ok(count)
+}
+
+/// Archives the data directory `data_dir` into `path`. Returns the number of entries.
+pub fn backup_data_dir(backend: Backend, data_dir: &Path, path: &Path) -> Result<usize> {
+    if !data_dir.exists() {
+        return Err(failure::err_msg(format!(
+            "there is no data directory at {}",
+            data_dir.display()
+        )));
+    }
+    let store = Store::open(backend, data_dir)?;
+    backup(store.backend(), path)
+}
+
+/// Restores the archive at `path` into the directory of its chain in `data_dir`, which must not
+/// hold any data unless `force` is set. The archive is checked, including its whole blockchain,
+/// before anything is written, under the rules of `consensus`. Returns the number of entries,
+/// the length of the restored chain and where it was restored to.
+pub fn restore_data_dir(
+    path: &Path,
+    data_dir: &DataDir,
+    force: bool,
+    consensus: &ConsensusMode,
+) -> Result<(usize, usize, PathBuf)> {
+    // restore into memory first, so a bad archive never touches the data directory
+    let staging = Arc::new(MemoryStorage::new());
+    let count = restore(path, staging.as_ref())?;
+    let chain = Blockchain::open_with(
+        Store::new(staging.clone())?,
+        None,
+        None,
+        None,
+        consensus.engine(None),
+    )?;
+
+    let genesis = chain
+        .get_block_by_index(0)
+        .ok_or_else(|| failure::err_msg("The backup holds no genesis block"))?;
+    let chain_dir = data_dir.chain_dir(&genesis);
+    if chain_dir.exists() && fs::read_dir(&chain_dir)?.next().is_some() {
+        if !force {
+            return Err(failure::err_msg(format!(
+                "{} is not empty, pass --force to overwrite it",
+                chain_dir.display()
+            )));
+        }
+        fs::remove_dir_all(&chain_dir)?;
+    }
+    let target = data_dir.backend().open(&chain_dir)?;
+    copy(staging.as_ref(), target.as_ref())?;
+    Ok((count, chain.len(), chain_dir))
+}
+
+/// Copies every entry of `from` into `to`.
+fn copy(from: &dyn Storage, to: &dyn Storage) -> Result<()> {
+    for tree in from.trees()? {
+        for (key, value) in from.scan(&tree)? {
+            to.insert(&tree, &key, &value)?;
+        }
+    }
+    to.flush()
+}
+
+fn write_field(writer: &mut impl Write, field: &[u8]) -> Result<()> {
+    writer.write_all(&(field.len() as u32).to_be_bytes())?;
+    writer.write_all(field)?;
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_field(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_u32(reader)?;
+    read_exact(reader, len)
+}
+
+// Reads a field of `len` bytes, growing it as the bytes come in rather than trusting the
+// length of a damaged archive with the memory for it up front.
+fn read_exact(reader: &mut impl Read, len: u32) -> Result<Vec<u8>> {
+    let mut field = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut field)?;
+    if field.len() < len as usize {
+        return Err(failure::err_msg(format!(
+            "the backup ends within a field of {} bytes",
+            len
+        )));
+    }
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn test_backup_and_restore() {
+        let root = env::temp_dir().join(format!("nb-backup-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let store = Store::memory();
+        let mut chain = Blockchain::open(store.clone(), Some(0)).unwrap();
+        chain
+            .add_new_transaction(&crate::Transaction::new("0", "alice", 1))
+            .unwrap();
+        chain.mine_new_block();
+        chain.mine_new_block();
+        store.put_peer("peer", &"127.0.0.1:4001").unwrap();
+        store.put_node_key(&NodeKey::from_seed([1; 32])).unwrap();
+        fs::create_dir_all(&root).unwrap();
+        let archive = root.join("backup.nb");
+        let count = backup(store.backend(), &archive).unwrap();
+
+        let data_dir = DataDir::open(&root.join("data"), Backend::Sled).unwrap();
+        let consensus = ConsensusMode::ProofOfWork;
+        let (restored, len, chain_dir) =
+            restore_data_dir(&archive, &data_dir, false, &consensus).unwrap();
+        assert_eq!((restored, len), (count, 3));
+        let genesis = chain.get_block_by_index(0).unwrap();
+        assert_eq!(chain_dir, data_dir.chain_dir(&genesis));
+        let copy = data_dir.store(&genesis).unwrap();
+        for tree in store.backend().trees().unwrap() {
+            assert_eq!(
+                copy.backend().scan(&tree).unwrap(),
+                store.backend().scan(&tree).unwrap(),
+                "{}",
+                tree
+            );
+        }
+        let copied = Blockchain::open(copy.clone(), Some(0)).unwrap();
+        assert_eq!(copied.tip_hash(), chain.tip_hash());
+        assert_eq!(copy.load_peers::<String>().unwrap(), ["127.0.0.1:4001"]);
+        assert_eq!(
+            copy.node_key().unwrap().public_key(),
+            NodeKey::from_seed([1; 32]).public_key()
+        );
+        drop((copied, copy));
+
+        // the chain is there already, and an archive must be one
+        assert!(restore_data_dir(&archive, &data_dir, false, &consensus).is_err());
+        fs::write(&archive, b"NBBACKUQ").unwrap();
+        assert!(restore_data_dir(&archive, &data_dir, true, &consensus).is_err());
+        // nor is a field longer than what is left of it
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        truncated.extend_from_slice(&u32::MAX.to_be_bytes());
+        truncated.extend_from_slice(b"blocks");
+        fs::write(&archive, truncated).unwrap();
+        let e = restore(&archive, Store::memory().backend()).unwrap_err();
+        assert!(e.to_string().contains("ends within a field"));
+        drop(data_dir);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! Block files, for handing whole chains around
+//!
+//! Like the `blk*.dat` files of Bitcoin, a block file is just the blocks of a chain from the
+//! genesis on, one record each: `MAGIC`, the length of the block as a little-endian u32, and the
+//! block as a store keeps it, its compact JSON. Unlike a JSON array of the chain, it can be
+//! written and read one block at a time, whatever the length of the chain.
+
+use super::*;
+use crate::{Blockchain, ConsensusMode};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+const MAGIC: &[u8] = b"NBLK";
+
+/// Writes the blocks stored in `store` into a new block file at `path`, copying them as they
+/// are stored, unless one is corrupt. Returns the number of blocks.
+pub fn export(store: &Store, path: &Path) -> Result<u64> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let count = store.block_count()?;
+    for index in 0..count {
+        let block = store.get_block_record(index)?.ok_or_else(|| {
+            failure::err_msg(format!("Block {} is missing from the store", index))
+        })?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(block.len() as u32).to_le_bytes())?;
+        writer.write_all(&block)?;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Stores the blocks of the block file at `path` in `store`. Returns the number of blocks.
+pub fn import(path: &Path, store: &Store) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    let mut magic = [0; 4];
+    loop {
+        // a clean end of file can only happen between records
+        match reader.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        if magic != MAGIC {
+            return Err(failure::err_msg(format!(
+                "block {} of {} does not start a record, it is not a block file",
+                count,
+                path.display()
+            )));
+        }
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let mut record = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut record)?;
+        let block: Block = serde_json::from_slice(&record)
+            .map_err(|e| failure::err_msg(format!("block {} is malformed: {}", count, e)))?;
+        if block.get_index() != count {
+            return Err(failure::err_msg(format!(
+                "block {} claims index {}",
+                count,
+                block.get_index()
+            )));
+        }
+        store.put_block(&block)?;
+        count += 1;
+    }
+    if count == 0 {
+        return Err(failure::err_msg(format!(
+            "{} holds no blocks",
+            path.display()
+        )));
+    }
+    Ok(count)
+}
+
+/// Imports the block file at `path` as the chain of its genesis block in `data_dir`, whose
+/// directory must not hold any data unless `force` is set. The chain is validated under the
+/// rules of `consensus` before anything is written. Returns the length of the chain and where
+/// it was imported to.
+pub fn import_data_dir(
+    path: &Path,
+    data_dir: &DataDir,
+    force: bool,
+    consensus: &ConsensusMode,
+) -> Result<(u64, PathBuf)> {
+    // import into memory first, so a bad block file never touches the data directory
+    let staging = Store::memory();
+    import(path, &staging)?;
+    let mut chain = Blockchain::open_with(staging, None, None, None, consensus.engine(None))?;
+
+    let genesis = chain
+        .get_block_by_index(0)
+        .ok_or_else(|| failure::err_msg("the block file holds no genesis block"))?
+        .into_owned();
+    let chain_dir = data_dir.chain_dir(&genesis);
+    if chain_dir.exists() && fs::read_dir(&chain_dir)?.next().is_some() {
+        if !force {
+            return Err(failure::err_msg(format!(
+                "{} is not empty, pass --force to overwrite it",
+                chain_dir.display()
+            )));
+        }
+        fs::remove_dir_all(&chain_dir)?;
+    }
+    let target = Store::new(data_dir.backend().open(&chain_dir)?)?;
+    chain.attach_store(target.clone())?;
+    target.flush()?;
+    Ok((chain.len() as u64, chain_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, REWARD_SENDER};
+    use std::env;
+
+    #[test]
+    fn test_blkdat() {
+        let store = Store::memory();
+        let mut chain = Blockchain::open(store.clone(), Some(0)).unwrap();
+        for _ in 0..3 {
+            chain
+                .add_new_transaction(&Transaction::new(REWARD_SENDER, "alice", 1))
+                .unwrap();
+            chain.mine_new_block();
+        }
+        let path = env::temp_dir().join(format!("nb-test-{}.blkdat", std::process::id()));
+        assert_eq!(export(&store, &path).unwrap(), 4);
+
+        let imported = Store::memory();
+        assert_eq!(import(&path, &imported).unwrap(), 4);
+        let reopened = Blockchain::open(imported, None).unwrap();
+        assert_eq!(reopened.tip_hash(), chain.tip_hash());
+
+        // a truncated record fails the import
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(import(&path, &Store::memory()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! The layout of a data directory
+//!
+//! ```text
+//! <data dir>/
+//!     nb.lock              the pid of the node using the directory
+//!     control.cookie       the secret consoles authenticate with, while the node runs
+//!     chains/<chain id>/   the database of every chain the node ever ran
+//!     wallets/<name>.key   the keys of the wallets of the user, see `Keystore`
+//! ```
+//!
+//! A chain is identified by its genesis block, so that a node started with other genesis
+//! parameters (difficulty, reward schedule) starts a chain of its own instead of mixing data.
+
+use super::*;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process;
+
+const LOCK_FILE: &str = "nb.lock";
+const CHAINS_DIR: &str = "chains";
+const UPGRADING_DIR: &str = ".upgrading";
+const GENESIS_FILE: &str = "genesis.json";
+const WALLETS_DIR: &str = "wallets";
+/// The file only the owner of the data directory may read that holds the secret of the control
+/// interface of the running node, see `control::ControlCookie`.
+pub const CONTROL_COOKIE_FILE: &str = "control.cookie";
+
+/// The id of the chain starting at `genesis`, the name of its directory.
+pub fn chain_id(genesis: &Block) -> String {
+    genesis.get_hash()[..16].to_owned()
+}
+
+/// A data directory locked by this process until dropped.
+pub struct DataDir {
+    root: PathBuf,
+    backend: Backend,
+}
+
+impl DataDir {
+    /// Locks the data directory `root` holding databases of kind `backend`, creating it if
+    /// needed. Fails if another node is still using it.
+    pub fn open(root: &Path, backend: Backend) -> Result<Self> {
+        fs::create_dir_all(root)?;
+        lock(&root.join(LOCK_FILE), root)?;
+        let data_dir = DataDir {
+            root: root.to_owned(),
+            backend,
+        };
+        data_dir.upgrade_layout()?;
+        Ok(data_dir)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Where `nb init --interactive` writes the genesis spec of the network it sets up.
+    pub fn genesis_file(&self) -> PathBuf {
+        self.root.join(GENESIS_FILE)
+    }
+
+    /// Where the keystore keeps the wallets, which all chains share.
+    pub fn wallets_dir(&self) -> PathBuf {
+        self.root.join(WALLETS_DIR)
+    }
+
+    /// The directory of the chain starting at `genesis`.
+    pub fn chain_dir(&self, genesis: &Block) -> PathBuf {
+        self.root.join(CHAINS_DIR).join(chain_id(genesis))
+    }
+
+    /// Opens (or creates) the database of the chain starting at `genesis`.
+    pub fn store(&self, genesis: &Block) -> Result<Store> {
+        Store::open(self.backend, &self.chain_dir(genesis))
+    }
+
+    /// Moves a database kept right in the data directory, as older versions did, to the
+    /// directory of its chain.
+    fn upgrade_layout(&self) -> Result<()> {
+        let legacy: Vec<PathBuf> = fs::read_dir(&self.root)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|path| {
+                path.file_name().is_some_and(|name| {
+                    ![
+                        LOCK_FILE,
+                        CHAINS_DIR,
+                        GENESIS_FILE,
+                        WALLETS_DIR,
+                        CONTROL_COOKIE_FILE,
+                    ]
+                    .contains(&name.to_str().unwrap_or_default())
+                })
+            })
+            .collect();
+        // the database is moved before it is opened to learn its chain, so that it is never
+        // moved away from under an open database, and an interrupted upgrade is picked up again
+        let staging = self.root.join(CHAINS_DIR).join(UPGRADING_DIR);
+        if !legacy.is_empty() {
+            fs::create_dir_all(&staging)?;
+            for path in legacy {
+                fs::rename(&path, staging.join(path.file_name().unwrap()))?;
+            }
+        }
+        if !staging.exists() {
+            return Ok(());
+        }
+        // a database that never got a chain belongs to the default one
+        let genesis = Store::open(self.backend, &staging)?
+            .get_block(0)?
+            .unwrap_or_else(|| Block::get_genesis(crate::INITIAL_DIFFICULTY));
+        let chain_dir = self.chain_dir(&genesis);
+        if chain_dir.exists() {
+            return Err(failure::err_msg(format!(
+                "{} holds an older database of chain {}, which has a directory already, remove one",
+                staging.display(),
+                chain_id(&genesis)
+            )));
+        }
+        fs::rename(&staging, &chain_dir)?;
+        info!(
+            target: STORAGE,
+            "Moved the database of chain {} to {}",
+            chain_id(&genesis),
+            chain_dir.display()
+        );
+        Ok(())
+    }
+}
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.root.join(LOCK_FILE));
+    }
+}
+
+/// Creates the lock file at `path`, taking over the lock of a node that is no longer running.
+fn lock(path: &Path, root: &Path) -> Result<()> {
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                write!(file, "{}", process::id())?;
+                return Ok(());
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let pid = fs::read_to_string(path)?.trim().parse().ok();
+                match pid {
+                    Some(pid) if is_running(pid) => {
+                        return Err(failure::err_msg(format!(
+                            "The data directory {} is in use, a node is already running (pid {}), remove {} if it is not",
+                            root.display(),
+                            pid,
+                            path.display()
+                        )))
+                    }
+                    _ => {
+                        warn!(target: STORAGE, "Removing the stale lock of {}", root.display());
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        // no portable way to tell, so never take over a lock
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_data_dir() {
+        let root = env::temp_dir().join(format!("nb-data-dir-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        // a database of an older version is moved to the directory of its chain
+        let genesis = Block::get_genesis(4);
+        let legacy = Store::open(Backend::Sled, &root).unwrap();
+        legacy.put_block(&genesis).unwrap();
+        legacy.flush().unwrap();
+        drop(legacy);
+        // sled finishes closing the database in the background
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let data_dir = DataDir::open(&root, Backend::Sled).unwrap();
+        assert!(data_dir.chain_dir(&genesis).join("db").exists());
+        let store = data_dir.store(&genesis).unwrap();
+        assert_eq!(store.block_count().unwrap(), 1);
+        assert_ne!(
+            data_dir.chain_dir(&genesis),
+            data_dir.chain_dir(&Block::get_genesis(5))
+        );
+
+        let error = DataDir::open(&root, Backend::Sled).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains(&format!("already running (pid {})", process::id())));
+        drop((store, data_dir));
+
+        // the lock of a node that is gone is taken over, and a genesis spec stays where it is
+        fs::write(root.join(LOCK_FILE), "4294967295").unwrap();
+        fs::write(root.join(GENESIS_FILE), "{}").unwrap();
+        drop(DataDir::open(&root, Backend::Sled).unwrap());
+        assert!(!root.join(LOCK_FILE).exists());
+        assert!(root.join(GENESIS_FILE).exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+type Tree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A storage backend that keeps everything in memory and loses it on exit.
+#[derive(Default)]
+pub struct MemoryStorage {
+    trees: Mutex<HashMap<String, Tree>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(trees.get(tree).and_then(|t| t.get(key)).cloned())
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut trees = self.trees.lock().unwrap();
+        trees
+            .entry(tree.to_owned())
+            .or_default()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<()> {
+        let mut trees = self.trees.lock().unwrap();
+        if let Some(t) = trees.get_mut(tree) {
+            t.remove(key);
+        }
+        Ok(())
+    }
+
+    fn scan(&self, tree: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(trees
+            .get(tree)
+            .map(|t| t.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(trees
+            .get(tree)
+            .map(|t| {
+                t.range(prefix.to_vec()..)
+                    .take_while(|(k, _)| k.starts_with(prefix))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn last(&self, tree: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let trees = self.trees.lock().unwrap();
+        Ok(trees
+            .get(tree)
+            .and_then(|t| t.iter().next_back())
+            .map(|(k, v)| (k.clone(), v.clone())))
+    }
+
+    fn clear(&self, tree: &str) -> Result<()> {
+        self.trees.lock().unwrap().remove(tree);
+        Ok(())
+    }
+
+    fn trees(&self) -> Result<Vec<String>> {
+        Ok(self.trees.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,571 @@
+//! Persistent storage of the node's data
+//!
+//! A backend only needs to provide a few raw key-value operations on named trees,
+//! everything typed (blocks, peers, the schema header) is built on top of it by `Store`.
+
+pub mod backup;
+pub mod blkdat;
+mod data_dir;
+mod memory;
+#[cfg(feature = "rocksdb")]
+mod rocksdb;
+pub mod schema;
+mod sled;
+
+pub use self::data_dir::{chain_id, DataDir, CONTROL_COOKIE_FILE};
+pub use self::memory::MemoryStorage;
+#[cfg(feature = "rocksdb")]
+pub use self::rocksdb::RocksDbStorage;
+pub use self::sled::SledStorage;
+
+use crate::logging::STORAGE;
+use crate::{Block, BlockAccounting, KeyRotation, NodeKey, Result};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// holds the schema header and other bookkeeping
+pub const META_TREE: &str = "meta";
+/// blocks keyed by their big-endian index
+pub const BLOCKS_TREE: &str = "blocks";
+/// the checksum of every block record, see `checksum`, keyed like the block
+pub const CHECKSUMS_TREE: &str = "checksums";
+/// when each block found corrupt was found so, keyed like the block until it is repaired
+pub const CORRUPT_TREE: &str = "corrupt";
+/// what happened to the stored data, e.g. repairs, keyed by a big-endian sequence number
+pub const AUDIT_LOG_TREE: &str = "audit_log";
+/// the big-endian index of the block holding each transaction, keyed by transaction id
+pub const TX_INDEX_TREE: &str = "tx_index";
+/// the big-endian index of each stored block, keyed by its hash
+pub const BLOCK_INDEX_TREE: &str = "block_index";
+/// an empty value for every transaction touching an address, keyed by the address, a zero byte,
+/// the big-endian index of the block holding the transaction and its id, see `history_key`
+pub const ADDRESS_INDEX_TREE: &str = "address_index";
+/// what each block minted, collected and moved, keyed by its big-endian index
+pub const ACCOUNTING_TREE: &str = "accounting";
+/// known peers keyed by their id
+pub const PEERS_TREE: &str = "peers";
+/// the key rotations of peers, and of the node itself, keyed by the retired key
+pub const KEY_ROTATIONS_TREE: &str = "key_rotations";
+/// the peers whose keys are pinned to their addresses, keyed by the address
+pub const PINNED_KEYS_TREE: &str = "pinned_keys";
+
+const NODE_KEY: &[u8] = b"node_key";
+/// the blocks `replace_blocks` is writing, until all of them are
+const REPLACING_KEY: &[u8] = b"replacing";
+
+/// The database a data directory is kept in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Sled,
+    /// for chains larger than sled comfortably handles, needs the `rocksdb` feature
+    RocksDb,
+}
+
+impl Backend {
+    /// Opens (or creates) a database of this kind in the directory `path`.
+    pub fn open(self, path: &Path) -> Result<Arc<dyn Storage>> {
+        match self {
+            Backend::Sled => Ok(Arc::new(SledStorage::open(path)?)),
+            #[cfg(feature = "rocksdb")]
+            Backend::RocksDb => Ok(Arc::new(RocksDbStorage::open(path)?)),
+            #[cfg(not(feature = "rocksdb"))]
+            Backend::RocksDb => Err(failure::err_msg(
+                "this build has no RocksDB support, rebuild nb with `--features rocksdb`",
+            )),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sled" => Ok(Backend::Sled),
+            "rocksdb" => Ok(Backend::RocksDb),
+            _ => Err(failure::err_msg(format!(
+                "unknown database {}, expected sled or rocksdb",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Sled => "sled",
+            Backend::RocksDb => "rocksdb",
+        })
+    }
+}
+
+/// A raw key-value storage backend organized in named trees.
+pub trait Storage: Send + Sync {
+    /// Returns the value stored under `key` in `tree`.
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Stores `value` under `key` in `tree`, replacing the old value if any.
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Removes `key` from `tree`. Removing a missing key is not an error.
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<()>;
+
+    /// Returns all entries of `tree` ordered by key.
+    fn scan(&self, tree: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Returns the entries of `tree` whose keys start with `prefix`, ordered by key.
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .scan(tree)?
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .collect())
+    }
+
+    /// Returns the entry of `tree` with the greatest key.
+    fn last(&self, tree: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+    /// Removes every entry of `tree`.
+    fn clear(&self, tree: &str) -> Result<()>;
+
+    /// Returns the names of all trees that were ever written to.
+    fn trees(&self) -> Result<Vec<String>>;
+
+    /// Makes sure everything written so far reaches the disk.
+    fn flush(&self) -> Result<()>;
+}
+
+/// Something that happened to the stored data, as the audit log keeps it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub at: u128,
+    pub event: String,
+}
+
+/// A typed view over a `Storage` backend. Cheap to clone.
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn Storage>,
+}
+
+impl Store {
+    /// Wraps a backend, upgrading its data to the current schema version first, and finishing
+    /// a replacement of the blocks a crash interrupted.
+    pub fn new(backend: Arc<dyn Storage>) -> Result<Self> {
+        schema::migrate(backend.as_ref())?;
+        let store = Store { backend };
+        if let Some(blocks) = store.get::<Vec<Block>>(META_TREE, REPLACING_KEY)? {
+            warn!(
+                target: STORAGE,
+                "Replacing the stored blocks was interrupted, finishing it"
+            );
+            store.write_blocks(&blocks)?;
+        }
+        Ok(store)
+    }
+
+    /// Opens (or creates) a database of kind `backend` in the directory `path`.
+    pub fn open(backend: Backend, path: &Path) -> Result<Self> {
+        Store::new(backend.open(path)?)
+    }
+
+    /// Creates a store that lives only in memory.
+    pub fn memory() -> Self {
+        Store::new(Arc::new(MemoryStorage::new())).expect("a fresh memory storage can be migrated")
+    }
+
+    /// Returns the raw backend.
+    pub fn backend(&self) -> &dyn Storage {
+        self.backend.as_ref()
+    }
+
+    /// Loads all stored blocks in index order.
+    pub fn load_blocks(&self) -> Result<Vec<Block>> {
+        (0..self.block_count()?)
+            .filter_map(|index| self.get_block(index).transpose())
+            .collect()
+    }
+
+    /// Loads the block at `index`, failing if its record does not match its checksum.
+    pub fn get_block(&self, index: u64) -> Result<Option<Block>> {
+        match self.get_block_record(index)? {
+            Some(record) => Ok(Some(decode(BLOCKS_TREE, &record)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads the record of the block at `index` as it is stored, its compact JSON, checking it
+    /// against its checksum. A record failing the check is marked corrupt, see `corrupt_blocks`.
+    pub fn get_block_record(&self, index: u64) -> Result<Option<Vec<u8>>> {
+        let key = index.to_be_bytes();
+        let record = match self.backend.get(BLOCKS_TREE, &key)? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let stored = self.backend.get(CHECKSUMS_TREE, &key)?;
+        if stored.as_deref() != Some(checksum(&record).as_bytes()) {
+            self.mark_corrupt(index)?;
+            return Err(failure::err_msg(format!(
+                "Stored block {} is corrupt, it does not match its checksum",
+                index
+            )));
+        }
+        Ok(Some(record))
+    }
+
+    fn mark_corrupt(&self, index: u64) -> Result<()> {
+        let key = index.to_be_bytes();
+        if self.backend.get(CORRUPT_TREE, &key)?.is_none() {
+            warn!(
+                target: STORAGE,
+                "Stored block {} is corrupt, it has to be downloaded again",
+                index
+            );
+            self.put(CORRUPT_TREE, &key, &crate::blockchain::get_time())?;
+            self.audit(format!("block {} found corrupt", index))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the indices of the stored blocks found corrupt and not repaired yet.
+    pub fn corrupt_blocks(&self) -> Result<Vec<u64>> {
+        Ok(self
+            .backend
+            .scan(CORRUPT_TREE)?
+            .into_iter()
+            .map(|(key, _)| read_index(&key))
+            .collect())
+    }
+
+    /// Stores `block` in place of the corrupt one at its index, as downloaded from `source`, and
+    /// records the repair in the audit log.
+    pub fn repair_block(&self, block: &Block, source: &str) -> Result<()> {
+        self.put_block(block)?;
+        self.backend
+            .remove(CORRUPT_TREE, &block.get_index().to_be_bytes())?;
+        self.audit(format!(
+            "block {} repaired from {}",
+            block.get_index(),
+            source
+        ))
+    }
+
+    /// Appends `event` to the audit log.
+    pub fn audit(&self, event: String) -> Result<()> {
+        let next = self
+            .backend
+            .last(AUDIT_LOG_TREE)?
+            .map_or(0, |(key, _)| read_index(&key) + 1);
+        let entry = AuditEntry {
+            at: crate::blockchain::get_time(),
+            event,
+        };
+        self.put(AUDIT_LOG_TREE, &next.to_be_bytes(), &entry)
+    }
+
+    /// Loads the audit log, oldest entries first.
+    pub fn load_audit_log(&self) -> Result<Vec<AuditEntry>> {
+        self.values(AUDIT_LOG_TREE)
+    }
+
+    /// Returns the number of stored blocks, which are keyed by their index.
+    pub fn block_count(&self) -> Result<u64> {
+        Ok(self
+            .backend
+            .last(BLOCKS_TREE)?
+            .map_or(0, |(key, _)| read_index(&key) + 1))
+    }
+
+    /// Stores a block under its index, along with its checksum, and indexes it by its hash and
+    /// its transactions.
+    pub fn put_block(&self, block: &Block) -> Result<()> {
+        let index = block.get_index().to_be_bytes();
+        let record = serde_json::to_vec(block)?;
+        // a block replaced at its index, e.g. on a fork, is not found by its hash anymore
+        if let Some(Ok(old)) = self
+            .backend
+            .get(BLOCKS_TREE, &index)?
+            .map(|record| serde_json::from_slice::<Block>(&record))
+        {
+            self.backend
+                .remove(BLOCK_INDEX_TREE, old.get_hash().as_bytes())?;
+        }
+        self.backend.insert(BLOCKS_TREE, &index, &record)?;
+        self.backend
+            .insert(CHECKSUMS_TREE, &index, checksum(&record).as_bytes())?;
+        self.backend
+            .insert(BLOCK_INDEX_TREE, block.get_hash().as_bytes(), &index)?;
+        for t in block.get_transactions() {
+            self.backend
+                .insert(TX_INDEX_TREE, t.get_id().as_bytes(), &index)?;
+            for address in t.addresses() {
+                self.backend.insert(
+                    ADDRESS_INDEX_TREE,
+                    &history_key(address, block.get_index(), t.get_id()),
+                    &[],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the block at `index`, it and its transactions from the indexes and its
+    /// accounting.
+    pub fn remove_block(&self, index: u64) -> Result<()> {
+        let key = index.to_be_bytes();
+        // the hash and transactions of a corrupt block cannot be told, they stay indexed
+        let block = self.backend.get(BLOCKS_TREE, &key)?;
+        if let Some(Ok(block)) = block.map(|record| serde_json::from_slice::<Block>(&record)) {
+            self.backend
+                .remove(BLOCK_INDEX_TREE, block.get_hash().as_bytes())?;
+            for t in block.get_transactions() {
+                self.backend.remove(TX_INDEX_TREE, t.get_id().as_bytes())?;
+                for address in t.addresses() {
+                    self.backend
+                        .remove(ADDRESS_INDEX_TREE, &history_key(address, index, t.get_id()))?;
+                }
+            }
+        }
+        self.backend.remove(ACCOUNTING_TREE, &key)?;
+        self.backend.remove(CHECKSUMS_TREE, &key)?;
+        self.backend.remove(CORRUPT_TREE, &key)?;
+        self.backend.remove(BLOCKS_TREE, &key)
+    }
+
+    /// Stores what the block at `index` minted, collected and moved.
+    pub fn put_accounting(&self, index: u64, accounting: &BlockAccounting) -> Result<()> {
+        self.put(ACCOUNTING_TREE, &index.to_be_bytes(), accounting)
+    }
+
+    /// Loads what the block at `index` minted, collected and moved.
+    pub fn get_accounting(&self, index: u64) -> Result<Option<BlockAccounting>> {
+        self.get(ACCOUNTING_TREE, &index.to_be_bytes())
+    }
+
+    /// Returns the index of the stored block holding the transaction with `id`.
+    pub fn find_transaction(&self, id: &str) -> Result<Option<u64>> {
+        Ok(self
+            .backend
+            .get(TX_INDEX_TREE, id.as_bytes())?
+            .map(|bytes| read_index(&bytes)))
+    }
+
+    /// Returns the number of transactions of the stored blocks, which are indexed by their id.
+    pub fn transaction_count(&self) -> Result<u64> {
+        Ok(self.backend.scan(TX_INDEX_TREE)?.len() as u64)
+    }
+
+    /// Returns the index of the stored block with `hash`.
+    pub fn find_block(&self, hash: &str) -> Result<Option<u64>> {
+        Ok(self
+            .backend
+            .get(BLOCK_INDEX_TREE, hash.as_bytes())?
+            .map(|bytes| read_index(&bytes)))
+    }
+
+    /// Returns the hashes of the stored blocks starting with `prefix`, by the block index.
+    pub fn find_blocks_by_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        self.keys_with_prefix(BLOCK_INDEX_TREE, prefix)
+    }
+
+    /// Returns the ids of the transactions of the stored blocks starting with `prefix`.
+    pub fn find_transactions_by_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        self.keys_with_prefix(TX_INDEX_TREE, prefix)
+    }
+
+    fn keys_with_prefix(&self, tree: &str, prefix: &str) -> Result<Vec<String>> {
+        self.backend
+            .scan_prefix(tree, prefix.as_bytes())?
+            .into_iter()
+            .map(|(key, _)| Ok(String::from_utf8(key)?))
+            .collect()
+    }
+
+    /// Returns the index of the stored block and the id of every transaction touching
+    /// `address`, oldest first.
+    pub fn find_history(&self, address: &str) -> Result<Vec<(u64, String)>> {
+        let prefix = history_key(address, 0, "");
+        let prefix = &prefix[..address.len() + 1];
+        self.backend
+            .scan_prefix(ADDRESS_INDEX_TREE, prefix)?
+            .into_iter()
+            .map(|(key, _)| {
+                let key = &key[prefix.len()..];
+                let id = String::from_utf8(key[8..].to_vec())?;
+                Ok((read_index(key), id))
+            })
+            .collect()
+    }
+
+    /// Replaces all stored blocks with `blocks`, dropping the accounting of the old ones.
+    ///
+    /// The new blocks are first kept in one record, which a store opened after a crash midway
+    /// finishes writing, so that the chain is never left cut short.
+    pub fn replace_blocks(&self, blocks: &[Block]) -> Result<()> {
+        self.put(META_TREE, REPLACING_KEY, &blocks)?;
+        self.backend.flush()?;
+        self.write_blocks(blocks)
+    }
+
+    fn write_blocks(&self, blocks: &[Block]) -> Result<()> {
+        self.backend.clear(BLOCKS_TREE)?;
+        self.backend.clear(CHECKSUMS_TREE)?;
+        self.backend.clear(CORRUPT_TREE)?;
+        self.backend.clear(BLOCK_INDEX_TREE)?;
+        self.backend.clear(TX_INDEX_TREE)?;
+        self.backend.clear(ADDRESS_INDEX_TREE)?;
+        self.backend.clear(ACCOUNTING_TREE)?;
+        for block in blocks {
+            self.put_block(block)?;
+        }
+        self.backend.flush()?;
+        self.backend.remove(META_TREE, REPLACING_KEY)?;
+        self.backend.flush()
+    }
+
+    /// Loads all stored peers.
+    pub fn load_peers<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.values(PEERS_TREE)
+    }
+
+    /// Stores a peer under its id.
+    pub fn put_peer<T: Serialize>(&self, id: &str, peer: &T) -> Result<()> {
+        self.put(PEERS_TREE, id.as_bytes(), peer)
+    }
+
+    /// Removes the peer with `id`.
+    pub fn remove_peer(&self, id: &str) -> Result<()> {
+        self.backend.remove(PEERS_TREE, id.as_bytes())
+    }
+
+    /// Loads all peers whose keys are pinned.
+    pub fn load_pinned_keys<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.values(PINNED_KEYS_TREE)
+    }
+
+    /// Stores a peer whose key is pinned under its address.
+    pub fn put_pinned_key<T: Serialize>(&self, address: &str, peer: &T) -> Result<()> {
+        self.put(PINNED_KEYS_TREE, address.as_bytes(), peer)
+    }
+
+    /// Forgets the key pinned to `address`.
+    pub fn remove_pinned_key(&self, address: &str) -> Result<()> {
+        self.backend.remove(PINNED_KEYS_TREE, address.as_bytes())
+    }
+
+    /// Loads all known key rotations.
+    pub fn load_key_rotations(&self) -> Result<Vec<KeyRotation>> {
+        self.values(KEY_ROTATIONS_TREE)
+    }
+
+    /// Stores a key rotation under the key it retires.
+    pub fn put_key_rotation(&self, rotation: &KeyRotation) -> Result<()> {
+        self.put(KEY_ROTATIONS_TREE, rotation.old.as_bytes(), rotation)
+    }
+
+    /// Replaces the key of the node.
+    pub fn put_node_key(&self, key: &NodeKey) -> Result<()> {
+        self.put(META_TREE, NODE_KEY, &key.to_hex())
+    }
+
+    /// Loads the key of the node, generating one the first time.
+    pub fn node_key(&self) -> Result<NodeKey> {
+        if let Some(seed) = self.get::<String>(META_TREE, NODE_KEY)? {
+            return NodeKey::from_hex(&seed)
+                .ok_or_else(|| failure::err_msg("The stored node key is corrupt"));
+        }
+        let key = NodeKey::generate();
+        self.put_node_key(&key)?;
+        Ok(key)
+    }
+
+    /// Makes sure everything written so far reaches the disk.
+    pub fn flush(&self) -> Result<()> {
+        self.backend.flush()
+    }
+
+    fn put<T: Serialize>(&self, tree: &str, key: &[u8], value: &T) -> Result<()> {
+        self.backend.insert(tree, key, &serde_json::to_vec(value)?)
+    }
+
+    fn get<T: DeserializeOwned>(&self, tree: &str, key: &[u8]) -> Result<Option<T>> {
+        match self.backend.get(tree, key)? {
+            Some(value) => Ok(Some(decode(tree, &value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn values<T: DeserializeOwned>(&self, tree: &str) -> Result<Vec<T>> {
+        self.backend
+            .scan(tree)?
+            .into_iter()
+            .map(|(_, value)| decode(tree, &value))
+            .collect()
+    }
+}
+
+fn decode<T: DeserializeOwned>(tree: &str, value: &[u8]) -> Result<T> {
+    serde_json::from_slice(value)
+        .map_err(|e| failure::err_msg(format!("Corrupt record in tree {}: {}", tree, e)))
+}
+
+/// The checksum of a stored record, the hex SHA-256 of its bytes.
+pub fn checksum(record: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(record);
+    hasher.result_str()
+}
+
+/// The key of the transaction `id` in the block at `index` under `address` in the address index.
+/// Addresses hold no zero bytes, so the keys of an address share the prefix up to that byte.
+pub fn history_key(address: &str, index: u64, id: &str) -> Vec<u8> {
+    [
+        address.as_bytes(),
+        &[0],
+        &index.to_be_bytes(),
+        id.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Decodes a big-endian block index key.
+pub fn read_index(bytes: &[u8]) -> u64 {
+    let mut index = [0; 8];
+    index.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupted_replace() {
+        let backend: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let store = Store::new(backend.clone()).unwrap();
+        let mut chain = crate::Blockchain::open(store.clone(), Some(0)).unwrap();
+        chain.mine_new_block();
+        chain.mine_new_block();
+        let blocks = chain.get_blocks();
+        store.replace_blocks(&blocks).unwrap();
+        assert_eq!(backend.get(META_TREE, REPLACING_KEY).unwrap(), None);
+
+        // a crash after the first block was written again
+        store.put(META_TREE, REPLACING_KEY, &blocks).unwrap();
+        backend.clear(BLOCKS_TREE).unwrap();
+        store.put_block(&blocks[0]).unwrap();
+        assert_eq!(store.block_count().unwrap(), 1);
+        let store = Store::new(backend.clone()).unwrap();
+        let hashes = |blocks: &[Block]| blocks.iter().map(Block::get_hash).collect::<Vec<_>>();
+        assert_eq!(hashes(&store.load_blocks().unwrap()), hashes(&blocks));
+        assert_eq!(store.find_block(chain.tip_hash()).unwrap(), Some(2));
+        assert_eq!(backend.get(META_TREE, REPLACING_KEY).unwrap(), None);
+    }
+}
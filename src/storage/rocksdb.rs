@@ -0,0 +1,114 @@
+use super::*;
+use ::rocksdb::{ColumnFamily, IteratorMode, Options, WriteBatch, DB};
+use std::sync::RwLock;
+
+/// the column family RocksDB always has, which we never use
+const DEFAULT_CF: &str = "default";
+
+/// the trees of every store, opened as column families right away
+const KNOWN_TREES: &[&str] = &[META_TREE, BLOCKS_TREE, TX_INDEX_TREE, PEERS_TREE];
+
+/// A storage backend on top of RocksDB, one column family per storage tree.
+pub struct RocksDbStorage {
+    // creating a column family needs exclusive access
+    db: RwLock<DB>,
+}
+
+impl RocksDbStorage {
+    /// Opens (or creates) the database in the directory `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        // every existing column family has to be opened as well
+        let mut cfs: Vec<String> = KNOWN_TREES.iter().map(|t| t.to_string()).collect();
+        if let Ok(existing) = DB::list_cf(&opts, path) {
+            for cf in existing {
+                if cf != DEFAULT_CF && !cfs.contains(&cf) {
+                    cfs.push(cf);
+                }
+            }
+        }
+        Ok(RocksDbStorage {
+            db: RwLock::new(DB::open_cf(&opts, path, &cfs)?),
+        })
+    }
+
+    /// Runs `f` on the column family of `tree`, creating it first if needed.
+    fn with_cf<T>(&self, tree: &str, f: impl FnOnce(&DB, &ColumnFamily) -> Result<T>) -> Result<T> {
+        {
+            let db = self.db.read().unwrap();
+            if let Some(cf) = db.cf_handle(tree) {
+                return f(&db, cf);
+            }
+        }
+        let mut db = self.db.write().unwrap();
+        if db.cf_handle(tree).is_none() {
+            db.create_cf(tree, &Options::default())?;
+        }
+        let db: &DB = &db;
+        f(db, db.cf_handle(tree).unwrap())
+    }
+}
+
+impl Storage for RocksDbStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.with_cf(tree, |db, cf| Ok(db.get_cf(cf, key)?))
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.with_cf(tree, |db, cf| Ok(db.put_cf(cf, key, value)?))
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<()> {
+        self.with_cf(tree, |db, cf| Ok(db.delete_cf(cf, key)?))
+    }
+
+    fn scan(&self, tree: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.with_cf(tree, |db, cf| {
+            Ok(db
+                .iterator_cf(cf, IteratorMode::Start)
+                .map(|(k, v)| (k.into_vec(), v.into_vec()))
+                .collect())
+        })
+    }
+
+    fn last(&self, tree: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.with_cf(tree, |db, cf| {
+            Ok(db
+                .iterator_cf(cf, IteratorMode::End)
+                .next()
+                .map(|(k, v)| (k.into_vec(), v.into_vec())))
+        })
+    }
+
+    fn clear(&self, tree: &str) -> Result<()> {
+        self.with_cf(tree, |db, cf| {
+            let mut batch = WriteBatch::default();
+            for (key, _) in db.iterator_cf(cf, IteratorMode::Start) {
+                batch.delete_cf(cf, key);
+            }
+            Ok(db.write(batch)?)
+        })
+    }
+
+    fn trees(&self) -> Result<Vec<String>> {
+        let mut trees: Vec<String> = KNOWN_TREES.iter().map(|t| t.to_string()).collect();
+        let db = self.db.read().unwrap();
+        if let Ok(existing) = DB::list_cf(&Options::default(), db.path()) {
+            for cf in existing {
+                if cf != DEFAULT_CF && !trees.contains(&cf) {
+                    trees.push(cf);
+                }
+            }
+        }
+        Ok(trees)
+    }
+
+    fn flush(&self) -> Result<()> {
+        for tree in self.trees()? {
+            self.with_cf(&tree, |db, cf| Ok(db.flush_cf(cf)?))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,650 @@
+//! Versioning of the on-disk format
+//!
+//! Every store carries a schema version header in its meta tree. When the layout of a stored
+//! type (`Block`, `Transaction`, ...) changes, bump `SCHEMA_VERSION` and append a `Migration`
+//! from the previous version to `MIGRATIONS`, so existing data directories get upgraded on
+//! open instead of failing to deserialize. A migration rewriting block records must store their
+//! new checksums as well, see `checksum_blocks`.
+
+use super::*;
+use crate::merkle::merkle_root;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The schema version written by this build.
+pub const SCHEMA_VERSION: u32 = 10;
+
+const VERSION_KEY: &[u8] = b"schema_version";
+
+/// Upgrades stored data from schema version `from` to `from + 1`.
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub run: fn(&dyn Storage) -> Result<()>,
+}
+
+/// All known migrations, ordered by `from`.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "index the transactions of stored blocks",
+        run: index_transactions,
+    },
+    Migration {
+        from: 2,
+        description: "add Merkle roots to stored blocks",
+        run: add_merkle_roots,
+    },
+    Migration {
+        from: 3,
+        description: "record the difficulty in stored blocks",
+        run: add_difficulty,
+    },
+    Migration {
+        from: 4,
+        description: "refuse blocks whose proof of work does not cover their contents",
+        run: refuse_unbound_proofs,
+    },
+    Migration {
+        from: 5,
+        description: "move the fields of stored blocks into a header with an explicit nonce",
+        run: add_headers,
+    },
+    Migration {
+        from: 6,
+        description: "record what stored blocks minted, collected and moved",
+        run: account_blocks,
+    },
+    Migration {
+        from: 7,
+        description: "store the checksums of stored blocks",
+        run: checksum_blocks,
+    },
+    Migration {
+        from: 8,
+        description: "index stored blocks by their hash",
+        run: index_blocks,
+    },
+    Migration {
+        from: 9,
+        description: "index the transactions of stored blocks by the addresses they touch",
+        run: index_addresses,
+    },
+];
+
+fn index_transactions(storage: &dyn Storage) -> Result<()> {
+    for (key, bytes) in storage.scan(BLOCKS_TREE)? {
+        let block: Value = serde_json::from_slice(&bytes)?;
+        for t in block["transactions"].as_array().into_iter().flatten() {
+            if let Some(id) = t["id"].as_str() {
+                storage.insert(TX_INDEX_TREE, id.as_bytes(), &key)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The layouts as of schema v2 and v3. Hashes depend on the field order, so migrations cannot
+// go through `serde_json::Value`, and must not use the current types, which may change.
+#[derive(Serialize, Deserialize)]
+struct TransactionV2 {
+    id: String,
+    sender: String,
+    recipient: String,
+    amount: i64,
+}
+
+#[derive(Deserialize)]
+struct BlockV2 {
+    index: u64,
+    timestamp: u128,
+    proof: u64,
+    transactions: Vec<TransactionV2>,
+    previous_hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockV3 {
+    index: u64,
+    timestamp: u128,
+    proof: u64,
+    merkle_root: String,
+    transactions: Vec<TransactionV2>,
+    previous_hash: String,
+}
+
+fn sha256<T: Serialize>(value: &T) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&serde_json::to_string(value)?);
+    Ok(hasher.result_str())
+}
+
+// The Merkle root is part of the block hash, so every block after the first is relinked to the
+// new hash of its predecessor. Proofs only depend on each other, so they stay valid.
+fn add_merkle_roots(storage: &dyn Storage) -> Result<()> {
+    let mut previous_hash = None;
+    for (key, bytes) in storage.scan(BLOCKS_TREE)? {
+        let old: BlockV2 = serde_json::from_slice(&bytes)?;
+        let leaves = old
+            .transactions
+            .iter()
+            .map(sha256)
+            .collect::<Result<Vec<_>>>()?;
+        let block = BlockV3 {
+            index: old.index,
+            timestamp: old.timestamp,
+            proof: old.proof,
+            merkle_root: merkle_root(&leaves),
+            transactions: old.transactions,
+            previous_hash: previous_hash.unwrap_or(old.previous_hash),
+        };
+        previous_hash = Some(sha256(&block)?);
+        storage.insert(BLOCKS_TREE, &key, &serde_json::to_vec(&block)?)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockV4 {
+    index: u64,
+    timestamp: u128,
+    proof: u64,
+    difficulty: u32,
+    merkle_root: String,
+    transactions: Vec<TransactionV2>,
+    previous_hash: String,
+}
+
+// Blocks used to need 16 leading zero bits. Stored chains that mined their blocks faster or
+// slower than the retargeting of v4 allows cannot be carried over, and are refused before
+// anything is rewritten, so that the user can re-sync with the blocks still in place.
+fn add_difficulty(storage: &dyn Storage) -> Result<()> {
+    const DIFFICULTY: u32 = 16;
+    const INTERVAL: usize = 10;
+    const TARGET_TIMESPAN: u128 = 10_000 * (INTERVAL as u128 - 1);
+    let stored = storage.scan(BLOCKS_TREE)?;
+    let mut blocks = Vec::with_capacity(stored.len());
+    for (key, bytes) in stored {
+        blocks.push((key, serde_json::from_slice::<BlockV3>(&bytes)?));
+    }
+    let timestamps: Vec<u128> = blocks.iter().map(|(_, block)| block.timestamp).collect();
+    let retargeted = (2 * INTERVAL..timestamps.len()).step_by(INTERVAL);
+    for index in retargeted {
+        let timespan = timestamps[index - 1].saturating_sub(timestamps[index - INTERVAL]);
+        if !(TARGET_TIMESPAN / 2..=TARGET_TIMESPAN * 2).contains(&timespan) {
+            return Err(resync_required(
+                timestamps.len() - index,
+                "mined too fast or too slowly for the difficulty retargeting",
+            ));
+        }
+    }
+    let mut previous_hash = None;
+    for (key, old) in blocks {
+        let block = BlockV4 {
+            index: old.index,
+            timestamp: old.timestamp,
+            proof: old.proof,
+            difficulty: DIFFICULTY,
+            merkle_root: old.merkle_root,
+            transactions: old.transactions,
+            previous_hash: previous_hash.unwrap_or(old.previous_hash),
+        };
+        previous_hash = Some(sha256(&block)?);
+        storage.insert(BLOCKS_TREE, &key, &serde_json::to_vec(&block)?)?;
+    }
+    Ok(())
+}
+
+// Proofs used to only depend on the previous proof, and cannot be carried over to the header
+// hash without redoing the work. Rather than dropping the blocks, stores holding any besides the
+// genesis block, which has no proof to check, are left as they are for the user to re-sync.
+fn refuse_unbound_proofs(storage: &dyn Storage) -> Result<()> {
+    let blocks = storage.scan(BLOCKS_TREE)?.len();
+    if blocks > 1 {
+        return Err(resync_required(
+            blocks - 1,
+            "mined under the old proof of work, which cannot be validated any more",
+        ));
+    }
+    Ok(())
+}
+
+/// The error of a migration that cannot carry `count` stored blocks over, `why`, and leaves them
+/// in place.
+fn resync_required(count: usize, why: &str) -> failure::Error {
+    failure::err_msg(format!(
+        "re-sync required: the data directory holds {} blocks {}, move it away to fetch the chain from peers again",
+        count, why
+    ))
+}
+
+#[derive(Serialize)]
+struct HeaderV6 {
+    index: u64,
+    timestamp: u128,
+    previous_hash: String,
+    merkle_root: String,
+    difficulty_target: u32,
+    nonce: u64,
+}
+
+#[derive(Serialize)]
+struct BlockV6 {
+    header: HeaderV6,
+    transactions: Vec<TransactionV2>,
+}
+
+// The proof of work now covers the serialized header, so the work of stored blocks is lost the
+// same way as in `refuse_unbound_proofs`, and stores holding any are refused the same way. The
+// genesis block is kept in the new layout.
+fn add_headers(storage: &dyn Storage) -> Result<()> {
+    let blocks = storage.scan(BLOCKS_TREE)?;
+    if blocks.len() > 1 {
+        return Err(resync_required(
+            blocks.len() - 1,
+            "whose proof of work does not cover the new header",
+        ));
+    }
+    if let Some((key, bytes)) = blocks.first() {
+        let old: BlockV4 = serde_json::from_slice(bytes)?;
+        let genesis = BlockV6 {
+            header: HeaderV6 {
+                index: old.index,
+                timestamp: old.timestamp,
+                previous_hash: old.previous_hash,
+                merkle_root: old.merkle_root,
+                difficulty_target: old.difficulty,
+                nonce: old.proof,
+            },
+            transactions: old.transactions,
+        };
+        storage.insert(BLOCKS_TREE, key, &serde_json::to_vec(&genesis)?)?;
+    }
+    Ok(())
+}
+
+// What `Ledger::record` returns, worked out from the v6 layout: rewards claim their amount, or
+// all their outputs on UTXO chains, and only what they claim beyond the fees is minted.
+fn account_blocks(storage: &dyn Storage) -> Result<()> {
+    let blocks = storage.scan(BLOCKS_TREE)?;
+    let utxo = match blocks.first() {
+        Some((_, genesis)) => {
+            let genesis: Value = serde_json::from_slice(genesis)?;
+            genesis["header"]["transaction_model"] == "Utxo"
+        }
+        None => return Ok(()),
+    };
+    for (key, bytes) in blocks {
+        let block: Value = serde_json::from_slice(&bytes)?;
+        let (mut claimed, mut fees, mut transferred, mut miner) = (0i64, 0i64, 0i64, None);
+        for t in block["transactions"].as_array().into_iter().flatten() {
+            let amount = t["amount"].as_i64().unwrap_or(0);
+            if t["sender"] == "0" {
+                let paid = match t["utxo"]["outputs"].as_array() {
+                    Some(outputs) if utxo => outputs.iter().fold(0i64, |total, output| {
+                        total.saturating_add(output["amount"].as_i64().unwrap_or(0))
+                    }),
+                    _ => amount,
+                };
+                claimed = claimed.saturating_add(paid);
+                miner = miner.or_else(|| t["recipient"].as_str().map(str::to_owned));
+            } else {
+                fees = fees.saturating_add(t["fee"].as_i64().unwrap_or(0));
+                transferred = transferred.saturating_add(amount);
+            }
+        }
+        let accounting = json!({
+            "subsidy": claimed.saturating_sub(fees).max(0),
+            "fees": fees,
+            "miner": miner,
+            "transferred": transferred,
+        });
+        storage.insert(ACCOUNTING_TREE, &key, &serde_json::to_vec(&accounting)?)?;
+    }
+    Ok(())
+}
+
+// Blocks are taken as they are, the checksums only catch what happens to them from now on.
+fn checksum_blocks(storage: &dyn Storage) -> Result<()> {
+    for (key, bytes) in storage.scan(BLOCKS_TREE)? {
+        storage.insert(CHECKSUMS_TREE, &key, checksum(&bytes).as_bytes())?;
+    }
+    Ok(())
+}
+
+// Hashes are taken over the canonical encoding of the header, which only `Block` knows, and it
+// still reads the v8 layout.
+fn index_blocks(storage: &dyn Storage) -> Result<()> {
+    for (key, bytes) in storage.scan(BLOCKS_TREE)? {
+        match serde_json::from_slice::<Block>(&bytes) {
+            Ok(block) => storage.insert(BLOCK_INDEX_TREE, block.get_hash().as_bytes(), &key)?,
+            // left to be repaired, which indexes it again
+            Err(e) => warn!(
+                target: STORAGE,
+                "Not indexing stored block {}, it is corrupt: {}",
+                read_index(&key),
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+// What `Transaction::addresses` returns, read from the v9 layout.
+fn index_addresses(storage: &dyn Storage) -> Result<()> {
+    for (key, bytes) in storage.scan(BLOCKS_TREE)? {
+        let block: Value = serde_json::from_slice(&bytes)?;
+        for t in block["transactions"].as_array().into_iter().flatten() {
+            let id = match t["id"].as_str() {
+                Some(id) => id,
+                None => continue,
+            };
+            let outputs = t["utxo"]["outputs"].as_array().into_iter().flatten();
+            let addresses = vec![&t["sender"], &t["recipient"]]
+                .into_iter()
+                .chain(outputs.map(|output| &output["recipient"]))
+                .filter_map(Value::as_str)
+                .filter(|address| *address != "0");
+            for address in addresses {
+                let key = history_key(address, read_index(&key), id);
+                storage.insert(ADDRESS_INDEX_TREE, &key, &[])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the schema version header, `None` if the store has none yet.
+pub fn read_version(storage: &dyn Storage) -> Result<Option<u32>> {
+    match storage.get(META_TREE, VERSION_KEY)? {
+        Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+fn write_version(storage: &dyn Storage, version: u32) -> Result<()> {
+    storage.insert(META_TREE, VERSION_KEY, &serde_json::to_vec(&version)?)
+}
+
+/// Brings the store up to `SCHEMA_VERSION`, stamping fresh stores with it.
+pub fn migrate(storage: &dyn Storage) -> Result<()> {
+    migrate_to(storage, SCHEMA_VERSION, MIGRATIONS)
+}
+
+fn migrate_to(storage: &dyn Storage, target: u32, migrations: &[Migration]) -> Result<()> {
+    let mut version = match read_version(storage)? {
+        Some(version) => version,
+        None if is_empty(storage)? => {
+            debug!(target: STORAGE, "Fresh storage, stamping it with schema v{}", target);
+            return write_version(storage, target);
+        }
+        None => {
+            return Err(failure::err_msg(
+                "the data directory has no schema version header, it was not written by nb",
+            ))
+        }
+    };
+    if version > target {
+        return Err(failure::err_msg(format!(
+            "the data directory was written with schema v{}, but this build only understands up to v{}",
+            version, target
+        )));
+    }
+    while version < target {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| failure::err_msg(format!("no migration from schema v{}", version)))?;
+        info!(
+            target: STORAGE,
+            "Migrating storage from schema v{} to v{}: {}",
+            version,
+            version + 1,
+            migration.description
+        );
+        (migration.run)(storage)?;
+        version += 1;
+        // record every step, so an interrupted upgrade resumes where it stopped
+        write_version(storage, version)?;
+    }
+    storage.flush()
+}
+
+fn is_empty(storage: &dyn Storage) -> Result<bool> {
+    Ok(storage.scan(BLOCKS_TREE)?.is_empty() && storage.scan(PEERS_TREE)?.is_empty())
+}
+
+/// Rewrites every JSON record of `tree` in place. Handy for migrations.
+pub fn map_records(
+    storage: &dyn Storage,
+    tree: &str,
+    f: impl Fn(&mut Value) -> Result<()>,
+) -> Result<()> {
+    for (key, bytes) in storage.scan(tree)? {
+        let mut record: Value = serde_json::from_slice(&bytes)?;
+        f(&mut record)?;
+        storage.insert(tree, &key, &serde_json::to_vec(&record)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_amount(storage: &dyn Storage) -> Result<()> {
+        map_records(storage, BLOCKS_TREE, |record| {
+            let amount = record["amount"].take();
+            record["value"] = amount;
+            Ok(())
+        })
+    }
+
+    fn double_value(storage: &dyn Storage) -> Result<()> {
+        map_records(storage, BLOCKS_TREE, |record| {
+            record["value"] = (record["value"].as_i64().unwrap() * 2).into();
+            Ok(())
+        })
+    }
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            from: 1,
+            description: "rename amount to value",
+            run: rename_amount,
+        },
+        Migration {
+            from: 2,
+            description: "double the value",
+            run: double_value,
+        },
+    ];
+
+    #[test]
+    fn test_account_blocks() {
+        let store = Store::memory();
+        let mut chain = crate::Blockchain::open(store.clone(), Some(0)).unwrap();
+        chain
+            .add_new_transaction(&crate::Transaction::new("0", "alice", 3))
+            .unwrap();
+        chain.mine_new_block();
+        chain
+            .add_new_transaction(&crate::Transaction::new("alice", "bob", 1).with_fee(1))
+            .unwrap();
+        chain
+            .add_new_transaction(&crate::Transaction::new("0", "carol", 1))
+            .unwrap();
+        chain.mine_new_block();
+        let accountings: Vec<_> = (0..3).map(|i| chain.get_block_accounting(i)).collect();
+        assert_eq!(accountings[2].as_ref().unwrap().transferred, 1);
+
+        store.backend().clear(ACCOUNTING_TREE).unwrap();
+        account_blocks(store.backend()).unwrap();
+        let migrated: Vec<_> = (0..3).map(|i| store.get_accounting(i).unwrap()).collect();
+        assert_eq!(migrated, accountings);
+    }
+
+    #[test]
+    fn test_index_addresses() {
+        let store = Store::memory();
+        let mut chain = crate::Blockchain::open(store.clone(), Some(0)).unwrap();
+        chain
+            .add_new_transaction(&crate::Transaction::new("0", "alice", 3))
+            .unwrap();
+        chain
+            .add_new_transaction(&crate::Transaction::new("alice", "bob", 1))
+            .unwrap();
+        chain.mine_new_block();
+        let indexed = store.backend().scan(ADDRESS_INDEX_TREE).unwrap();
+        assert_eq!(store.find_history("alice").unwrap().len(), 2);
+
+        store.backend().clear(ADDRESS_INDEX_TREE).unwrap();
+        index_addresses(store.backend()).unwrap();
+        assert_eq!(store.backend().scan(ADDRESS_INDEX_TREE).unwrap(), indexed);
+    }
+
+    #[test]
+    fn test_index_blocks() {
+        let store = Store::memory();
+        let mut chain = crate::Blockchain::open(store.clone(), Some(0)).unwrap();
+        chain.mine_new_block();
+        let hash = chain.tip_hash();
+        assert_eq!(store.find_block(hash).unwrap(), Some(1));
+
+        store.backend().clear(BLOCK_INDEX_TREE).unwrap();
+        assert_eq!(store.find_block(hash).unwrap(), None);
+        index_blocks(store.backend()).unwrap();
+        assert_eq!(store.find_block(hash).unwrap(), Some(1));
+        assert_eq!(chain.get_block_by_hash(hash).unwrap().get_index(), 1);
+    }
+
+    #[test]
+    fn test_add_difficulty() {
+        let storage = MemoryStorage::new();
+        write_version(&storage, 3).unwrap();
+        let block = |index: u64, timestamp: u128| {
+            let block = json!({
+                "index": index,
+                "timestamp": timestamp,
+                "proof": 100,
+                "merkle_root": "",
+                "transactions": [],
+                "previous_hash": "1",
+            });
+            storage
+                .insert(
+                    BLOCKS_TREE,
+                    &index.to_be_bytes(),
+                    &serde_json::to_vec(&block).unwrap(),
+                )
+                .unwrap();
+        };
+        // every 10 seconds, then all at once
+        for index in 0..10 {
+            block(index, index as u128 * 10_000);
+        }
+        for index in 10..25 {
+            block(index, 100_000);
+        }
+        let stored = storage.scan(BLOCKS_TREE).unwrap();
+        let error = migrate_to(&storage, 4, MIGRATIONS).unwrap_err();
+        assert!(error.to_string().starts_with("re-sync required"));
+        assert_eq!(storage.scan(BLOCKS_TREE).unwrap(), stored);
+        assert_eq!(read_version(&storage).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn test_refuse_unbound_proofs() {
+        let storage = MemoryStorage::new();
+        write_version(&storage, 4).unwrap();
+        let block = |index: u64| {
+            let block = json!({"index": index, "proof": 100, "transactions": []});
+            storage
+                .insert(
+                    BLOCKS_TREE,
+                    &index.to_be_bytes(),
+                    &serde_json::to_vec(&block).unwrap(),
+                )
+                .unwrap();
+        };
+        block(0);
+        migrate_to(&storage, 5, MIGRATIONS).unwrap();
+        assert_eq!(read_version(&storage).unwrap(), Some(5));
+
+        write_version(&storage, 4).unwrap();
+        block(1);
+        let stored = storage.scan(BLOCKS_TREE).unwrap();
+        let error = migrate_to(&storage, 5, MIGRATIONS).unwrap_err();
+        assert!(error.to_string().starts_with("re-sync required"));
+        assert_eq!(storage.scan(BLOCKS_TREE).unwrap(), stored);
+        assert_eq!(read_version(&storage).unwrap(), Some(4));
+    }
+
+    #[test]
+    fn test_add_headers() {
+        let storage = MemoryStorage::new();
+        write_version(&storage, 5).unwrap();
+        let block = |index: u64| {
+            let block = json!({
+                "index": index,
+                "timestamp": 0,
+                "proof": 100,
+                "difficulty": 16,
+                "merkle_root": "",
+                "transactions": [],
+                "previous_hash": "1",
+            });
+            storage
+                .insert(
+                    BLOCKS_TREE,
+                    &index.to_be_bytes(),
+                    &serde_json::to_vec(&block).unwrap(),
+                )
+                .unwrap();
+        };
+        block(0);
+        block(1);
+        let stored = storage.scan(BLOCKS_TREE).unwrap();
+        let error = migrate_to(&storage, 6, MIGRATIONS).unwrap_err();
+        assert!(error.to_string().starts_with("re-sync required"));
+        assert_eq!(storage.scan(BLOCKS_TREE).unwrap(), stored);
+        assert_eq!(read_version(&storage).unwrap(), Some(5));
+
+        storage.remove(BLOCKS_TREE, &1u64.to_be_bytes()).unwrap();
+        migrate_to(&storage, 6, MIGRATIONS).unwrap();
+        let genesis: Value = serde_json::from_slice(
+            &storage
+                .get(BLOCKS_TREE, &0u64.to_be_bytes())
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(genesis["header"]["nonce"], 100);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let storage = MemoryStorage::new();
+        migrate_to(&storage, 1, TEST_MIGRATIONS).unwrap();
+        assert_eq!(read_version(&storage).unwrap(), Some(1));
+        storage
+            .insert(BLOCKS_TREE, b"0", br#"{"amount":21}"#)
+            .unwrap();
+
+        migrate_to(&storage, 3, TEST_MIGRATIONS).unwrap();
+        assert_eq!(read_version(&storage).unwrap(), Some(3));
+        let record: Value =
+            serde_json::from_slice(&storage.get(BLOCKS_TREE, b"0").unwrap().unwrap()).unwrap();
+        assert_eq!(record["value"], 42);
+
+        // a build that only knows older schemas must refuse the data
+        assert!(migrate_to(&storage, 2, TEST_MIGRATIONS).is_err());
+
+        // data without a header cannot be trusted
+        let storage = MemoryStorage::new();
+        storage.insert(BLOCKS_TREE, b"0", b"{}").unwrap();
+        assert!(migrate_to(&storage, 1, TEST_MIGRATIONS).is_err());
+    }
+}
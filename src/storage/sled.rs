@@ -0,0 +1,95 @@
+use super::*;
+
+const DEFAULT_TREE: &str = "__sled__default";
+
+/// A storage backend on top of a sled database, one sled tree per storage tree.
+pub struct SledStorage {
+    db: ::sled::Db,
+}
+
+impl SledStorage {
+    /// Opens (or creates) the database in the directory `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        // background threads of a database dropped a moment ago may still hold its file lock
+        let mut retries = 20;
+        loop {
+            match ::sled::open(path) {
+                Ok(db) => return Ok(SledStorage { db }),
+                Err(::sled::Error::Io(_)) if retries > 0 => {
+                    retries -= 1;
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, tree: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.open_tree(tree)?.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn insert(&self, tree: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.db.open_tree(tree)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, tree: &str, key: &[u8]) -> Result<()> {
+        self.db.open_tree(tree)?.remove(key)?;
+        Ok(())
+    }
+
+    fn scan(&self, tree: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .open_tree(tree)?
+            .iter()
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect()
+    }
+
+    fn scan_prefix(&self, tree: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .open_tree(tree)?
+            .scan_prefix(prefix)
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect()
+    }
+
+    fn last(&self, tree: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self.db.open_tree(tree)?.iter().next_back() {
+            Some(entry) => {
+                let (k, v) = entry?;
+                Ok(Some((k.to_vec(), v.to_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn clear(&self, tree: &str) -> Result<()> {
+        self.db.open_tree(tree)?.clear()?;
+        Ok(())
+    }
+
+    fn trees(&self) -> Result<Vec<String>> {
+        Ok(self
+            .db
+            .tree_names()
+            .into_iter()
+            .map(|name| String::from_utf8_lossy(&name).into_owned())
+            // sled always has a default tree, which we never use
+            .filter(|name| name != DEFAULT_TREE)
+            .collect())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Double-spend attack simulation
+//!
+//! The attacker mines a coin and pays it to a merchant on the public chain while secretly mining
+//! a fork that sends the same coin back to itself. Once the fork is `release_after` blocks long it is revealed, and
+//! the attack succeeds if the honest network switches to the fork after the merchant has already
+//! accepted the payment. Every round, the attacker finds the next block with probability
+//! `attacker_share`, the share of the hash power it controls.
+
+use super::*;
+use crate::logging::CONSENSUS;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub struct DoubleSpend {
+    /// the share of the hash power controlled by the attacker, between 0 and 1
+    pub attacker_share: f64,
+    /// how many blocks the merchant waits for before accepting the payment
+    pub confirmations: u64,
+    /// how many blocks the attacker mines privately before releasing the fork
+    pub release_after: u64,
+    /// gives up if the fork is not released after this many blocks in total
+    pub max_rounds: u64,
+    /// the difficulty the chain starts at
+    pub difficulty: u32,
+    pub seed: u64,
+}
+
+#[derive(Debug)]
+pub struct AttackReport {
+    pub honest_blocks: u64,
+    pub fork_blocks: u64,
+    /// confirmations of the payment when the fork was released
+    pub confirmations: u64,
+    pub fork_released: bool,
+    pub fork_adopted: bool,
+    pub succeeded: bool,
+}
+
+impl Default for DoubleSpend {
+    fn default() -> Self {
+        DoubleSpend {
+            attacker_share: 0.3,
+            confirmations: 3,
+            release_after: 4,
+            max_rounds: 100,
+            difficulty: INITIAL_DIFFICULTY,
+            seed: 0,
+        }
+    }
+}
+
+impl DoubleSpend {
+    pub fn run(&self) -> AttackReport {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut clock = Clock::default();
+        let mut honest = Blockchain::with_difficulty(self.difficulty);
+        mine(&mut honest, "attacker", &mut clock);
+        let payment = Transaction::new("attacker", "merchant", 1);
+        let refund = Transaction::new("attacker", "attacker", 1);
+        honest
+            .add_new_transaction(&payment)
+            .expect("the attacker holds the reward it mined");
+        // the fork branches off right before the payment gets mined
+        let mut fork = fork(&honest);
+        fork.add_new_transaction(&refund)
+            .expect("the payment is not on the fork");
+
+        let mut report = AttackReport {
+            honest_blocks: 0,
+            fork_blocks: 0,
+            confirmations: 0,
+            fork_released: false,
+            fork_adopted: false,
+            succeeded: false,
+        };
+        for round in 0..self.max_rounds {
+            if rng.gen_bool(self.attacker_share) {
+                mine(&mut fork, "attacker", &mut clock);
+                report.fork_blocks += 1;
+            } else {
+                mine(&mut honest, "honest", &mut clock);
+                report.honest_blocks += 1;
+            }
+            debug!(
+                target: CONSENSUS,
+                "round {}: honest chain {} blocks, private fork {} blocks",
+                round, report.honest_blocks, report.fork_blocks
+            );
+            if report.fork_blocks >= self.release_after {
+                report.fork_released = true;
+                break;
+            }
+        }
+        // the payment was mined into the first honest block
+        report.confirmations = report.honest_blocks;
+        if report.fork_released {
+            info!(
+                target: CONSENSUS,
+                "Releasing the private fork of {} blocks",
+                report.fork_blocks
+            );
+            report.fork_adopted = honest.prefers(&fork);
+        }
+        report.succeeded = report.fork_adopted && report.confirmations >= self.confirmations;
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_double_spend() {
+        let report = DoubleSpend {
+            attacker_share: 1.0,
+            confirmations: 0,
+            release_after: 2,
+            difficulty: 8,
+            ..Default::default()
+        }
+        .run();
+        assert!(report.fork_adopted && report.succeeded);
+
+        let report = DoubleSpend {
+            attacker_share: 0.0,
+            max_rounds: 2,
+            difficulty: 8,
+            ..Default::default()
+        }
+        .run();
+        assert_eq!(report.honest_blocks, 2);
+        assert!(!report.fork_released && !report.succeeded);
+    }
+}
@@ -0,0 +1,41 @@
+//! Helpers staging scenarios on in-process chains, to demonstrate or test the protocol safely
+
+mod double_spend;
+mod selfish_mining;
+mod tutorial;
+mod vectors;
+
+use crate::blockchain::{INITIAL_DIFFICULTY, TARGET_BLOCK_TIME};
+use crate::*;
+pub use double_spend::{AttackReport, DoubleSpend};
+pub use selfish_mining::{MinerStrategy, MiningReport, SelfishMining};
+pub use tutorial::Tutorial;
+pub use vectors::{BlockVector, TestVectors, TransactionVector, VectorParams};
+
+/// A simulated clock where every block takes exactly the targeted block time, so that the
+/// difficulty of simulated chains stays put however fast they are mined.
+#[derive(Default)]
+pub struct Clock {
+    now: u128,
+}
+
+impl Clock {
+    /// Advances the clock by one block interval and returns the new time.
+    pub fn tick(&mut self) -> u128 {
+        self.now += TARGET_BLOCK_TIME;
+        self.now
+    }
+}
+
+/// Mines a block on `chain` rewarding `miner`, with whatever transactions are pending.
+pub fn mine(chain: &mut Blockchain, miner: &str, clock: &mut Clock) -> Block {
+    chain
+        .add_new_transaction(&Transaction::new(REWARD_SENDER, miner, 1))
+        .expect("the flat reward is always due");
+    chain.mine_new_block_at(clock.tick()).clone()
+}
+
+/// Copies `chain`, without its pending transactions.
+pub fn fork(chain: &Blockchain) -> Blockchain {
+    Blockchain::from_blocks(chain.get_blocks())
+}
@@ -0,0 +1,183 @@
+//! Selfish mining simulation
+//!
+//! A pool controlling `pool_share` of the hash power either mines honestly, publishing every
+//! block right away, or selfishly: it withholds the blocks it finds on a private fork and only
+//! reveals them to orphan the honest miners' blocks (Eyal and Sirer's strategy). When both
+//! branches are equally long, `gamma` is the share of the honest miners that build on the
+//! pool's branch. Revenue is counted on the chain everybody ends up agreeing on.
+
+use super::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+
+const POOL: &str = "pool";
+const HONEST: &str = "honest";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinerStrategy {
+    Honest,
+    Selfish,
+}
+
+impl fmt::Display for MinerStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinerStrategy::Honest => write!(f, "honest"),
+            MinerStrategy::Selfish => write!(f, "selfish"),
+        }
+    }
+}
+
+pub struct SelfishMining {
+    /// the share of the hash power controlled by the pool, between 0 and 1
+    pub pool_share: f64,
+    /// the share of honest miners building on the pool's branch during a tie, between 0 and 1
+    pub gamma: f64,
+    /// how many blocks are found in total
+    pub blocks: u64,
+    /// the difficulty the chain starts at
+    pub difficulty: u32,
+    pub seed: u64,
+}
+
+#[derive(Debug)]
+pub struct MiningReport {
+    pub strategy: MinerStrategy,
+    pub pool_share: f64,
+    /// blocks found by the pool, including orphaned ones
+    pub pool_mined: u64,
+    pub honest_mined: u64,
+    /// blocks of the pool and the honest miners on the final chain
+    pub pool_blocks: u64,
+    pub honest_blocks: u64,
+}
+
+impl MiningReport {
+    /// The pool's share of the rewards paid out on the final chain.
+    pub fn relative_revenue(&self) -> f64 {
+        let total = self.pool_blocks + self.honest_blocks;
+        if total == 0 {
+            return 0.0;
+        }
+        self.pool_blocks as f64 / total as f64
+    }
+
+    pub fn orphaned(&self) -> u64 {
+        self.pool_mined + self.honest_mined - self.pool_blocks - self.honest_blocks
+    }
+}
+
+impl Default for SelfishMining {
+    fn default() -> Self {
+        SelfishMining {
+            pool_share: 0.35,
+            gamma: 0.5,
+            blocks: 50,
+            difficulty: INITIAL_DIFFICULTY,
+            seed: 0,
+        }
+    }
+}
+
+impl SelfishMining {
+    pub fn run(&self, strategy: MinerStrategy) -> MiningReport {
+        // who finds the blocks does not depend on the strategy
+        let mut finder = StdRng::seed_from_u64(self.seed);
+        let mut split = StdRng::seed_from_u64(self.seed.wrapping_add(1));
+        let mut clock = Clock::default();
+        // what the honest miners build on, and the pool's fork that may be ahead of it
+        let mut public = Blockchain::with_difficulty(self.difficulty);
+        let mut private = fork(&public);
+        // whether both branches are equally long and the honest miners are split
+        let mut tie = false;
+        let (mut pool_mined, mut honest_mined) = (0, 0);
+
+        for _ in 0..self.blocks {
+            if finder.gen_bool(self.pool_share) {
+                pool_mined += 1;
+                mine(&mut private, POOL, &mut clock);
+                if strategy == MinerStrategy::Honest || tie {
+                    publish(&mut public, &private);
+                    tie = false;
+                }
+                continue;
+            }
+            honest_mined += 1;
+            if tie {
+                if split.gen_bool(self.gamma) {
+                    public = fork(&private);
+                }
+                mine(&mut public, HONEST, &mut clock);
+                private = fork(&public);
+                tie = false;
+                continue;
+            }
+            mine(&mut public, HONEST, &mut clock);
+            let lead = private.len() as i64 - public.len() as i64;
+            match lead {
+                // the pool had nothing to hide, and goes on from the honest block
+                l if l < 0 => private = fork(&public),
+                // it was one block ahead, and races the honest block
+                0 => tie = true,
+                // it was two blocks ahead, and orphans the honest block
+                1 => publish(&mut public, &private),
+                // it is still far enough ahead to keep hiding its blocks
+                _ => {}
+            }
+        }
+        // in the end, the pool reveals whatever it is still hiding
+        publish(&mut public, &private);
+
+        let mut report = MiningReport {
+            strategy,
+            pool_share: self.pool_share,
+            pool_mined,
+            honest_mined,
+            pool_blocks: 0,
+            honest_blocks: 0,
+        };
+        for block in public.iter_blocks() {
+            match block.get_miner() {
+                Some(POOL) => report.pool_blocks += 1,
+                Some(_) => report.honest_blocks += 1,
+                None => {}
+            }
+        }
+        report
+    }
+}
+
+/// Lets the honest miners see the pool's fork, which they adopt if it is longer.
+fn publish(public: &mut Blockchain, private: &Blockchain) {
+    if public.prefers(private) {
+        *public = fork(private);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selfish_mining() {
+        let simulation = SelfishMining {
+            pool_share: 0.5,
+            blocks: 8,
+            difficulty: 8,
+            ..Default::default()
+        };
+        let honest = simulation.run(MinerStrategy::Honest);
+        // honest mining orphans nothing
+        assert_eq!(honest.orphaned(), 0);
+        assert_eq!(honest.pool_blocks, honest.pool_mined);
+
+        let selfish = simulation.run(MinerStrategy::Selfish);
+        // the same seed lets the same miners find the blocks
+        assert_eq!(selfish.pool_mined, honest.pool_mined);
+        assert_eq!(
+            selfish.pool_blocks + selfish.honest_blocks + selfish.orphaned(),
+            8
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! A guided walkthrough of the node, for `nb tutorial`
+//!
+//! The user enters console commands, as into `nb`, but they run on in-process chains: the chain
+//! of the user's node and, once added, the chain of a peer, both mined on the clock of the
+//! testkit. Every step asks for one command and checks that the user entered it before moving
+//! on, from picking a wallet address over mining and sending coins to a fork between the two
+//! nodes and its resolution.
+
+use super::*;
+use crate::node::command::{self, Command};
+
+/// The difficulty the chains of the tutorial are mined at, low enough to mine right away.
+const TUTORIAL_DIFFICULTY: u32 = 8;
+
+/// Where the peer of the tutorial claims to listen, see the `add_peer` step.
+const PEER_ADDR: &str = "127.0.0.1:4001";
+
+/// How many blocks the peer mines during the fork, one more than the user.
+const PEER_BLOCKS: u64 = 2;
+
+pub struct Tutorial {
+    chain: Blockchain,
+    // the chain of the peer, once added
+    peer: Option<Blockchain>,
+    // the wallet of the user, where mining rewards go
+    address: Option<String>,
+    clock: Clock,
+    step: usize,
+}
+
+impl Default for Tutorial {
+    fn default() -> Self {
+        Tutorial {
+            chain: Blockchain::with_difficulty(TUTORIAL_DIFFICULTY),
+            peer: None,
+            address: None,
+            clock: Clock::default(),
+            step: 0,
+        }
+    }
+}
+
+impl Tutorial {
+    /// What the current step is about and the command it asks for, `None` once the tutorial is
+    /// finished.
+    pub fn instructions(&self) -> Option<String> {
+        let text = match self.step {
+            0 => "A wallet is an address coins are sent to. Pick one, and have the rewards of the blocks your node mines sent to it".to_owned(),
+            1 => "Mine a block. It pays you the mining reward of 1 coin".to_owned(),
+            2 => "Check the balance of your address".to_owned(),
+            3 => "Send a coin to bob. The transaction waits in the mempool until a block confirms it".to_owned(),
+            4 => "Mine a block to confirm your transaction".to_owned(),
+            5 => "A second node comes up. Add it as a peer, and it downloads your chain".to_owned(),
+            6 => format!(
+                "Now both nodes mine at once, each on its own, so their chains fork. Mine your block, while the peer finds {}",
+                PEER_BLOCKS
+            ),
+            7 => "Resolve the fork. Your node adopts the chain with the most work, whichever node mined it".to_owned(),
+            8 => "Check your balance again".to_owned(),
+            _ => return None,
+        };
+        Some(format!("{}:\n  {}", text, self.example()))
+    }
+
+    /// The command the current step asks for, as the user would enter it.
+    pub fn example(&self) -> String {
+        let address = self.address.as_deref().unwrap_or("alice");
+        match self.step {
+            0 => "set_reward_address alice".to_owned(),
+            1 | 4 | 6 => "mine".to_owned(),
+            2 | 8 => format!("balance {}", address),
+            3 => format!("new_trans {} bob 1", address),
+            5 => format!("add_peer {}", PEER_ADDR),
+            7 => "resolve".to_owned(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.instructions().is_none()
+    }
+
+    /// Runs `input` if it is the command the current step asks for, moving on to the next step,
+    /// and returns what it printed. Anything else is turned down with what the step asks for.
+    pub fn enter(&mut self, input: &str) -> std::result::Result<String, String> {
+        let command = command::parse(input)?;
+        let output = match (self.step, command) {
+            (0, Command::SetRewardAddress(address)) => {
+                if !is_valid_address(&address) {
+                    return Err(format!(
+                        "{:?} is not a valid address, use 1 to 64 letters, digits, - or _",
+                        address
+                    ));
+                }
+                let output = format!("rewards go to {}\n", address);
+                self.address = Some(address);
+                output
+            }
+            (1, Command::Mine) | (4, Command::Mine) => self.mine(),
+            (2, Command::Balance(address, _))
+            | (2, Command::GetBalance(address, None))
+            | (8, Command::Balance(address, _))
+            | (8, Command::GetBalance(address, None)) => {
+                format!("{}\n", self.chain.get_balance(&address))
+            }
+            (3, Command::NewTrans(sender, receiver, amount, fee, expiry)) => {
+                let mut transaction = Transaction::new(&sender, &receiver, amount).with_fee(fee);
+                if let Some(expiry) = expiry {
+                    transaction = transaction.with_expiry(expiry);
+                }
+                self.chain
+                    .add_new_transaction(&transaction)
+                    .map_err(|rejection| rejection.to_string())?;
+                format!("{}\n", transaction.get_id())
+            }
+            (5, Command::AddPeer(peer)) => {
+                self.peer = Some(fork(&self.chain));
+                format!(
+                    "{} is a peer now, and downloaded your {} blocks\n",
+                    peer,
+                    self.chain.len()
+                )
+            }
+            (6, Command::Mine) => {
+                let mut output = self.mine();
+                let peer = self.peer.as_mut().expect("the peer was added");
+                for _ in 0..PEER_BLOCKS {
+                    let block = mine(peer, "peer", &mut self.clock);
+                    output += &format!("the peer mined {}", BlockSummary::of(&block));
+                }
+                output
+            }
+            (7, Command::Resolve) => {
+                let peer = self.peer.as_ref().expect("the peer was added");
+                if !self.chain.prefers(peer) {
+                    return Err("the chain of the peer has no more work than yours".to_owned());
+                }
+                let orphaned = self.chain.last_block().get_index();
+                self.chain = fork(peer);
+                format!(
+                    "node updated, your block {} is orphaned, and its reward with it\n",
+                    orphaned
+                )
+            }
+            _ => return Err(format!("Not quite, this step asks for: {}", self.example())),
+        };
+        self.step += 1;
+        Ok(output)
+    }
+
+    fn mine(&mut self) -> String {
+        let miner = self.address.as_deref().expect("the address was set first");
+        BlockSummary::of(&mine(&mut self.chain, miner, &mut self.clock)).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tutorial() {
+        let mut tutorial = Tutorial::default();
+        assert!(tutorial.enter("mine").is_err());
+        assert!(tutorial.enter("set_reward_address 0").is_err());
+        tutorial.enter("set_reward_address carol").unwrap();
+        tutorial.enter("mine").unwrap();
+        assert_eq!(tutorial.enter("balance carol").unwrap(), "1\n");
+        // a transaction the user cannot afford stays in the step
+        assert!(tutorial.enter("new_trans carol bob 2").is_err());
+        assert_eq!(tutorial.example(), "new_trans carol bob 1");
+        tutorial.enter("new_trans carol bob 1").unwrap();
+        tutorial.enter("mine").unwrap();
+        tutorial.enter("add_peer 127.0.0.1:4001").unwrap();
+        tutorial.enter("mine").unwrap();
+        assert_eq!(tutorial.chain.get_balance("carol"), 2);
+        tutorial.enter("resolve").unwrap();
+        assert!(!tutorial.is_finished());
+        assert_eq!(tutorial.enter("balance carol").unwrap(), "1\n");
+        assert!(tutorial.is_finished());
+        assert_eq!(tutorial.chain.get_balance("bob"), 1);
+        assert_eq!(tutorial.chain.len(), 5);
+    }
+}
@@ -0,0 +1,318 @@
+//! Test vectors: blocks and transactions as they are serialized, with their hashes and proofs
+//!
+//! `nb genvectors` writes them as JSON files, which the golden tests compare this build with and
+//! which implementations in other languages can check themselves against. They come out the
+//! same every time: transactions carry fixed nonces and keys, blocks fixed timestamps, and proofs
+//! of work are the first nonce from 0 on that meets the difficulty.
+
+use super::*;
+use crate::encoding::ENCODING_VERSION;
+use crate::script::Op;
+use crate::{
+    Allocation, GenesisSpec, NodeKey, OutPoint, Output, RewardSchedule, TransactionModel,
+    BLOCK_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// What the vectors were made under.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VectorParams {
+    /// the encoding the headers of blocks but genesis blocks are hashed in
+    pub encoding_version: u32,
+    pub block_version: u32,
+    /// the difficulty the blocks are mined with
+    pub difficulty: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransactionVector {
+    pub description: String,
+    /// the transaction as it goes over the wire
+    pub json: String,
+    /// the hash of its content
+    pub id: String,
+    /// what its signatures are taken over, see `Transaction::signature_hash`
+    pub signature_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockVector {
+    pub description: String,
+    /// the block as it goes over the wire
+    pub json: String,
+    /// the hex of the bytes the header hash is taken over, see `BlockHeader::preimage`
+    pub preimage: String,
+    pub hash: String,
+    pub merkle_root: String,
+    pub difficulty: u32,
+    /// the proof of work, 100 for genesis blocks, which are not mined
+    pub nonce: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestVectors {
+    pub params: VectorParams,
+    pub transactions: Vec<TransactionVector>,
+    pub blocks: Vec<BlockVector>,
+}
+
+const PARAMS_FILE: &str = "params.json";
+const TRANSACTIONS_FILE: &str = "transactions.json";
+const BLOCKS_FILE: &str = "blocks.json";
+
+impl TestVectors {
+    /// Makes the vectors, mining their blocks with `difficulty`.
+    pub fn generate(difficulty: u32) -> Self {
+        let mut clock = Clock::default();
+        let mut chain = Blockchain::with_difficulty(difficulty);
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1).with_nonce(1);
+        chain
+            .add_new_transaction(&reward)
+            .expect("the reward is due");
+        chain.mine_new_block_at(clock.tick());
+        let payment = Transaction::new("alice", "bob", 1)
+            .with_fee(1)
+            .with_expiry(Expiry::Height(100))
+            .with_nonce(2);
+        let reward = Transaction::new(REWARD_SENDER, "alice", 1).with_nonce(3);
+        for t in [&reward, &payment] {
+            chain.add_new_transaction(t).expect("alice holds the coin");
+        }
+        chain.mine_new_block_at(clock.tick());
+
+        // from a fixed seed, as ed25519 signs the same message the same way every time
+        let signer = NodeKey::from_seed([1; 32]);
+        chain.set_miner_key(Some(signer.clone()));
+        let signed_reward = Transaction::new(REWARD_SENDER, &signer.public_key(), 1).with_nonce(8);
+        chain
+            .add_new_transaction(&signed_reward)
+            .expect("the reward is due");
+        chain.mine_new_block_at(clock.tick());
+
+        let mut spec = GenesisSpec {
+            name: Some("vectors".to_owned()),
+            timestamp: 1_600_000_000_000,
+            difficulty: Some(difficulty),
+            reward_schedule: Some(RewardSchedule {
+                initial_reward: 50,
+                halving_interval: 210,
+                max_supply: Some(21_000),
+                coinbase_maturity: 10,
+            }),
+            transaction_model: Some(TransactionModel::Utxo),
+            address_prefix: None,
+            legacy_genesis: None,
+            allocations: vec![Allocation {
+                address: "alice".to_owned(),
+                amount: 1000,
+            }],
+        };
+        // a chain of free-form addresses, as the vectors were first made with
+        spec.legacy_genesis = Some(spec.block().get_hash());
+        let mut named = Blockchain::from_blocks(vec![Block::get_genesis_from(&spec)]);
+        named.set_signals(&[0]).expect("bit 0 is in range");
+        let allocation = named.get_block_by_index(0).unwrap().get_transactions()[0].clone();
+        let spend = Transaction::spend(
+            "alice",
+            vec![OutPoint {
+                transaction: allocation.get_id().to_owned(),
+                index: 0,
+            }],
+            vec![
+                Output {
+                    recipient: "bob".to_owned(),
+                    amount: 600,
+                },
+                Output {
+                    recipient: "alice".to_owned(),
+                    amount: 399,
+                },
+            ],
+        )
+        .with_fee(1)
+        .with_nonce(4);
+        named
+            .add_new_transaction(&spend)
+            .expect("alice holds the allocation");
+        named.mine_new_block_at(spec.timestamp + TARGET_BLOCK_TIME);
+
+        let transactions = vec![
+            ("a mining reward", reward),
+            ("a payment with a fee and an expiry", payment),
+            ("a genesis allocation on a UTXO chain", allocation),
+            ("a UTXO spend with change", spend),
+            (
+                "an issuance of tokens",
+                Transaction::issue("alice", "bob", "gold", 5, 100).with_nonce(5),
+            ),
+            (
+                "a payment locked by the preimage of a hash",
+                Transaction::new("alice", "bob", 1)
+                    .with_script(vec![
+                        Op::Push("secret".to_owned()),
+                        Op::Sha256,
+                        Op::Push(
+                            "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+                                .to_owned(),
+                        ),
+                        Op::Equal,
+                    ])
+                    .with_nonce(6),
+            ),
+            (
+                "a payment signed by the key of its sender",
+                Transaction::new(&signer.public_key(), "bob", 1)
+                    .with_nonce(7)
+                    .sign(&signer),
+            ),
+        ]
+        .into_iter()
+        .map(|(description, t)| TransactionVector {
+            description: description.to_owned(),
+            json: serde_json::to_string(&t).unwrap(),
+            id: t.get_id().to_owned(),
+            signature_hash: t.signature_hash(),
+        })
+        .collect();
+
+        let blocks = vec![
+            ("the genesis block of the default chain", &chain, 0),
+            ("a block minting the reward", &chain, 1),
+            ("a block with a payment", &chain, 2),
+            ("a block signed by its miner", &chain, 3),
+            (
+                "the genesis block of a named UTXO chain with allocations",
+                &named,
+                0,
+            ),
+            ("a block of a named chain, signalling bit 0", &named, 1),
+        ]
+        .into_iter()
+        .map(|(description, chain, index)| {
+            let block = chain.get_block_by_index(index).unwrap();
+            BlockVector {
+                description: description.to_owned(),
+                json: serde_json::to_string(&*block).unwrap(),
+                preimage: block
+                    .get_header()
+                    .preimage()
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+                hash: block.get_hash(),
+                merkle_root: block.get_merkle_root().to_owned(),
+                difficulty: block.get_difficulty(),
+                nonce: block.get_nonce(),
+            }
+        })
+        .collect();
+
+        TestVectors {
+            params: VectorParams {
+                encoding_version: ENCODING_VERSION,
+                block_version: BLOCK_VERSION,
+                difficulty,
+            },
+            transactions,
+            blocks,
+        }
+    }
+
+    /// Writes the vectors into `dir`, the params, transactions and blocks in a file each.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let files = [
+            (PARAMS_FILE, serde_json::to_string_pretty(&self.params)?),
+            (
+                TRANSACTIONS_FILE,
+                serde_json::to_string_pretty(&self.transactions)?,
+            ),
+            (BLOCKS_FILE, serde_json::to_string_pretty(&self.blocks)?),
+        ];
+        for (file, json) in files.iter() {
+            fs::write(dir.join(file), format!("{}\n", json))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the vectors `write` wrote into `dir`.
+    pub fn read(dir: &Path) -> Result<Self> {
+        let read = |file: &str| {
+            fs::read_to_string(dir.join(file)).map_err(|e| {
+                failure::err_msg(format!("Cannot read {}: {}", dir.join(file).display(), e))
+            })
+        };
+        Ok(TestVectors {
+            params: serde_json::from_str(&read(PARAMS_FILE)?)?,
+            transactions: serde_json::from_str(&read(TRANSACTIONS_FILE)?)?,
+            blocks: serde_json::from_str(&read(BLOCKS_FILE)?)?,
+        })
+    }
+
+    /// Checks that every vector reads back into what it says: the same JSON, hashes and proof,
+    /// which genesis blocks go without.
+    /// Fails with the description of the first that does not.
+    pub fn check(&self) -> std::result::Result<(), String> {
+        for vector in &self.transactions {
+            let fails = || format!("the transaction vector {:?} fails", vector.description);
+            let t: Transaction = serde_json::from_str(&vector.json).map_err(|_| fails())?;
+            if serde_json::to_string(&t).ok().as_ref() != Some(&vector.json)
+                || t.get_id() != vector.id
+                || !t.has_valid_id()
+                || t.verify().is_err()
+                || t.signature_hash() != vector.signature_hash
+            {
+                return Err(fails());
+            }
+        }
+        for vector in &self.blocks {
+            let fails = || format!("the block vector {:?} fails", vector.description);
+            let block: Block = serde_json::from_str(&vector.json).map_err(|_| fails())?;
+            let preimage: String = block
+                .get_header()
+                .preimage()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            if serde_json::to_string(&block).ok().as_ref() != Some(&vector.json)
+                || preimage != vector.preimage
+                || block.get_hash() != vector.hash
+                || block.get_merkle_root() != vector.merkle_root
+                || !block.has_valid_merkle_root()
+                || block.get_difficulty() != vector.difficulty
+                || block.get_nonce() != vector.nonce
+                || block.get_index() > 0 && !block.has_valid_proof()
+            {
+                return Err(fails());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        let vectors = TestVectors::generate(4);
+        assert_eq!(vectors, TestVectors::generate(4));
+        assert_eq!(vectors.check(), Ok(()));
+        assert_eq!(vectors.blocks[0].hash, Block::get_genesis(4).get_hash());
+
+        let dir = std::env::temp_dir().join(format!("nb-vectors-{}", std::process::id()));
+        vectors.write(&dir).unwrap();
+        assert_eq!(TestVectors::read(&dir).unwrap(), vectors);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut tampered = vectors;
+        tampered.blocks[1].nonce += 1;
+        assert_eq!(
+            tampered.check(),
+            Err("the block vector \"a block minting the reward\" fails".to_owned())
+        );
+    }
+}
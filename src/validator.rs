@@ -0,0 +1,39 @@
+//! Rules of one node for the transactions it admits to its mempool
+//!
+//! The consensus rules are the same on every node, as they decide which blocks are valid. A
+//! `TxValidator` adds rules of a single node on top, e.g. for the exercise of a course in which
+//! amounts must be prime: they run when a transaction enters the mempool, once it passed the
+//! consensus rules, and turn it down with why, see `Rejection::Policy`. Mining rewards are left
+//! alone, and so are blocks: a block of another miner holding a transaction the rules turn down
+//! is still valid, only this node does not relay or mine the transaction.
+
+use crate::{Blockchain, Transaction};
+use std::fmt;
+
+/// A rule for the transactions a node admits, see the module docs. Closures taking the
+/// transaction and the chain are rules too.
+pub trait TxValidator: Send + Sync {
+    /// Checks `transaction`, which is about to join the pending transactions of `chain`,
+    /// failing with why it is turned down.
+    fn validate(&self, transaction: &Transaction, chain: &Blockchain) -> Result<(), String>;
+
+    /// What the rule is called in logs.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl<F> TxValidator for F
+where
+    F: Fn(&Transaction, &Blockchain) -> Result<(), String> + Send + Sync,
+{
+    fn validate(&self, transaction: &Transaction, chain: &Blockchain) -> Result<(), String> {
+        self(transaction, chain)
+    }
+}
+
+impl fmt::Debug for dyn TxValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TxValidator({})", self.name())
+    }
+}
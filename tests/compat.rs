@@ -0,0 +1,215 @@
+//! Cross-version compatibility
+//!
+//! Runs a node of a previous release next to a node of this build on localhost and checks that
+//! they still shake hands, sync and relay each other's transactions and blocks. Building the
+//! previous release takes a while, so the test is ignored by default:
+//!
+//! ```sh
+//! cargo test --test compat -- --ignored
+//! ```
+//!
+//! The previous release is the latest git tag, built in a temporary worktree. Set
+//! `NB_COMPAT_REV` to build another revision instead, or `NB_COMPAT_BIN` to use a binary at hand.
+
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WAIT: Duration = Duration::from_secs(20);
+
+struct LocalNode {
+    addr: String,
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl LocalNode {
+    /// Runs `bin` on `addr`, with `flags` besides the common ones, taking commands on stdin.
+    fn start(bin: &Path, flags: &[&str], addr: &str, work_dir: &Path) -> Self {
+        let name = addr.replace(':', "-");
+        let log = File::create(work_dir.join(format!("{}.log", name))).unwrap();
+        let mut child = Command::new(bin)
+            .args(flags)
+            .args(["--addr", addr, "--difficulty", "4", "--data-dir"])
+            .arg(work_dir.join(name))
+            .env("RUST_LOG", "info")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .unwrap_or_else(|e| panic!("cannot run {}: {}", bin.display(), e));
+        let stdin = child.stdin.take().unwrap();
+        let node = LocalNode {
+            addr: addr.to_owned(),
+            child,
+            stdin,
+        };
+        wait_for(|| TcpStream::connect(addr).is_ok(), "the node to listen");
+        node
+    }
+
+    fn send(&mut self, command: &str) {
+        writeln!(self.stdin, "{}", command).unwrap();
+    }
+
+    /// Asks the node for its blocks over the wire, as a peer would.
+    fn blocks(&self, me: &Value) -> Vec<Value> {
+        match query(&self.addr, &json!({ "HowAreYou": me })) {
+            Some(response) => response["MyBlocks"][1]
+                .as_array()
+                .cloned()
+                .unwrap_or_default(),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for LocalNode {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, "exit");
+        thread::sleep(Duration::from_millis(500));
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn query(addr: &str, request: &Value) -> Option<Value> {
+    let mut stream = TcpStream::connect(addr).ok()?;
+    serde_json::to_writer(&mut stream, request).ok()?;
+    stream.flush().ok()?;
+    stream.shutdown(Shutdown::Write).ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    serde_json::from_str(&response).ok()
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool, what: &str) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < WAIT, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn transactions(blocks: &[Value]) -> Vec<Value> {
+    blocks
+        .iter()
+        .flat_map(|block| {
+            block["transactions"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn has_transaction(blocks: &[Value], recipient: &str) -> bool {
+    transactions(blocks)
+        .iter()
+        .any(|t| t["recipient"] == recipient)
+}
+
+fn git(args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .expect("git is installed");
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+fn previous_binary(work_dir: &Path) -> PathBuf {
+    if let Ok(bin) = env::var("NB_COMPAT_BIN") {
+        return bin.into();
+    }
+    let rev =
+        env::var("NB_COMPAT_REV").unwrap_or_else(|_| git(&["describe", "--tags", "--abbrev=0"]));
+    let worktree = work_dir.join("previous");
+    let worktree_str = worktree.to_str().unwrap();
+    git(&["worktree", "add", "--detach", worktree_str, &rev]);
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned()))
+        .args(["build", "--bin", "nb", "--manifest-path"])
+        .arg(worktree.join("Cargo.toml"))
+        .env("CARGO_TARGET_DIR", work_dir.join("target"))
+        .status()
+        .expect("cargo can be run");
+    git(&["worktree", "remove", "--force", worktree_str]);
+    assert!(status.success(), "cannot build nb at {}", rev);
+    work_dir.join("target").join("debug").join("nb")
+}
+
+#[test]
+#[ignore]
+fn test_previous_release() {
+    let work_dir = env::temp_dir().join(format!("nb-compat-{}", process::id()));
+    fs::create_dir_all(&work_dir).unwrap();
+    let previous = previous_binary(&work_dir);
+
+    // the harness talks to both nodes as a peer of its own
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let me = json!({
+        "id": "compat-harness",
+        "address": listener.local_addr().unwrap().to_string(),
+    });
+
+    let mut old = LocalNode::start(&previous, &[], "127.0.0.1:4810", &work_dir);
+    old.send("mine");
+    old.send("mine");
+    wait_for(
+        || old.blocks(&me).len() == 3,
+        "the previous release to mine",
+    );
+
+    // handshake and sync, with a console attached to an nbd of its own
+    let mut new = LocalNode::start(
+        Path::new(env!("CARGO_BIN_EXE_nb")),
+        &["--standalone"],
+        "127.0.0.1:4811",
+        &work_dir,
+    );
+    new.send(&format!("add_peer {}", old.addr));
+    new.send("resolve");
+    wait_for(|| new.blocks(&me).len() == 3, "this build to sync");
+    assert_eq!(new.blocks(&me), old.blocks(&me));
+
+    // transactions and blocks relayed from the previous release, paid with its mining rewards
+    let miner = transactions(&old.blocks(&me))
+        .iter()
+        .find(|t| t["sender"] == "0")
+        .and_then(|t| t["recipient"].as_str().map(str::to_owned))
+        .expect("the previous release rewards its blocks");
+    old.send(&format!("new_trans {} bob 1", miner));
+    old.send("mine");
+    wait_for(
+        || has_transaction(&new.blocks(&me), "bob"),
+        "a block of the previous release",
+    );
+
+    // and the other way round
+    new.send(&format!("new_trans {} dave 1", miner));
+    thread::sleep(Duration::from_secs(1));
+    old.send("mine");
+    wait_for(
+        || has_transaction(&new.blocks(&me), "dave"),
+        "a transaction of this build to be mined by the previous release",
+    );
+    let len = new.blocks(&me).len();
+    new.send("mine");
+    wait_for(|| old.blocks(&me).len() == len + 1, "a block of this build");
+    assert_eq!(new.blocks(&me), old.blocks(&me));
+
+    drop((old, new));
+    let _ = fs::remove_dir_all(&work_dir);
+}
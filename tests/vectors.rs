@@ -0,0 +1,29 @@
+//! Golden test vectors
+//!
+//! The files in `tests/vectors` hold blocks and transactions as this build serializes and
+//! hashes them, see `nb::testkit::TestVectors`. The test fails once a change makes them come
+//! out differently, which breaks every other implementation checking itself against them. If
+//! the change is meant to, write them anew and commit them with it:
+//!
+//! ```sh
+//! cargo run --bin nb -- genvectors --difficulty 8 --out tests/vectors
+//! ```
+
+use nb::testkit::TestVectors;
+use std::path::Path;
+
+#[test]
+fn test_golden_vectors() {
+    let golden =
+        TestVectors::read(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/vectors")).unwrap();
+    assert_eq!(golden.check(), Ok(()));
+    let generated = TestVectors::generate(golden.params.difficulty);
+    assert_eq!(generated.params, golden.params);
+    for (generated, golden) in generated.transactions.iter().zip(&golden.transactions) {
+        assert_eq!(generated, golden);
+    }
+    for (generated, golden) in generated.blocks.iter().zip(&golden.blocks) {
+        assert_eq!(generated, golden);
+    }
+    assert_eq!(generated, golden);
+}